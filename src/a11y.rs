@@ -0,0 +1,68 @@
+// src/a11y.rs
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+
+/// Reports what changed on screen as plain lines of text, for screen
+/// readers that cannot follow a full-screen redraw. Lines go to the
+/// configured announce file (a log or FIFO read by a speech tool); without
+/// one, the latest announcement is shown in the status line instead.
+pub struct Announcer {
+    out: Option<File>,
+    last_mode: String,
+    last_line: Option<usize>,
+    last_message: String,
+    /// Most recent announcement, for display when there is no announce file.
+    pub latest: String,
+}
+
+impl Announcer {
+    pub fn new(announce_file: Option<&str>) -> Self {
+        let out = announce_file.and_then(|path| OpenOptions::new().create(true).append(true).open(path).ok());
+        Announcer {
+            out,
+            last_mode: String::new(),
+            last_line: None,
+            last_message: String::new(),
+            latest: String::new(),
+        }
+    }
+
+    pub fn has_output(&self) -> bool {
+        self.out.is_some()
+    }
+
+    fn announce(&mut self, text: String) {
+        if let Some(out) = self.out.as_mut() {
+            let _ = writeln!(out, "{}", text);
+            let _ = out.flush();
+        }
+        self.latest = text;
+    }
+
+    /// Makes the next update announce the cursor line again, e.g. after the
+    /// buffer was replaced.
+    pub fn forget_line(&mut self) {
+        self.last_line = None;
+    }
+
+    /// Announces the mode, the cursor line (when the cursor moves to another
+    /// one) and the status message, each only when it changed.
+    pub fn update(&mut self, mode: &str, line: usize, line_text: &str, message: &str) {
+        if mode != self.last_mode {
+            self.last_mode = mode.to_string();
+            self.announce(format!("Mode {}", mode));
+        }
+        if self.last_line != Some(line) {
+            self.last_line = Some(line);
+            let text = if line_text.trim().is_empty() { "blank" } else { line_text };
+            self.announce(format!("Line {}: {}", line + 1, text));
+        }
+        if message != self.last_message {
+            self.last_message = message.to_string();
+            if !message.is_empty() {
+                self.announce(message.to_string());
+            }
+        }
+    }
+}
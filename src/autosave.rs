@@ -0,0 +1,31 @@
+// src/autosave.rs
+
+use crate::config::AutosaveConfig;
+use std::time::{Duration, Instant};
+
+/// Decides when modified buffers are written back to their files. The main
+/// loop wakes often enough while idle for `due` to be checked on every pass.
+pub struct Autosave {
+    interval: Option<Duration>,
+    last_run: Instant,
+}
+
+impl Autosave {
+    pub fn new(config: &AutosaveConfig) -> Self {
+        Autosave {
+            interval: (config.enabled && config.interval_secs > 0).then(|| Duration::from_secs(config.interval_secs)),
+            last_run: Instant::now(),
+        }
+    }
+
+    /// True once every interval; never when autosave is off.
+    pub fn due(&mut self) -> bool {
+        match self.interval {
+            Some(interval) if self.last_run.elapsed() >= interval => {
+                self.last_run = Instant::now();
+                true
+            }
+            _ => false,
+        }
+    }
+}
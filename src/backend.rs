@@ -0,0 +1,139 @@
+// src/backend.rs
+
+use crossterm::style::{ContentStyle, PrintStyledContent, StyledContent};
+use crossterm::terminal::{Clear, ClearType};
+use crossterm::{cursor, queue};
+use std::io::{Error, Stdout, Write};
+
+/// Part of a row `clear_region` blanks.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClearRegion {
+    /// The whole row the cursor is on.
+    Line,
+    /// From the cursor to the end of its row.
+    RestOfLine,
+}
+
+/// Where the renderer draws: the terminal, or anything else that can put
+/// styled text at a position, like a grid in memory for tests.
+pub trait Backend {
+    fn move_to(&mut self, col: u16, row: u16) -> Result<(), Error>;
+
+    /// Draws `text` from the cursor on, leaving the cursor after it.
+    fn print_styled(&mut self, text: &str, style: ContentStyle) -> Result<(), Error>;
+
+    /// Draws `text` in the default colors.
+    fn print(&mut self, text: &str) -> Result<(), Error> {
+        self.print_styled(text, ContentStyle::new())
+    }
+
+    fn clear_region(&mut self, region: ClearRegion) -> Result<(), Error>;
+
+    fn show_cursor(&mut self, visible: bool) -> Result<(), Error>;
+
+    /// Makes everything drawn so far visible.
+    fn flush(&mut self) -> Result<(), Error>;
+}
+
+/// The terminal, through crossterm. Commands are queued until `flush`.
+impl Backend for Stdout {
+    fn move_to(&mut self, col: u16, row: u16) -> Result<(), Error> {
+        queue!(self, cursor::MoveTo(col, row))
+    }
+
+    fn print_styled(&mut self, text: &str, style: ContentStyle) -> Result<(), Error> {
+        queue!(self, PrintStyledContent(StyledContent::new(style, text)))
+    }
+
+    fn clear_region(&mut self, region: ClearRegion) -> Result<(), Error> {
+        match region {
+            ClearRegion::Line => queue!(self, Clear(ClearType::CurrentLine)),
+            ClearRegion::RestOfLine => queue!(self, Clear(ClearType::UntilNewLine)),
+        }
+    }
+
+    fn show_cursor(&mut self, visible: bool) -> Result<(), Error> {
+        if visible { queue!(self, cursor::Show) } else { queue!(self, cursor::Hide) }
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Write::flush(self)
+    }
+}
+
+/// A screen in memory, one char per cell, for checking what the renderer
+/// draws without a terminal.
+pub struct TestBackend {
+    cells: Vec<Vec<(char, ContentStyle)>>,
+    cursor: (u16, u16),
+    pub cursor_visible: bool,
+}
+
+impl TestBackend {
+    pub fn new(cols: u16, rows: u16) -> Self {
+        TestBackend {
+            cells: vec![vec![(' ', ContentStyle::new()); cols as usize]; rows as usize],
+            cursor: (0, 0),
+            cursor_visible: true,
+        }
+    }
+
+    /// The text of every row, as wide as the screen.
+    pub fn lines(&self) -> Vec<String> {
+        self.cells.iter().map(|row| row.iter().map(|(ch, _)| ch).collect()).collect()
+    }
+
+    /// Column and row of the cursor.
+    pub fn cursor(&self) -> (u16, u16) {
+        self.cursor
+    }
+
+    /// Style of the cell at `col` and `row`, if it is on the screen.
+    pub fn style_at(&self, col: u16, row: u16) -> Option<ContentStyle> {
+        self.cells.get(row as usize)?.get(col as usize).map(|(_, style)| *style)
+    }
+}
+
+impl Backend for TestBackend {
+    fn move_to(&mut self, col: u16, row: u16) -> Result<(), Error> {
+        self.cursor = (col, row);
+        Ok(())
+    }
+
+    fn print_styled(&mut self, text: &str, style: ContentStyle) -> Result<(), Error> {
+        let (mut col, row) = self.cursor;
+        if let Some(cells) = self.cells.get_mut(row as usize) {
+            for ch in text.chars() {
+                if let Some(cell) = cells.get_mut(col as usize) {
+                    *cell = (ch, style);
+                }
+                col = col.saturating_add(1);
+            }
+        }
+        self.cursor = (col, row);
+        Ok(())
+    }
+
+    fn clear_region(&mut self, region: ClearRegion) -> Result<(), Error> {
+        let (col, row) = self.cursor;
+        let start = match region {
+            ClearRegion::Line => 0,
+            ClearRegion::RestOfLine => col as usize,
+        };
+        if let Some(cells) = self.cells.get_mut(row as usize) {
+            for cell in cells.iter_mut().skip(start) {
+                *cell = (' ', ContentStyle::new());
+            }
+        }
+        Ok(())
+    }
+
+    fn show_cursor(&mut self, visible: bool) -> Result<(), Error> {
+        self.cursor_visible = visible;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,52 @@
+// src/brackets.rs
+
+use crate::buffer::EditorBuffer;
+
+const PAIRS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+
+/// Chars looked at on the way to a match before giving up, so a stray
+/// bracket in a large file does not stall every keystroke.
+const MAX_SCAN: usize = 100_000;
+
+/// The bracket at the cursor, or else the one just before it, along with
+/// the bracket it pairs with. Returns their char indices in buffer order.
+pub fn matching_pair(buffer: &EditorBuffer, cursor: usize) -> Option<(usize, usize)> {
+    let at = [Some(cursor), cursor.checked_sub(1)]
+        .into_iter()
+        .flatten()
+        .find(|&idx| buffer.rope.get_char(idx).is_some_and(|ch| PAIRS.iter().any(|&(open, close)| ch == open || ch == close)))?;
+    let other = matching_bracket(buffer, at)?;
+    Some((at.min(other), at.max(other)))
+}
+
+/// Index of the bracket pairing with the one at `idx`, scanning forward
+/// from an opener and backward from a closer while counting nesting.
+fn matching_bracket(buffer: &EditorBuffer, idx: usize) -> Option<usize> {
+    let ch = buffer.rope.get_char(idx)?;
+    if let Some(&(open, close)) = PAIRS.iter().find(|&&(open, _)| open == ch) {
+        let mut depth = 0;
+        for (offset, next) in buffer.rope.chars_at(idx + 1).take(MAX_SCAN).enumerate() {
+            if next == open {
+                depth += 1;
+            } else if next == close {
+                if depth == 0 {
+                    return Some(idx + 1 + offset);
+                }
+                depth -= 1;
+            }
+        }
+    } else if let Some(&(open, close)) = PAIRS.iter().find(|&&(_, close)| close == ch) {
+        let mut depth = 0;
+        for (offset, prev) in buffer.rope.chars_at(idx).reversed().take(MAX_SCAN).enumerate() {
+            if prev == close {
+                depth += 1;
+            } else if prev == open {
+                if depth == 0 {
+                    return Some(idx - 1 - offset);
+                }
+                depth -= 1;
+            }
+        }
+    }
+    None
+}
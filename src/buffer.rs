@@ -1,8 +1,13 @@
 // src/buffer.rs
 
+use crate::crypt::Encryption;
+use crate::remote::{self, RemotePath};
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
 use ropey::Rope;
-use std::fs::{write, read_to_string};
-use std::io;
+use serde::Deserialize;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 
 #[derive(Clone, Debug)]
 pub enum EditOp {
@@ -10,25 +15,154 @@ pub enum EditOp {
     Delete { char_idx: usize, content: String },
 }
 
+impl EditOp {
+    pub fn char_idx(&self) -> usize {
+        match self {
+            EditOp::Insert { char_idx, .. } | EditOp::Delete { char_idx, .. } => *char_idx,
+        }
+    }
+
+    pub fn char_len(&self) -> usize {
+        match self {
+            EditOp::Insert { content, .. } | EditOp::Delete { content, .. } => content.chars().count(),
+        }
+    }
+
+    /// The op that undoes this one.
+    pub fn inverted(&self) -> EditOp {
+        match self {
+            EditOp::Insert { char_idx, content } => EditOp::Delete { char_idx: *char_idx, content: content.clone() },
+            EditOp::Delete { char_idx, content } => EditOp::Insert { char_idx: *char_idx, content: content.clone() },
+        }
+    }
+
+    /// Applies this op to `rope`, which must be the state right before it.
+    pub fn apply(&self, rope: &mut Rope) {
+        match self {
+            EditOp::Insert { char_idx, content } => rope.insert(*char_idx, content),
+            EditOp::Delete { char_idx, .. } => rope.remove(*char_idx..*char_idx + self.char_len()),
+        }
+    }
+
+    /// Reverts this op on `rope`, which must be the state right after it was applied.
+    fn revert(&self, rope: &mut Rope) {
+        match self {
+            EditOp::Insert { char_idx, .. } => rope.remove(*char_idx..*char_idx + self.char_len()),
+            EditOp::Delete { char_idx, content } => rope.insert(*char_idx, content),
+        }
+    }
+}
+
+/// Char range that the text touched by `ops`, applied in order, ends up
+/// at: what they inserted and where they deleted.
+pub fn edited_span(ops: &[EditOp]) -> Option<(usize, usize)> {
+    let mut span: Option<(usize, usize)> = None;
+    for op in ops {
+        let (at, len) = (op.char_idx(), op.char_len());
+        span = Some(match (op, span) {
+            (EditOp::Insert { .. }, None) => (at, at + len),
+            (EditOp::Delete { .. }, None) => (at, at),
+            (EditOp::Insert { .. }, Some((start, end))) => {
+                let shift = |idx: usize| if idx >= at { idx + len } else { idx };
+                (shift(start).min(at), shift(end).max(at + len))
+            }
+            (EditOp::Delete { .. }, Some((start, end))) => {
+                let shift = |idx: usize| if idx >= at + len { idx - len } else { idx.min(at) };
+                (shift(start).min(at), shift(end).max(at))
+            }
+        });
+    }
+    span
+}
+
 #[derive(Clone, Debug)]
 pub struct EditAction {
     pub ops: Vec<EditOp>,
     pub timestamp: std::time::Instant,
+    /// Rope as it was before the first op (snapshot strategy only).
+    pub before: Option<Rope>,
+    /// Rope as it was after the last op, captured when the action is undone.
+    pub after: Option<Rope>,
+}
+
+/// How undo/redo restores the buffer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UndoStrategy {
+    /// Replay the recorded ops backwards (undo) or forwards (redo).
+    #[default]
+    #[serde(rename = "replay")]
+    OpReplay,
+    /// Swap in rope snapshots taken around each action. Ropey clones share
+    /// structure, so this is O(1) per action no matter how many ops it groups.
+    Snapshot,
+}
+
+/// Line break convention of a file, the one most of its lines use. The
+/// rope always holds plain `\n`, and every line gets the file's convention
+/// when it is written, an odd line of the other kind included.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+}
+
+impl LineEnding {
+    /// The convention most lines of `text` use.
+    pub fn detect(text: &str) -> Self {
+        Self::from_counts(text.matches("\r\n").count(), text.matches('\n').count())
+    }
+
+    /// The convention for a text with `crlf` of its `lf` line breaks being `\r\n`.
+    pub fn from_counts(crlf: usize, lf: usize) -> Self {
+        if crlf > 0 && crlf * 2 >= lf {
+            LineEnding::Crlf
+        } else {
+            LineEnding::Lf
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "LF",
+            LineEnding::Crlf => "CRLF",
+        }
+    }
 }
 
+#[derive(Clone)]
 pub struct EditorBuffer {
     pub rope: Rope,
+    /// Edited since it was last loaded or saved.
+    pub dirty: bool,
+    pub line_ending: LineEnding,
+    /// Encoding of the file, and whether it started with a byte order mark.
+    pub encoding: &'static Encoding,
+    pub bom: bool,
+    /// Set when the file is encrypted; saving encrypts it again.
+    pub encryption: Option<Encryption>,
 }
 
 impl EditorBuffer {
     pub fn new() -> Self {
-        EditorBuffer { rope: Rope::new() }
+        EditorBuffer {
+            rope: Rope::new(),
+            dirty: false,
+            line_ending: LineEnding::Lf,
+            encoding: UTF_8,
+            bom: false,
+            encryption: None,
+        }
     }
 
     pub fn insert_char(&mut self, idx: usize, ch: char) {
         self.rope.insert_char(idx, ch);
     }
 
+    pub fn insert(&mut self, idx: usize, text: &str) {
+        self.rope.insert(idx, text);
+    }
+
     pub fn remove(&mut self, start: usize, len: usize) {
         self.rope.remove(start..start + len);
     }
@@ -41,7 +175,18 @@ impl EditorBuffer {
         self.rope.len_lines()
     }
 
-    pub fn line(&self, idx: usize) -> ropey::RopeSlice {
+    /// Lines as the status bar counts them: a final line break does not
+    /// start another line.
+    pub fn line_count(&self) -> usize {
+        let len = self.rope.len_chars();
+        if len > 0 && self.rope.char(len - 1) == '\n' {
+            self.rope.len_lines() - 1
+        } else {
+            self.rope.len_lines()
+        }
+    }
+
+    pub fn line(&self, idx: usize) -> ropey::RopeSlice<'_> {
         self.rope.line(idx)
     }
 
@@ -53,6 +198,81 @@ impl EditorBuffer {
         self.rope.line_to_char(line_idx)
     }
 
+    /// Returns the char range of the identifier touching `idx`, if any.
+    pub fn word_at(&self, idx: usize) -> Option<(usize, usize)> {
+        let len = self.len_chars();
+        let is_word_at = |i: usize| i < len && is_word_char(self.rope.char(i));
+        let anchor = if is_word_at(idx) {
+            idx
+        } else if idx > 0 && is_word_at(idx - 1) {
+            idx - 1
+        } else {
+            return None;
+        };
+        let mut start = anchor;
+        while start > 0 && is_word_at(start - 1) {
+            start -= 1;
+        }
+        let mut end = anchor + 1;
+        while is_word_at(end) {
+            end += 1;
+        }
+        Some((start, end))
+    }
+
+    /// Start of the word before `idx`, for Ctrl+Left. Whitespace and line
+    /// breaks are skipped; a run of punctuation counts as a word.
+    pub fn word_left(&self, idx: usize) -> usize {
+        let mut idx = idx.min(self.len_chars());
+        while idx > 0 && self.rope.char(idx - 1).is_whitespace() {
+            idx -= 1;
+        }
+        if idx > 0 {
+            let class = char_class(self.rope.char(idx - 1));
+            while idx > 0 && char_class(self.rope.char(idx - 1)) == class {
+                idx -= 1;
+            }
+        }
+        idx
+    }
+
+    /// End of the word after `idx`, for Ctrl+Right.
+    pub fn word_right(&self, idx: usize) -> usize {
+        let len = self.len_chars();
+        let mut idx = idx.min(len);
+        while idx < len && self.rope.char(idx).is_whitespace() {
+            idx += 1;
+        }
+        if idx < len {
+            let class = char_class(self.rope.char(idx));
+            while idx < len && char_class(self.rope.char(idx)) == class {
+                idx += 1;
+            }
+        }
+        idx
+    }
+
+    /// Length of a line in chars, not counting its line break.
+    pub fn line_len(&self, line_idx: usize) -> usize {
+        let line = self.rope.line(line_idx);
+        let len = line.len_chars();
+        if len > 0 && line.char(len - 1) == '\n' { len - 1 } else { len }
+    }
+
+    /// Chars of whitespace a line starts with.
+    pub fn indent_len(&self, line_idx: usize) -> usize {
+        self.rope.line(line_idx).chars().take(self.line_len(line_idx)).take_while(|c| c.is_whitespace()).count()
+    }
+
+    /// "CRLF" if most lines end in \r\n, otherwise "LF".
+    pub fn line_ending(&self) -> &'static str {
+        self.line_ending.name()
+    }
+
+    pub fn encoding_name(&self) -> &'static str {
+        self.encoding.name()
+    }
+
     pub fn slice<R>(&self, range: R) -> String 
     where R: std::ops::RangeBounds<usize>
     {
@@ -70,104 +290,745 @@ impl EditorBuffer {
     }
 }
 
-pub struct UndoRedoStacks {
-    undo_stack: Vec<EditAction>,
-    redo_stack: Vec<EditAction>,
+impl Default for EditorBuffer {
+    fn default() -> Self {
+        EditorBuffer::new()
+    }
 }
 
-const GROUP_TIME_THRESHOLD: std::time::Duration = std::time::Duration::from_millis(200);
+pub fn is_word_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
 
-impl UndoRedoStacks {
-    pub fn new() -> Self {
-        UndoRedoStacks {
-            undo_stack: Vec::new(),
-            redo_stack: Vec::new(),
+/// Case conversions for the selection or the word under the cursor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Case {
+    Upper,
+    Lower,
+    /// Each word capitalized, the rest of it lowercase.
+    Title,
+    Toggle,
+}
+
+impl Case {
+    pub fn apply(self, text: &str) -> String {
+        match self {
+            Case::Upper => text.to_uppercase(),
+            Case::Lower => text.to_lowercase(),
+            Case::Title => {
+                let mut result = String::with_capacity(text.len());
+                let mut in_word = false;
+                for ch in text.chars() {
+                    if !in_word && ch.is_alphanumeric() {
+                        result.extend(ch.to_uppercase());
+                    } else {
+                        result.extend(ch.to_lowercase());
+                    }
+                    in_word = is_word_char(ch);
+                }
+                result
+            }
+            Case::Toggle => {
+                let mut result = String::with_capacity(text.len());
+                for ch in text.chars() {
+                    if ch.is_uppercase() {
+                        result.extend(ch.to_lowercase());
+                    } else {
+                        result.extend(ch.to_uppercase());
+                    }
+                }
+                result
+            }
         }
     }
+}
 
-    pub fn add_insert(&mut self, char_idx: usize, content: String) {
-        let now = std::time::Instant::now();
-        if let Some(last) = self.undo_stack.last_mut() {
-            if now.duration_since(last.timestamp) < GROUP_TIME_THRESHOLD {
-                last.ops.push(EditOp::Insert { char_idx, content });
-                last.timestamp = now;
-                return;
-            }
+/// Word chars, whitespace and everything else stop word-wise movement
+/// where they meet.
+fn char_class(ch: char) -> u8 {
+    if is_word_char(ch) {
+        0
+    } else if ch.is_whitespace() {
+        1
+    } else {
+        2
+    }
+}
+
+/// Checks that the `len` bytes at `start` in `text` are not part of a longer word.
+pub fn is_whole_word_at(text: &str, start: usize, len: usize) -> bool {
+    let before = text[..start].chars().next_back();
+    let after = text[start + len..].chars().next();
+    !before.is_some_and(is_word_char) && !after.is_some_and(is_word_char)
+}
+
+/// A state of the text in the undo tree, reached from its parent by an action.
+struct UndoNode {
+    /// The edit leading here from the parent; empty for the root.
+    action: EditAction,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    /// Child redo goes down to: the one made or visited last.
+    redo_child: Option<usize>,
+}
+
+/// Undo history as a tree of text states. Undo goes up to the parent and
+/// redo down the branch visited last; an edit after undoing starts a new
+/// branch instead of throwing away the undone one.
+pub struct UndoTree {
+    /// `nodes[0]` is the text before any recorded edit.
+    nodes: Vec<UndoNode>,
+    /// Node of the text as it is now.
+    current: usize,
+    strategy: UndoStrategy,
+    grouping: Grouping,
+    /// Row picked in the undo tree panel.
+    pub selected: usize,
+}
+
+/// Whether the next op may join the last action.
+enum Grouping {
+    /// When it carries on typing or deleting where the last op left off,
+    /// within the same word or line.
+    Chunk,
+    /// Between `begin_group` and `end_group`, which may nest: the first op
+    /// starts an action and the rest join it.
+    Open { depth: usize, started: bool },
+    /// Never; the last action is finished.
+    Closed,
+}
+
+/// Whether `op` carries on from `last` as a single chunk of typing or
+/// deleting. Typing breaks after a line break and where a word starts after
+/// whitespace; deleting breaks at line breaks.
+fn continues(last: &EditOp, op: &EditOp) -> bool {
+    match (last, op) {
+        (EditOp::Insert { char_idx: last_idx, content: prev }, EditOp::Insert { char_idx, content: next }) => {
+            *char_idx == last_idx + last.char_len()
+                && !prev.ends_with('\n')
+                && !(prev.ends_with(char::is_whitespace) && next.starts_with(|ch: char| !ch.is_whitespace()))
         }
-        self.undo_stack.push(EditAction {
-            ops: vec![EditOp::Insert { char_idx, content }],
-            timestamp: now,
+        // Backspace moves left, Delete stays put
+        (EditOp::Delete { char_idx: last_idx, .. }, EditOp::Delete { char_idx, content }) => {
+            (char_idx + op.char_len() == *last_idx || char_idx == last_idx) && !content.contains('\n')
+        }
+        _ => false,
+    }
+}
+
+impl UndoTree {
+    pub fn new(strategy: UndoStrategy) -> Self {
+        let root = UndoNode {
+            action: EditAction {
+                ops: Vec::new(),
+                timestamp: std::time::Instant::now(),
+                before: None,
+                after: None,
+            },
+            parent: None,
+            children: Vec::new(),
+            redo_child: None,
+        };
+        UndoTree {
+            nodes: vec![root],
+            current: 0,
+            strategy,
+            grouping: Grouping::Chunk,
+            selected: 0,
+        }
+    }
+
+    /// Adds `action` as a new child of the current node and moves there.
+    fn push(&mut self, action: EditAction) {
+        let idx = self.nodes.len();
+        self.nodes.push(UndoNode {
+            action,
+            parent: Some(self.current),
+            children: Vec::new(),
+            redo_child: None,
         });
-        self.redo_stack.clear();
+        let parent = &mut self.nodes[self.current];
+        parent.children.push(idx);
+        parent.redo_child = Some(idx);
+        self.current = idx;
     }
 
-    pub fn add_delete(&mut self, char_idx: usize, content: String) {
+    /// Starts an action that every op recorded until `end_group` joins,
+    /// so a batch of edits is undone in one step. Groups begun inside a
+    /// group are part of it.
+    pub fn begin_group(&mut self) {
+        match &mut self.grouping {
+            Grouping::Open { depth, .. } => *depth += 1,
+            grouping => *grouping = Grouping::Open { depth: 1, started: false },
+        }
+    }
+
+    /// Ends the group begun last. The next op starts a new action once the
+    /// outermost group ends, whether or not it recorded anything.
+    pub fn end_group(&mut self) {
+        match &mut self.grouping {
+            Grouping::Open { depth, .. } if *depth > 1 => *depth -= 1,
+            grouping => *grouping = Grouping::Closed,
+        }
+    }
+
+    /// Records an insert that has already been applied to `buffer`.
+    pub fn add_insert(&mut self, buffer: &EditorBuffer, char_idx: usize, content: String) {
+        self.record(buffer, EditOp::Insert { char_idx, content });
+    }
+
+    /// Records a delete that has already been applied to `buffer`.
+    pub fn add_delete(&mut self, buffer: &EditorBuffer, char_idx: usize, content: String) {
+        self.record(buffer, EditOp::Delete { char_idx, content });
+    }
+
+    fn record(&mut self, buffer: &EditorBuffer, op: EditOp) {
         let now = std::time::Instant::now();
-        if let Some(last) = self.undo_stack.last_mut() {
-            if now.duration_since(last.timestamp) < GROUP_TIME_THRESHOLD {
-                last.ops.push(EditOp::Delete { char_idx, content });
-                last.timestamp = now;
-                return;
-            }
+        // Only the tip of a branch takes more ops; joining an action that has
+        // branches below it would change the text they start from
+        let current = &self.nodes[self.current];
+        let joinable = current.parent.is_some() && current.children.is_empty();
+        let joins = match &mut self.grouping {
+            Grouping::Chunk => joinable && current.action.ops.last().is_some_and(|last| continues(last, &op)),
+            Grouping::Open { started, .. } => std::mem::replace(started, true),
+            Grouping::Closed => false,
+        };
+        if joins {
+            let last = &mut self.nodes[self.current].action;
+            last.ops.push(op);
+            last.timestamp = now;
+            return;
+        }
+        if matches!(self.grouping, Grouping::Closed) {
+            self.grouping = Grouping::Chunk;
         }
-        self.undo_stack.push(EditAction {
-            ops: vec![EditOp::Delete { char_idx, content }],
+        let before = match self.strategy {
+            UndoStrategy::Snapshot => {
+                let mut rope = buffer.rope.clone();
+                op.revert(&mut rope);
+                Some(rope)
+            }
+            UndoStrategy::OpReplay => None,
+        };
+        self.push(EditAction {
+            ops: vec![op],
             timestamp: now,
+            before,
+            after: None,
         });
-        self.redo_stack.clear();
     }
 
-    pub fn undo(&mut self, buffer: &mut EditorBuffer, cursor: &mut usize, dirty_lines: &mut std::collections::HashSet<usize>) {
-        if let Some(action) = self.undo_stack.pop() {
+    /// Undoes the action that led to the current state and returns the ops
+    /// that were effectively applied, in order.
+    pub fn undo(&mut self, buffer: &mut EditorBuffer, cursor: &mut usize, dirty_lines: &mut std::collections::HashSet<usize>) -> Vec<EditOp> {
+        let Some(parent) = self.nodes[self.current].parent else {
+            return Vec::new();
+        };
+        let action = &mut self.nodes[self.current].action;
+        let old_lines = buffer.len_lines();
+        if let Some(before) = action.before.clone() {
+            action.after = Some(std::mem::replace(&mut buffer.rope, before));
+        } else {
             for op in action.ops.iter().rev() {
                 match op {
-                    EditOp::Insert { char_idx, content } => {
-                        buffer.remove(*char_idx, content.len());
-                        *cursor = *char_idx;
-                        dirty_lines.insert(buffer.char_to_line(*char_idx));
-                    }
-                    EditOp::Delete { char_idx, content } => {
-                        buffer.insert_char(*char_idx, content.chars().next().unwrap()); // Slight simplification
-                        *cursor = *char_idx + content.len();
-                        dirty_lines.insert(buffer.char_to_line(*char_idx));
-                    }
+                    EditOp::Insert { char_idx, .. } => buffer.remove(*char_idx, op.char_len()),
+                    EditOp::Delete { char_idx, content } => buffer.insert(*char_idx, content),
                 }
             }
-            self.redo_stack.push(action);
         }
+        if let Some(first) = action.ops.first() {
+            *cursor = match first {
+                EditOp::Insert { char_idx, .. } => *char_idx,
+                EditOp::Delete { char_idx, .. } => *char_idx + first.char_len(),
+            };
+        }
+        mark_action_dirty(action, buffer, old_lines, dirty_lines);
+        let applied = action.ops.iter().rev().map(EditOp::inverted).collect();
+        self.nodes[parent].redo_child = Some(self.current);
+        self.current = parent;
+        applied
     }
 
-    pub fn redo(&mut self, buffer: &mut EditorBuffer, cursor: &mut usize, dirty_lines: &mut std::collections::HashSet<usize>) {
-        if let Some(action) = self.redo_stack.pop() {
+    /// Redoes the action down the branch visited last and returns the ops
+    /// that were applied, in order.
+    pub fn redo(&mut self, buffer: &mut EditorBuffer, cursor: &mut usize, dirty_lines: &mut std::collections::HashSet<usize>) -> Vec<EditOp> {
+        let Some(child) = self.nodes[self.current].redo_child else {
+            return Vec::new();
+        };
+        let action = &self.nodes[child].action;
+        let old_lines = buffer.len_lines();
+        if let Some(after) = action.after.clone() {
+            buffer.rope = after;
+        } else {
             for op in &action.ops {
                 match op {
-                    EditOp::Insert { char_idx, content } => {
-                        for c in content.chars() {
-                            buffer.insert_char(*char_idx, c);
-                        }
-                        *cursor = *char_idx + content.len();
-                        dirty_lines.insert(buffer.char_to_line(*char_idx));
-                    }
-                    EditOp::Delete { char_idx, content } => {
-                        buffer.remove(*char_idx, content.len());
-                        *cursor = *char_idx;
-                        dirty_lines.insert(buffer.char_to_line(*char_idx));
+                    EditOp::Insert { char_idx, content } => buffer.insert(*char_idx, content),
+                    EditOp::Delete { char_idx, .. } => buffer.remove(*char_idx, op.char_len()),
+                }
+            }
+        }
+        if let Some(last) = action.ops.last() {
+            *cursor = match last {
+                EditOp::Insert { char_idx, .. } => *char_idx + last.char_len(),
+                EditOp::Delete { char_idx, .. } => *char_idx,
+            };
+        }
+        mark_action_dirty(action, buffer, old_lines, dirty_lines);
+        let applied = action.ops.clone();
+        self.current = child;
+        applied
+    }
+
+    /// Brings the text to the state of node `target` by undoing up to where
+    /// its branch splits off and redoing down to it. Returns the ops applied.
+    fn jump(&mut self, target: usize, buffer: &mut EditorBuffer, cursor: &mut usize, dirty_lines: &mut std::collections::HashSet<usize>) -> Vec<EditOp> {
+        let mut ancestors = std::collections::HashSet::new();
+        let mut node = Some(self.current);
+        while let Some(idx) = node {
+            ancestors.insert(idx);
+            node = self.nodes[idx].parent;
+        }
+        // Nodes between the split and the target, target first
+        let mut path = Vec::new();
+        let mut split = target;
+        while !ancestors.contains(&split) {
+            path.push(split);
+            split = self.nodes[split].parent.expect("the root is an ancestor of every node");
+        }
+        let mut applied = Vec::new();
+        while self.current != split {
+            applied.extend(self.undo(buffer, cursor, dirty_lines));
+        }
+        for &child in path.iter().rev() {
+            self.nodes[self.current].redo_child = Some(child);
+            applied.extend(self.redo(buffer, cursor, dirty_lines));
+        }
+        applied
+    }
+
+    /// Nodes in the order the panel lists them and how deep each is
+    /// indented: depth first, with a branch indented one step further than
+    /// the line it split off from.
+    fn rows(&self) -> Vec<(usize, usize)> {
+        let mut rows = Vec::new();
+        let mut stack = vec![(0, 0)];
+        while let Some((idx, indent)) = stack.pop() {
+            rows.push((idx, indent));
+            for (i, &child) in self.nodes[idx].children.iter().enumerate().rev() {
+                stack.push((child, if i == 0 { indent } else { indent + 1 }));
+            }
+        }
+        rows
+    }
+
+    /// Points the panel at the current state, for a panel being opened.
+    pub fn select_current(&mut self) {
+        self.selected = self.rows().iter().position(|&(idx, _)| idx == self.current).unwrap_or(0);
+    }
+
+    /// One line per state: "@" marks the current one, and states that do
+    /// not follow the row above say which one they branch from.
+    pub fn labels(&self) -> Vec<String> {
+        let rows = self.rows();
+        let mut labels = Vec::with_capacity(rows.len());
+        let mut prev = None;
+        for &(idx, indent) in &rows {
+            let node = &self.nodes[idx];
+            let marker = if idx == self.current { '@' } else { 'o' };
+            let mut label = format!("{}{} {}", "  ".repeat(indent), marker, idx);
+            match node.action.ops.first() {
+                None => label.push_str("  original text"),
+                Some(op) => {
+                    let (sign, content) = match op {
+                        EditOp::Insert { content, .. } => ('+', content),
+                        EditOp::Delete { content, .. } => ('-', content),
+                    };
+                    let preview: String = content.chars().take(24).collect();
+                    label.push_str(&format!("  {}{:?}", sign, preview));
+                    if node.action.ops.len() > 1 {
+                        label.push_str(&format!(" and {} more", node.action.ops.len() - 1));
                     }
+                    label.push_str(&format!(", {} ago", format_age(node.action.timestamp.elapsed())));
                 }
             }
-            self.undo_stack.push(action);
+            if let Some(parent) = node.parent
+                && Some(parent) != prev
+            {
+                label.push_str(&format!("  (from {})", parent));
+            }
+            labels.push(label);
+            prev = Some(idx);
+        }
+        labels
+    }
+
+    pub fn select_next(&mut self) {
+        if self.selected + 1 < self.nodes.len() {
+            self.selected += 1;
+        }
+    }
+
+    pub fn select_prev(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    /// Brings the text to the state picked in the panel. Returns the ops applied.
+    pub fn jump_to_selected(&mut self, buffer: &mut EditorBuffer, cursor: &mut usize, dirty_lines: &mut std::collections::HashSet<usize>) -> Vec<EditOp> {
+        match self.rows().get(self.selected) {
+            Some(&(target, _)) => self.jump(target, buffer, cursor, dirty_lines),
+            None => Vec::new(),
         }
     }
 }
 
+/// "5s", "3m" or "2h", for how long ago an action was made.
+fn format_age(age: std::time::Duration) -> String {
+    let secs = age.as_secs();
+    if secs < 60 {
+        format!("{}s", secs)
+    } else if secs < 3600 {
+        format!("{}m", secs / 60)
+    } else {
+        format!("{}h", secs / 3600)
+    }
+}
+
+/// Marks everything from the first line an action touched down to the end of
+/// the longer of the old and new buffers, since line counts may have shifted.
+fn mark_action_dirty(action: &EditAction, buffer: &EditorBuffer, old_lines: usize, dirty_lines: &mut std::collections::HashSet<usize>) {
+    let first_char = action.ops.iter().map(EditOp::char_idx).min().unwrap_or(0);
+    let first_line = buffer.char_to_line(first_char.min(buffer.len_chars()));
+    dirty_lines.extend(first_line..old_lines.max(buffer.len_lines()));
+}
+
 // File IO functions
+/// Gives the freshly created `temp` the mode bits and, where allowed, the
+/// owner of the file it is about to replace.
+fn copy_metadata(original: &fs::Metadata, temp: &fs::File) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        // Only root may give a file away; keeping the group usually works
+        if std::os::unix::fs::fchown(temp, Some(original.uid()), Some(original.gid())).is_err() {
+            let _ = std::os::unix::fs::fchown(temp, None, Some(original.gid()));
+        }
+    }
+    // After the chown, which may clear setuid and setgid bits
+    temp.set_permissions(original.permissions())
+}
+
+/// Creates a temporary file in `dir` for writing `name`, under a name no
+/// file or link has yet so nothing planted there is written through. It
+/// starts out no more readable than the original.
+fn create_temp(dir: &Path, name: &str, original: Option<&fs::Metadata>) -> io::Result<(PathBuf, fs::File)> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(original.map_or(0o666, |original| original.permissions().mode() & 0o777));
+    }
+    #[cfg(not(unix))]
+    let _ = original;
+    for attempt in 0..100 {
+        let temp = match attempt {
+            0 => dir.join(format!(".{}.rusty-tmp", name)),
+            _ => dir.join(format!(".{}.rusty-tmp{}", name, attempt)),
+        };
+        match options.open(&temp) {
+            Ok(file) => return Ok((temp, file)),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::AlreadyExists, "no free name for a temporary file"))
+}
+
+/// Writes the buffer to `path` in its file's encoding and line endings.
+/// An `sftp://` path is written locally, then uploaded.
 pub fn save_file(path: &str, buffer: &EditorBuffer) -> io::Result<()> {
-    write(path, buffer.slice(..))
+    if let Some(remote) = RemotePath::parse(path) {
+        return remote.store(|local| save_file(local, buffer));
+    }
+    if remote::is_url(path) {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "web addresses are read-only"));
+    }
+    if let Some(encryption) = &buffer.encryption {
+        let mut plaintext = Vec::new();
+        write_text(&mut plaintext, buffer)?;
+        let ciphertext = encryption.encrypt(&plaintext)?;
+        return write_atomically(path, |writer| writer.write_all(&ciphertext));
+    }
+    write_atomically(path, |writer| write_text(writer, buffer))
+}
+
+/// Writes the buffer's text as its file holds it.
+fn write_text(writer: &mut impl Write, buffer: &EditorBuffer) -> io::Result<()> {
+    if buffer.encoding != UTF_8 {
+        return writer.write_all(&encode(buffer)?);
+    }
+    if buffer.bom {
+        writer.write_all(b"\xEF\xBB\xBF")?;
+    }
+    match buffer.line_ending {
+        LineEnding::Lf => buffer.rope.write_to(writer),
+        LineEnding::Crlf => {
+            for chunk in buffer.rope.chunks() {
+                writer.write_all(chunk.replace('\n', "\r\n").as_bytes())?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Writes a temporary file next to `path` with `write` and renames it over
+/// `path`, so a crash leaves either the old file or the new one, never half
+/// of it. An existing file keeps its permissions and owner.
+pub fn write_atomically(path: &str, write: impl FnOnce(&mut io::BufWriter<fs::File>) -> io::Result<()>) -> io::Result<()> {
+    // Replace the file a symlink points to, not the link
+    let target = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+    let original = fs::metadata(&target).ok();
+    let dir = match target.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let name = target.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file name"))?;
+    let (temp, file) = create_temp(&dir, &name.to_string_lossy(), original.as_ref())?;
+
+    let result = (|| {
+        // Owner and permissions are the original's before any content goes in
+        if let Some(original) = &original {
+            copy_metadata(original, &file)?;
+        }
+        let mut writer = io::BufWriter::new(file);
+        write(&mut writer)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        fs::rename(&temp, &target)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+        return result;
+    }
+    // Make the rename itself durable
+    if let Ok(dir) = fs::File::open(&dir) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+/// Copies the file at `path`, if there is one, to `path~` or into `dir`
+/// before it gets overwritten.
+pub fn backup_file(path: &str, dir: Option<&Path>) -> io::Result<()> {
+    let source = Path::new(path);
+    if !source.is_file() {
+        return Ok(());
+    }
+    let target = match dir {
+        Some(dir) => {
+            // Named after the absolute path so equal names do not collide
+            fs::create_dir_all(dir)?;
+            let name = source.canonicalize()?.to_string_lossy().replace(['/', '\\', ':'], "%");
+            dir.join(format!("{}~", name))
+        }
+        None => PathBuf::from(format!("{}~", path)),
+    };
+    fs::copy(source, target).map(|_| ())
+}
+
+/// The buffer's text in its file's encoding, line endings and BOM, for
+/// anything but plain UTF-8.
+fn encode(buffer: &EditorBuffer) -> io::Result<Vec<u8>> {
+    let mut text = buffer.rope.to_string();
+    if buffer.line_ending == LineEnding::Crlf {
+        text = text.replace('\n', "\r\n");
+    }
+    // encoding_rs only decodes UTF-16, so that is done by hand
+    let utf16 = |bom: [u8; 2], to_bytes: fn(u16) -> [u8; 2]| {
+        let mut bytes = if buffer.bom { bom.to_vec() } else { Vec::new() };
+        bytes.extend(text.encode_utf16().flat_map(to_bytes));
+        bytes
+    };
+    if buffer.encoding == UTF_16LE {
+        return Ok(utf16([0xFF, 0xFE], u16::to_le_bytes));
+    }
+    if buffer.encoding == UTF_16BE {
+        return Ok(utf16([0xFE, 0xFF], u16::to_be_bytes));
+    }
+    let (bytes, _, unmappable) = buffer.encoding.encode(&text);
+    if unmappable {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("the text has characters {} cannot hold", buffer.encoding.name()),
+        ));
+    }
+    Ok(bytes.into_owned())
+}
+
+/// Decodes a file: a byte order mark decides the encoding, then UTF-8 is
+/// tried, and anything else is taken as Windows-1252 (a superset of Latin-1).
+/// Line breaks come back as plain `\n`.
+fn decode(bytes: &[u8]) -> (String, &'static Encoding, bool, LineEnding) {
+    let (encoding, bom_len) = Encoding::for_bom(bytes).unwrap_or_else(|| {
+        if std::str::from_utf8(bytes).is_ok() { (UTF_8, 0) } else { (WINDOWS_1252, 0) }
+    });
+    let content = encoding.decode_without_bom_handling(&bytes[bom_len..]).0.into_owned();
+    let line_ending = LineEnding::detect(&content);
+    let content = if content.contains("\r\n") { content.replace("\r\n", "\n") } else { content };
+    (content, encoding, bom_len > 0, line_ending)
+}
+
+/// The bytes of a local, remote or web file.
+fn read_bytes(path: &str) -> io::Result<Vec<u8>> {
+    if let Some(remote) = RemotePath::parse(path) {
+        return fs::read(remote.fetch()?.path());
+    }
+    if remote::is_url(path) {
+        return fs::read(remote::fetch_url(path)?.path());
+    }
+    fs::read(path)
+}
+
+/// Reads a text file the way `open_file` does, as plain text, decrypting
+/// it with `encryption` when it is encrypted.
+pub fn read_text(path: &str, encryption: Option<&Encryption>) -> io::Result<String> {
+    let bytes = read_bytes(path)?;
+    let bytes = match encryption {
+        Some(encryption) => encryption.decrypt(&bytes)?,
+        None => bytes,
+    };
+    Ok(decode(&bytes).0)
 }
 
 pub fn open_file(path: &str) -> io::Result<EditorBuffer> {
-    let content = read_to_string(path)?;
-    Ok(EditorBuffer {
-        rope: Rope::from_str(&content),
-    })
+    crate::loader::load(path, |_, _| {})
+}
+
+/// Decrypts the file at `path` into a buffer that is encrypted again on
+/// save. A file not there yet comes back empty, to be created on save.
+pub fn open_encrypted(path: &str, encryption: Encryption) -> io::Result<EditorBuffer> {
+    let mut buffer = EditorBuffer::new();
+    match read_bytes(path) {
+        Ok(bytes) => {
+            let (text, encoding, bom, line_ending) = decode(&encryption.decrypt(&bytes)?);
+            buffer.rope = Rope::from_str(&text);
+            buffer.encoding = encoding;
+            buffer.bom = bom;
+            buffer.line_ending = line_ending;
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+    buffer.encryption = Some(encryption);
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn insert(buffer: &mut EditorBuffer, undo_redo: &mut UndoTree, at: usize, text: &str) {
+        buffer.insert(at, text);
+        undo_redo.add_insert(buffer, at, text.to_string());
+    }
+
+    fn delete(buffer: &mut EditorBuffer, undo_redo: &mut UndoTree, start: usize, end: usize) {
+        let content = buffer.slice(start..end);
+        buffer.remove(start, end - start);
+        undo_redo.add_delete(buffer, start, content);
+    }
+
+    /// Undoes a step and returns the text it leaves.
+    fn undo(buffer: &mut EditorBuffer, undo_redo: &mut UndoTree) -> String {
+        undo_redo.undo(buffer, &mut 0, &mut HashSet::new());
+        buffer.rope.to_string()
+    }
+
+    fn redo(buffer: &mut EditorBuffer, undo_redo: &mut UndoTree) -> String {
+        undo_redo.redo(buffer, &mut 0, &mut HashSet::new());
+        buffer.rope.to_string()
+    }
+
+    #[test]
+    fn mixed_line_endings_round_trip() {
+        let dir = std::env::temp_dir().join(format!("rusty-line-endings-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        // Mostly LF with one CRLF line, and mostly CRLF with one LF line:
+        // the odd line takes the convention of the others either way
+        for (name, text, saved) in [
+            ("lf.txt", "one\ntwo\r\nthree\nfour\n", "one\ntwo\nthree\nfour\n"),
+            ("crlf.txt", "one\r\ntwo\r\nthree\nfour\r\n", "one\r\ntwo\r\nthree\r\nfour\r\n"),
+        ] {
+            let path = dir.join(name).to_string_lossy().into_owned();
+            fs::write(&path, text).unwrap();
+            let buffer = open_file(&path).unwrap();
+            assert_eq!(buffer.line_count(), 4);
+            assert_eq!(buffer.line_len(1), "two".len(), "{}", name);
+            save_file(&path, &buffer).unwrap();
+            assert_eq!(fs::read_to_string(&path).unwrap(), saved, "{}", name);
+            assert_eq!(decode(text.as_bytes()).3, buffer.line_ending, "{}", name);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn an_edit_after_undoing_branches_and_the_old_branch_stays_reachable() {
+        let (mut buffer, mut undo_redo) = (EditorBuffer::new(), UndoTree::new(UndoStrategy::OpReplay));
+        insert(&mut buffer, &mut undo_redo, 0, "old\n");
+        assert_eq!(undo(&mut buffer, &mut undo_redo), "");
+        insert(&mut buffer, &mut undo_redo, 0, "new\n");
+        // Redo follows the branch made last, and has nothing to do at its tip
+        assert_eq!(redo(&mut buffer, &mut undo_redo), "new\n");
+        assert_eq!(undo(&mut buffer, &mut undo_redo), "");
+        assert_eq!(redo(&mut buffer, &mut undo_redo), "new\n");
+        // The undone branch is listed first and can be gone back to
+        assert_eq!(undo_redo.labels().len(), 3);
+        undo_redo.selected = 1;
+        undo_redo.jump_to_selected(&mut buffer, &mut 0, &mut HashSet::new());
+        assert_eq!(buffer.rope.to_string(), "old\n");
+        assert_eq!(undo(&mut buffer, &mut undo_redo), "");
+        assert_eq!(redo(&mut buffer, &mut undo_redo), "old\n");
+    }
+
+    #[test]
+    fn snapshots_and_replayed_ops_undo_to_the_same_texts() {
+        let texts: Vec<Vec<String>> = [UndoStrategy::OpReplay, UndoStrategy::Snapshot]
+            .into_iter()
+            .map(|strategy| {
+                let (mut buffer, mut undo_redo) = (EditorBuffer::new(), UndoTree::new(strategy));
+                insert(&mut buffer, &mut undo_redo, 0, "one two\n");
+                undo_redo.begin_group();
+                delete(&mut buffer, &mut undo_redo, 0, 4);
+                insert(&mut buffer, &mut undo_redo, 3, " three");
+                undo_redo.end_group();
+                insert(&mut buffer, &mut undo_redo, 10, "four\n");
+                let mut texts = vec![buffer.rope.to_string()];
+                for _ in 0..3 {
+                    texts.push(undo(&mut buffer, &mut undo_redo));
+                }
+                for _ in 0..3 {
+                    texts.push(redo(&mut buffer, &mut undo_redo));
+                }
+                texts
+            })
+            .collect();
+        assert_eq!(texts[0], ["two three\nfour\n", "two three\n", "one two\n", "", "one two\n", "two three\n", "two three\nfour\n"]);
+        assert_eq!(texts[0], texts[1]);
+    }
+
+    #[test]
+    fn a_group_records_one_node_however_deeply_nested() {
+        let (mut buffer, mut undo_redo) = (EditorBuffer::new(), UndoTree::new(UndoStrategy::OpReplay));
+        undo_redo.begin_group();
+        insert(&mut buffer, &mut undo_redo, 0, "a\n");
+        undo_redo.begin_group();
+        insert(&mut buffer, &mut undo_redo, 2, "b\n");
+        undo_redo.end_group();
+        delete(&mut buffer, &mut undo_redo, 0, 2);
+        undo_redo.end_group();
+        // The root and the group
+        assert_eq!(undo_redo.labels().len(), 2);
+        assert_eq!(undo(&mut buffer, &mut undo_redo), "");
+    }
 }
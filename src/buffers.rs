@@ -0,0 +1,158 @@
+// src/buffers.rs
+
+use crate::buffer::{EditorBuffer, UndoTree, UndoStrategy};
+use crate::fold::FoldSet;
+use crate::highlight::HighlightCache;
+use crate::lsp::LspClient;
+use crate::syntax::SyntaxTree;
+use ropey::Rope;
+use std::time::SystemTime;
+
+/// Everything that belongs to one open file. The active buffer's state
+/// lives in the main loop; the others are parked here.
+pub struct BufferState {
+    pub buffer: EditorBuffer,
+    pub undo_redo: UndoTree,
+    pub path: Option<String>,
+    pub cursor_char_idx: usize,
+    pub viewport_row: usize,
+    pub folds: FoldSet,
+    pub syntax: Option<SyntaxTree>,
+    pub highlights: HighlightCache,
+    pub disk_base: Rope,
+    pub disk_mtime: Option<SystemTime>,
+    /// The file changed on disk while the buffer had unsaved edits.
+    pub disk_changed: bool,
+    pub lsp: Option<LspClient>,
+    pub branch: Option<String>,
+}
+
+impl BufferState {
+    pub fn new(buffer: EditorBuffer, path: Option<String>, undo: UndoStrategy) -> Self {
+        BufferState {
+            disk_base: buffer.rope.clone(),
+            buffer,
+            undo_redo: UndoTree::new(undo),
+            path,
+            cursor_char_idx: 0,
+            viewport_row: 0,
+            folds: FoldSet::new(),
+            syntax: None,
+            highlights: HighlightCache::new(),
+            disk_mtime: None,
+            disk_changed: false,
+            lsp: None,
+            branch: None,
+        }
+    }
+}
+
+/// The open buffers in the order they were opened. The slot of the active
+/// buffer is empty while its state is checked out to the main loop.
+pub struct BufferList {
+    slots: Vec<Option<BufferState>>,
+    pub active: usize,
+    /// Row highlighted in the buffer picker.
+    pub selected: usize,
+}
+
+impl BufferList {
+    pub fn new() -> Self {
+        BufferList {
+            slots: vec![None],
+            active: 0,
+            selected: 0,
+        }
+    }
+
+    /// Buffers open, the active one included, so never none.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Index of the buffer showing `path`, the active one included.
+    pub fn find(&self, path: &str, active_path: Option<&str>) -> Option<usize> {
+        (0..self.slots.len()).find(|&i| match &self.slots[i] {
+            Some(state) => state.path.as_deref() == Some(path),
+            None => active_path == Some(path),
+        })
+    }
+
+    /// State of a parked buffer; `None` for the active one.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut BufferState> {
+        self.slots.get_mut(index)?.as_mut()
+    }
+
+    /// Parks a newly opened buffer at the end and returns its index.
+    pub fn push(&mut self, state: BufferState) -> usize {
+        self.slots.push(Some(state));
+        self.slots.len() - 1
+    }
+
+    /// Drops the buffer at `index`, which must not be the active one.
+    pub fn remove(&mut self, index: usize) {
+        if index != self.active && index < self.slots.len() {
+            self.slots.remove(index);
+            if self.active > index {
+                self.active -= 1;
+            }
+        }
+    }
+
+    /// Checks out the buffer at `index`: `swap` trades its state for that
+    /// of the active one, which is then parked in its place.
+    pub fn switch(&mut self, index: usize, swap: impl FnOnce(&mut BufferState)) {
+        let mut state = self.slots[index].take().expect("active buffer is checked out");
+        swap(&mut state);
+        self.slots[self.active] = Some(state);
+        self.active = index;
+    }
+
+    /// One line per buffer for the picker; the active one is described by
+    /// the arguments since its state is not stored here.
+    pub fn labels(&self, active_path: Option<&str>, active_modified: bool) -> Vec<String> {
+        self.slots
+            .iter()
+            .enumerate()
+            .map(|(i, slot)| {
+                let (path, modified) = match slot {
+                    Some(state) => (state.path.as_deref(), state.buffer.dirty),
+                    None => (active_path, active_modified),
+                };
+                format!(
+                    "{} {}{}",
+                    if i == self.active { '%' } else { ' ' },
+                    path.unwrap_or("[No Name]"),
+                    if modified { " [+]" } else { "" }
+                )
+            })
+            .collect()
+    }
+
+    /// The states of all buffers but the active one.
+    pub fn parked_mut(&mut self) -> impl Iterator<Item = &mut BufferState> {
+        self.slots.iter_mut().flatten()
+    }
+
+    /// Whether a parked buffer has unsaved edits.
+    pub fn any_dirty(&self) -> bool {
+        self.slots.iter().flatten().any(|state| state.buffer.dirty)
+    }
+
+    pub fn select_next(&mut self) {
+        if self.selected + 1 < self.slots.len() {
+            self.selected += 1;
+        }
+    }
+
+    pub fn select_prev(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+}
+
+impl Default for BufferList {
+    fn default() -> Self {
+        BufferList::new()
+    }
+}
@@ -0,0 +1,193 @@
+// src/build.rs
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A compiler message pointing at a file location.
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub path: String,
+    /// Canonical form of `path`, used to match it against the open file.
+    pub canonical_path: Option<PathBuf>,
+    /// Zero-based line.
+    pub line: usize,
+    /// Zero-based column.
+    pub col: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+fn severity_of(text: &str) -> Option<Severity> {
+    if text.starts_with("error") {
+        Some(Severity::Error)
+    } else if text.starts_with("warning") {
+        Some(Severity::Warning)
+    } else {
+        None
+    }
+}
+
+/// Splits `path:line:col` off the front of `text`, returning the remainder.
+fn parse_location(text: &str) -> Option<(&str, usize, usize, &str)> {
+    let mut parts = text.splitn(4, ':');
+    let path = parts.next()?;
+    let line = parts.next()?.trim().parse::<usize>().ok()?;
+    let col = parts.next()?.trim().parse::<usize>().ok()?;
+    if path.is_empty() {
+        return None;
+    }
+    Some((path, line, col, parts.next().unwrap_or("").trim()))
+}
+
+/// Parses rustc-style (`error: msg` then ` --> file:l:c`) and gcc-style
+/// (`file:l:c: error: msg`) compiler output.
+pub fn parse_output(output: &str) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut pending: Option<(Severity, String)> = None;
+    for line in output.lines() {
+        let trimmed = line.trim_start();
+        if let Some(severity) = severity_of(trimmed) {
+            pending = Some((severity, trimmed.to_string()));
+        } else if let Some(location) = trimmed.strip_prefix("--> ") {
+            if let (Some((severity, message)), Some((path, l, c, _))) = (pending.take(), parse_location(location)) {
+                diagnostics.push(Diagnostic::new(path, l, c, severity, message));
+            }
+        } else if let Some((path, l, c, rest)) = parse_location(trimmed)
+            && let Some(severity) = severity_of(rest)
+        {
+            diagnostics.push(Diagnostic::new(path, l, c, severity, rest.to_string()));
+        }
+    }
+    diagnostics
+}
+
+impl Diagnostic {
+    fn new(path: &str, line: usize, col: usize, severity: Severity, message: String) -> Self {
+        Diagnostic {
+            path: path.to_string(),
+            canonical_path: Path::new(path).canonicalize().ok(),
+            line: line.saturating_sub(1),
+            col: col.saturating_sub(1),
+            severity,
+            message,
+        }
+    }
+}
+
+/// Runs the build command on a worker thread and collects its diagnostics
+/// into the quickfix list.
+pub struct BuildRunner {
+    rx: Option<Receiver<Result<Vec<Diagnostic>, String>>>,
+    pub quickfix: Vec<Diagnostic>,
+    /// Entry of the quickfix list jumped to last or highlighted in the
+    /// error list; None until one is visited after a build.
+    pub selected: Option<usize>,
+}
+
+impl BuildRunner {
+    pub fn new() -> Self {
+        BuildRunner { rx: None, quickfix: Vec::new(), selected: None }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.rx.is_some()
+    }
+
+    /// Starts `command` through the shell unless a build is already running.
+    pub fn start(&mut self, command: &str) {
+        if self.is_running() {
+            return;
+        }
+        let (tx, rx) = channel();
+        let command = command.to_string();
+        thread::spawn(move || {
+            let diagnostics = Command::new("sh").arg("-c").arg(&command).output().map_err(|err| err.to_string()).map(|output| {
+                let mut text = String::from_utf8_lossy(&output.stderr).into_owned();
+                text.push_str(&String::from_utf8_lossy(&output.stdout));
+                parse_output(&text)
+            });
+            let _ = tx.send(diagnostics);
+        });
+        self.rx = Some(rx);
+    }
+
+    /// Picks up the result of a finished build: Ok when the quickfix list
+    /// was replaced, the reason when the command could not be run, None
+    /// while it runs or when no build was started.
+    pub fn poll(&mut self) -> Option<Result<(), String>> {
+        let rx = self.rx.as_ref()?;
+        match rx.try_recv() {
+            Ok(result) => {
+                self.rx = None;
+                Some(result.map(|diagnostics| {
+                    self.quickfix = diagnostics;
+                    self.selected = None;
+                }))
+            }
+            Err(std::sync::mpsc::TryRecvError::Empty) => None,
+            Err(std::sync::mpsc::TryRecvError::Disconnected) => {
+                self.rx = None;
+                None
+            }
+        }
+    }
+
+    pub fn count(&self, severity: Severity) -> usize {
+        self.quickfix.iter().filter(|d| d.severity == severity).count()
+    }
+
+    /// One row per entry of the quickfix list: location, then message.
+    pub fn labels(&self) -> Vec<String> {
+        self.quickfix
+            .iter()
+            .map(|d| format!("{}:{}:{}: {}", d.path, d.line + 1, d.col + 1, d.message))
+            .collect()
+    }
+
+    pub fn select_next(&mut self) {
+        let next = self.selected.unwrap_or(0) + 1;
+        if next < self.quickfix.len() {
+            self.selected = Some(next);
+        }
+    }
+
+    pub fn select_prev(&mut self) {
+        self.selected = Some(self.selected.unwrap_or(0).saturating_sub(1));
+    }
+
+    /// The selected entry, the first one if none was visited yet.
+    pub fn current(&self) -> Option<&Diagnostic> {
+        self.quickfix.get(self.selected.unwrap_or(0))
+    }
+
+    /// Moves on to the next entry and returns it; None past the last one.
+    pub fn next_entry(&mut self) -> Option<&Diagnostic> {
+        let next = self.selected.map_or(0, |selected| selected + 1);
+        if next >= self.quickfix.len() {
+            return None;
+        }
+        self.selected = Some(next);
+        self.quickfix.get(next)
+    }
+
+    /// Moves back to the previous entry and returns it; None before the first one.
+    pub fn prev_entry(&mut self) -> Option<&Diagnostic> {
+        let prev = self.selected?.checked_sub(1)?;
+        self.selected = Some(prev);
+        self.quickfix.get(prev)
+    }
+}
+
+impl Default for BuildRunner {
+    fn default() -> Self {
+        BuildRunner::new()
+    }
+}
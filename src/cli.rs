@@ -0,0 +1,70 @@
+// src/cli.rs
+
+pub const USAGE: &str = "\
+Usage: rusty [options] [+LINE] [FILE]
+       rusty --diff LEFT RIGHT
+       rusty --session NAME [options] [FILE]
+       rusty --remote FILE
+
+Options:
+  -R, --readonly      open without allowing edits or saves
+  --trust             let the .rusty.toml here set commands and files to write
+  +LINE               put the cursor on LINE of FILE
+  --diff A B          compare two files side by side
+  -s, --session NAME  attach to session NAME, starting it if needed;
+                      Ctrl+\\ detaches and leaves it running
+  --sessions          list the running sessions
+  --remote FILE       open FILE in the editor started last, if one runs
+  -h, --help          print this help
+  -V, --version       print the version";
+
+/// Command line options.
+#[derive(Debug, Default)]
+pub struct Args {
+    pub file: Option<String>,
+    /// One-based line to start on.
+    pub line: Option<usize>,
+    pub read_only: bool,
+    /// Trust the project config of the working directory from now on.
+    pub trust: bool,
+    pub diff: Option<(String, String)>,
+    pub session: Option<String>,
+    pub sessions: bool,
+    pub remote: bool,
+    /// Set in the background process that runs a session.
+    pub serve: Option<String>,
+    pub help: bool,
+    pub version: bool,
+}
+
+impl Args {
+    /// Parses the arguments after the program name.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Args, String> {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-R" | "--readonly" => parsed.read_only = true,
+                "--trust" => parsed.trust = true,
+                "-h" | "--help" => parsed.help = true,
+                "-V" | "--version" => parsed.version = true,
+                "-s" | "--session" => parsed.session = Some(args.next().ok_or("--session needs a name")?),
+                "--sessions" => parsed.sessions = true,
+                "--remote" => parsed.remote = true,
+                "--serve" => parsed.serve = Some(args.next().ok_or("--serve needs a name")?),
+                "--diff" => match (args.next(), args.next()) {
+                    (Some(left), Some(right)) => parsed.diff = Some((left, right)),
+                    _ => return Err("--diff needs two files".to_string()),
+                },
+                _ if arg.starts_with('+') => {
+                    let line = arg[1..].parse().map_err(|_| format!("invalid line number: {}", arg))?;
+                    parsed.line = Some(line);
+                }
+                _ if arg.starts_with('-') && arg.len() > 1 => return Err(format!("unknown option: {}", arg)),
+                _ if parsed.file.is_none() => parsed.file = Some(arg),
+                _ => return Err(format!("unexpected argument: {}", arg)),
+            }
+        }
+        Ok(parsed)
+    }
+}
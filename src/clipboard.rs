@@ -0,0 +1,141 @@
+// src/clipboard.rs
+
+use serde::Deserialize;
+use std::env;
+use std::io::{stdout, Write};
+
+/// Where copied text goes besides the editor's own copy.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClipboardBackend {
+    /// OSC 52 over SSH, where the OS clipboard at hand is not the user's;
+    /// otherwise the OS clipboard, or OSC 52 when there is none.
+    #[default]
+    Auto,
+    /// The OS clipboard of the machine the editor runs on.
+    System,
+    /// An escape sequence asking the terminal to set its clipboard, which
+    /// reaches the local machine from a remote one.
+    Osc52,
+    /// Only the editor's own copy.
+    Internal,
+}
+
+/// A clipboard text can be put on and, for some, read back from.
+trait Backend {
+    fn set_text(&mut self, text: &str);
+
+    /// None when the backend cannot be read or holds no text.
+    fn get_text(&mut self) -> Option<String>;
+
+    /// Sets rich text where the backend takes it, plain text otherwise.
+    fn set_styled(&mut self, plain: &str, _ansi: &str, _html: &str) {
+        self.set_text(plain);
+    }
+}
+
+impl Backend for arboard::Clipboard {
+    fn set_text(&mut self, text: &str) {
+        let _ = arboard::Clipboard::set_text(self, text);
+    }
+
+    fn get_text(&mut self) -> Option<String> {
+        arboard::Clipboard::get_text(self).ok()
+    }
+
+    fn set_styled(&mut self, _plain: &str, ansi: &str, html: &str) {
+        let _ = self.set_html(html, Some(ansi));
+    }
+}
+
+/// Sets the terminal's clipboard with OSC 52. Terminals rarely allow
+/// reading it back, so pasting uses the editor's own copy.
+struct Osc52 {
+    /// Inside tmux the sequence is wrapped to be passed on to the terminal.
+    tmux: bool,
+}
+
+impl Backend for Osc52 {
+    fn set_text(&mut self, text: &str) {
+        let sequence = format!("\x1b]52;c;{}\x07", base64(text.as_bytes()));
+        let sequence = if self.tmux { format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b")) } else { sequence };
+        let mut out = stdout();
+        let _ = out.write_all(sequence.as_bytes());
+        let _ = out.flush();
+    }
+
+    fn get_text(&mut self) -> Option<String> {
+        None
+    }
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &byte)| n | u32::from(byte) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Whether the editor runs in an SSH session.
+fn over_ssh() -> bool {
+    env::var_os("SSH_TTY").is_some() || env::var_os("SSH_CONNECTION").is_some()
+}
+
+/// Clipboard backed by the OS or the terminal as `backend` says, with an
+/// internal copy kept either way so the editor can always paste its own text.
+pub struct Clipboard {
+    backend: Option<Box<dyn Backend>>,
+    internal: String,
+}
+
+impl Clipboard {
+    pub fn new(backend: ClipboardBackend) -> Self {
+        let osc52 = || Box::new(Osc52 { tmux: env::var_os("TMUX").is_some() }) as Box<dyn Backend>;
+        let system = || arboard::Clipboard::new().ok().map(|system| Box::new(system) as Box<dyn Backend>);
+        let backend = match backend {
+            ClipboardBackend::Auto if over_ssh() => Some(osc52()),
+            ClipboardBackend::Auto => system().or_else(|| Some(osc52())),
+            ClipboardBackend::System => system(),
+            ClipboardBackend::Osc52 => Some(osc52()),
+            ClipboardBackend::Internal => None,
+        };
+        Clipboard {
+            backend,
+            internal: String::new(),
+        }
+    }
+
+    pub fn set_text(&mut self, text: String) {
+        if let Some(backend) = self.backend.as_mut() {
+            backend.set_text(&text);
+        }
+        self.internal = text;
+    }
+
+    /// Text from the backend, or the internal copy when the backend cannot
+    /// be read or holds no text.
+    pub fn get_text(&mut self) -> String {
+        self.backend
+            .as_mut()
+            .and_then(|backend| backend.get_text())
+            .unwrap_or_else(|| self.internal.clone())
+    }
+
+    /// Copies text styled with ANSI escapes, plus an HTML flavor for apps that
+    /// accept rich text. The internal copy keeps the plain text.
+    pub fn set_styled(&mut self, plain: String, ansi: String, html: String) {
+        if let Some(backend) = self.backend.as_mut() {
+            backend.set_styled(&plain, &ansi, &html);
+        }
+        self.internal = plain;
+    }
+}
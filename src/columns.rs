@@ -0,0 +1,62 @@
+// src/columns.rs
+
+use ropey::RopeSlice;
+
+/// Screen columns `ch` covers when it starts at column `col`: a tab reaches
+/// the next tab stop, anything else takes one.
+pub fn cell_width(ch: char, col: usize, tab_width: usize) -> usize {
+    if ch == '\t' { tab_width - col % tab_width } else { 1 }
+}
+
+/// Screen column where the char at `char_col` of `line` starts.
+pub fn display_col(line: RopeSlice, char_col: usize, tab_width: usize) -> usize {
+    line.chars().take(char_col).fold(0, |col, ch| col + cell_width(ch, col, tab_width))
+}
+
+/// The char of `line` that covers screen column `col`, or the end of the
+/// line when `col` lies past it, along with how many columns past it.
+pub fn char_col(line: RopeSlice, col: usize, tab_width: usize) -> (usize, usize) {
+    let mut start = 0;
+    for (char_col, ch) in line.chars().enumerate() {
+        if ch == '\n' {
+            return (char_col, col - start);
+        }
+        let width = cell_width(ch, start, tab_width);
+        if col < start + width {
+            return (char_col, 0);
+        }
+        start += width;
+    }
+    (line.len_chars(), col - start)
+}
+
+/// Char offsets in `line` where each screen row starts when it is wrapped
+/// at `width` columns, the first being 0. Rows break after the last blank
+/// that fits, or mid-word when a word fills the whole row. Tabs are measured
+/// from the start of their row.
+pub fn wrap_starts(line: RopeSlice, width: usize, tab_width: usize) -> Vec<usize> {
+    let chars: Vec<char> = line.chars().take_while(|&ch| ch != '\n').collect();
+    let mut starts = vec![0];
+    let mut start = 0;
+    loop {
+        let mut col = 0;
+        let mut end = start;
+        let mut after_blank = None;
+        while end < chars.len() {
+            let cells = cell_width(chars[end], col, tab_width);
+            if col + cells > width && end > start {
+                break;
+            }
+            col += cells;
+            end += 1;
+            if chars[end - 1].is_whitespace() {
+                after_blank = Some(end);
+            }
+        }
+        if end >= chars.len() {
+            return starts;
+        }
+        start = after_blank.unwrap_or(end);
+        starts.push(start);
+    }
+}
@@ -0,0 +1,437 @@
+// src/config.rs
+
+use crate::buffer::UndoStrategy;
+use crate::clipboard::ClipboardBackend;
+use crate::render::LineNumbers;
+use crate::status::{default_segments, Segment};
+use crate::theme::ColorSupport;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::{self, read_to_string};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Name of the per-project config file, looked up in the working directory.
+pub const PROJECT_CONFIG_FILE: &str = ".rusty.toml";
+
+/// Settings that run commands or name files to write, which a project
+/// config only sets in a directory the user trusted: a cloned repository
+/// could run anything or append to any file of the user's.
+const TRUSTED_SETTINGS: &[(&str, &str)] = &[
+    ("build", "command"),
+    ("lsp", "servers"),
+    ("accessibility", "announce_file"),
+    ("backup", "directory"),
+];
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub build: BuildConfig,
+    pub status_line: StatusLineConfig,
+    pub editor: EditorConfig,
+    pub history: HistoryConfig,
+    pub autosave: AutosaveConfig,
+    pub backup: BackupConfig,
+    pub recovery: RecoveryConfig,
+    pub accessibility: AccessibilityConfig,
+    pub lsp: LspConfig,
+    pub auto_pairs: AutoPairsConfig,
+    pub snippets: SnippetsConfig,
+    pub clipboard: ClipboardConfig,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct BuildConfig {
+    /// Shell command run by the build key.
+    pub command: String,
+}
+
+impl Default for BuildConfig {
+    fn default() -> Self {
+        BuildConfig {
+            command: "cargo check".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct EditorConfig {
+    /// Let the cursor move past the end of a line; typing there pads with spaces.
+    pub virtual_space: bool,
+    /// Let the view scroll until the last line is at the top of the screen.
+    pub scroll_past_end: bool,
+    /// Show the sign column (diagnostics, folds, ...) left of the line numbers.
+    pub sign_column: bool,
+    /// Lines kept in view above and below the cursor when it nears the
+    /// edge of the screen, so the view scrolls before the cursor reaches it.
+    pub scroll_off: usize,
+    /// Lines scrolled per step of the mouse wheel.
+    pub scroll_lines: usize,
+    /// Spaces Tab indents by and Shift+Tab takes off.
+    pub indent_width: usize,
+    /// Tab inserts a tab character instead of spaces.
+    pub use_tabs: bool,
+    /// Columns between tab stops when tab characters are shown.
+    pub tab_width: usize,
+    /// Start with spaces, tabs and line breaks drawn as glyphs (Ctrl+A w toggles).
+    pub show_invisibles: bool,
+    /// Saving strips trailing whitespace from the lines changed since the last save.
+    pub trim_trailing_whitespace: bool,
+    /// Start with long lines wrapped at the window edge (Alt+z toggles).
+    pub soft_wrap: bool,
+    /// "absolute", "relative", "hybrid" or "off" (Ctrl+A n cycles).
+    pub line_numbers: LineNumbers,
+    /// Columns marked with a vertical guide, e.g. `[80, 120]`.
+    pub rulers: Vec<usize>,
+    /// Built-in "dark" or "light", or the name of a file in the themes
+    /// directory of the config directory, without its .toml.
+    pub theme: String,
+    /// "auto" to detect from COLORTERM and TERM, or "truecolor", "256" or
+    /// "16" when detection gets it wrong; theme colors are brought down to fit.
+    pub colors: ColorSupport,
+    /// Offer words from the open buffers while typing, not only on Ctrl+Space.
+    pub auto_complete: bool,
+    /// "replay" undoes by running the recorded edits backwards; "snapshot"
+    /// swaps in copies of the text taken around each change instead.
+    pub undo: UndoStrategy,
+}
+
+impl Default for EditorConfig {
+    fn default() -> Self {
+        EditorConfig {
+            virtual_space: false,
+            scroll_past_end: false,
+            sign_column: true,
+            scroll_off: 0,
+            scroll_lines: 3,
+            indent_width: 4,
+            use_tabs: false,
+            tab_width: 4,
+            show_invisibles: false,
+            trim_trailing_whitespace: false,
+            soft_wrap: false,
+            line_numbers: LineNumbers::Absolute,
+            rulers: Vec::new(),
+            theme: "dark".to_string(),
+            colors: ColorSupport::Auto,
+            auto_complete: false,
+            undo: UndoStrategy::OpReplay,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct AccessibilityConfig {
+    /// Screen-reader friendly mode: no blinking, no color-only cues, fewer
+    /// decorative redraws, and announcements of what changed.
+    pub screen_reader: bool,
+    /// File (or FIFO) that announcements are appended to, one per line.
+    /// A project config sets it only in a trusted directory.
+    pub announce_file: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ClipboardConfig {
+    /// "auto", "system", "osc52" (through the terminal, e.g. over SSH) or
+    /// "internal" to keep copies inside the editor.
+    pub backend: ClipboardBackend,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct LspConfig {
+    /// Language server command per file extension, run through `sh -c`.
+    /// A project config sets these only in a trusted directory.
+    pub servers: HashMap<String, String>,
+}
+
+impl Default for LspConfig {
+    fn default() -> Self {
+        LspConfig {
+            servers: HashMap::from([("rs".to_string(), "rust-analyzer".to_string())]),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct AutoPairsConfig {
+    /// Typing an opening bracket or quote also types its closer.
+    pub enabled: bool,
+    /// Openers each followed by their closer, e.g. "()[]".
+    pub pairs: String,
+    /// Pairs per file extension in place of `pairs`; "" turns pairing off.
+    pub languages: HashMap<String, String>,
+}
+
+impl Default for AutoPairsConfig {
+    fn default() -> Self {
+        AutoPairsConfig {
+            enabled: true,
+            pairs: "()[]{}\"\"''".to_string(),
+            // Lifetimes would get a stray quote
+            languages: HashMap::from([("rs".to_string(), "()[]{}\"\"".to_string())]),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct SnippetsConfig {
+    /// Snippet bodies by the word that Tab expands, for every file, e.g.
+    /// `todo = "// TODO(${1:who}): $0"`.
+    pub global: HashMap<String, String>,
+    /// Snippets per file extension, taking precedence over `global`.
+    pub languages: HashMap<String, HashMap<String, String>>,
+}
+
+impl Default for SnippetsConfig {
+    fn default() -> Self {
+        SnippetsConfig {
+            global: HashMap::new(),
+            languages: HashMap::from([(
+                "rs".to_string(),
+                HashMap::from([("fn".to_string(), "fn ${1:name}(${2}) {\n    $0\n}".to_string())]),
+            )]),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct HistoryConfig {
+    /// Keep local history snapshots of edited files.
+    pub enabled: bool,
+    /// Minimum time between two periodic snapshots of the same file.
+    pub interval_secs: u64,
+    /// Snapshots kept per file; older ones are deleted.
+    pub max_snapshots: usize,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        HistoryConfig {
+            enabled: true,
+            interval_secs: 120,
+            max_snapshots: 50,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct AutosaveConfig {
+    /// Write modified buffers that have a file back to it periodically.
+    pub enabled: bool,
+    /// Seconds between two autosaves.
+    pub interval_secs: u64,
+}
+
+impl Default for AutosaveConfig {
+    fn default() -> Self {
+        AutosaveConfig {
+            enabled: false,
+            interval_secs: 30,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct BackupConfig {
+    /// Copy a file aside before saving over it.
+    pub enabled: bool,
+    /// Directory for the copies; next to the file (as `name~`) when unset.
+    /// A project config sets it only in a trusted directory.
+    pub directory: Option<PathBuf>,
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        BackupConfig {
+            enabled: true,
+            directory: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct RecoveryConfig {
+    /// Keep swap files of unsaved changes to recover after a crash.
+    pub enabled: bool,
+    /// Minimum time between two writes of the swap file.
+    pub interval_secs: u64,
+}
+
+impl Default for RecoveryConfig {
+    fn default() -> Self {
+        RecoveryConfig {
+            enabled: true,
+            interval_secs: 4,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct StatusLineConfig {
+    /// Segments shown right-aligned in the status line, in order.
+    pub segments: Vec<Segment>,
+    /// Template shown instead of the segments, e.g.
+    /// "{file} {modified} | {line}:{col} | {percent}% | {encoding}".
+    pub format: Option<String>,
+}
+
+impl Default for StatusLineConfig {
+    fn default() -> Self {
+        StatusLineConfig {
+            segments: default_segments(),
+            format: None,
+        }
+    }
+}
+
+/// `$XDG_CONFIG_HOME/rusty`, falling back to `~/.config/rusty`.
+pub fn config_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("rusty"))
+}
+
+/// `$XDG_DATA_HOME/rusty`, falling back to `~/.local/share/rusty`.
+pub fn data_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("share")))?;
+    Some(base.join("rusty"))
+}
+
+/// A directory only this user can get into, for sockets and temporary
+/// copies of files: `$XDG_RUNTIME_DIR/rusty-UID`, falling back to the
+/// system's temp directory. One someone else made there first is refused.
+pub fn runtime_dir() -> io::Result<PathBuf> {
+    let base = std::env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from).unwrap_or_else(std::env::temp_dir);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+        // SAFETY: getuid cannot fail
+        let uid = unsafe { libc::getuid() };
+        let dir = base.join(format!("rusty-{}", uid));
+        fs::DirBuilder::new().recursive(true).mode(0o700).create(&dir)?;
+        let metadata = fs::symlink_metadata(&dir)?;
+        if !metadata.is_dir() || metadata.uid() != uid {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("{} belongs to someone else", dir.display())));
+        }
+        if metadata.permissions().mode() & 0o077 != 0 {
+            fs::set_permissions(&dir, fs::Permissions::from_mode(0o700))?;
+        }
+        Ok(dir)
+    }
+    #[cfg(not(unix))]
+    {
+        let dir = base.join("rusty");
+        fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+}
+
+/// File listing the directories trusted with `rusty --trust`, one per line.
+fn trusted_dirs_file() -> Option<PathBuf> {
+    Some(data_dir()?.join("trusted"))
+}
+
+/// Whether the user trusted `dir` to run the commands its project config sets.
+pub fn is_trusted(dir: &Path) -> bool {
+    let (Some(file), Ok(dir)) = (trusted_dirs_file(), dir.canonicalize()) else {
+        return false;
+    };
+    read_to_string(file).is_ok_and(|text| text.lines().any(|line| Path::new(line) == dir))
+}
+
+/// Adds `dir` to the trusted directories.
+pub fn trust(dir: &Path) -> io::Result<()> {
+    let file = trusted_dirs_file().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no data directory"))?;
+    if is_trusted(dir) {
+        return Ok(());
+    }
+    let dir = dir.canonicalize()?;
+    if let Some(parent) = file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut text = read_to_string(&file).unwrap_or_default();
+    text.push_str(&format!("{}\n", dir.display()));
+    fs::write(file, text)
+}
+
+/// Parses the TOML table in `path`; None when there is no such file.
+fn read_table(path: &Path) -> Option<Result<toml::Table, String>> {
+    let text = read_to_string(path).ok()?;
+    Some(toml::from_str(&text).map_err(|err| format!("{}: {}", path.display(), err.message())))
+}
+
+/// Lays `over` onto `base`, merging tables key by key.
+fn merge_tables(base: &mut toml::Table, over: toml::Table) {
+    for (key, value) in over {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(over)) => merge_tables(base, over),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+impl Config {
+    /// Loads the user config with the project config laid over it, and
+    /// says what went wrong with them. A malformed file is left out; an
+    /// untrusted project config cannot set commands or files to write.
+    pub fn load() -> (Self, Option<String>) {
+        let mut errors = Vec::new();
+        let mut table = toml::Table::new();
+        if let Some(result) = config_dir().and_then(|dir| read_table(&dir.join("config.toml"))) {
+            match result {
+                Ok(user) => table = user,
+                Err(err) => errors.push(err),
+            }
+        }
+        match read_table(Path::new(PROJECT_CONFIG_FILE)) {
+            Some(Ok(mut project)) => {
+                if !is_trusted(Path::new(".")) {
+                    let mut ignored = Vec::new();
+                    for (section, key) in TRUSTED_SETTINGS {
+                        if let Some(toml::Value::Table(section_table)) = project.get_mut(*section)
+                            && section_table.remove(*key).is_some()
+                        {
+                            ignored.push(format!("{}.{}", section, key));
+                        }
+                    }
+                    if !ignored.is_empty() {
+                        errors.push(format!(
+                            "Ignored {} in the untrusted {}; start with --trust to allow it",
+                            ignored.join(", "),
+                            PROJECT_CONFIG_FILE
+                        ));
+                    }
+                }
+                merge_tables(&mut table, project);
+            }
+            Some(Err(err)) => errors.push(err),
+            None => {}
+        }
+        let config = match toml::Value::Table(table).try_into() {
+            Ok(config) => config,
+            Err(err) => {
+                errors.push(format!("Invalid config: {}", err.message()));
+                Config::default()
+            }
+        };
+        (config, (!errors.is_empty()).then(|| errors.join("; ")))
+    }
+}
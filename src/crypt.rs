@@ -0,0 +1,109 @@
+// src/crypt.rs
+
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// A tool files are encrypted with, told apart by their extension.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cipher {
+    /// age with an identity file, whose own recipient the file is
+    /// encrypted to again on save.
+    Age,
+    /// GnuPG, symmetric with a passphrase.
+    Gpg,
+}
+
+impl Cipher {
+    pub fn for_path(path: &str) -> Option<Self> {
+        match Path::new(path).extension()?.to_str()? {
+            "age" => Some(Cipher::Age),
+            "gpg" | "pgp" => Some(Cipher::Gpg),
+            _ => None,
+        }
+    }
+
+    /// What is asked for before opening a file.
+    pub fn secret_name(self) -> &'static str {
+        match self {
+            Cipher::Age => "Identity file",
+            Cipher::Gpg => "Passphrase",
+        }
+    }
+
+    /// Whether the secret is hidden while typed; a path to a key is not.
+    pub fn masks_secret(self) -> bool {
+        self == Cipher::Gpg
+    }
+}
+
+/// How a buffer's file is encrypted, and the secret that opens it. The
+/// plain text only ever goes through pipes to the tool, never to disk.
+#[derive(Clone)]
+pub struct Encryption {
+    pub cipher: Cipher,
+    secret: String,
+}
+
+impl Encryption {
+    pub fn new(cipher: Cipher, secret: String) -> Self {
+        Encryption { cipher, secret }
+    }
+
+    pub fn decrypt(&self, ciphertext: &[u8]) -> io::Result<Vec<u8>> {
+        match self.cipher {
+            Cipher::Age => run(Command::new("age").arg("--decrypt").arg("--identity").arg(&self.secret), ciphertext.to_vec()),
+            Cipher::Gpg => run(gpg().arg("--decrypt"), self.with_passphrase(ciphertext)),
+        }
+    }
+
+    pub fn encrypt(&self, plaintext: &[u8]) -> io::Result<Vec<u8>> {
+        match self.cipher {
+            Cipher::Age => run(Command::new("age").arg("--encrypt").arg("--identity").arg(&self.secret), plaintext.to_vec()),
+            Cipher::Gpg => run(gpg().args(["--symmetric", "--output", "-"]), self.with_passphrase(plaintext)),
+        }
+    }
+
+    /// gpg reads the passphrase as the first line of its input.
+    fn with_passphrase(&self, data: &[u8]) -> Vec<u8> {
+        let mut input = format!("{}\n", self.secret).into_bytes();
+        input.extend_from_slice(data);
+        input
+    }
+}
+
+/// gpg set up to take the passphrase from its input rather than a prompt
+/// of its own, and to leave it out of the agent's cache.
+fn gpg() -> Command {
+    let mut gpg = Command::new("gpg");
+    gpg.args(["--batch", "--quiet", "--no-symkey-cache", "--pinentry-mode", "loopback", "--passphrase-fd", "0"]);
+    gpg
+}
+
+/// Runs `command` with `input` on its stdin, fed from a thread so a tool
+/// writing before it has read everything cannot deadlock. Returns its
+/// output, or its complaint as the error.
+fn run(command: &mut Command, input: Vec<u8>) -> io::Result<Vec<u8>> {
+    let name = command.get_program().to_string_lossy().into_owned();
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| io::Error::new(err.kind(), format!("could not run {}: {}", name, err)))?;
+    let mut stdin = child.stdin.take();
+    let writer = thread::spawn(move || {
+        if let Some(stdin) = stdin.as_mut() {
+            let _ = stdin.write_all(&input);
+        }
+    });
+    let output = child.wait_with_output()?;
+    let _ = writer.join();
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let message = stderr.lines().next().map_or_else(|| format!("{} failed", name), str::to_string);
+        return Err(io::Error::other(message));
+    }
+    Ok(output.stdout)
+}
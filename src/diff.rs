@@ -0,0 +1,126 @@
+// src/diff.rs
+
+use similar::{DiffOp, TextDiff};
+use std::fs::read_to_string;
+use std::io;
+
+/// Lines of context kept above a hunk when jumping to it.
+const HUNK_CONTEXT: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RowKind {
+    Same,
+    Added,
+    Removed,
+    Changed,
+}
+
+/// One aligned row of a side-by-side diff. A missing side is drawn blank.
+#[derive(Clone, Debug)]
+pub struct DiffRow {
+    pub left: Option<usize>,
+    pub right: Option<usize>,
+    pub kind: RowKind,
+}
+
+/// Two texts aligned row by row, with a shared scroll position so both
+/// panes stay in sync.
+pub struct DiffView {
+    pub left_name: String,
+    pub right_name: String,
+    pub left: Vec<String>,
+    pub right: Vec<String>,
+    pub rows: Vec<DiffRow>,
+    /// First row shown on screen.
+    pub top: usize,
+    /// Row index where each run of changes starts.
+    hunks: Vec<usize>,
+    pub current_hunk: Option<usize>,
+}
+
+fn split_lines(text: &str) -> Vec<String> {
+    text.lines().map(str::to_string).collect()
+}
+
+impl DiffView {
+    pub fn from_texts(left_name: &str, left_text: &str, right_name: &str, right_text: &str) -> Self {
+        let diff = TextDiff::from_lines(left_text, right_text);
+        let mut rows = Vec::new();
+        for op in diff.ops() {
+            match *op {
+                DiffOp::Equal { old_index, new_index, len } => {
+                    rows.extend((0..len).map(|i| DiffRow { left: Some(old_index + i), right: Some(new_index + i), kind: RowKind::Same }));
+                }
+                DiffOp::Delete { old_index, old_len, .. } => {
+                    rows.extend((0..old_len).map(|i| DiffRow { left: Some(old_index + i), right: None, kind: RowKind::Removed }));
+                }
+                DiffOp::Insert { new_index, new_len, .. } => {
+                    rows.extend((0..new_len).map(|i| DiffRow { left: None, right: Some(new_index + i), kind: RowKind::Added }));
+                }
+                DiffOp::Replace { old_index, old_len, new_index, new_len } => {
+                    // Pair lines up as changed, then spill the longer side
+                    for i in 0..old_len.max(new_len) {
+                        let left = (i < old_len).then_some(old_index + i);
+                        let right = (i < new_len).then_some(new_index + i);
+                        let kind = match (left, right) {
+                            (Some(_), Some(_)) => RowKind::Changed,
+                            (Some(_), None) => RowKind::Removed,
+                            _ => RowKind::Added,
+                        };
+                        rows.push(DiffRow { left, right, kind });
+                    }
+                }
+            }
+        }
+
+        let hunks = rows
+            .iter()
+            .enumerate()
+            .filter(|(i, row)| row.kind != RowKind::Same && (*i == 0 || rows[i - 1].kind == RowKind::Same))
+            .map(|(i, _)| i)
+            .collect();
+
+        DiffView {
+            left_name: left_name.to_string(),
+            right_name: right_name.to_string(),
+            left: split_lines(left_text),
+            right: split_lines(right_text),
+            rows,
+            top: 0,
+            hunks,
+            current_hunk: None,
+        }
+    }
+
+    /// Errors name the file that could not be read.
+    pub fn from_files(left_path: &str, right_path: &str) -> io::Result<Self> {
+        let read = |path: &str| read_to_string(path).map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path, err)));
+        let left = read(left_path)?;
+        let right = read(right_path)?;
+        Ok(Self::from_texts(left_path, &left, right_path, &right))
+    }
+
+    pub fn hunk_count(&self) -> usize {
+        self.hunks.len()
+    }
+
+    pub fn scroll(&mut self, delta: isize, visible_rows: usize) {
+        let max_top = self.rows.len().saturating_sub(visible_rows);
+        self.top = self.top.saturating_add_signed(delta).min(max_top);
+    }
+
+    pub fn next_hunk(&mut self) {
+        let next = self.current_hunk.map_or(0, |h| h + 1);
+        if next < self.hunks.len() {
+            self.current_hunk = Some(next);
+            self.top = self.hunks[next].saturating_sub(HUNK_CONTEXT);
+        }
+    }
+
+    pub fn prev_hunk(&mut self) {
+        if let Some(prev) = self.current_hunk.and_then(|h| h.checked_sub(1)) {
+            self.current_hunk = Some(prev);
+            self.top = self.hunks[prev].saturating_sub(HUNK_CONTEXT);
+        }
+    }
+}
@@ -0,0 +1,858 @@
+// src/editor.rs
+
+use crate::buffer::{EditOp, EditorBuffer, UndoStrategy, UndoTree};
+use crate::buffers::BufferState;
+use crate::columns;
+use crate::config::EditorConfig;
+use crate::ex;
+use crate::fold::FoldSet;
+use crate::input::Command;
+use crate::pairs::Typed;
+use crate::plugins::HookOutput;
+use crate::search::{ReplaceWalk, Search};
+use ropey::Rope;
+use std::collections::HashSet;
+
+/// The editing engine on its own: a buffer, its undo history, folds and a
+/// cursor, driven by the same commands as the terminal editor but needing
+/// no terminal, so it can be embedded, fuzzed and tested.
+pub struct Editor {
+    pub buffer: EditorBuffer,
+    pub undo_redo: UndoTree,
+    /// Char index of the cursor.
+    pub cursor: usize,
+    /// Where the selection started; the cursor is its other end.
+    pub anchor: Option<usize>,
+    /// Columns the cursor is past the end of its line, in virtual space.
+    pub virtual_col: usize,
+    pub folds: FoldSet,
+    /// Lines changed since a frontend last drew them, which it clears.
+    pub dirty_lines: HashSet<usize>,
+    pub tab_width: usize,
+    /// Columns Tab indents by, and whether it puts in a tab instead.
+    pub indent_width: usize,
+    pub use_tabs: bool,
+    /// Whether the cursor may go past the end of a line.
+    pub virtual_space: bool,
+    /// Columns of text a row holds when long lines wrap, None when they
+    /// do not. A frontend keeps it in step with its text area.
+    pub wrap_width: Option<usize>,
+    /// Whether `begin_command` opened an undo group for `end_command` to close.
+    group_open: bool,
+}
+
+/// Typing and deleting group into words and lines by themselves; any
+/// other command is one undo step and ends the chunk being typed.
+fn is_typing(command: &Command) -> bool {
+    matches!(command, Command::InsertChar(_) | Command::InsertNewline | Command::Backspace | Command::DeleteForward)
+}
+
+/// The movement a Shift+movement command extends the selection by.
+fn selection_move(command: &Command) -> Option<Command> {
+    Some(match command {
+        Command::SelectLeft => Command::MoveLeft,
+        Command::SelectRight => Command::MoveRight,
+        Command::SelectUp => Command::MoveUp,
+        Command::SelectDown => Command::MoveDown,
+        Command::SelectWordLeft => Command::MoveWordLeft,
+        Command::SelectWordRight => Command::MoveWordRight,
+        _ => return None,
+    })
+}
+
+impl Editor {
+    pub fn new() -> Self {
+        Editor::from_text("")
+    }
+
+    pub fn from_text(text: &str) -> Self {
+        let mut buffer = EditorBuffer::new();
+        buffer.rope = Rope::from_str(text);
+        Editor {
+            buffer,
+            undo_redo: UndoTree::new(UndoStrategy::default()),
+            cursor: 0,
+            anchor: None,
+            virtual_col: 0,
+            folds: FoldSet::new(),
+            dirty_lines: HashSet::new(),
+            tab_width: 4,
+            indent_width: 4,
+            use_tabs: false,
+            virtual_space: false,
+            wrap_width: None,
+            group_open: false,
+        }
+    }
+
+    /// An empty buffer with the undo strategy, tab and indent widths and
+    /// virtual space of `config`.
+    pub fn with_config(config: &EditorConfig) -> Self {
+        Editor {
+            undo_redo: UndoTree::new(config.undo),
+            tab_width: config.tab_width.max(1),
+            indent_width: config.indent_width.max(1),
+            use_tabs: config.use_tabs,
+            virtual_space: config.virtual_space,
+            ..Editor::new()
+        }
+    }
+
+    pub fn text(&self) -> String {
+        self.buffer.rope.to_string()
+    }
+
+    /// Line and column of the cursor, both from 0.
+    pub fn cursor_position(&self) -> (usize, usize) {
+        let line = self.buffer.char_to_line(self.cursor);
+        (line, self.cursor - self.buffer.line_to_char(line))
+    }
+
+    /// Screen column of the cursor in its line, tabs taken into account.
+    pub fn cursor_display_col(&self) -> usize {
+        let (line, col) = self.cursor_position();
+        columns::display_col(self.buffer.line(line), col, self.tab_width)
+    }
+
+    /// Start and end of the selected chars, in order.
+    pub fn selection(&self) -> Option<(usize, usize)> {
+        self.anchor.map(|anchor| (anchor.min(self.cursor), anchor.max(self.cursor)))
+    }
+
+    /// Chars of `line` including its line break, used when nothing is selected.
+    pub fn line_range(&self, line: usize) -> (usize, usize) {
+        let end = if line + 1 < self.buffer.len_lines() { self.buffer.line_to_char(line + 1) } else { self.buffer.len_chars() };
+        (self.buffer.line_to_char(line), end)
+    }
+
+    /// First and last line touched by a selection. One that ends at the
+    /// start of a line leaves that line out.
+    pub fn selected_lines(&self, (start, end): (usize, usize)) -> (usize, usize) {
+        let (first, last) = (self.buffer.char_to_line(start), self.buffer.char_to_line(end));
+        (first, if last > first && self.buffer.line_to_char(last) == end { last - 1 } else { last })
+    }
+
+    /// Char index on `line` at screen column `col`, clamped to the line's
+    /// end, and the columns past the end as the virtual offset when the
+    /// cursor may go there.
+    pub fn column_on_line(&self, line: usize, col: usize) -> (usize, usize) {
+        let (char_col, past_end) = columns::char_col(self.buffer.line(line), col, self.tab_width);
+        let virtual_col = if self.virtual_space { past_end } else { 0 };
+        (self.buffer.line_to_char(line) + char_col, virtual_col)
+    }
+
+    /// Char offsets where the screen rows of `line` start; a closed fold
+    /// and a line that does not wrap are a single row.
+    pub fn row_starts(&self, line: usize) -> Vec<usize> {
+        match self.wrap_width {
+            Some(width) if line < self.buffer.len_lines() && self.folds.closed_fold_end(&self.buffer, line).is_none() => {
+                columns::wrap_starts(self.buffer.line(line), width, self.tab_width)
+            }
+            _ => vec![0],
+        }
+    }
+
+    /// Runs `command` as one undo step and returns the edits it made, in
+    /// order. None for commands that need more of the editor than the
+    /// engine has, like prompts, panes or files; those still drop the
+    /// selection as any command does.
+    pub fn apply_command(&mut self, command: &Command) -> Option<Vec<EditOp>> {
+        let selection = self.begin_command(command);
+        let edits = self.execute(command, selection);
+        self.end_command();
+        edits
+    }
+
+    /// Gets ready for `command`: Shift+movement and dragging extend the
+    /// selection and most other commands drop it, only moving along a line
+    /// or across lines keeps the cursor in virtual space, and anything but
+    /// typing opens an undo group. Returns the selection from before, for
+    /// the command to act on. A frontend running commands of its own calls
+    /// this and `end_command` around them.
+    pub fn begin_command(&mut self, command: &Command) -> Option<(usize, usize)> {
+        let selection = self.selection();
+        if selection_move(command).is_some() || matches!(command, Command::MouseDrag { .. }) {
+            self.anchor.get_or_insert(self.cursor);
+        } else if !command.keeps_selection() {
+            self.anchor = None;
+        }
+        if !matches!(
+            command,
+            Command::MoveLeft
+                | Command::MoveRight
+                | Command::MoveUp
+                | Command::MoveDown
+                | Command::SelectLeft
+                | Command::SelectRight
+                | Command::SelectUp
+                | Command::SelectDown
+                | Command::PageUp
+                | Command::PageDown
+                | Command::InsertChar(_)
+                | Command::ScrollUp
+                | Command::ScrollDown
+        ) {
+            self.virtual_col = 0;
+        }
+        self.group_open = !is_typing(command);
+        if self.group_open {
+            self.undo_redo.begin_group();
+        }
+        selection
+    }
+
+    /// Closes the undo group `begin_command` opened, if it did.
+    pub fn end_command(&mut self) {
+        if std::mem::take(&mut self.group_open) {
+            self.undo_redo.end_group();
+        }
+    }
+
+    /// Runs `command` on `selection`, the one `begin_command` returned,
+    /// and returns the edits it made. None, having done nothing, for
+    /// commands the engine does not know.
+    pub fn execute(&mut self, command: &Command, selection: Option<(usize, usize)>) -> Option<Vec<EditOp>> {
+        if let Some(movement) = selection_move(command) {
+            return self.execute(&movement, selection);
+        }
+        let (line, col) = self.cursor_position();
+        let mut edits = Vec::new();
+        match command {
+            Command::InsertChar(c) => {
+                edits.extend(self.fill_virtual_space());
+                edits.push(self.insert(self.cursor, c.to_string()));
+            }
+            Command::InsertNewline => edits.push(self.insert(self.cursor, "\n".to_string())),
+            // Text put in over a selection replaces it
+            Command::InsertText(text) => {
+                if let Some((start, end)) = selection.filter(|(start, end)| end > start) {
+                    edits.push(self.delete(start, end));
+                }
+                if !text.is_empty() {
+                    edits.push(self.insert(self.cursor, text.clone()));
+                }
+            }
+            Command::Backspace if self.cursor > 0 => edits.push(self.delete(self.cursor - 1, self.cursor)),
+            Command::DeleteForward if self.cursor < self.buffer.len_chars() => edits.push(self.delete(self.cursor, self.cursor + 1)),
+            Command::Backspace | Command::DeleteForward => {}
+            Command::DeleteWordBackward => {
+                let start = self.buffer.word_left(self.cursor);
+                if start < self.cursor {
+                    edits.push(self.delete(start, self.cursor));
+                }
+            }
+            Command::DeleteWordForward => {
+                let end = self.buffer.word_right(self.cursor);
+                if end > self.cursor {
+                    edits.push(self.delete(self.cursor, end));
+                }
+            }
+            // Kills to the end of the line, or the line break when already there
+            Command::KillLine => {
+                let line_end = self.buffer.line_to_char(line) + self.buffer.line_len(line);
+                let end = if self.cursor < line_end { line_end } else { self.line_range(line).1 };
+                if end > self.cursor {
+                    edits.push(self.delete(self.cursor, end));
+                }
+            }
+            Command::Cut => {
+                let (start, end) = selection.unwrap_or_else(|| self.line_range(line));
+                if end > start {
+                    edits.push(self.delete(start, end));
+                }
+            }
+            Command::DeleteLine => {
+                let (mut start, end) = self.line_range(line);
+                // The last line has no break of its own, so the one before it goes
+                if end == self.buffer.len_chars() && line > 0 {
+                    start = self.buffer.line_to_char(line - 1) + self.buffer.line_len(line - 1);
+                }
+                if end > start {
+                    let display_col = self.cursor_display_col();
+                    edits.push(self.delete(start, end));
+                    (self.cursor, self.virtual_col) = self.column_on_line(line.min(self.buffer.len_lines() - 1), display_col);
+                }
+            }
+            Command::DuplicateLine => {
+                let selected = selection.filter(|(start, end)| end > start);
+                let (text, at) = match selected {
+                    Some((start, end)) => (self.buffer.slice(start..end), end),
+                    None => {
+                        let (start, end) = self.line_range(line);
+                        let text = self.buffer.slice(start..end);
+                        // The last line has no break to copy along
+                        if text.ends_with('\n') { (text, end) } else { (format!("\n{}", text), end) }
+                    }
+                };
+                let len = text.chars().count();
+                edits.push(self.insert(at, text));
+                if selected.is_some() {
+                    // The copy is selected, so it can be duplicated again
+                    self.anchor = Some(at);
+                    self.cursor = at + len;
+                } else {
+                    self.cursor = self.buffer.line_to_char(line + 1) + col;
+                }
+            }
+            Command::MoveLineUp | Command::MoveLineDown => edits = self.move_lines(matches!(command, Command::MoveLineUp), selection),
+            Command::ChangeCase(case) => {
+                let range = selection.filter(|(start, end)| end > start).or_else(|| self.buffer.word_at(self.cursor));
+                if let Some((start, end)) = range {
+                    let old_text = self.buffer.slice(start..end);
+                    let new_text = case.apply(&old_text);
+                    if new_text != old_text {
+                        let (cursor, new_end) = (self.cursor, start + new_text.chars().count());
+                        edits.push(self.delete(start, end));
+                        edits.push(self.insert(start, new_text));
+                        // Some chars change length, e.g. ß becomes SS
+                        self.cursor = match self.anchor {
+                            Some(anchor) if anchor < cursor => new_end,
+                            Some(_) => {
+                                self.anchor = Some(new_end);
+                                cursor
+                            }
+                            None => cursor.min(new_end),
+                        };
+                    }
+                }
+            }
+            // Without a selection over several lines, Tab pads to the next indent stop
+            Command::Indent if selection.is_none_or(|(start, end)| self.buffer.char_to_line(start) == self.buffer.char_to_line(end)) => {
+                self.anchor = None;
+                let width = self.indent_width;
+                let padding = if self.use_tabs { "\t".to_string() } else { " ".repeat(width - self.cursor_display_col() % width) };
+                edits.push(self.insert(self.cursor, padding));
+            }
+            Command::Indent | Command::Dedent => edits = self.shift_selected_lines(matches!(command, Command::Dedent), selection),
+            Command::MoveLeft if self.virtual_col > 0 => self.virtual_col -= 1,
+            Command::MoveLeft => self.cursor = self.cursor.saturating_sub(1),
+            Command::MoveRight if self.virtual_space && col >= self.buffer.line_len(line) => self.virtual_col += 1,
+            Command::MoveRight => self.cursor = (self.cursor + 1).min(self.buffer.len_chars()),
+            Command::MoveWordLeft => self.cursor = self.buffer.word_left(self.cursor),
+            Command::MoveWordRight => self.cursor = self.buffer.word_right(self.cursor),
+            Command::MoveHome => {
+                let line_start = self.buffer.line_to_char(line);
+                let first_non_blank = line_start + self.buffer.indent_len(line);
+                self.cursor = if self.cursor == first_non_blank { line_start } else { first_non_blank };
+            }
+            Command::MoveEnd => self.cursor = self.buffer.line_to_char(line) + self.buffer.line_len(line),
+            Command::MoveDocStart => self.cursor = 0,
+            Command::MoveDocEnd => self.cursor = self.buffer.len_chars(),
+            Command::MoveUp | Command::MoveDown if self.wrap_width.is_some() => {
+                self.cursor = self.move_wrapped(matches!(command, Command::MoveUp));
+                self.virtual_col = 0;
+            }
+            // Closed folds are stepped over as a single line
+            Command::MoveUp if line > 0 => {
+                let target_line = self.folds.prev_visible_line(&self.buffer, line);
+                (self.cursor, self.virtual_col) = self.column_on_line(target_line, self.cursor_display_col() + self.virtual_col);
+            }
+            Command::MoveDown if self.folds.next_visible_line(&self.buffer, line) < self.buffer.len_lines() => {
+                let target_line = self.folds.next_visible_line(&self.buffer, line);
+                (self.cursor, self.virtual_col) = self.column_on_line(target_line, self.cursor_display_col() + self.virtual_col);
+            }
+            Command::MoveUp | Command::MoveDown => {}
+            Command::Undo => edits = self.undo_redo.undo(&mut self.buffer, &mut self.cursor, &mut self.dirty_lines),
+            Command::Redo => edits = self.undo_redo.redo(&mut self.buffer, &mut self.cursor, &mut self.dirty_lines),
+            Command::JumpToUndoState => edits = self.undo_redo.jump_to_selected(&mut self.buffer, &mut self.cursor, &mut self.dirty_lines),
+            _ => return None,
+        }
+        self.mark_dirty(&edits);
+        Some(edits)
+    }
+
+    /// Replaces the first match of `search` at or after `from` with
+    /// `replacement`. Returns the span the replacement takes and the edits.
+    pub fn replace_next(&mut self, search: &Search, from: usize, replacement: &str) -> Option<((usize, usize), Vec<EditOp>)> {
+        let (span, edits) = search.replace_next(&mut self.buffer, &mut self.undo_redo, from, replacement)?;
+        self.mark_dirty(&edits);
+        Some((span, edits))
+    }
+
+    /// Starts going through the matches of a search from the cursor, each
+    /// replaced or not as `answer_replace` is told. However many commands
+    /// that takes, it is undone in one step.
+    pub fn start_replace_walk(&mut self) -> ReplaceWalk {
+        self.undo_redo.begin_group();
+        ReplaceWalk::new(self.cursor)
+    }
+
+    /// Answers the match `walk` is on with `answer`, one of the replace
+    /// commands, and selects the next match, the cursor at its start.
+    /// `ReplaceRest` goes on replacing to the end. Once no match is left
+    /// the walk is over. Returns the edits.
+    pub fn answer_replace(&mut self, walk: &mut ReplaceWalk, search: &Search, replacement: &str, answer: &Command) -> Vec<EditOp> {
+        let mut edits = Vec::new();
+        if matches!(answer, Command::StopReplace) {
+            self.end_replace_walk(walk);
+            return edits;
+        }
+        let mut from = self.cursor;
+        loop {
+            if let Some((start, end)) = walk.current {
+                from = end;
+                if matches!(answer, Command::ReplaceYes | Command::ReplaceRest)
+                    && let Some(((_, new_end), ops)) = self.replace_next(search, start, replacement)
+                {
+                    walk.replaced(start, end - start, new_end - start);
+                    from = new_end;
+                    edits.extend(ops);
+                }
+            }
+            walk.advance(search, &self.buffer, from);
+            if walk.current.is_none() || !matches!(answer, Command::ReplaceRest) {
+                break;
+            }
+        }
+        match walk.current {
+            Some((start, end)) => {
+                self.cursor = start;
+                self.anchor = Some(end);
+            }
+            None => self.end_replace_walk(walk),
+        }
+        edits
+    }
+
+    /// Ends `walk` where it is, closing the undo step it makes.
+    pub fn end_replace_walk(&mut self, walk: &mut ReplaceWalk) {
+        walk.current = None;
+        self.undo_redo.end_group();
+    }
+
+    /// Replaces every match of `search` with `replacement`. Returns how
+    /// many there were and the edits.
+    pub fn replace_all(&mut self, search: &Search, replacement: &str) -> (usize, Vec<EditOp>) {
+        let (count, edits) = search.replace_all(&mut self.buffer, &mut self.undo_redo, replacement);
+        self.cursor = self.cursor.min(self.buffer.len_chars());
+        self.mark_dirty(&edits);
+        (count, edits)
+    }
+
+    /// Replaces the whole buffer with `text` as one undo step, as a merge,
+    /// a reload or a restored snapshot does, and returns the edits.
+    pub fn replace_text(&mut self, text: String) -> Vec<EditOp> {
+        let old = self.buffer.rope.to_string();
+        if old == text {
+            return Vec::new();
+        }
+        let cursor = self.cursor;
+        self.undo_redo.begin_group();
+        let edits = vec![self.delete(0, self.buffer.len_chars()), self.insert(0, text)];
+        self.undo_redo.end_group();
+        self.cursor = cursor.min(self.buffer.len_chars());
+        self.mark_dirty(&edits);
+        edits
+    }
+
+    /// Sorts lines `first..=last`, by the number they start with when
+    /// `numeric`, and returns the edits.
+    pub fn sort_lines(&mut self, first: usize, last: usize, numeric: bool, reverse: bool) -> Vec<EditOp> {
+        let edits = ex::sort_lines(&mut self.buffer, &mut self.undo_redo, first, last, numeric, reverse);
+        self.cursor = self.cursor.min(self.buffer.len_chars());
+        self.mark_dirty(&edits);
+        edits
+    }
+
+    /// Replaces the regex matches of `pattern` in lines `first..=last`, as
+    /// `:s` does. Returns how many there were and the edits.
+    pub fn substitute(&mut self, first: usize, last: usize, pattern: &str, replacement: &str, global: bool) -> Result<(usize, Vec<EditOp>), String> {
+        let (count, edits) = ex::substitute(&mut self.buffer, &mut self.undo_redo, first, last, pattern, replacement, global)?;
+        self.cursor = self.cursor.min(self.buffer.len_chars());
+        self.mark_dirty(&edits);
+        Ok((count, edits))
+    }
+
+    /// Puts lines `first..=last` through the shell `command`, as `:!` does,
+    /// and returns the edits.
+    pub fn filter_lines(&mut self, first: usize, last: usize, command: &str) -> Result<Vec<EditOp>, String> {
+        let edits = ex::filter_lines(&mut self.buffer, &mut self.undo_redo, first, last, command)?;
+        self.cursor = self.cursor.min(self.buffer.len_chars());
+        self.mark_dirty(&edits);
+        Ok(edits)
+    }
+
+    /// Types `c` at the cursor, in place of `over`, the placeholder of a
+    /// snippet stop, when given. `pairing` tells from the text and where
+    /// `c` goes whether a closer comes along or one is stepped over.
+    pub fn type_char(&mut self, c: char, over: Option<(usize, usize)>, pairing: impl FnOnce(&EditorBuffer, usize) -> Typed) -> Vec<EditOp> {
+        let mut edits = Vec::new();
+        if let Some((start, end)) = over {
+            edits.push(self.delete(start, end));
+        }
+        // Typing in virtual space first fills the gap with spaces
+        edits.extend(self.fill_virtual_space());
+        let at = self.cursor;
+        let text = match pairing(&self.buffer, at) {
+            Typed::StepOver => String::new(),
+            Typed::Plain => c.to_string(),
+            Typed::Pair(closer) => format!("{c}{closer}"),
+        };
+        if !text.is_empty() {
+            edits.push(self.insert(at, text));
+        }
+        self.cursor = at + 1;
+        self.dirty_lines.insert(self.buffer.char_to_line(self.cursor));
+        edits
+    }
+
+    /// Replays the edits a plugin or script made on its copy of the buffer
+    /// as one undo step and takes its cursor; its status is the caller's.
+    /// Returns the edits.
+    pub fn apply_hook_output(&mut self, output: HookOutput) -> Vec<EditOp> {
+        if output.edits.is_empty() && !output.handled {
+            return Vec::new();
+        }
+        self.undo_redo.begin_group();
+        let edits: Vec<EditOp> = output
+            .edits
+            .into_iter()
+            .map(|op| match op {
+                EditOp::Insert { char_idx, content } => self.insert(char_idx, content),
+                EditOp::Delete { char_idx, content } => self.delete(char_idx, char_idx + content.chars().count()),
+            })
+            .collect();
+        self.undo_redo.end_group();
+        self.cursor = output.cursor.min(self.buffer.len_chars());
+        self.mark_dirty(&edits);
+        edits
+    }
+
+    /// Strips trailing whitespace from the lines that differ from `base`,
+    /// the text last saved, as one undo step, keeping the cursor on the
+    /// same text. Returns the edits.
+    pub fn trim_changed_lines(&mut self, base: &Rope) -> Vec<EditOp> {
+        let (old_text, new_text) = (base.to_string(), self.buffer.rope.to_string());
+        let diff = similar::TextDiff::from_lines(&old_text, &new_text);
+        let changed: Vec<usize> = diff
+            .ops()
+            .iter()
+            .filter(|op| !matches!(op, similar::DiffOp::Equal { .. }))
+            .flat_map(|op| op.new_range())
+            .collect();
+        let mut cursor = self.cursor;
+        let mut edits = Vec::new();
+        self.undo_redo.begin_group();
+        // Bottom-up so earlier char indices stay valid
+        for &line in changed.iter().rev() {
+            let line_start = self.buffer.line_to_char(line);
+            let len = self.buffer.line_len(line);
+            let kept = self.buffer.slice(line_start..line_start + len).trim_end().chars().count();
+            if kept == len {
+                continue;
+            }
+            let start = line_start + kept;
+            edits.push(self.delete(start, line_start + len));
+            if cursor > start {
+                cursor -= (cursor - start).min(len - kept);
+            }
+        }
+        self.undo_redo.end_group();
+        self.cursor = cursor;
+        self.mark_dirty(&edits);
+        edits
+    }
+
+    /// Trades the buffer, undo history, cursor and folds for those of
+    /// `state`, as switching to another open file does.
+    pub fn swap_buffer(&mut self, state: &mut BufferState) {
+        std::mem::swap(&mut self.buffer, &mut state.buffer);
+        std::mem::swap(&mut self.undo_redo, &mut state.undo_redo);
+        std::mem::swap(&mut self.cursor, &mut state.cursor_char_idx);
+        std::mem::swap(&mut self.folds, &mut state.folds);
+    }
+
+    /// Marks the lines from the first edit down as needing a redraw.
+    fn mark_dirty(&mut self, edits: &[EditOp]) {
+        let len = self.buffer.len_chars();
+        if let Some(first) = edits.iter().map(|op| self.buffer.char_to_line(op.char_idx().min(len))).min() {
+            self.dirty_lines.extend(first..self.buffer.len_lines() + 1);
+        }
+    }
+
+    /// Swaps the selected lines, or the cursor's, with the line above or
+    /// below. The cursor and the selection go along with them.
+    fn move_lines(&mut self, up: bool, selection: Option<(usize, usize)>) -> Vec<EditOp> {
+        let (first, last) = match selection.filter(|(start, end)| end > start) {
+            Some(selection) => self.selected_lines(selection),
+            None => {
+                let line = self.buffer.char_to_line(self.cursor);
+                (line, line)
+            }
+        };
+        // The empty line after a final line break stays where it is
+        let movable = if up {
+            first > 0
+        } else {
+            last + 1 < self.buffer.len_lines() && self.buffer.line_to_char(last + 1) < self.buffer.len_chars()
+        };
+        if !movable {
+            return Vec::new();
+        }
+        let neighbor = if up { first - 1 } else { last + 1 };
+        let (top, bottom) = (first.min(neighbor), last.max(neighbor));
+        let line_text = |first: usize, last: usize| {
+            self.buffer.slice(self.buffer.line_to_char(first)..self.buffer.line_to_char(last) + self.buffer.line_len(last))
+        };
+        let old_text = line_text(top, bottom);
+        let neighbor_text = line_text(neighbor, neighbor);
+        let block_text = line_text(first, last);
+        let new_text = if up { format!("{}\n{}", block_text, neighbor_text) } else { format!("{}\n{}", neighbor_text, block_text) };
+        let start = self.buffer.line_to_char(top);
+        let cursor = self.cursor;
+        let edits = vec![self.delete(start, start + old_text.chars().count()), self.insert(start, new_text)];
+        let shift = neighbor_text.chars().count() + 1;
+        if up {
+            self.cursor = cursor - shift;
+            self.anchor = self.anchor.map(|anchor| anchor - shift);
+        } else {
+            self.cursor = cursor + shift;
+            self.anchor = self.anchor.map(|anchor| anchor + shift);
+        }
+        edits
+    }
+
+    /// Indents or dedents the selected lines, or the cursor's. The cursor
+    /// and the anchor keep their place in the text of their lines.
+    fn shift_selected_lines(&mut self, dedent: bool, selection: Option<(usize, usize)>) -> Vec<EditOp> {
+        let (first, last) = match selection.filter(|(start, end)| end > start) {
+            Some(selection) => self.selected_lines(selection),
+            None => {
+                let line = self.buffer.char_to_line(self.cursor);
+                (line, line)
+            }
+        };
+        let positions: Vec<(usize, usize)> = [Some(self.cursor), self.anchor]
+            .into_iter()
+            .flatten()
+            .map(|idx| {
+                let line = self.buffer.char_to_line(idx);
+                (line, idx - self.buffer.line_to_char(line))
+            })
+            .collect();
+        let (edits, growth) = self.shift_lines(first, last, dedent);
+        let moved: Vec<usize> = positions
+            .into_iter()
+            .map(|(line, col)| {
+                let grown = if (first..=last).contains(&line) { growth[line - first] } else { 0 };
+                self.buffer.line_to_char(line) + col.saturating_add_signed(grown)
+            })
+            .collect();
+        self.cursor = moved[0];
+        self.anchor = moved.get(1).copied();
+        edits
+    }
+
+    /// Indents lines `first..=last` by `indent_width` spaces, or a tab
+    /// with `use_tabs`, or takes up to that much indentation (or one tab)
+    /// off them. Blank lines are not indented. Returns the ops applied and
+    /// how much each line grew.
+    fn shift_lines(&mut self, first: usize, last: usize, dedent: bool) -> (Vec<EditOp>, Vec<isize>) {
+        let mut edits = Vec::new();
+        let mut growth = Vec::new();
+        for line in first..=last {
+            let start = self.buffer.line_to_char(line);
+            if dedent {
+                let indent = self.buffer.slice(start..start + self.buffer.indent_len(line));
+                let len = if indent.starts_with('\t') {
+                    1
+                } else {
+                    indent.chars().take(self.indent_width).take_while(|c| *c == ' ').count()
+                };
+                if len > 0 {
+                    let content = self.buffer.slice(start..start + len);
+                    self.buffer.remove(start, len);
+                    self.undo_redo.add_delete(&self.buffer, start, content.clone());
+                    edits.push(EditOp::Delete { char_idx: start, content });
+                }
+                growth.push(-(len as isize));
+            } else if self.buffer.line_len(line) > 0 {
+                let content = if self.use_tabs { "\t".to_string() } else { " ".repeat(self.indent_width) };
+                let len = content.chars().count();
+                self.buffer.insert(start, &content);
+                self.undo_redo.add_insert(&self.buffer, start, content.clone());
+                edits.push(EditOp::Insert { char_idx: start, content });
+                growth.push(len as isize);
+            } else {
+                growth.push(0);
+            }
+        }
+        (edits, growth)
+    }
+
+    /// Cursor moved a screen row up or down through wrapped lines, keeping
+    /// its column within the row.
+    fn move_wrapped(&self, up: bool) -> usize {
+        let (line, char_col) = self.cursor_position();
+        let starts = self.row_starts(line);
+        let row = starts.partition_point(|&start| start <= char_col) - 1;
+        let col = columns::display_col(self.buffer.line(line).slice(starts[row]..), char_col - starts[row], self.tab_width);
+        let (target_line, target_row) = if up && row > 0 {
+            (line, row - 1)
+        } else if up {
+            if line == 0 {
+                return self.cursor;
+            }
+            let prev = self.folds.prev_visible_line(&self.buffer, line);
+            (prev, self.row_starts(prev).len() - 1)
+        } else if row + 1 < starts.len() {
+            (line, row + 1)
+        } else {
+            let next = self.folds.next_visible_line(&self.buffer, line);
+            if next >= self.buffer.len_lines() {
+                return self.cursor;
+            }
+            (next, 0)
+        };
+        let starts = self.row_starts(target_line);
+        let start = starts[target_row];
+        let mut target_col = start + columns::char_col(self.buffer.line(target_line).slice(start..), col, self.tab_width).0;
+        // A column past the end of a row that wraps stays on its last char
+        if let Some(&next_start) = starts.get(target_row + 1) {
+            target_col = target_col.min(next_start - 1);
+        }
+        self.buffer.line_to_char(target_line) + target_col
+    }
+
+    /// Fills the gap between the end of the line and a cursor in virtual
+    /// space with spaces, so that text typed there lands where it is shown.
+    pub fn fill_virtual_space(&mut self) -> Option<EditOp> {
+        if self.virtual_col == 0 {
+            return None;
+        }
+        let padding = " ".repeat(std::mem::take(&mut self.virtual_col));
+        Some(self.insert(self.cursor, padding))
+    }
+
+    /// Inserts `text` at `at`, moving a cursor at or after it along.
+    pub fn insert(&mut self, at: usize, text: String) -> EditOp {
+        self.buffer.insert(at, &text);
+        self.undo_redo.add_insert(&self.buffer, at, text.clone());
+        if self.cursor >= at {
+            self.cursor += text.chars().count();
+        }
+        EditOp::Insert { char_idx: at, content: text }
+    }
+
+    /// Deletes the chars `start..end`, leaving the cursor at `start`.
+    pub fn delete(&mut self, start: usize, end: usize) -> EditOp {
+        let content = self.buffer.slice(start..end);
+        self.buffer.remove(start, end - start);
+        self.undo_redo.add_delete(&self.buffer, start, content.clone());
+        self.cursor = start;
+        EditOp::Delete { char_idx: start, content }
+    }
+}
+
+impl Default for Editor {
+    fn default() -> Self {
+        Editor::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn type_text(editor: &mut Editor, text: &str) {
+        for c in text.chars() {
+            let command = if c == '\n' { Command::InsertNewline } else { Command::InsertChar(c) };
+            editor.apply_command(&command);
+        }
+    }
+
+    #[test]
+    fn typing_inserts_and_deleting_removes_at_the_cursor() {
+        let mut editor = Editor::new();
+        type_text(&mut editor, "one\ntwo");
+        assert_eq!(editor.text(), "one\ntwo");
+        assert_eq!(editor.cursor_position(), (1, 3));
+
+        editor.apply_command(&Command::Backspace);
+        editor.apply_command(&Command::MoveDocStart);
+        editor.apply_command(&Command::DeleteForward);
+        assert_eq!(editor.text(), "ne\ntw");
+
+        let edits = editor.apply_command(&Command::DeleteLine).unwrap();
+        assert!(matches!(edits.as_slice(), [EditOp::Delete { char_idx: 0, content }] if content == "ne\n"));
+        assert_eq!(editor.text(), "tw");
+    }
+
+    #[test]
+    fn moving_down_steps_over_a_closed_fold() {
+        let mut editor = Editor::from_text("a\nb\nc\nd\n");
+        editor.folds.add(&editor.buffer, 1, 2);
+        editor.apply_command(&Command::MoveDown);
+        assert_eq!(editor.cursor_position(), (1, 0));
+        editor.apply_command(&Command::MoveDown);
+        assert_eq!(editor.cursor_position(), (3, 0));
+        editor.apply_command(&Command::MoveUp);
+        assert_eq!(editor.cursor_position(), (1, 0));
+    }
+
+    #[test]
+    fn moving_down_goes_through_the_rows_of_a_wrapped_line() {
+        let mut editor = Editor::from_text("abcdefgh\nxy");
+        editor.wrap_width = Some(4);
+        editor.apply_command(&Command::MoveRight);
+        editor.apply_command(&Command::MoveDown);
+        assert_eq!(editor.cursor_position(), (0, 5));
+        editor.apply_command(&Command::MoveDown);
+        assert_eq!(editor.cursor_position(), (1, 1));
+        editor.apply_command(&Command::MoveUp);
+        assert_eq!(editor.cursor_position(), (0, 5));
+    }
+
+    #[test]
+    fn replacing_the_whole_text_undoes_in_one_step() {
+        let mut editor = Editor::from_text("old text");
+        editor.cursor = 5;
+        editor.replace_text("new".to_string());
+        assert_eq!((editor.text().as_str(), editor.cursor), ("new", 3));
+        editor.apply_command(&Command::Undo);
+        assert_eq!(editor.text(), "old text");
+    }
+
+    #[test]
+    fn a_replace_walk_replaces_the_matches_said_yes_to_and_undoes_in_one_step() {
+        let mut editor = Editor::from_text("a b a b a");
+        let search = Search::new("a", false, false).unwrap();
+        let mut walk = editor.start_replace_walk();
+        for answer in [Command::StartReplaceEach, Command::ReplaceYes, Command::ReplaceNo] {
+            editor.answer_replace(&mut walk, &search, "x", &answer);
+        }
+        assert_eq!((editor.cursor, editor.anchor), (8, Some(9)));
+        editor.answer_replace(&mut walk, &search, "x", &Command::ReplaceYes);
+        assert_eq!((editor.text().as_str(), walk.current, walk.count), ("x b a b x", None, 2));
+        editor.apply_command(&Command::Undo);
+        assert_eq!(editor.text(), "a b a b a");
+    }
+
+    #[test]
+    fn trimming_strips_trailing_blanks_only_from_changed_lines() {
+        let base = Rope::from_str("kept  \nold\n");
+        let mut editor = Editor::from_text("kept  \nnew  \n");
+        editor.cursor = 13;
+        editor.trim_changed_lines(&base);
+        assert_eq!((editor.text().as_str(), editor.cursor), ("kept  \nnew\n", 11));
+    }
+
+    #[test]
+    fn a_typed_word_and_each_other_command_undo_in_one_step() {
+        let mut editor = Editor::from_text("one\ntwo\n");
+        editor.cursor = 4;
+        type_text(&mut editor, "new");
+        editor.apply_command(&Command::SelectUp);
+        editor.apply_command(&Command::Indent);
+        assert_eq!(editor.text(), "    one\n    newtwo\n");
+
+        editor.apply_command(&Command::Undo);
+        assert_eq!(editor.text(), "one\nnewtwo\n");
+        editor.apply_command(&Command::Undo);
+        assert_eq!(editor.text(), "one\ntwo\n");
+        editor.apply_command(&Command::Redo);
+        assert_eq!(editor.text(), "one\nnewtwo\n");
+        editor.apply_command(&Command::Redo);
+        assert_eq!(editor.text(), "    one\n    newtwo\n");
+    }
+}
@@ -0,0 +1,402 @@
+// src/ex.rs
+
+use crate::buffer::{EditOp, EditorBuffer, UndoTree};
+use crate::search::Search;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// A parsed ex command line, e.g. `:10,50s/foo/bar/g`.
+#[derive(Debug, PartialEq)]
+pub enum ExCommand {
+    /// Replace the regular expression `pattern` with `replacement` on lines
+    /// `first..=last`, every match per line when `global`, otherwise only
+    /// the first. The replacement may refer to groups as `$1`.
+    Substitute {
+        first: usize,
+        last: usize,
+        pattern: String,
+        replacement: String,
+        global: bool,
+    },
+    /// Sort lines `first..=last`, by the first number on each line when
+    /// `numeric`, in descending order when `reverse`.
+    Sort {
+        first: usize,
+        last: usize,
+        numeric: bool,
+        reverse: bool,
+    },
+    /// Replace lines `first..=last` with what `command` prints when given
+    /// them on its standard input.
+    Filter {
+        first: usize,
+        last: usize,
+        command: String,
+    },
+    /// Three-way merge the file on disk into the buffer.
+    Merge,
+    /// Browse the local history snapshots of the current file.
+    History,
+    /// Show session and project statistics.
+    Stats,
+    /// Open the commit message buffer, or commit with its text when run
+    /// from it.
+    Commit,
+    /// Compare the buffer side by side with another version of the text.
+    Diff(DiffTarget),
+    /// Run a plugin's transform named `name` on lines `first..=last`.
+    Transform {
+        first: usize,
+        last: usize,
+        name: String,
+    },
+    /// List the plugins that are loaded.
+    Plugins,
+    /// Run a command defined in the init script.
+    Call(String),
+}
+
+/// What `:diff` compares the buffer against.
+#[derive(Debug, PartialEq)]
+pub enum DiffTarget {
+    /// The file as saved on disk.
+    Disk,
+    /// The file as committed at git HEAD.
+    Head,
+    /// Another open buffer, by number in the buffer list or by path.
+    Buffer(String),
+}
+
+/// Parses a line address: a 1-based number, `.` for the cursor line or `$`
+/// for the last line. Returns the 0-based line and the unparsed rest.
+fn parse_address(input: &str, current_line: usize, last_line: usize) -> Result<(Option<usize>, &str), String> {
+    if let Some(rest) = input.strip_prefix('.') {
+        return Ok((Some(current_line), rest));
+    }
+    if let Some(rest) = input.strip_prefix('$') {
+        return Ok((Some(last_line), rest));
+    }
+    let digits = input.len() - input.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    if digits == 0 {
+        return Ok((None, input));
+    }
+    match input[..digits].parse::<usize>() {
+        Ok(0) | Err(_) => Err(format!("Invalid line: {}", &input[..digits])),
+        Ok(line) => Ok((Some((line - 1).min(last_line)), &input[digits..])),
+    }
+}
+
+/// Parses `[range]`, where range is `%`, `addr` or `addr,addr`. No range
+/// means the cursor line.
+fn parse_range(input: &str, current_line: usize, last_line: usize) -> Result<(usize, usize, &str), String> {
+    if let Some(rest) = input.strip_prefix('%') {
+        return Ok((0, last_line, rest));
+    }
+    let (first, rest) = parse_address(input, current_line, last_line)?;
+    let Some(rest) = rest.strip_prefix(',') else {
+        let line = first.unwrap_or(current_line);
+        return Ok((line, line, rest));
+    };
+    let (last, rest) = parse_address(rest, current_line, last_line)?;
+    let (first, last) = (first.unwrap_or(current_line), last.unwrap_or(current_line));
+    if first > last {
+        return Err("Backwards range".to_string());
+    }
+    Ok((first, last, rest))
+}
+
+/// Splits `rest` on unescaped `delimiter`; `\<delimiter>` stands for the delimiter itself.
+fn split_delimited(rest: &str, delimiter: char) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = rest.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(next) if next == delimiter => parts.last_mut().unwrap().push(next),
+                Some(next) => {
+                    parts.last_mut().unwrap().push('\\');
+                    parts.last_mut().unwrap().push(next);
+                }
+                None => parts.last_mut().unwrap().push('\\'),
+            },
+            c if c == delimiter => parts.push(String::new()),
+            c => parts.last_mut().unwrap().push(c),
+        }
+    }
+    parts
+}
+
+pub fn parse(input: &str, current_line: usize, last_line: usize) -> Result<ExCommand, String> {
+    let input = input.trim().trim_start_matches(':');
+    if input == "merge" {
+        return Ok(ExCommand::Merge);
+    }
+    if input == "history" {
+        return Ok(ExCommand::History);
+    }
+    if input == "stats" {
+        return Ok(ExCommand::Stats);
+    }
+    if input == "commit" {
+        return Ok(ExCommand::Commit);
+    }
+    if input == "plugins" {
+        return Ok(ExCommand::Plugins);
+    }
+    if let Some(target) = input.strip_prefix("diff").filter(|rest| rest.is_empty() || rest.starts_with(' ')) {
+        return Ok(ExCommand::Diff(match target.trim() {
+            "" | "disk" => DiffTarget::Disk,
+            "head" => DiffTarget::Head,
+            other => DiffTarget::Buffer(other.to_string()),
+        }));
+    }
+    let (first, last, rest) = parse_range(input, current_line, last_line)?;
+    if let Some(command) = rest.strip_prefix('!') {
+        if command.trim().is_empty() {
+            return Err("Expected a shell command after !".to_string());
+        }
+        // Without a range the whole buffer goes through the command
+        let (first, last) = if rest.len() == input.len() { (0, last_line) } else { (first, last) };
+        return Ok(ExCommand::Filter { first, last, command: command.trim().to_string() });
+    }
+    if let Some(flags) = rest.strip_prefix("sort") {
+        let (reverse, flags) = match flags.strip_prefix('!') {
+            Some(flags) => (true, flags),
+            None => (false, flags),
+        };
+        let numeric = match flags.trim() {
+            "" => false,
+            "n" => true,
+            flags => return Err(format!("Unknown sort option: {}", flags)),
+        };
+        // Without a range the whole buffer is sorted
+        let (first, last) = if rest.len() == input.len() { (0, last_line) } else { (first, last) };
+        return Ok(ExCommand::Sort { first, last, numeric, reverse });
+    }
+    if let Some(name) = rest.strip_prefix("transform") {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err("Expected the name of a transform".to_string());
+        }
+        // Without a range the whole buffer is transformed
+        let (first, last) = if rest.len() == input.len() { (0, last_line) } else { (first, last) };
+        return Ok(ExCommand::Transform { first, last, name: name.to_string() });
+    }
+    // A plain word is a command of the init script; it cannot be `s`,
+    // whose delimiter is never a letter
+    if rest.len() == input.len() && input.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return Ok(ExCommand::Call(input.to_string()));
+    }
+    let Some(rest) = rest.strip_prefix('s') else {
+        return Err(format!("Unknown command: {}", input));
+    };
+    let Some(delimiter) = rest.chars().next().filter(|c| !c.is_alphanumeric() && *c != '\\' && !c.is_whitespace()) else {
+        return Err("Expected s/pattern/replacement/[g]".to_string());
+    };
+    let parts = split_delimited(&rest[delimiter.len_utf8()..], delimiter);
+    let (pattern, replacement, flags) = match parts.as_slice() {
+        [pattern, replacement] => (pattern, replacement, ""),
+        [pattern, replacement, flags] => (pattern, replacement, flags.as_str()),
+        _ => return Err("Expected s/pattern/replacement/[g]".to_string()),
+    };
+    if pattern.is_empty() {
+        return Err("Empty pattern".to_string());
+    }
+    if let Some(flag) = flags.chars().find(|c| *c != 'g') {
+        return Err(format!("Unknown flag: {}", flag));
+    }
+    Ok(ExCommand::Substitute {
+        first,
+        last,
+        pattern: pattern.clone(),
+        replacement: replacement.clone(),
+        global: flags.contains('g'),
+    })
+}
+
+/// Replaces the matches of the regular expression `pattern` on lines
+/// `first..=last` only, as one undoable edit. Returns the number of
+/// replacements and the edits applied.
+pub fn substitute(
+    buffer: &mut EditorBuffer,
+    undo_redo: &mut UndoTree,
+    first: usize,
+    last: usize,
+    pattern: &str,
+    replacement: &str,
+    global: bool,
+) -> Result<(usize, Vec<EditOp>), String> {
+    let search = Search::new(pattern, true, false).map_err(|err| format!("Invalid regex: {}", err))?;
+    Ok(search.replace_in_lines(buffer, undo_redo, first, last, replacement, global))
+}
+
+/// The first number on a line, for a numeric sort. Lines without one sort
+/// before all others.
+fn line_number(line: &str) -> Option<i64> {
+    let start = line.find(|c: char| c.is_ascii_digit())?;
+    let negative = line[..start].ends_with('-');
+    let digits = line[start..].find(|c: char| !c.is_ascii_digit()).map_or(&line[start..], |end| &line[start..start + end]);
+    let value = digits.parse::<i64>().unwrap_or(i64::MAX);
+    Some(if negative { -value } else { value })
+}
+
+/// Sorts lines `first..=last` and puts them back as one undoable edit.
+/// Returns the edits applied, none when the lines were already in order.
+pub fn sort_lines(
+    buffer: &mut EditorBuffer,
+    undo_redo: &mut UndoTree,
+    first: usize,
+    last: usize,
+    numeric: bool,
+    reverse: bool,
+) -> Vec<EditOp> {
+    let mut last = last.min(buffer.len_lines().saturating_sub(1));
+    // The empty line after a final line break is not sorted in
+    if last > first && buffer.line_to_char(last) == buffer.len_chars() {
+        last -= 1;
+    }
+    let start = buffer.line_to_char(first);
+    let end = buffer.line_to_char(last) + buffer.line_len(last);
+    let old_text = buffer.slice(start..end);
+    let mut lines: Vec<&str> = old_text.split('\n').collect();
+    if numeric {
+        lines.sort_by_key(|line| line_number(line));
+    } else {
+        lines.sort();
+    }
+    if reverse {
+        lines.reverse();
+    }
+    let new_text = lines.join("\n");
+    if new_text == old_text {
+        return Vec::new();
+    }
+    undo_redo.begin_group();
+    buffer.remove(start, end - start);
+    undo_redo.add_delete(buffer, start, old_text.clone());
+    buffer.insert(start, &new_text);
+    undo_redo.add_insert(buffer, start, new_text.clone());
+    undo_redo.end_group();
+    vec![EditOp::Delete { char_idx: start, content: old_text }, EditOp::Insert { char_idx: start, content: new_text }]
+}
+
+/// Pipes lines `first..=last` through `sh -c command` and puts its output
+/// in their place as one undoable edit. Nothing changes when the command
+/// fails. Returns the edits applied.
+pub fn filter_lines(
+    buffer: &mut EditorBuffer,
+    undo_redo: &mut UndoTree,
+    first: usize,
+    last: usize,
+    command: &str,
+) -> Result<Vec<EditOp>, String> {
+    let mut last = last.min(buffer.len_lines().saturating_sub(1));
+    // The empty line after a final line break is not part of the text
+    if last > first && buffer.line_to_char(last) == buffer.len_chars() {
+        last -= 1;
+    }
+    let start = buffer.line_to_char(first);
+    let end = buffer.line_to_char(last) + buffer.line_len(last);
+    let old_text = buffer.slice(start..end);
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| format!("Could not run {}: {}", command, err))?;
+    // Fed from another thread so a command that writes before it has read
+    // everything cannot block on a full pipe
+    let input = format!("{}\n", old_text);
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let writer = thread::spawn(move || {
+        let _ = stdin.write_all(input.as_bytes());
+    });
+    let output = child.wait_with_output().map_err(|err| format!("Could not run {}: {}", command, err))?;
+    let _ = writer.join();
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(match stderr.lines().next() {
+            Some(line) => format!("{} failed: {}", command, line),
+            None => format!("{} failed with {}", command, output.status),
+        });
+    }
+    let new_text = String::from_utf8_lossy(&output.stdout).replace("\r\n", "\n");
+    let new_text = new_text.strip_suffix('\n').unwrap_or(&new_text).to_string();
+    if new_text == old_text {
+        return Ok(Vec::new());
+    }
+    undo_redo.begin_group();
+    buffer.remove(start, end - start);
+    undo_redo.add_delete(buffer, start, old_text.clone());
+    let mut edits = vec![EditOp::Delete { char_idx: start, content: old_text }];
+    if !new_text.is_empty() {
+        buffer.insert(start, &new_text);
+        undo_redo.add_insert(buffer, start, new_text.clone());
+        edits.push(EditOp::Insert { char_idx: start, content: new_text });
+    }
+    undo_redo.end_group();
+    Ok(edits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::UndoStrategy;
+    use std::collections::HashSet;
+
+    fn substitute_command(input: &str) -> ExCommand {
+        parse(input, 4, 9).unwrap()
+    }
+
+    #[test]
+    fn ranges_name_lines_from_one_and_default_to_the_cursor_line() {
+        let range = |input| match substitute_command(input) {
+            ExCommand::Substitute { first, last, .. } => (first, last),
+            other => panic!("not a substitute: {:?}", other),
+        };
+        assert_eq!(range("s/a/b/"), (4, 4));
+        assert_eq!(range("%s/a/b/"), (0, 9));
+        assert_eq!(range(".,$s/a/b/"), (4, 9));
+        assert_eq!(range("2,.s/a/b/"), (1, 4));
+        assert_eq!(range("3,99s/a/b/"), (2, 9));
+        assert_eq!(parse("7,3s/a/b/", 4, 9), Err("Backwards range".to_string()));
+        assert_eq!(parse("0s/a/b/", 4, 9), Err("Invalid line: 0".to_string()));
+    }
+
+    #[test]
+    fn substitute_takes_escaped_delimiters_and_the_g_flag() {
+        assert_eq!(
+            substitute_command(r":s#a\#b#c\d#g"),
+            ExCommand::Substitute { first: 4, last: 4, pattern: "a#b".to_string(), replacement: r"c\d".to_string(), global: true }
+        );
+        assert_eq!(
+            substitute_command(r"s/a\/b/c/"),
+            ExCommand::Substitute { first: 4, last: 4, pattern: "a/b".to_string(), replacement: "c".to_string(), global: false }
+        );
+        assert_eq!(parse("s/a/b/x", 4, 9), Err("Unknown flag: x".to_string()));
+        assert_eq!(parse("s//b/", 4, 9), Err("Empty pattern".to_string()));
+    }
+
+    #[test]
+    fn substitute_replaces_regex_matches_in_its_range_and_undoes_in_one_step() {
+        let mut buffer = EditorBuffer::new();
+        buffer.insert(0, "a1 a2\na3 a4\na5\n");
+        let mut undo_redo = UndoTree::new(UndoStrategy::default());
+        let (count, _) = substitute(&mut buffer, &mut undo_redo, 0, 1, r"a(\d)", "b$1", true).unwrap();
+        assert_eq!((count, buffer.rope.to_string().as_str()), (4, "b1 b2\nb3 b4\na5\n"));
+
+        let (count, _) = substitute(&mut buffer, &mut undo_redo, 0, 2, "b", "c", false).unwrap();
+        assert_eq!((count, buffer.rope.to_string().as_str()), (2, "c1 b2\nc3 b4\na5\n"));
+
+        let (mut cursor, mut dirty) = (0, HashSet::new());
+        undo_redo.undo(&mut buffer, &mut cursor, &mut dirty);
+        assert_eq!(buffer.rope.to_string(), "b1 b2\nb3 b4\na5\n");
+        undo_redo.undo(&mut buffer, &mut cursor, &mut dirty);
+        assert_eq!(buffer.rope.to_string(), "a1 a2\na3 a4\na5\n");
+
+        assert!(substitute(&mut buffer, &mut undo_redo, 0, 2, "(", "", true).is_err());
+    }
+}
@@ -0,0 +1,163 @@
+// src/export.rs
+
+use crate::buffer::EditorBuffer;
+use crate::search::Search;
+use crate::syntax::{HighlightKind, SyntaxTree};
+use crate::theme::{Style, Theme};
+use crossterm::style::Color;
+use std::fmt::Write as _;
+use std::fs::write;
+use std::io;
+
+/// Channels of an RGB color; exports leave out any other kind.
+fn rgb(color: Option<Color>) -> Option<(u8, u8, u8)> {
+    match color {
+        Some(Color::Rgb { r, g, b }) => Some((r, g, b)),
+        _ => None,
+    }
+}
+
+/// `style` as inline CSS.
+fn css(style: &Style) -> String {
+    let mut css = String::new();
+    if let Some((r, g, b)) = rgb(style.fg) {
+        let _ = write!(css, "color:#{:02x}{:02x}{:02x};", r, g, b);
+    }
+    if let Some((r, g, b)) = rgb(style.bg) {
+        let _ = write!(css, "background:#{:02x}{:02x}{:02x};", r, g, b);
+    }
+    for (on, property) in [(style.bold, "font-weight:bold;"), (style.italic, "font-style:italic;"), (style.underline, "text-decoration:underline;")] {
+        if on {
+            css.push_str(property);
+        }
+    }
+    css
+}
+
+/// `style` as ANSI escape sequences.
+fn sgr(style: &Style) -> String {
+    let mut sgr = String::new();
+    if let Some((r, g, b)) = rgb(style.fg) {
+        let _ = write!(sgr, "\x1b[38;2;{};{};{}m", r, g, b);
+    }
+    if let Some((r, g, b)) = rgb(style.bg) {
+        let _ = write!(sgr, "\x1b[48;2;{};{};{}m", r, g, b);
+    }
+    for (on, code) in [(style.bold, "\x1b[1m"), (style.italic, "\x1b[3m"), (style.underline, "\x1b[4m")] {
+        if on {
+            sgr.push_str(code);
+        }
+    }
+    sgr
+}
+
+fn escape_html(out: &mut String, ch: char) {
+    match ch {
+        '&' => out.push_str("&amp;"),
+        '<' => out.push_str("&lt;"),
+        '>' => out.push_str("&gt;"),
+        '"' => out.push_str("&quot;"),
+        _ => out.push(ch),
+    }
+}
+
+/// Highlight class for every byte of `text`, which starts at char `start`.
+fn byte_kinds(buffer: &EditorBuffer, start: usize, text: &str, syntax: Option<&mut SyntaxTree>) -> Vec<Option<HighlightKind>> {
+    let base = buffer.rope.char_to_byte(start);
+    let mut kinds = vec![None; text.len()];
+    if let Some(syntax) = syntax {
+        syntax.parse(buffer);
+        for (span_start, span_end, kind) in syntax.highlights(base, base + text.len()) {
+            let from = span_start.saturating_sub(base);
+            let to = (span_end - base).min(text.len());
+            kinds[from..to].fill(Some(kind));
+        }
+    }
+    kinds
+}
+
+/// Renders the chars in `start..end` as a standalone HTML page in the
+/// colors of `theme`, inlined so the snippet looks the same wherever it is
+/// pasted.
+pub fn buffer_to_html(buffer: &EditorBuffer, start: usize, end: usize, syntax: Option<&mut SyntaxTree>, theme: &Theme, title: &str) -> String {
+    let text = buffer.slice(start..end);
+    let kinds = byte_kinds(buffer, start, &text, syntax);
+
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>");
+    title.chars().for_each(|ch| escape_html(&mut html, ch));
+    let _ = write!(html, "</title>\n</head>\n<body style=\"{}\">\n<pre style=\"font-family:monospace\">", css(&theme.text));
+
+    let mut open: Option<HighlightKind> = None;
+    for (pos, ch) in text.char_indices() {
+        if kinds[pos] != open {
+            if open.is_some() {
+                html.push_str("</span>");
+            }
+            if let Some(kind) = kinds[pos] {
+                let _ = write!(html, "<span style=\"{}\">", css(theme.syntax(kind)));
+            }
+            open = kinds[pos];
+        }
+        escape_html(&mut html, ch);
+    }
+    if open.is_some() {
+        html.push_str("</span>");
+    }
+    html.push_str("</pre>\n</body>\n</html>\n");
+    html
+}
+
+/// Renders the chars in `start..end` in the colors of `theme`, with search
+/// matches reversed, as ANSI escape sequences for pasting into terminals
+/// and chat clients.
+pub fn buffer_to_ansi(
+    buffer: &EditorBuffer,
+    start: usize,
+    end: usize,
+    syntax: Option<&mut SyntaxTree>,
+    theme: &Theme,
+    search: Option<&Search>,
+) -> String {
+    let text = buffer.slice(start..end);
+    let kinds = byte_kinds(buffer, start, &text, syntax);
+    let mut found = vec![false; text.len()];
+    for (start, end) in search.into_iter().flat_map(|search| search.matches_in(&text)) {
+        found[start..end].fill(true);
+    }
+
+    let base = sgr(&theme.text);
+    let mut ansi = String::new();
+    // Style of the last char written on this line, None at its start
+    let mut current = None;
+    for (pos, ch) in text.char_indices() {
+        // Close styles at line ends so each pasted line stands alone
+        if ch == '\n' {
+            if current.is_some() {
+                ansi.push_str("\x1b[0m");
+            }
+            ansi.push(ch);
+            current = None;
+            continue;
+        }
+        let style = (kinds[pos], found[pos]);
+        if current != Some(style) {
+            ansi.push_str("\x1b[0m");
+            ansi.push_str(&base);
+            if let Some(kind) = style.0 {
+                ansi.push_str(&sgr(theme.syntax(kind)));
+            }
+            if style.1 {
+                ansi.push_str("\x1b[7m");
+            }
+            current = Some(style);
+        }
+        ansi.push(ch);
+    }
+    ansi.push_str("\x1b[0m");
+    ansi
+}
+
+pub fn export_html(path: &str, buffer: &EditorBuffer, start: usize, end: usize, syntax: Option<&mut SyntaxTree>, theme: &Theme) -> io::Result<()> {
+    write(path, buffer_to_html(buffer, start, end, syntax, theme, path))
+}
@@ -0,0 +1,153 @@
+// src/fileio.rs
+
+use crate::buffer::{self, EditorBuffer};
+use crate::config::BackupConfig;
+use crate::crypt::Encryption;
+use crate::{loader, recovery, remote};
+use ropey::Rope;
+use std::io;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+/// A finished file operation.
+pub enum FileEvent {
+    Opened {
+        path: String,
+        result: io::Result<EditorBuffer>,
+    },
+    Saved {
+        /// Index of the buffer in the buffer list when the save was started.
+        buffer: usize,
+        path: String,
+        /// The text that was written.
+        rope: Rope,
+        /// The file was written encrypted, so no plain copy may be kept.
+        encrypted: bool,
+        autosave: bool,
+        result: io::Result<()>,
+    },
+}
+
+enum Message {
+    Progress(String, u64, u64),
+    Done(FileEvent),
+}
+
+/// Opens and saves files on worker threads so the main loop keeps drawing
+/// and reading keys. Saves work on a snapshot of the rope, which is cheap
+/// to clone.
+pub struct FileWorker {
+    tx: Sender<Message>,
+    rx: Receiver<Message>,
+    jobs: Vec<(String, JoinHandle<()>)>,
+    /// Path, bytes read and size of the file being opened.
+    pub progress: Option<(String, u64, u64)>,
+}
+
+/// Backs up the old file if configured, writes the new one and drops the
+/// swap file that is no longer needed.
+fn write(path: &str, backup: &BackupConfig, buffer: &EditorBuffer) -> io::Result<()> {
+    // Backups are of local files only
+    if backup.enabled && !remote::is_remote(path) {
+        buffer::backup_file(path, backup.directory.as_deref())?;
+    }
+    buffer::save_file(path, buffer)?;
+    recovery::discard(path);
+    Ok(())
+}
+
+impl FileWorker {
+    pub fn new() -> Self {
+        let (tx, rx) = channel();
+        FileWorker {
+            tx,
+            rx,
+            jobs: Vec::new(),
+            progress: None,
+        }
+    }
+
+    /// Whether `path` is still being opened or saved, or its result has not
+    /// been picked up by `poll` yet.
+    pub fn is_busy(&self, path: &str) -> bool {
+        self.jobs.iter().any(|(job_path, _)| job_path == path)
+    }
+
+    pub fn open(&mut self, path: &str) {
+        let tx = self.tx.clone();
+        let path = path.to_string();
+        let job_path = path.clone();
+        let handle = thread::spawn(move || {
+            let progress_tx = tx.clone();
+            let result = loader::load(&path, |done, total| {
+                let _ = progress_tx.send(Message::Progress(path.clone(), done, total));
+            });
+            let _ = tx.send(Message::Done(FileEvent::Opened { path, result }));
+        });
+        self.jobs.push((job_path, handle));
+    }
+
+    /// Decrypts `path` with `encryption`, a file that does not exist yet
+    /// opening empty.
+    pub fn open_encrypted(&mut self, path: &str, encryption: Encryption) {
+        let tx = self.tx.clone();
+        let path = path.to_string();
+        let job_path = path.clone();
+        let handle = thread::spawn(move || {
+            let result = buffer::open_encrypted(&path, encryption);
+            let _ = tx.send(Message::Done(FileEvent::Opened { path, result }));
+        });
+        self.jobs.push((job_path, handle));
+    }
+
+    /// Writes `snapshot` to `path`; `buffer` is its index in the buffer list.
+    pub fn save(&mut self, buffer: usize, path: &str, snapshot: EditorBuffer, backup: &BackupConfig, autosave: bool) {
+        let tx = self.tx.clone();
+        let path = path.to_string();
+        let job_path = path.clone();
+        let backup = backup.clone();
+        let handle = thread::spawn(move || {
+            let result = write(&path, &backup, &snapshot);
+            let encrypted = snapshot.encryption.is_some();
+            let _ = tx.send(Message::Done(FileEvent::Saved { buffer, path, rope: snapshot.rope, encrypted, autosave, result }));
+        });
+        self.jobs.push((job_path, handle));
+    }
+
+    /// Operations that finished since the last call.
+    pub fn poll(&mut self) -> Vec<FileEvent> {
+        // A job sends its result before it ends, so whatever finished by now
+        // has its result waiting in the channel
+        let finished: Vec<bool> = self.jobs.iter().map(|(_, handle)| handle.is_finished()).collect();
+        let mut events = Vec::new();
+        while let Ok(message) = self.rx.try_recv() {
+            match message {
+                Message::Progress(path, done, total) => self.progress = Some((path, done, total)),
+                Message::Done(event) => {
+                    if let FileEvent::Opened { path, .. } = &event
+                        && self.progress.as_ref().is_some_and(|(progress_path, ..)| progress_path == path)
+                    {
+                        self.progress = None;
+                    }
+                    events.push(event);
+                }
+            }
+        }
+        let mut finished = finished.into_iter();
+        self.jobs.retain(|_| !finished.next().unwrap_or(false));
+        events
+    }
+
+    /// Lets running saves finish, before the editor exits.
+    pub fn wait(&mut self) {
+        for (_, handle) in self.jobs.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Default for FileWorker {
+    fn default() -> Self {
+        FileWorker::new()
+    }
+}
@@ -0,0 +1,147 @@
+// src/fold.rs
+
+use crate::buffer::{EditOp, EditorBuffer};
+
+/// A manually created fold over whole lines, tracked in char indices so it
+/// can be shifted by edits above it.
+#[derive(Clone, Debug)]
+pub struct Fold {
+    /// Char index of the first char of the first folded line.
+    pub start: usize,
+    /// Char index just past the last folded line (including its newline).
+    pub end: usize,
+    pub closed: bool,
+}
+
+impl Fold {
+    pub fn start_line(&self, buffer: &EditorBuffer) -> usize {
+        buffer.char_to_line(self.start)
+    }
+
+    pub fn end_line(&self, buffer: &EditorBuffer) -> usize {
+        buffer.char_to_line(self.end.saturating_sub(1).max(self.start))
+    }
+}
+
+/// Sorted, non-overlapping set of folds for one buffer.
+pub struct FoldSet {
+    folds: Vec<Fold>,
+}
+
+impl FoldSet {
+    pub fn new() -> Self {
+        FoldSet { folds: Vec::new() }
+    }
+
+    pub fn folds(&self) -> &[Fold] {
+        &self.folds
+    }
+
+    /// Creates a closed fold over `start_line..=end_line`, replacing any folds it overlaps.
+    /// Returns false if the range is a single line.
+    pub fn add(&mut self, buffer: &EditorBuffer, start_line: usize, end_line: usize) -> bool {
+        if end_line <= start_line {
+            return false;
+        }
+        let start = buffer.line_to_char(start_line);
+        let end = if end_line + 1 < buffer.len_lines() {
+            buffer.line_to_char(end_line + 1)
+        } else {
+            buffer.len_chars()
+        };
+        self.folds.retain(|f| f.end <= start || f.start >= end);
+        let pos = self.folds.partition_point(|f| f.start < start);
+        self.folds.insert(pos, Fold { start, end, closed: true });
+        true
+    }
+
+    fn index_at_line(&self, buffer: &EditorBuffer, line: usize) -> Option<usize> {
+        self.folds
+            .iter()
+            .position(|f| f.start_line(buffer) <= line && line <= f.end_line(buffer))
+    }
+
+    /// Opens or closes the fold containing `line`. Returns false if there is none.
+    pub fn toggle(&mut self, buffer: &EditorBuffer, line: usize) -> bool {
+        match self.index_at_line(buffer, line) {
+            Some(i) => {
+                self.folds[i].closed = !self.folds[i].closed;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Deletes the fold containing `line`. Returns false if there is none.
+    pub fn remove(&mut self, buffer: &EditorBuffer, line: usize) -> bool {
+        match self.index_at_line(buffer, line) {
+            Some(i) => {
+                self.folds.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Shifts folds after an edit, dropping any fold the edit touched.
+    pub fn apply_edit(&mut self, op: &EditOp) {
+        let (idx, len, inserted) = match op {
+            EditOp::Insert { char_idx, content } => (*char_idx, content.chars().count(), true),
+            EditOp::Delete { char_idx, content } => (*char_idx, content.chars().count(), false),
+        };
+        self.folds.retain_mut(|f| {
+            let edit_end = if inserted { idx } else { idx + len };
+            if idx >= f.end {
+                true
+            } else if edit_end < f.start {
+                if inserted {
+                    f.start += len;
+                    f.end += len;
+                } else {
+                    f.start -= len;
+                    f.end -= len;
+                }
+                true
+            } else {
+                false
+            }
+        });
+    }
+
+    /// Returns the last line of the closed fold starting at `line`, if any.
+    pub fn closed_fold_end(&self, buffer: &EditorBuffer, line: usize) -> Option<usize> {
+        self.folds
+            .iter()
+            .find(|f| f.closed && f.start_line(buffer) == line)
+            .map(|f| f.end_line(buffer))
+    }
+
+    /// Returns the closed fold hiding `line`. The first line of a fold stays
+    /// visible as its placeholder, so it is never hidden.
+    pub fn hiding(&self, buffer: &EditorBuffer, line: usize) -> Option<&Fold> {
+        self.folds
+            .iter()
+            .find(|f| f.closed && f.start_line(buffer) < line && line <= f.end_line(buffer))
+    }
+
+    /// Maps `line` to the line actually displayed for it.
+    pub fn visible_line(&self, buffer: &EditorBuffer, line: usize) -> usize {
+        self.hiding(buffer, line).map_or(line, |f| f.start_line(buffer))
+    }
+
+    /// The visible line shown right after `line`.
+    pub fn next_visible_line(&self, buffer: &EditorBuffer, line: usize) -> usize {
+        self.closed_fold_end(buffer, line).unwrap_or(line) + 1
+    }
+
+    /// The visible line shown right before `line`.
+    pub fn prev_visible_line(&self, buffer: &EditorBuffer, line: usize) -> usize {
+        self.visible_line(buffer, line.saturating_sub(1))
+    }
+}
+
+impl Default for FoldSet {
+    fn default() -> Self {
+        FoldSet::new()
+    }
+}
@@ -0,0 +1,186 @@
+// src/git_gutter.rs
+
+use crate::signs::{Sign, PRIORITY_GIT};
+use crossterm::style::Color;
+use ropey::Rope;
+use similar::{DiffOp, TextDiff};
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Command;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The buffer is diffed again at most this often while it is being edited.
+const REDIFF_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Diffing gives up on the finer detail of files this slow to compare.
+const DIFF_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// How a buffer line differs from the committed version of the file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineChange {
+    Added,
+    Modified,
+    /// Lines were removed right below this one (above it on the first line).
+    Deleted,
+}
+
+impl LineChange {
+    pub fn sign(self) -> Sign {
+        let (glyph, color) = match self {
+            LineChange::Added => ('+', Color::Green),
+            LineChange::Modified => ('~', Color::Blue),
+            LineChange::Deleted => ('-', Color::Red),
+        };
+        Sign { glyph, color, priority: PRIORITY_GIT }
+    }
+}
+
+/// Work for the thread that reads committed files and diffs against them.
+enum Job {
+    Diff(String, Rope),
+    /// Read the committed versions again.
+    Forget,
+}
+
+/// Marks on the lines of the active buffer that differ from the file at git
+/// HEAD, kept up to date as it is edited. `git show` and the diffs run on
+/// a thread of their own; the markers follow a moment later.
+pub struct GitGutter {
+    jobs: Sender<Job>,
+    results: Receiver<(String, Vec<(usize, LineChange)>)>,
+    /// File and text last sent to be diffed.
+    requested: Option<(String, Rope)>,
+    /// The committed versions were forgotten, so even unchanged text is
+    /// diffed again.
+    stale: bool,
+    checked: Instant,
+    markers: Vec<(usize, LineChange)>,
+}
+
+impl GitGutter {
+    pub fn new() -> Self {
+        let (jobs, jobs_rx) = channel();
+        let (results_tx, results) = channel();
+        thread::spawn(move || diff_jobs(jobs_rx, results_tx));
+        GitGutter {
+            jobs,
+            results,
+            requested: None,
+            stale: false,
+            checked: Instant::now(),
+            markers: Vec::new(),
+        }
+    }
+
+    pub fn markers(&self) -> &[(usize, LineChange)] {
+        &self.markers
+    }
+
+    /// Takes in the markers diffed since the last call, and has `rope`
+    /// diffed against the committed version of `path` if either changed,
+    /// waiting `REDIFF_INTERVAL` between diffs of the same file.
+    pub fn update(&mut self, path: Option<&str>, rope: &Rope) {
+        let current = self.requested.as_ref().map(|(requested, _)| requested.as_str());
+        while let Ok((diffed, markers)) = self.results.try_recv() {
+            // Those of a file no longer shown are too late
+            if current == Some(diffed.as_str()) {
+                self.markers = markers;
+            }
+        }
+        let same_path = current == path;
+        if same_path && self.checked.elapsed() < REDIFF_INTERVAL {
+            return;
+        }
+        self.checked = Instant::now();
+        if same_path && !self.stale && self.requested.as_ref().is_some_and(|(_, requested)| requested == rope) {
+            return;
+        }
+        self.stale = false;
+        if !same_path {
+            self.markers.clear();
+        }
+        let Some(path) = path else {
+            self.requested = None;
+            return;
+        };
+        self.requested = Some((path.to_string(), rope.clone()));
+        let _ = self.jobs.send(Job::Diff(path.to_string(), rope.clone()));
+    }
+
+    /// Reads the committed versions again, after a commit or checkout
+    /// made outside the editor.
+    pub fn forget_heads(&mut self) {
+        let _ = self.jobs.send(Job::Forget);
+        self.stale = true;
+    }
+}
+
+impl Default for GitGutter {
+    fn default() -> Self {
+        GitGutter::new()
+    }
+}
+
+/// Runs the diffs asked for until the gutter goes away. Only the latest
+/// text waiting is diffed; the ones before it are already out of date.
+fn diff_jobs(jobs: Receiver<Job>, results: Sender<(String, Vec<(usize, LineChange)>)>) {
+    // Committed text of each file asked about; None when it is not tracked
+    let mut heads: HashMap<String, Option<String>> = HashMap::new();
+    while let Ok(job) = jobs.recv() {
+        let mut latest = None;
+        for job in std::iter::once(job).chain(jobs.try_iter()) {
+            match job {
+                Job::Diff(path, rope) => latest = Some((path, rope)),
+                Job::Forget => heads.clear(),
+            }
+        }
+        let Some((path, rope)) = latest else {
+            continue;
+        };
+        let head = heads.entry(path.clone()).or_insert_with(|| committed_text(&path));
+        let markers = match head {
+            Some(head) => changed_lines(head, &rope.to_string()),
+            None => Vec::new(),
+        };
+        if results.send((path, markers)).is_err() {
+            return;
+        }
+    }
+}
+
+/// The file at `path` as of HEAD, with line endings made LF as in buffers.
+/// None outside a repository or when the file is not committed.
+pub fn committed_text(path: &str) -> Option<String> {
+    let path = Path::new(path);
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let name = path.file_name()?.to_str()?;
+    let output = Command::new("git").arg("-C").arg(dir).arg("show").arg(format!("HEAD:./{}", name)).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).replace("\r\n", "\n"))
+}
+
+/// Markers for the lines of `text` that differ from `head`.
+fn changed_lines(head: &str, text: &str) -> Vec<(usize, LineChange)> {
+    let diff = TextDiff::configure().timeout(DIFF_TIMEOUT).diff_lines(head, text);
+    let mut markers = Vec::new();
+    for op in diff.ops() {
+        match *op {
+            DiffOp::Equal { .. } => {}
+            DiffOp::Delete { new_index, .. } => markers.push((new_index.saturating_sub(1), LineChange::Deleted)),
+            DiffOp::Insert { new_index, new_len, .. } => {
+                markers.extend((new_index..new_index + new_len).map(|line| (line, LineChange::Added)));
+            }
+            // Lines pair up as modified and the rest of the new side is added
+            DiffOp::Replace { old_len, new_index, new_len, .. } => {
+                markers.extend((new_index..new_index + new_len).map(|line| {
+                    (line, if line - new_index < old_len { LineChange::Modified } else { LineChange::Added })
+                }));
+            }
+        }
+    }
+    markers
+}
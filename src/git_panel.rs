@@ -0,0 +1,182 @@
+// src/git_panel.rs
+
+use std::env;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// A changed file as `git status` reports it.
+pub struct GitEntry {
+    /// Status in the index and in the work tree, e.g. 'M', 'A' or '?'.
+    pub staged: char,
+    pub unstaged: char,
+    /// Relative to the repository root.
+    pub path: String,
+}
+
+/// The changes in the repository around the working directory, to stage
+/// and commit without leaving the editor.
+pub struct GitPanel {
+    root: Option<PathBuf>,
+    pub entries: Vec<GitEntry>,
+    pub selected: usize,
+}
+
+/// Runs git in `dir`, returning its output or the first line of its complaint.
+fn git(dir: &Path, args: &[&str], input: Option<&str>) -> Result<String, String> {
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| format!("Could not run git: {}", err))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(input.unwrap_or_default().as_bytes());
+    }
+    let output = child.wait_with_output().map_err(|err| format!("Could not run git: {}", err))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(stderr.lines().next().unwrap_or("git failed").to_string());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+impl GitPanel {
+    pub fn new() -> Self {
+        GitPanel {
+            root: None,
+            entries: Vec::new(),
+            selected: 0,
+        }
+    }
+
+    fn root(&self) -> Result<&Path, String> {
+        self.root.as_deref().ok_or_else(|| "Not in a git repository".to_string())
+    }
+
+    /// Reads `git status` again, keeping the selection on the same file.
+    pub fn refresh(&mut self) -> Result<(), String> {
+        let root = git(Path::new("."), &["rev-parse", "--show-toplevel"], None)?;
+        let root = PathBuf::from(root.trim_end());
+        // NUL-separated so paths come unquoted
+        let status = git(&root, &["status", "--porcelain", "-z"], None)?;
+        let selected = self.entries.get(self.selected).map(|entry| entry.path.clone());
+        self.entries.clear();
+        let mut records = status.split('\0');
+        while let Some(record) = records.next() {
+            let mut chars = record.chars();
+            let (Some(staged), Some(unstaged), Some(' ')) = (chars.next(), chars.next(), chars.next()) else {
+                continue;
+            };
+            // Renames and copies are followed by the path they came from
+            if matches!(staged, 'R' | 'C') {
+                records.next();
+            }
+            self.entries.push(GitEntry { staged, unstaged, path: chars.as_str().to_string() });
+        }
+        self.selected = selected
+            .and_then(|path| self.entries.iter().position(|entry| entry.path == path))
+            .unwrap_or(0)
+            .min(self.entries.len().saturating_sub(1));
+        self.root = Some(root);
+        Ok(())
+    }
+
+    /// One row per changed file: the two status letters, then the path.
+    pub fn labels(&self) -> Vec<String> {
+        self.entries
+            .iter()
+            .map(|entry| format!("{}{} {}", entry.staged, entry.unstaged, entry.path))
+            .collect()
+    }
+
+    pub fn select_next(&mut self) {
+        if self.selected + 1 < self.entries.len() {
+            self.selected += 1;
+        }
+    }
+
+    pub fn select_prev(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    /// Selects the entry of the file at `path`, if it has changes.
+    pub fn select_path(&mut self, path: &str) {
+        let Ok(root) = self.root() else {
+            return;
+        };
+        let Ok(path) = Path::new(path).canonicalize() else {
+            return;
+        };
+        if let Some(index) = self.entries.iter().position(|entry| root.join(&entry.path).canonicalize().ok() == Some(path.clone())) {
+            self.selected = index;
+        }
+    }
+
+    /// The selected file, relative to the working directory when it is
+    /// below it so it matches the name it is open under.
+    pub fn selected_path(&self) -> Option<String> {
+        let path = self.root.as_ref()?.join(&self.entries.get(self.selected)?.path);
+        let relative = env::current_dir()
+            .ok()
+            .and_then(|dir| dir.canonicalize().ok())
+            .and_then(|dir| path.strip_prefix(dir).ok().map(Path::to_path_buf));
+        Some(relative.unwrap_or(path).to_string_lossy().into_owned())
+    }
+
+    /// Stages the selected file, or unstages it when all its changes
+    /// already are.
+    pub fn toggle_selected(&mut self) -> Result<(), String> {
+        let root = self.root()?.to_path_buf();
+        let Some(entry) = self.entries.get(self.selected) else {
+            return Ok(());
+        };
+        if entry.unstaged != ' ' {
+            git(&root, &["add", "--", &entry.path], None)?;
+        } else {
+            git(&root, &["restore", "--staged", "--", &entry.path], None)?;
+        }
+        self.refresh()
+    }
+
+    /// Where git keeps the message of the commit being written, which is
+    /// also the name of the buffer it is composed in.
+    pub fn message_path(&self) -> Result<String, String> {
+        let root = self.root()?;
+        let path = git(root, &["rev-parse", "--git-path", "COMMIT_EDITMSG"], None)?;
+        Ok(root.join(path.trim_end()).to_string_lossy().into_owned())
+    }
+
+    /// Starting text of the message buffer, listing what will be committed.
+    pub fn message_template(&self) -> String {
+        let mut template = String::from("\n# Write the commit message above and run :commit.\n# Lines starting with '#' are left out.\n#\n");
+        let staged: Vec<&GitEntry> = self.entries.iter().filter(|entry| !matches!(entry.staged, ' ' | '?')).collect();
+        if staged.is_empty() {
+            template.push_str("# Nothing is staged yet.\n");
+        } else {
+            template.push_str("# Changes to be committed:\n");
+            for entry in staged {
+                template.push_str(&format!("#   {} {}\n", entry.staged, entry.path));
+            }
+        }
+        template
+    }
+
+    /// Commits what is staged with `message`, comment lines removed.
+    /// Returns git's one-line summary of the new commit.
+    pub fn commit(&mut self, message: &str) -> Result<String, String> {
+        let root = self.root()?.to_path_buf();
+        let output = git(&root, &["commit", "--cleanup=strip", "--file=-"], Some(message))?;
+        let _ = self.refresh();
+        Ok(output.lines().next().unwrap_or("Committed").to_string())
+    }
+}
+
+impl Default for GitPanel {
+    fn default() -> Self {
+        GitPanel::new()
+    }
+}
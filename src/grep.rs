@@ -0,0 +1,102 @@
+// src/grep.rs
+
+use crate::hex;
+use crate::search::Search;
+use ignore::WalkBuilder;
+use std::fs;
+use std::path::Path;
+
+/// Hits kept; a search that finds more stops there.
+const MAX_HITS: usize = 1000;
+
+/// A line that matched.
+pub struct GrepHit {
+    pub path: String,
+    /// Zero-based line number.
+    pub line: usize,
+    /// The line without its indentation.
+    pub preview: String,
+}
+
+/// Matches of a search across the files under a directory, in file name
+/// order. Files left out by .gitignore and binary files are skipped.
+pub struct GrepResults {
+    pub hits: Vec<GrepHit>,
+    pub selected: usize,
+    /// The search stopped at `MAX_HITS`.
+    pub truncated: bool,
+}
+
+impl GrepResults {
+    pub fn new() -> Self {
+        GrepResults {
+            hits: Vec::new(),
+            selected: 0,
+            truncated: false,
+        }
+    }
+
+    /// Searches every text file under `root`, replacing the previous hits.
+    pub fn run(&mut self, root: &Path, search: &Search) {
+        self.hits.clear();
+        self.selected = 0;
+        self.truncated = false;
+        // .gitignore counts even outside a git checkout
+        let walker = WalkBuilder::new(root).require_git(false).sort_by_file_name(|a, b| a.cmp(b)).build();
+        for entry in walker.flatten() {
+            if !entry.file_type().is_some_and(|file_type| file_type.is_file()) {
+                continue;
+            }
+            let Some(path) = entry.path().to_str() else {
+                continue;
+            };
+            if hex::is_binary(path) {
+                continue;
+            }
+            // Files that are not UTF-8 fail to read and are left out
+            let Ok(text) = fs::read_to_string(path) else {
+                continue;
+            };
+            let shown = entry.path().strip_prefix(root).unwrap_or(entry.path()).to_string_lossy();
+            for (line, content) in text.lines().enumerate() {
+                if search.matches_in(content).next().is_none() {
+                    continue;
+                }
+                if self.hits.len() == MAX_HITS {
+                    self.truncated = true;
+                    return;
+                }
+                self.hits.push(GrepHit {
+                    path: shown.to_string(),
+                    line,
+                    preview: content.trim().to_string(),
+                });
+            }
+        }
+    }
+
+    /// "path:line: text" for each hit, with lines counted from 1.
+    pub fn labels(&self) -> Vec<String> {
+        self.hits.iter().map(|hit| format!("{}:{}: {}", hit.path, hit.line + 1, hit.preview)).collect()
+    }
+
+    pub fn select_next(&mut self) {
+        if self.selected + 1 < self.hits.len() {
+            self.selected += 1;
+        }
+    }
+
+    pub fn select_prev(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    pub fn selected_hit(&self) -> Option<&GrepHit> {
+        self.hits.get(self.selected)
+    }
+}
+
+impl Default for GrepResults {
+    fn default() -> Self {
+        GrepResults::new()
+    }
+}
@@ -0,0 +1,83 @@
+// src/hex.rs
+
+use crate::buffer::write_atomically;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+
+pub const BYTES_PER_ROW: usize = 16;
+
+/// How much of a file is looked at to tell binary from text.
+const SNIFF_LEN: usize = 8192;
+
+/// Whether `path` looks like a binary file: it has NUL bytes near the start
+/// and no byte order mark (UTF-16 text is full of NULs).
+pub fn is_binary(path: &str) -> bool {
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    let Ok(file) = File::open(path) else {
+        return false;
+    };
+    if file.take(SNIFF_LEN as u64).read_to_end(&mut head).is_err() {
+        return false;
+    }
+    encoding_rs::Encoding::for_bom(&head).is_none() && head.contains(&0)
+}
+
+/// A binary file shown as offset, hex and ASCII columns. Bytes are edited
+/// in place by typing hex digits over them.
+pub struct HexView {
+    pub path: String,
+    pub bytes: Vec<u8>,
+    /// Byte under the cursor.
+    pub cursor: usize,
+    /// First row shown on screen.
+    pub top: usize,
+    /// The high nibble of the cursor byte has been typed, the low one is next.
+    pub low_nibble: bool,
+    pub modified: bool,
+}
+
+impl HexView {
+    pub fn open(path: &str) -> io::Result<Self> {
+        Ok(HexView {
+            path: path.to_string(),
+            bytes: fs::read(path)?,
+            cursor: 0,
+            top: 0,
+            low_nibble: false,
+            modified: false,
+        })
+    }
+
+    /// Moves the cursor by `delta` bytes and keeps it within `rows` rows on screen.
+    pub fn move_by(&mut self, delta: isize, rows: usize) {
+        let last = self.bytes.len().saturating_sub(1);
+        self.cursor = self.cursor.saturating_add_signed(delta).min(last);
+        self.low_nibble = false;
+        let row = self.cursor / BYTES_PER_ROW;
+        if row < self.top {
+            self.top = row;
+        } else if row >= self.top + rows {
+            self.top = row + 1 - rows;
+        }
+    }
+
+    /// Types one hex digit over the cursor byte, moving on after the second.
+    pub fn type_digit(&mut self, digit: u8, rows: usize) {
+        let Some(byte) = self.bytes.get_mut(self.cursor) else {
+            return;
+        };
+        *byte = if self.low_nibble { (*byte & 0xF0) | digit } else { (*byte & 0x0F) | (digit << 4) };
+        self.modified = true;
+        if self.low_nibble {
+            self.move_by(1, rows);
+        } else {
+            self.low_nibble = true;
+        }
+    }
+
+    pub fn save(&mut self) -> io::Result<()> {
+        write_atomically(&self.path, |writer| writer.write_all(&self.bytes))?;
+        self.modified = false;
+        Ok(())
+    }
+}
@@ -0,0 +1,73 @@
+// src/highlight.rs
+
+use crate::buffer::EditorBuffer;
+use crate::fold::FoldSet;
+use crate::syntax::{HighlightKind, SyntaxTree};
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+/// Highlighted byte range within one line.
+pub type Span = (usize, usize, HighlightKind);
+
+/// Highlight spans per buffer line, computed from the syntax tree the first
+/// time a line is drawn and reused until an edit touches that line.
+pub struct HighlightCache {
+    lines: HashMap<usize, Vec<Span>>,
+}
+
+fn line_spans(syntax: &SyntaxTree, buffer: &EditorBuffer, line: usize) -> Vec<Span> {
+    let start = buffer.rope.line_to_byte(line);
+    let end = start + buffer.line(line).len_bytes();
+    syntax
+        .highlights(start, end)
+        .into_iter()
+        .map(|(span_start, span_end, kind)| (span_start.max(start) - start, span_end.min(end) - start, kind))
+        .filter(|(span_start, span_end, _)| span_start < span_end)
+        .collect()
+}
+
+impl HighlightCache {
+    pub fn new() -> Self {
+        HighlightCache { lines: HashMap::new() }
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+
+    /// Spans of `line`, highlighting it now if it is not cached.
+    pub fn spans(&mut self, syntax: &SyntaxTree, buffer: &EditorBuffer, line: usize) -> &[Span] {
+        self.lines.entry(line).or_insert_with(|| line_spans(syntax, buffer, line))
+    }
+
+    /// Call after an incremental re-parse with the line ranges it reported:
+    /// forgets those lines and marks the visible ones among them dirty.
+    pub fn invalidate(
+        &mut self,
+        changed: &[Range<usize>],
+        buffer: &EditorBuffer,
+        folds: &FoldSet,
+        viewport_row: usize,
+        rows: usize,
+        dirty_lines: &mut HashSet<usize>,
+    ) {
+        let is_changed = |line: &usize| changed.iter().any(|range| range.contains(line));
+        self.lines.retain(|line, _| !is_changed(line));
+        let mut line = viewport_row;
+        for _ in 0..rows {
+            if line >= buffer.len_lines() {
+                break;
+            }
+            if is_changed(&line) {
+                dirty_lines.insert(line);
+            }
+            line = folds.next_visible_line(buffer, line);
+        }
+    }
+}
+
+impl Default for HighlightCache {
+    fn default() -> Self {
+        HighlightCache::new()
+    }
+}
@@ -0,0 +1,125 @@
+// src/history.rs
+
+use crate::config::{data_dir, HistoryConfig};
+use ropey::Rope;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+const SNAPSHOT_NAME_FORMAT: &str = "%Y-%m-%d_%H-%M-%S-%3f";
+
+/// Timestamped copies of edited files, kept outside the project so they
+/// survive past the undo stacks (and the editor session).
+pub struct LocalHistory {
+    enabled: bool,
+    interval: Duration,
+    max_snapshots: usize,
+    last_check: Instant,
+    /// Content of the last snapshot (or of the file as loaded).
+    last_saved: Option<Rope>,
+    /// Snapshots of the current file, newest first.
+    pub snapshots: Vec<PathBuf>,
+    pub selected: usize,
+}
+
+/// Directory holding the snapshots of `path`, named after its absolute path.
+fn file_dir(path: &str) -> Option<PathBuf> {
+    let absolute = Path::new(path).canonicalize().ok()?;
+    let name = absolute.to_string_lossy().replace(['/', '\\', ':'], "%");
+    Some(data_dir()?.join("history").join(name))
+}
+
+impl LocalHistory {
+    pub fn new(config: &HistoryConfig) -> Self {
+        LocalHistory {
+            enabled: config.enabled,
+            interval: Duration::from_secs(config.interval_secs),
+            max_snapshots: config.max_snapshots,
+            last_check: Instant::now(),
+            last_saved: None,
+            snapshots: Vec::new(),
+            selected: 0,
+        }
+    }
+
+    /// Starts tracking a freshly loaded file; unedited content is not snapshotted.
+    pub fn reset(&mut self, rope: &Rope) {
+        self.last_saved = Some(rope.clone());
+        self.last_check = Instant::now();
+    }
+
+    /// Takes a snapshot when the interval has passed and the buffer changed.
+    pub fn tick(&mut self, path: Option<&str>, rope: &Rope) {
+        if !self.enabled || self.last_check.elapsed() < self.interval {
+            return;
+        }
+        self.last_check = Instant::now();
+        if let Some(path) = path {
+            let _ = self.snapshot(path, rope);
+        }
+    }
+
+    /// Writes `rope` as a new snapshot of `path` unless nothing changed since
+    /// the last one, then drops the oldest beyond the configured limit.
+    pub fn snapshot(&mut self, path: &str, rope: &Rope) -> io::Result<()> {
+        if !self.enabled || self.last_saved.as_ref() == Some(rope) {
+            return Ok(());
+        }
+        let dir = file_dir(path).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no history directory"))?;
+        fs::create_dir_all(&dir)?;
+        let name = format!("{}.txt", chrono::Local::now().format(SNAPSHOT_NAME_FORMAT));
+        rope.write_to(io::BufWriter::new(fs::File::create(dir.join(name))?))?;
+        self.last_saved = Some(rope.clone());
+
+        let mut existing = Self::list(&dir);
+        for old in existing.drain(self.max_snapshots.min(existing.len())..) {
+            let _ = fs::remove_file(old);
+        }
+        Ok(())
+    }
+
+    fn list(dir: &Path) -> Vec<PathBuf> {
+        let mut snapshots: Vec<PathBuf> = fs::read_dir(dir)
+            .map(|entries| entries.filter_map(|e| e.ok()).map(|e| e.path()).collect())
+            .unwrap_or_default();
+        // Names are timestamps, so sorting them sorts by age
+        snapshots.sort_by(|a, b| b.cmp(a));
+        snapshots
+    }
+
+    /// Reloads the snapshot list of `path` for the browser.
+    pub fn refresh(&mut self, path: &str) {
+        self.snapshots = file_dir(path).map(|dir| Self::list(&dir)).unwrap_or_default();
+        self.selected = 0;
+    }
+
+    pub fn labels(&self) -> Vec<String> {
+        self.snapshots
+            .iter()
+            .map(|path| {
+                let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+                chrono::NaiveDateTime::parse_from_str(&stem, SNAPSHOT_NAME_FORMAT)
+                    .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+                    .unwrap_or(stem)
+            })
+            .collect()
+    }
+
+    pub fn select_next(&mut self) {
+        if self.selected + 1 < self.snapshots.len() {
+            self.selected += 1;
+        }
+    }
+
+    pub fn select_prev(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    /// Label and content of the selected snapshot.
+    pub fn selected_snapshot(&self) -> Option<(String, String)> {
+        let path = self.snapshots.get(self.selected)?;
+        let text = fs::read_to_string(path).ok()?;
+        Some((self.labels().swap_remove(self.selected), text))
+    }
+}
@@ -1,43 +1,417 @@
 // src/input.rs
 
-use crossterm::event::{read, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
-use crate::buffer::EditorBuffer;
-use std::collections::HashSet;
+use crossterm::event::{poll, read, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseButton, MouseEventKind};
+use crate::buffer::{Case, EditorBuffer};
+use crate::search::Search;
 use std::io::Result;
+use std::time::{Duration, Instant};
+
+/// Incomplete chords are dropped when the next key takes longer than this.
+const CHORD_TIMEOUT: Duration = Duration::from_millis(1500);
+
+/// Command for a chord: Ctrl+A then a key, or Alt+y / Alt+p then a
+/// register name.
+fn chord_command(prefix: KeyEvent, key: KeyEvent) -> Option<Command> {
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+    if prefix.modifiers.contains(KeyModifiers::ALT) {
+        return match (prefix.code, key.code) {
+            (KeyCode::Char('y'), KeyCode::Char(name)) if name.is_ascii_lowercase() => Some(Command::YankToRegister(name)),
+            (KeyCode::Char('p'), KeyCode::Char(name)) if name.is_ascii_lowercase() => Some(Command::PasteFromRegister(name)),
+            _ => None,
+        };
+    }
+    match key.code {
+        KeyCode::Char('s') if ctrl => Some(Command::StartSaveFile),
+        KeyCode::Char('o') if ctrl => Some(Command::StartOpenFile),
+        KeyCode::Char('b') => Some(Command::RunBuild),
+        KeyCode::Char('l') => Some(Command::OpenErrorList),
+        KeyCode::Char('t') => Some(Command::OpenTaskPicker),
+        KeyCode::Char('d') => Some(Command::StartDiff),
+        KeyCode::Char('f') => Some(Command::FoldAllFunctions),
+        KeyCode::Char('g') => Some(Command::StartGrep),
+        KeyCode::Char('k') => Some(Command::DeleteLine),
+        KeyCode::Char('w') => Some(Command::ToggleInvisibles),
+        KeyCode::Char('u') => Some(Command::ShowUndoTree),
+        KeyCode::Char('n') => Some(Command::CycleLineNumbers),
+        KeyCode::Char('z') => Some(Command::AlignView(ViewAlign::Center)),
+        KeyCode::Char('e') => Some(Command::ToggleFileTree),
+        KeyCode::Char('v') => Some(Command::OpenGitPanel),
+        KeyCode::Up => Some(Command::AlignView(ViewAlign::Top)),
+        KeyCode::Down => Some(Command::AlignView(ViewAlign::Bottom)),
+        KeyCode::Char('h') => Some(Command::RunEx("history".to_string())),
+        KeyCode::Char('m') => Some(Command::RunEx("merge".to_string())),
+        KeyCode::Char('i') => Some(Command::RunEx("stats".to_string())),
+        KeyCode::Char('-') => Some(Command::SplitHorizontal),
+        KeyCode::Char('|') => Some(Command::SplitVertical),
+        KeyCode::Char('o') => Some(Command::FocusNextPane),
+        KeyCode::Char('c') => Some(Command::ClosePane),
+        _ => None,
+    }
+}
+
+/// Human-readable name of a key, e.g. "Ctrl+A" or "Alt+x".
+fn key_name(key: &KeyEvent) -> String {
+    let mut name = String::new();
+    if key.modifiers.contains(KeyModifiers::CONTROL) {
+        name.push_str("Ctrl+");
+    }
+    if key.modifiers.contains(KeyModifiers::ALT) {
+        name.push_str("Alt+");
+    }
+    match key.code {
+        KeyCode::Char(c) if key.modifiers.contains(KeyModifiers::CONTROL) => name.push(c.to_ascii_uppercase()),
+        KeyCode::Char(c) => name.push(c),
+        code => name.push_str(&format!("{:?}", code)),
+    }
+    name
+}
+
+/// Where `AlignView` puts the cursor line on screen.
+#[derive(Debug, Clone, Copy)]
+pub enum ViewAlign {
+    Top,
+    Center,
+    Bottom,
+}
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum InputMode {
     Editing,
     Finding,
+    /// Replace prompt: first the pattern, then the replacement text.
+    ReplacePattern,
+    ReplaceWith,
+    /// Asking about each match in turn.
+    ReplaceConfirm,
+    /// Search across the files under the working directory, then its results.
+    GrepPattern,
+    GrepResults,
+    UndoTree,
+    /// The quickfix list of the last build.
+    ErrorList,
+    /// The file tree sidebar has the keyboard.
+    FileTree,
     EnteringFileNameOpen,
     EnteringFileNameSave,
+    EnteringFileNameExport,
+    EnteringDiffFiles,
+    /// The passphrase or key of an encrypted file being opened.
+    EnteringSecret,
+    EnteringCommand,
+    Diff,
+    TaskPicker,
+    TaskOutput,
+    HistoryBrowser,
+    /// Changed files listed by `git status`, to stage before committing.
+    GitPanel,
+    Completion,
+    BufferPicker,
+    RecoverPrompt,
+    DiskChangedPrompt,
+    Hex,
 }
 
 #[derive(Debug)]
 pub enum Command {
     Quit,
     InsertChar(char),
+    /// Text put in at the cursor in place of the selection, as a paste
+    /// does; no key is bound to it.
+    InsertText(String),
     MoveLeft,
     MoveRight,
     MoveUp,
     MoveDown,
+    SelectLeft,
+    SelectRight,
+    SelectUp,
+    SelectDown,
+    /// Ctrl+Left/Right, by words rather than chars.
+    MoveWordLeft,
+    MoveWordRight,
+    SelectWordLeft,
+    SelectWordRight,
+    /// Home goes to the first non-blank char, or to column 0 when already there.
+    MoveHome,
+    MoveEnd,
+    /// Ctrl+Home/End, or Alt+< and Alt+> where the terminal eats those.
+    MoveDocStart,
+    MoveDocEnd,
+    PageUp,
+    PageDown,
     Backspace,
+    /// Delete: removes the char under the cursor, joining lines at a line end.
+    DeleteForward,
+    /// Ctrl+Backspace and Ctrl+Delete, to the word boundary as Ctrl+Left/Right finds it.
+    DeleteWordBackward,
+    DeleteWordForward,
+    /// Ctrl+K: cuts to the end of the line, or the line break when already there.
+    KillLine,
+    /// Removes the cursor line with its line break.
+    DeleteLine,
+    /// Copies the cursor line below itself, or the selection after itself.
+    DuplicateLine,
+    /// Alt+Up/Down: swaps the cursor line, or the selected lines, with the one above or below.
+    MoveLineUp,
+    MoveLineDown,
+    /// Alt+U, Alt+L, Alt+C and Alt+~ on the selection or the word under the cursor.
+    ChangeCase(Case),
+    /// Tab and Shift+Tab: every selected line when the selection spans
+    /// several, else the cursor line (Tab then just inserts at the cursor).
+    Indent,
+    Dedent,
+    /// Draws spaces, tabs and line breaks as ·, → and ¶.
+    ToggleInvisibles,
+    ToggleSoftWrap,
+    CycleLineNumbers,
+    /// Ctrl+A z, Ctrl+A Up and Ctrl+A Down: scroll so the cursor line is at
+    /// the center, top or bottom of the screen, leaving the cursor where it is.
+    AlignView(ViewAlign),
     InsertNewline,
     Undo,
     Redo,
     StartFind,
     ConfirmFind,
+    /// Up and Down in the find prompt, through earlier search terms.
+    FindHistoryOlder,
+    FindHistoryNewer,
+    SearchWordUnderCursor,
+    /// Drops the search, whose matches stay highlighted until then.
+    ClearHighlights,
+    StartReplace,
+    ConfirmReplacePattern,
+    ReplaceNext,
+    ReplaceAll,
+    /// Interactive replace and its answers: yes, no, all the rest, quit.
+    StartReplaceEach,
+    ReplaceYes,
+    ReplaceNo,
+    ReplaceRest,
+    StopReplace,
+    StartGrep,
+    ConfirmGrep,
+    GrepSelectNext,
+    GrepSelectPrev,
+    /// Opens the file of the selected result at its line.
+    OpenGrepResult,
+    CloseGrepResults,
+    /// Lists every state the undo history went through, branches included.
+    ShowUndoTree,
+    UndoTreeSelectNext,
+    UndoTreeSelectPrev,
+    /// Brings the text to the state picked in the undo tree.
+    JumpToUndoState,
+    CloseUndoTree,
+    /// Ctrl+A e: shows the file tree and gives it the keyboard, or hides it
+    /// once it has the keyboard.
+    ToggleFileTree,
+    FileTreeSelectNext,
+    FileTreeSelectPrev,
+    /// Opens the selected file, or opens or closes the selected folder.
+    FileTreeOpen,
+    FileTreeExpand,
+    FileTreeCollapse,
+    /// Gives the keyboard back to the text, leaving the tree shown.
+    LeaveFileTree,
+    ToggleFold,
+    RemoveFold,
+    FoldAllFunctions,
     StartOpenFile,
     ConfirmOpenFile,
+    /// Saves to the buffer's own path, asking for one if it has none.
+    SaveFile,
     StartSaveFile,
     ConfirmSaveFile,
+    StartExportHtml,
+    ConfirmExportHtml,
+    CopyStyled,
+    Copy,
+    Cut,
+    Paste,
+    YankToRegister(char),
+    PasteFromRegister(char),
+    CycleKillRing,
+    StartDiff,
+    ConfirmDiff,
+    DiffScrollUp,
+    DiffScrollDown,
+    DiffNextHunk,
+    DiffPrevHunk,
+    CloseDiff,
+    RunBuild,
+    OpenErrorList,
+    /// Jumps to the next or previous entry of the quickfix list.
+    NextError,
+    PrevError,
+    ErrorSelectNext,
+    ErrorSelectPrev,
+    OpenSelectedError,
+    CloseErrorList,
+    ScrollUp,
+    ScrollDown,
+    OpenTaskPicker,
+    TaskSelectNext,
+    TaskSelectPrev,
+    RunSelectedTask,
+    RerunTask,
+    TaskScrollUp,
+    TaskScrollDown,
+    KillTask,
+    CloseTaskPanel,
+    StartCommandLine,
+    RunEx(String),
+    HistorySelectNext,
+    HistorySelectPrev,
+    DiffHistorySnapshot,
+    RestoreHistorySnapshot,
+    CloseHistory,
+    OpenGitPanel,
+    GitSelectNext,
+    GitSelectPrev,
+    /// Stages the selected file, or unstages it if nothing is left to stage.
+    GitToggleStage,
+    GitOpenFile,
+    GitRefresh,
+    CloseGitPanel,
+    TriggerCompletion,
+    CompletionNext,
+    CompletionPrev,
+    AcceptCompletion,
+    CancelCompletion,
+    OpenBufferPicker,
+    NextBuffer,
+    BufferSelectNext,
+    BufferSelectPrev,
+    SwitchToSelectedBuffer,
+    CloseBufferPicker,
+    SplitHorizontal,
+    SplitVertical,
+    FocusNextPane,
+    ClosePane,
+    /// Left click at a screen cell.
+    MouseDown { column: u16, row: u16 },
+    /// Mouse moved with the left button held.
+    MouseDrag { column: u16, row: u16 },
+    /// Mouse wheel over a screen cell.
+    WheelUp { column: u16, row: u16 },
+    WheelDown { column: u16, row: u16 },
+    /// Loads the swap file found when the buffer was opened.
+    RecoverSwap,
+    DiscardSwap,
+    /// Answers to the prompt for the secret of an encrypted file.
+    ConfirmSecret,
+    CancelSecret,
+    /// The terminal window got the focus back.
+    FocusGained,
+    /// A file opened in the background has finished loading.
+    OpenLoadedFile,
+    /// A file another rusty asked this one to open, with `--remote`.
+    OpenRequested(String),
+    /// The terminal was resized or a session reattached: paint it all again.
+    Redraw,
+    /// A key a plugin took over, leaving the editor nothing to do.
+    KeyHandled,
+    /// Answers to the prompt about a file that changed on disk.
+    ReloadFromDisk,
+    OverwriteFile,
+    DiffWithDisk,
+    HexLeft,
+    HexRight,
+    HexUp,
+    HexDown,
+    HexPageUp,
+    HexPageDown,
+    /// A hex digit typed over the byte under the cursor.
+    HexDigit(u8),
+    SaveHex,
+    CloseHex,
+}
+
+impl Command {
+    /// Whether the command edits the buffer or writes it to disk.
+    pub fn modifies_buffer(&self) -> bool {
+        matches!(
+            self,
+            Command::InsertChar(_)
+                | Command::InsertText(_)
+                | Command::Backspace
+                | Command::DeleteForward
+                | Command::DeleteWordBackward
+                | Command::DeleteWordForward
+                | Command::KillLine
+                | Command::DeleteLine
+                | Command::DuplicateLine
+                | Command::MoveLineUp
+                | Command::MoveLineDown
+                | Command::ChangeCase(_)
+                | Command::Indent
+                | Command::Dedent
+                | Command::InsertNewline
+                | Command::Undo
+                | Command::Redo
+                | Command::JumpToUndoState
+                | Command::StartReplace
+                | Command::ReplaceNext
+                | Command::ReplaceAll
+                | Command::StartReplaceEach
+                | Command::Cut
+                | Command::Paste
+                | Command::PasteFromRegister(_)
+                | Command::CycleKillRing
+                | Command::TriggerCompletion
+                | Command::AcceptCompletion
+                | Command::RestoreHistorySnapshot
+                | Command::RecoverSwap
+                | Command::ReloadFromDisk
+                | Command::HexDigit(_)
+                | Command::SaveHex
+                | Command::OverwriteFile
+                | Command::SaveFile
+                | Command::StartSaveFile
+                | Command::ConfirmSaveFile
+        )
+    }
+
+    /// Whether the selection stays once the command is done: folding,
+    /// exporting and copying act on it, and moving lines, changing case
+    /// and indenting leave it on the text they changed.
+    pub fn keeps_selection(&self) -> bool {
+        matches!(
+            self,
+            Command::ToggleFold
+                | Command::MoveLineUp
+                | Command::MoveLineDown
+                | Command::ChangeCase(_)
+                | Command::Indent
+                | Command::Dedent
+                | Command::AlignView(_)
+                | Command::StartExportHtml
+                | Command::ConfirmExportHtml
+                | Command::CopyStyled
+                | Command::Copy
+                | Command::YankToRegister(_)
+        )
+    }
 }
 
 pub struct InputHandler {
     pub mode: InputMode,
     pub filename_input: String,
+    /// Encrypted file the secret being typed is for, and whether it is
+    /// shown masked.
+    secret_for: String,
+    mask_secret: bool,
     pub find_input: String,
-    pub confirmed_find_term: Option<String>,
+    /// Whether the find prompt takes a regular expression instead of literal text.
+    pub find_regex: bool,
+    pub search: Option<Search>,
+    /// First key of a chord waiting for its second key, and when it was pressed.
+    pending_chord: Option<(KeyEvent, Instant)>,
+    /// Set when the last chord did not match anything.
+    pub chord_error: Option<String>,
+    /// Name of the key typed while editing that the last command came
+    /// from, offered to plugins.
+    pub last_key: Option<String>,
 }
 
 impl InputHandler {
@@ -45,8 +419,14 @@ impl InputHandler {
         InputHandler {
             mode: InputMode::Editing,
             filename_input: String::new(),
+            secret_for: String::new(),
+            mask_secret: false,
             find_input: String::new(),
-            confirmed_find_term: None,
+            find_regex: false,
+            search: None,
+            pending_chord: None,
+            chord_error: None,
+            last_key: None,
         }
     }
 
@@ -54,52 +434,223 @@ impl InputHandler {
         &self.mode
     }
 
+    /// Short label for the current mode, shown in the status line.
+    pub fn mode_name(&self) -> &'static str {
+        match self.mode {
+            InputMode::Editing => "EDIT",
+            InputMode::Finding => "FIND",
+            InputMode::ReplacePattern | InputMode::ReplaceWith | InputMode::ReplaceConfirm => "REPLACE",
+            InputMode::GrepPattern | InputMode::GrepResults => "GREP",
+            InputMode::UndoTree => "UNDO",
+            InputMode::ErrorList => "ERRORS",
+            InputMode::FileTree => "TREE",
+            InputMode::EnteringFileNameOpen => "OPEN",
+            InputMode::EnteringFileNameSave => "SAVE",
+            InputMode::EnteringFileNameExport => "EXPORT",
+            InputMode::EnteringDiffFiles | InputMode::Diff => "DIFF",
+            InputMode::EnteringSecret => "SECRET",
+            InputMode::EnteringCommand => "CMD",
+            InputMode::HistoryBrowser => "HISTORY",
+            InputMode::GitPanel => "GIT",
+            InputMode::Completion => "COMPLETE",
+            InputMode::BufferPicker => "BUFFERS",
+            InputMode::RecoverPrompt => "RECOVER",
+            InputMode::DiskChangedPrompt => "CHANGED",
+            InputMode::Hex => "HEX",
+            InputMode::TaskPicker | InputMode::TaskOutput => "TASKS",
+        }
+    }
+
+    /// Keys typed so far of an incomplete chord, for the status line.
+    pub fn pending_keys(&self) -> String {
+        self.pending_chord.as_ref().map(|(key, _)| format!("{} …", key_name(key))).unwrap_or_default()
+    }
+
+    /// Whether more input is already waiting to be read.
+    pub fn input_pending(&self) -> Result<bool> {
+        poll(Duration::ZERO)
+    }
+
+    /// Gives up on a pending chord whose second key did not come in time.
+    pub fn expire_chord(&mut self) {
+        if let Some((_, started)) = self.pending_chord
+            && started.elapsed() >= CHORD_TIMEOUT
+        {
+            self.pending_chord = None;
+        }
+    }
+
     /// Reads and parses command input from terminal.
+    /// Blocks until an event arrives; see `wait_for_input`.
     /// Returns Some(Command) if an actionable command is parsed.
     pub fn process_input(&mut self) -> Result<Option<Command>> {
-        while let Event::Key(key_event) = read()? {
-            if key_event.kind != KeyEventKind::Press {
-                continue;
-            }
+        self.last_key = None;
+        loop {
+            let key_event = match read()? {
+                Event::Key(key_event) if key_event.kind == KeyEventKind::Press => key_event,
+                Event::Key(_) => continue,
+                // The mouse only works on the text and the file tree
+                Event::Mouse(mouse) if matches!(self.mode, InputMode::Editing | InputMode::FileTree) => match mouse.kind {
+                    MouseEventKind::Down(MouseButton::Left) => {
+                        return Ok(Some(Command::MouseDown { column: mouse.column, row: mouse.row }));
+                    }
+                    MouseEventKind::Drag(MouseButton::Left) => {
+                        return Ok(Some(Command::MouseDrag { column: mouse.column, row: mouse.row }));
+                    }
+                    MouseEventKind::ScrollUp => return Ok(Some(Command::WheelUp { column: mouse.column, row: mouse.row })),
+                    MouseEventKind::ScrollDown => return Ok(Some(Command::WheelDown { column: mouse.column, row: mouse.row })),
+                    _ => continue,
+                },
+                Event::Mouse(_) => continue,
+                Event::FocusGained if self.mode == InputMode::Editing => return Ok(Some(Command::FocusGained)),
+                Event::Resize(..) => return Ok(Some(Command::Redraw)),
+                _ => return Ok(None),
+            };
             match self.mode {
+                InputMode::Editing if self.pending_chord.is_some() => {
+                    let (prefix, _) = self.pending_chord.take().unwrap();
+                    let command = chord_command(prefix, key_event);
+                    if command.is_none() {
+                        self.chord_error = Some(format!("{} {} is not bound", key_name(&prefix), key_name(&key_event)));
+                    }
+                    return Ok(command);
+                }
                 InputMode::Editing => {
+                    self.last_key = Some(key_name(&key_event));
+                    if key_event.modifiers.contains(KeyModifiers::ALT) {
+                        match key_event.code {
+                            KeyCode::Char('x') => return Ok(Some(Command::StartCommandLine)),
+                            KeyCode::Char('b') => return Ok(Some(Command::RunBuild)),
+                            KeyCode::Char('t') => return Ok(Some(Command::OpenTaskPicker)),
+                            KeyCode::Char('v') => return Ok(Some(Command::CycleKillRing)),
+                            KeyCode::Down if key_event.modifiers.contains(KeyModifiers::SHIFT) => {
+                                return Ok(Some(Command::DuplicateLine));
+                            }
+                            KeyCode::Up => return Ok(Some(Command::MoveLineUp)),
+                            KeyCode::Down => return Ok(Some(Command::MoveLineDown)),
+                            KeyCode::Char('u') => return Ok(Some(Command::ChangeCase(Case::Upper))),
+                            KeyCode::Char('l') => return Ok(Some(Command::ChangeCase(Case::Lower))),
+                            KeyCode::Char('c') => return Ok(Some(Command::ChangeCase(Case::Title))),
+                            KeyCode::Char('~') => return Ok(Some(Command::ChangeCase(Case::Toggle))),
+                            KeyCode::Char('z') => return Ok(Some(Command::ToggleSoftWrap)),
+                            KeyCode::Char('<') => return Ok(Some(Command::MoveDocStart)),
+                            KeyCode::Char('>') => return Ok(Some(Command::MoveDocEnd)),
+                            // Terminals that send Ctrl+Backspace as a plain Backspace
+                            KeyCode::Backspace => return Ok(Some(Command::DeleteWordBackward)),
+                            KeyCode::Char('y') | KeyCode::Char('p') => {
+                                self.pending_chord = Some((key_event, Instant::now()));
+                                return Ok(None);
+                            }
+                            _ => {}
+                        }
+                    }
+                    if key_event.modifiers.contains(KeyModifiers::CONTROL | KeyModifiers::SHIFT) {
+                        match key_event.code {
+                            KeyCode::Up => return Ok(Some(Command::ScrollUp)),
+                            KeyCode::Down => return Ok(Some(Command::ScrollDown)),
+                            KeyCode::Left => return Ok(Some(Command::SelectWordLeft)),
+                            KeyCode::Right => return Ok(Some(Command::SelectWordRight)),
+                            // Save As; terminals that cannot tell Ctrl+Shift+S
+                            // from Ctrl+S still have Ctrl+A Ctrl+S
+                            KeyCode::Char('s') | KeyCode::Char('S') => return Ok(Some(Command::StartSaveFile)),
+                            // Also Ctrl+A g
+                            KeyCode::Char('f') | KeyCode::Char('F') => return Ok(Some(Command::StartGrep)),
+                            // Also Ctrl+A k
+                            KeyCode::Char('k') | KeyCode::Char('K') => return Ok(Some(Command::DeleteLine)),
+                            _ => {}
+                        }
+                    }
                     if key_event.modifiers.contains(KeyModifiers::CONTROL) {
                         match key_event.code {
                             KeyCode::Char('q') => return Ok(Some(Command::Quit)),
                             KeyCode::Char('z') => return Ok(Some(Command::Undo)),
                             KeyCode::Char('y') => return Ok(Some(Command::Redo)),
                             KeyCode::Char('f') => return Ok(Some(Command::StartFind)),
+                            KeyCode::Char('h') => return Ok(Some(Command::StartReplace)),
+                            KeyCode::Char('n') => return Ok(Some(Command::SearchWordUnderCursor)),
+                            KeyCode::Char('k') => return Ok(Some(Command::KillLine)),
+                            KeyCode::Char('d') => return Ok(Some(Command::DuplicateLine)),
+                            KeyCode::Char('t') => return Ok(Some(Command::ToggleFold)),
+                            KeyCode::Char('e') => return Ok(Some(Command::RemoveFold)),
+                            KeyCode::Char('g') => return Ok(Some(Command::FoldAllFunctions)),
+                            KeyCode::Char('p') => return Ok(Some(Command::StartExportHtml)),
+                            KeyCode::Char('r') => return Ok(Some(Command::CopyStyled)),
+                            KeyCode::Char('c') => return Ok(Some(Command::Copy)),
+                            KeyCode::Char('x') => return Ok(Some(Command::Cut)),
+                            KeyCode::Char('v') => return Ok(Some(Command::Paste)),
+                            KeyCode::Char('w') => return Ok(Some(Command::StartDiff)),
                             KeyCode::Char('o') => return Ok(Some(Command::StartOpenFile)),
-                            KeyCode::Char('s') => return Ok(Some(Command::StartSaveFile)),
-                            KeyCode::Left => return Ok(Some(Command::MoveLeft)),
-                            KeyCode::Right => return Ok(Some(Command::MoveRight)),
+                            KeyCode::Char('s') => return Ok(Some(Command::SaveFile)),
+                            KeyCode::Char('l') => return Ok(Some(Command::StartCommandLine)),
+                            KeyCode::Char(' ') => return Ok(Some(Command::TriggerCompletion)),
+                            KeyCode::Char('b') => return Ok(Some(Command::OpenBufferPicker)),
+                            KeyCode::Tab => return Ok(Some(Command::NextBuffer)),
+                            KeyCode::Char('a') => {
+                                self.pending_chord = Some((key_event, Instant::now()));
+                                return Ok(None);
+                            }
+                            KeyCode::Backspace => return Ok(Some(Command::DeleteWordBackward)),
+                            KeyCode::Delete => return Ok(Some(Command::DeleteWordForward)),
+                            KeyCode::Home => return Ok(Some(Command::MoveDocStart)),
+                            KeyCode::End => return Ok(Some(Command::MoveDocEnd)),
+                            KeyCode::Left => return Ok(Some(Command::MoveWordLeft)),
+                            KeyCode::Right => return Ok(Some(Command::MoveWordRight)),
                             KeyCode::Up => return Ok(Some(Command::MoveUp)),
                             KeyCode::Down => return Ok(Some(Command::MoveDown)),
                             _ => {}
                         }
                     }
+                    if key_event.modifiers.contains(KeyModifiers::SHIFT) {
+                        match key_event.code {
+                            KeyCode::Left => return Ok(Some(Command::SelectLeft)),
+                            KeyCode::Right => return Ok(Some(Command::SelectRight)),
+                            KeyCode::Up => return Ok(Some(Command::SelectUp)),
+                            KeyCode::Down => return Ok(Some(Command::SelectDown)),
+                            _ => {}
+                        }
+                    }
                     match key_event.code {
+                        KeyCode::Esc => return Ok(Some(Command::ClearHighlights)),
+                        KeyCode::F(5) => return Ok(Some(Command::RunBuild)),
+                        KeyCode::F(6) => return Ok(Some(Command::OpenTaskPicker)),
+                        KeyCode::F(7) => {
+                            self.mode = InputMode::TaskOutput;
+                            return Ok(Some(Command::RerunTask));
+                        }
+                        KeyCode::F(8) if key_event.modifiers.contains(KeyModifiers::SHIFT) => return Ok(Some(Command::PrevError)),
+                        KeyCode::F(8) => return Ok(Some(Command::NextError)),
+                        KeyCode::Left => return Ok(Some(Command::MoveLeft)),
+                        KeyCode::Right => return Ok(Some(Command::MoveRight)),
+                        KeyCode::Up => return Ok(Some(Command::MoveUp)),
+                        KeyCode::Down => return Ok(Some(Command::MoveDown)),
+                        KeyCode::Home => return Ok(Some(Command::MoveHome)),
+                        KeyCode::End => return Ok(Some(Command::MoveEnd)),
+                        KeyCode::PageUp => return Ok(Some(Command::PageUp)),
+                        KeyCode::PageDown => return Ok(Some(Command::PageDown)),
                         KeyCode::Backspace => return Ok(Some(Command::Backspace)),
+                        KeyCode::Delete => return Ok(Some(Command::DeleteForward)),
+                        KeyCode::Tab => return Ok(Some(Command::Indent)),
+                        KeyCode::BackTab => return Ok(Some(Command::Dedent)),
                         KeyCode::Enter => return Ok(Some(Command::InsertNewline)),
                         KeyCode::Char(c) => return Ok(Some(Command::InsertChar(c))),
                         _ => {}
                     }
                 }
-                InputMode::Finding => {
+                InputMode::Finding | InputMode::ReplacePattern | InputMode::GrepPattern => {
                     match key_event.code {
                         KeyCode::Esc => {
-                            self.confirmed_find_term = None;
+                            self.search = None;
                             self.mode = InputMode::Editing;
                             return Ok(None);
                         }
-                        KeyCode::Enter => {
-                            if !self.find_input.is_empty() {
-                                self.confirmed_find_term = Some(self.find_input.clone());
-                            } else {
-                                self.confirmed_find_term = None;
-                            }
-                            self.mode = InputMode::Editing;
-                            return Ok(Some(Command::ConfirmFind));
+                        KeyCode::Enter if self.mode == InputMode::ReplacePattern => return Ok(Some(Command::ConfirmReplacePattern)),
+                        KeyCode::Enter if self.mode == InputMode::GrepPattern => return Ok(Some(Command::ConfirmGrep)),
+                        KeyCode::Enter => return Ok(Some(Command::ConfirmFind)),
+                        KeyCode::Up => return Ok(Some(Command::FindHistoryOlder)),
+                        KeyCode::Down => return Ok(Some(Command::FindHistoryNewer)),
+                        KeyCode::Char('r') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                            self.find_regex = !self.find_regex;
+                            return Ok(None);
                         }
                         KeyCode::Backspace => {
                             self.find_input.pop();
@@ -112,6 +663,37 @@ impl InputHandler {
                         _ => {}
                     }
                 }
+                // The matches stay highlighted after the prompt closes
+                InputMode::ReplaceWith => match key_event.code {
+                    KeyCode::Esc => {
+                        self.mode = InputMode::Editing;
+                        return Ok(None);
+                    }
+                    KeyCode::Enter => return Ok(Some(Command::ReplaceNext)),
+                    KeyCode::Char('a') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                        self.mode = InputMode::Editing;
+                        return Ok(Some(Command::ReplaceAll));
+                    }
+                    KeyCode::Char('e') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                        return Ok(Some(Command::StartReplaceEach));
+                    }
+                    KeyCode::Backspace => {
+                        self.filename_input.pop();
+                        return Ok(None);
+                    }
+                    KeyCode::Char(c) => {
+                        self.filename_input.push(c);
+                        return Ok(None);
+                    }
+                    _ => {}
+                },
+                InputMode::ReplaceConfirm => match key_event.code {
+                    KeyCode::Char('y') => return Ok(Some(Command::ReplaceYes)),
+                    KeyCode::Char('n') => return Ok(Some(Command::ReplaceNo)),
+                    KeyCode::Char('a') => return Ok(Some(Command::ReplaceRest)),
+                    KeyCode::Char('q') | KeyCode::Esc => return Ok(Some(Command::StopReplace)),
+                    _ => {}
+                },
                 InputMode::EnteringFileNameOpen => match key_event.code {
                     KeyCode::Esc => {
                         self.mode = InputMode::Editing;
@@ -150,15 +732,349 @@ impl InputHandler {
                     }
                     _ => {}
                 },
+                InputMode::EnteringSecret => match key_event.code {
+                    KeyCode::Esc => {
+                        self.mode = InputMode::Editing;
+                        self.filename_input.clear();
+                        return Ok(Some(Command::CancelSecret));
+                    }
+                    KeyCode::Enter => {
+                        self.mode = InputMode::Editing;
+                        return Ok(Some(Command::ConfirmSecret));
+                    }
+                    KeyCode::Backspace => {
+                        self.filename_input.pop();
+                        return Ok(None);
+                    }
+                    KeyCode::Char(c) => {
+                        self.filename_input.push(c);
+                        return Ok(None);
+                    }
+                    _ => {}
+                },
+                InputMode::EnteringDiffFiles => match key_event.code {
+                    KeyCode::Esc => {
+                        self.mode = InputMode::Editing;
+                        return Ok(None);
+                    }
+                    KeyCode::Enter => {
+                        self.mode = InputMode::Editing;
+                        return Ok(Some(Command::ConfirmDiff));
+                    }
+                    KeyCode::Backspace => {
+                        self.filename_input.pop();
+                        return Ok(None);
+                    }
+                    KeyCode::Char(c) => {
+                        self.filename_input.push(c);
+                        return Ok(None);
+                    }
+                    _ => {}
+                },
+                InputMode::EnteringCommand => match key_event.code {
+                    KeyCode::Esc => {
+                        self.mode = InputMode::Editing;
+                        return Ok(None);
+                    }
+                    KeyCode::Enter => {
+                        self.mode = InputMode::Editing;
+                        return Ok(Some(Command::RunEx(self.filename_input.clone())));
+                    }
+                    KeyCode::Backspace => {
+                        self.filename_input.pop();
+                        return Ok(None);
+                    }
+                    KeyCode::Char(c) => {
+                        self.filename_input.push(c);
+                        return Ok(None);
+                    }
+                    _ => {}
+                },
+                InputMode::Diff => match key_event.code {
+                    KeyCode::Esc | KeyCode::Char('q') => {
+                        self.mode = InputMode::Editing;
+                        return Ok(Some(Command::CloseDiff));
+                    }
+                    KeyCode::Up | KeyCode::Char('k') => return Ok(Some(Command::DiffScrollUp)),
+                    KeyCode::Down | KeyCode::Char('j') => return Ok(Some(Command::DiffScrollDown)),
+                    KeyCode::Char('n') => return Ok(Some(Command::DiffNextHunk)),
+                    KeyCode::Char('p') => return Ok(Some(Command::DiffPrevHunk)),
+                    _ => {}
+                },
+                InputMode::TaskPicker => match key_event.code {
+                    KeyCode::Esc | KeyCode::Char('q') => {
+                        self.mode = InputMode::Editing;
+                        return Ok(Some(Command::CloseTaskPanel));
+                    }
+                    KeyCode::Up | KeyCode::Char('k') => return Ok(Some(Command::TaskSelectPrev)),
+                    KeyCode::Down | KeyCode::Char('j') => return Ok(Some(Command::TaskSelectNext)),
+                    KeyCode::Enter => {
+                        self.mode = InputMode::TaskOutput;
+                        return Ok(Some(Command::RunSelectedTask));
+                    }
+                    _ => {}
+                },
+                InputMode::BufferPicker => match key_event.code {
+                    KeyCode::Esc | KeyCode::Char('q') => {
+                        self.mode = InputMode::Editing;
+                        return Ok(Some(Command::CloseBufferPicker));
+                    }
+                    KeyCode::Up | KeyCode::Char('k') => return Ok(Some(Command::BufferSelectPrev)),
+                    KeyCode::Down | KeyCode::Char('j') => return Ok(Some(Command::BufferSelectNext)),
+                    KeyCode::Enter => {
+                        self.mode = InputMode::Editing;
+                        return Ok(Some(Command::SwitchToSelectedBuffer));
+                    }
+                    _ => {}
+                },
+                InputMode::UndoTree => match key_event.code {
+                    KeyCode::Esc | KeyCode::Char('q') => {
+                        self.mode = InputMode::Editing;
+                        return Ok(Some(Command::CloseUndoTree));
+                    }
+                    KeyCode::Up | KeyCode::Char('k') => return Ok(Some(Command::UndoTreeSelectPrev)),
+                    KeyCode::Down | KeyCode::Char('j') => return Ok(Some(Command::UndoTreeSelectNext)),
+                    KeyCode::Enter => {
+                        self.mode = InputMode::Editing;
+                        return Ok(Some(Command::JumpToUndoState));
+                    }
+                    _ => {}
+                },
+                InputMode::FileTree => match key_event.code {
+                    KeyCode::Esc | KeyCode::Tab => {
+                        self.mode = InputMode::Editing;
+                        return Ok(Some(Command::LeaveFileTree));
+                    }
+                    KeyCode::Char('q') => {
+                        self.mode = InputMode::Editing;
+                        return Ok(Some(Command::ToggleFileTree));
+                    }
+                    KeyCode::Up | KeyCode::Char('k') => return Ok(Some(Command::FileTreeSelectPrev)),
+                    KeyCode::Down | KeyCode::Char('j') => return Ok(Some(Command::FileTreeSelectNext)),
+                    KeyCode::Left | KeyCode::Char('h') => return Ok(Some(Command::FileTreeCollapse)),
+                    KeyCode::Right | KeyCode::Char('l') => return Ok(Some(Command::FileTreeExpand)),
+                    KeyCode::Enter => return Ok(Some(Command::FileTreeOpen)),
+                    _ => {}
+                },
+                InputMode::GrepResults => match key_event.code {
+                    KeyCode::Esc | KeyCode::Char('q') => {
+                        self.mode = InputMode::Editing;
+                        return Ok(Some(Command::CloseGrepResults));
+                    }
+                    KeyCode::Up | KeyCode::Char('k') => return Ok(Some(Command::GrepSelectPrev)),
+                    KeyCode::Down | KeyCode::Char('j') => return Ok(Some(Command::GrepSelectNext)),
+                    KeyCode::Enter => {
+                        self.mode = InputMode::Editing;
+                        return Ok(Some(Command::OpenGrepResult));
+                    }
+                    _ => {}
+                },
+                InputMode::ErrorList => match key_event.code {
+                    KeyCode::Esc | KeyCode::Char('q') => {
+                        self.mode = InputMode::Editing;
+                        return Ok(Some(Command::CloseErrorList));
+                    }
+                    KeyCode::Up | KeyCode::Char('k') => return Ok(Some(Command::ErrorSelectPrev)),
+                    KeyCode::Down | KeyCode::Char('j') => return Ok(Some(Command::ErrorSelectNext)),
+                    KeyCode::Enter => {
+                        self.mode = InputMode::Editing;
+                        return Ok(Some(Command::OpenSelectedError));
+                    }
+                    _ => {}
+                },
+                InputMode::RecoverPrompt => match key_event.code {
+                    KeyCode::Char('y') => {
+                        self.mode = InputMode::Editing;
+                        return Ok(Some(Command::RecoverSwap));
+                    }
+                    KeyCode::Char('n') | KeyCode::Esc => {
+                        self.mode = InputMode::Editing;
+                        return Ok(Some(Command::DiscardSwap));
+                    }
+                    _ => {}
+                },
+                InputMode::DiskChangedPrompt => {
+                    let command = match key_event.code {
+                        KeyCode::Char('r') => Some(Command::ReloadFromDisk),
+                        KeyCode::Char('o') => Some(Command::OverwriteFile),
+                        KeyCode::Char('d') => Some(Command::DiffWithDisk),
+                        KeyCode::Esc => None,
+                        _ => continue,
+                    };
+                    self.mode = InputMode::Editing;
+                    return Ok(command);
+                }
+                InputMode::Hex => match key_event.code {
+                    // Closing is left to the main loop, which may want a confirmation
+                    KeyCode::Char('q') if key_event.modifiers.contains(KeyModifiers::CONTROL) => return Ok(Some(Command::Quit)),
+                    KeyCode::Char('s') if key_event.modifiers.contains(KeyModifiers::CONTROL) => return Ok(Some(Command::SaveHex)),
+                    KeyCode::Esc | KeyCode::Char('q') => return Ok(Some(Command::CloseHex)),
+                    KeyCode::Left | KeyCode::Char('h') => return Ok(Some(Command::HexLeft)),
+                    KeyCode::Right | KeyCode::Char('l') => return Ok(Some(Command::HexRight)),
+                    KeyCode::Up | KeyCode::Char('k') => return Ok(Some(Command::HexUp)),
+                    KeyCode::Down | KeyCode::Char('j') => return Ok(Some(Command::HexDown)),
+                    KeyCode::PageUp => return Ok(Some(Command::HexPageUp)),
+                    KeyCode::PageDown => return Ok(Some(Command::HexPageDown)),
+                    KeyCode::Char(c) if c.is_ascii_hexdigit() => {
+                        return Ok(Some(Command::HexDigit(c.to_digit(16).unwrap_or(0) as u8)));
+                    }
+                    _ => {}
+                },
+                InputMode::HistoryBrowser => match key_event.code {
+                    KeyCode::Esc | KeyCode::Char('q') => {
+                        self.mode = InputMode::Editing;
+                        return Ok(Some(Command::CloseHistory));
+                    }
+                    KeyCode::Up | KeyCode::Char('k') => return Ok(Some(Command::HistorySelectPrev)),
+                    KeyCode::Down | KeyCode::Char('j') => return Ok(Some(Command::HistorySelectNext)),
+                    KeyCode::Enter | KeyCode::Char('d') => return Ok(Some(Command::DiffHistorySnapshot)),
+                    KeyCode::Char('r') => {
+                        self.mode = InputMode::Editing;
+                        return Ok(Some(Command::RestoreHistorySnapshot));
+                    }
+                    _ => {}
+                },
+                InputMode::GitPanel => match key_event.code {
+                    KeyCode::Esc | KeyCode::Char('q') => {
+                        self.mode = InputMode::Editing;
+                        return Ok(Some(Command::CloseGitPanel));
+                    }
+                    KeyCode::Up | KeyCode::Char('k') => return Ok(Some(Command::GitSelectPrev)),
+                    KeyCode::Down | KeyCode::Char('j') => return Ok(Some(Command::GitSelectNext)),
+                    KeyCode::Char(' ') | KeyCode::Char('s') => return Ok(Some(Command::GitToggleStage)),
+                    KeyCode::Char('r') => return Ok(Some(Command::GitRefresh)),
+                    KeyCode::Enter => {
+                        self.mode = InputMode::Editing;
+                        return Ok(Some(Command::GitOpenFile));
+                    }
+                    KeyCode::Char('c') => {
+                        self.mode = InputMode::Editing;
+                        return Ok(Some(Command::RunEx("commit".to_string())));
+                    }
+                    _ => {}
+                },
+                InputMode::Completion => match key_event.code {
+                    KeyCode::Up => return Ok(Some(Command::CompletionPrev)),
+                    KeyCode::Down => return Ok(Some(Command::CompletionNext)),
+                    KeyCode::Char('p') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                        return Ok(Some(Command::CompletionPrev));
+                    }
+                    KeyCode::Char('n') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                        return Ok(Some(Command::CompletionNext));
+                    }
+                    KeyCode::Enter | KeyCode::Tab => {
+                        self.mode = InputMode::Editing;
+                        return Ok(Some(Command::AcceptCompletion));
+                    }
+                    KeyCode::Esc => {
+                        self.mode = InputMode::Editing;
+                        return Ok(Some(Command::CancelCompletion));
+                    }
+                    // Typing on closes the popup
+                    KeyCode::Char(c) if !key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                        self.mode = InputMode::Editing;
+                        return Ok(Some(Command::InsertChar(c)));
+                    }
+                    KeyCode::Backspace => {
+                        self.mode = InputMode::Editing;
+                        return Ok(Some(Command::Backspace));
+                    }
+                    _ => {}
+                },
+                InputMode::TaskOutput => match key_event.code {
+                    KeyCode::Char('c') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                        return Ok(Some(Command::KillTask));
+                    }
+                    KeyCode::Esc | KeyCode::Char('q') => {
+                        self.mode = InputMode::Editing;
+                        return Ok(Some(Command::CloseTaskPanel));
+                    }
+                    KeyCode::Up | KeyCode::Char('k') => return Ok(Some(Command::TaskScrollUp)),
+                    KeyCode::Down | KeyCode::Char('j') => return Ok(Some(Command::TaskScrollDown)),
+                    KeyCode::Char('r') => return Ok(Some(Command::RerunTask)),
+                    _ => {}
+                },
+                InputMode::EnteringFileNameExport => match key_event.code {
+                    KeyCode::Esc => {
+                        self.mode = InputMode::Editing;
+                        return Ok(None);
+                    }
+                    KeyCode::Enter => {
+                        self.mode = InputMode::Editing;
+                        return Ok(Some(Command::ConfirmExportHtml));
+                    }
+                    KeyCode::Backspace => {
+                        self.filename_input.pop();
+                        return Ok(None);
+                    }
+                    KeyCode::Char(c) => {
+                        self.filename_input.push(c);
+                        return Ok(None);
+                    }
+                    _ => {}
+                },
             }
         }
-        Ok(None)
     }
 
     pub fn start_find(&mut self) {
         self.mode = InputMode::Finding;
         self.find_input.clear();
-        self.confirmed_find_term = None;
+        self.search = None;
+    }
+
+    pub fn start_replace(&mut self) {
+        self.mode = InputMode::ReplacePattern;
+        self.find_input.clear();
+        self.search = None;
+    }
+
+    /// Compiles the pattern like `confirm_find` and moves on to asking for
+    /// the replacement.
+    pub fn confirm_replace_pattern(
+        &mut self,
+        buffer: &EditorBuffer,
+        dirty_lines: &mut std::collections::HashSet<usize>,
+    ) -> std::result::Result<(), String> {
+        self.confirm_find(buffer, dirty_lines)?;
+        if self.search.is_some() {
+            self.mode = InputMode::ReplaceWith;
+            self.filename_input.clear();
+        }
+        Ok(())
+    }
+
+    pub fn start_grep(&mut self) {
+        self.mode = InputMode::GrepPattern;
+        self.find_input.clear();
+        self.search = None;
+    }
+
+    pub fn start_grep_results(&mut self) {
+        self.mode = InputMode::GrepResults;
+    }
+
+    pub fn start_undo_tree(&mut self) {
+        self.mode = InputMode::UndoTree;
+    }
+
+    pub fn start_file_tree(&mut self) {
+        self.mode = InputMode::FileTree;
+    }
+
+    pub fn leave_file_tree(&mut self) {
+        self.mode = InputMode::Editing;
+    }
+
+    pub fn start_replace_confirm(&mut self) {
+        self.mode = InputMode::ReplaceConfirm;
+    }
+
+    /// Confirms `word` as a whole-word search term without going through the prompt.
+    pub fn set_word_search(&mut self, word: String) {
+        self.search = Search::new(&word, false, true).ok();
+        self.find_input = word;
+        self.find_regex = false;
     }
 
     pub fn start_open_file(&mut self) {
@@ -171,15 +1087,89 @@ impl InputHandler {
         self.filename_input.clear();
     }
 
-    pub fn confirm_find(&mut self, buffer: &EditorBuffer, dirty_lines: &mut std::collections::HashSet<usize>) {
-        if self.find_input.is_empty() {
-            self.confirmed_find_term = None;
-        } else {
-            self.confirmed_find_term = Some(self.find_input.clone());
+    /// Asks for the secret of the encrypted file at `path`.
+    pub fn start_secret_prompt(&mut self, path: &str, masked: bool) {
+        self.mode = InputMode::EnteringSecret;
+        self.filename_input.clear();
+        self.secret_for = path.to_string();
+        self.mask_secret = masked;
+    }
+
+    pub fn start_export_html(&mut self) {
+        self.mode = InputMode::EnteringFileNameExport;
+        self.filename_input.clear();
+    }
+
+    pub fn start_diff_files(&mut self) {
+        self.mode = InputMode::EnteringDiffFiles;
+        self.filename_input.clear();
+    }
+
+    pub fn start_command_line(&mut self) {
+        self.mode = InputMode::EnteringCommand;
+        self.filename_input.clear();
+    }
+
+    pub fn start_diff_view(&mut self) {
+        self.mode = InputMode::Diff;
+    }
+
+    pub fn start_task_picker(&mut self) {
+        self.mode = InputMode::TaskPicker;
+    }
+
+    pub fn start_history_browser(&mut self) {
+        self.mode = InputMode::HistoryBrowser;
+    }
+
+    pub fn start_error_list(&mut self) {
+        self.mode = InputMode::ErrorList;
+    }
+
+    pub fn start_git_panel(&mut self) {
+        self.mode = InputMode::GitPanel;
+    }
+
+    pub fn start_buffer_picker(&mut self) {
+        self.mode = InputMode::BufferPicker;
+    }
+
+    pub fn start_recover_prompt(&mut self) {
+        self.mode = InputMode::RecoverPrompt;
+    }
+
+    pub fn start_disk_changed_prompt(&mut self) {
+        self.mode = InputMode::DiskChangedPrompt;
+    }
+
+    pub fn start_hex_view(&mut self) {
+        self.mode = InputMode::Hex;
+    }
+
+    pub fn start_completion(&mut self) {
+        self.mode = InputMode::Completion;
+    }
+
+    /// Waits up to `timeout` for input; false if none arrived.
+    pub fn wait_for_input(&self, timeout: Duration) -> Result<bool> {
+        poll(timeout)
+    }
+
+    /// Compiles the typed term; a regular expression that does not parse
+    /// comes back as the error to show.
+    pub fn confirm_find(&mut self, buffer: &EditorBuffer, dirty_lines: &mut std::collections::HashSet<usize>) -> std::result::Result<(), String> {
+        self.mode = InputMode::Editing;
+        self.search = None;
+        if !self.find_input.is_empty() {
+            let search = Search::new(&self.find_input, self.find_regex, false).map_err(|err| {
+                // The parser's message ends with a one-line summary
+                err.to_string().lines().last().unwrap_or_default().trim_start_matches("error: ").to_string()
+            })?;
+            self.search = Some(search);
             let total_lines = buffer.len_lines();
             dirty_lines.extend(0..total_lines); // Only add valid line indexes for redraw
         }
-        self.mode = InputMode::Editing;
+        Ok(())
     }
 
 
@@ -191,6 +1181,21 @@ impl InputHandler {
         }
     }
 
+    /// The file and the secret typed for it, which is not kept.
+    pub fn confirm_secret(&mut self) -> Option<(String, String)> {
+        let secret = std::mem::take(&mut self.filename_input);
+        (!secret.is_empty()).then(|| (std::mem::take(&mut self.secret_for), secret))
+    }
+
+    /// The text typed at the prompt as it is shown, secrets masked.
+    pub fn shown_input(&self) -> String {
+        if self.mode == InputMode::EnteringSecret && self.mask_secret {
+            "*".repeat(self.filename_input.chars().count())
+        } else {
+            self.filename_input.clone()
+        }
+    }
+
     pub fn confirm_save_file(&mut self) -> Option<String> {
         if self.filename_input.is_empty() {
             None
@@ -198,4 +1203,27 @@ impl InputHandler {
             Some(self.filename_input.clone())
         }
     }
+
+    pub fn confirm_export_html(&mut self) -> Option<String> {
+        if self.filename_input.is_empty() {
+            None
+        } else {
+            Some(self.filename_input.clone())
+        }
+    }
+
+    /// Returns the two paths typed into the diff prompt, separated by whitespace.
+    pub fn confirm_diff_files(&mut self) -> Option<(String, String)> {
+        let mut paths = self.filename_input.split_whitespace();
+        match (paths.next(), paths.next()) {
+            (Some(left), Some(right)) => Some((left.to_string(), right.to_string())),
+            _ => None,
+        }
+    }
+}
+
+impl Default for InputHandler {
+    fn default() -> Self {
+        InputHandler::new()
+    }
 }
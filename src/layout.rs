@@ -0,0 +1,257 @@
+// src/layout.rs
+
+use crate::render::Renderer;
+
+/// A rectangle of terminal cells.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl Rect {
+    pub fn contains(&self, column: u16, row: u16) -> bool {
+        (self.x..self.x + self.width).contains(&column) && (self.y..self.y + self.height).contains(&row)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SplitDirection {
+    /// One pane above the other.
+    Horizontal,
+    /// Side by side.
+    Vertical,
+}
+
+/// Pane rectangles by pane index, and the separators between them.
+type Arrangement = (Vec<(usize, Rect)>, Vec<(SplitDirection, Rect)>);
+
+/// Panes smaller than this are not split any further.
+const MIN_PANE_WIDTH: u16 = 20;
+const MIN_PANE_HEIGHT: u16 = 3;
+
+enum Node {
+    Pane(usize),
+    Split {
+        direction: SplitDirection,
+        first: Box<Node>,
+        second: Box<Node>,
+    },
+}
+
+/// One view onto a buffer. The active pane's cursor and renderer are held
+/// by the main loop; the fields here are only current for the others.
+pub struct Pane {
+    /// Index into the buffer list.
+    pub buffer: usize,
+    pub cursor_char_idx: usize,
+    pub viewport_row: usize,
+    pub renderer: Option<Renderer>,
+}
+
+/// The text area split into panes, as a tree of horizontal and vertical splits.
+pub struct Layout {
+    root: Node,
+    area: Rect,
+    pub panes: Vec<Pane>,
+    pub active: usize,
+}
+
+impl Node {
+    /// Pane rectangles and separator rectangles for this subtree.
+    fn arrange(&self, area: Rect, panes: &mut Vec<(usize, Rect)>, separators: &mut Vec<(SplitDirection, Rect)>) {
+        match self {
+            Node::Pane(id) => panes.push((*id, area)),
+            Node::Split { direction, first, second } => {
+                let (a, separator, b) = split_rect(area, *direction);
+                first.arrange(a, panes, separators);
+                separators.push((*direction, separator));
+                second.arrange(b, panes, separators);
+            }
+        }
+    }
+
+    fn leaves(&self, out: &mut Vec<usize>) {
+        match self {
+            Node::Pane(id) => out.push(*id),
+            Node::Split { first, second, .. } => {
+                first.leaves(out);
+                second.leaves(out);
+            }
+        }
+    }
+
+    /// Splits the leaf showing `target`, putting `new` after it.
+    fn split(&mut self, target: usize, new: usize, direction: SplitDirection) -> bool {
+        match self {
+            Node::Pane(id) if *id == target => {
+                *self = Node::Split {
+                    direction,
+                    first: Box::new(Node::Pane(target)),
+                    second: Box::new(Node::Pane(new)),
+                };
+                true
+            }
+            Node::Pane(_) => false,
+            Node::Split { first, second, .. } => first.split(target, new, direction) || second.split(target, new, direction),
+        }
+    }
+
+    /// Removes the leaf `target`, letting its sibling take the space.
+    fn remove(&mut self, target: usize) {
+        let Node::Split { first, second, .. } = self else {
+            return;
+        };
+        let sibling = if matches!(**first, Node::Pane(id) if id == target) {
+            std::mem::replace(&mut **second, Node::Pane(0))
+        } else if matches!(**second, Node::Pane(id) if id == target) {
+            std::mem::replace(&mut **first, Node::Pane(0))
+        } else {
+            first.remove(target);
+            second.remove(target);
+            return;
+        };
+        *self = sibling;
+    }
+
+    fn renumber(&mut self, removed: usize) {
+        match self {
+            Node::Pane(id) if *id > removed => *id -= 1,
+            Node::Pane(_) => {}
+            Node::Split { first, second, .. } => {
+                first.renumber(removed);
+                second.renumber(removed);
+            }
+        }
+    }
+}
+
+/// Halves `area` with a one-cell separator in between.
+fn split_rect(area: Rect, direction: SplitDirection) -> (Rect, Rect, Rect) {
+    match direction {
+        SplitDirection::Horizontal => {
+            let first = area.height.saturating_sub(1) / 2;
+            let second = area.height.saturating_sub(first + 1);
+            (
+                Rect { height: first, ..area },
+                Rect { y: area.y + first, height: 1, ..area },
+                Rect { y: area.y + first + 1, height: second, ..area },
+            )
+        }
+        SplitDirection::Vertical => {
+            let first = area.width.saturating_sub(1) / 2;
+            let second = area.width.saturating_sub(first + 1);
+            (
+                Rect { width: first, ..area },
+                Rect { x: area.x + first, width: 1, ..area },
+                Rect { x: area.x + first + 1, width: second, ..area },
+            )
+        }
+    }
+}
+
+impl Layout {
+    pub fn new(area: Rect) -> Self {
+        Layout {
+            root: Node::Pane(0),
+            area,
+            panes: vec![Pane {
+                buffer: 0,
+                cursor_char_idx: 0,
+                viewport_row: 0,
+                renderer: None,
+            }],
+            active: 0,
+        }
+    }
+
+    /// Moves the whole text area, e.g. beside a sidebar; `arrange` hands
+    /// the renderers their new rectangles.
+    pub fn set_area(&mut self, area: Rect) {
+        self.area = area;
+    }
+
+    /// Panes on screen; there is always at least one.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> usize {
+        self.panes.len()
+    }
+
+    fn arrangement(&self) -> Arrangement {
+        let mut panes = Vec::new();
+        let mut separators = Vec::new();
+        self.root.arrange(self.area, &mut panes, &mut separators);
+        (panes, separators)
+    }
+
+    /// Lines between panes, to be drawn after any full-screen redraw.
+    pub fn separators(&self) -> Vec<(SplitDirection, Rect)> {
+        self.arrangement().1
+    }
+
+    /// Gives every pane's renderer (the active one passed in) its rectangle.
+    pub fn arrange(&mut self, active_renderer: &mut Renderer) {
+        for (id, rect) in self.arrangement().0 {
+            match self.panes[id].renderer.as_mut() {
+                Some(renderer) => renderer.set_area(rect),
+                None => active_renderer.set_area(rect),
+            }
+        }
+    }
+
+    /// Splits the active pane, the new pane showing the same buffer at the
+    /// same position. False if the pane is too small to split.
+    pub fn split(&mut self, direction: SplitDirection, renderer: Renderer, cursor_char_idx: usize, viewport_row: usize) -> bool {
+        let Some(&(_, rect)) = self.arrangement().0.iter().find(|(id, _)| *id == self.active) else {
+            return false;
+        };
+        let (a, _, b) = split_rect(rect, direction);
+        if a.width.min(b.width) < MIN_PANE_WIDTH || a.height.min(b.height) < MIN_PANE_HEIGHT {
+            return false;
+        }
+        let new = self.panes.len();
+        self.panes.push(Pane {
+            buffer: self.panes[self.active].buffer,
+            cursor_char_idx,
+            viewport_row,
+            renderer: Some(renderer),
+        });
+        self.root.split(self.active, new, direction)
+    }
+
+    /// The pane after the active one, in screen order.
+    pub fn next(&self) -> usize {
+        let mut order = Vec::new();
+        self.root.leaves(&mut order);
+        let position = order.iter().position(|&id| id == self.active).unwrap_or(0);
+        order[(position + 1) % order.len()]
+    }
+
+    /// Removes an inactive pane.
+    pub fn remove(&mut self, index: usize) {
+        if index == self.active || self.panes.len() < 2 {
+            return;
+        }
+        self.root.remove(index);
+        self.root.renumber(index);
+        self.panes.remove(index);
+        if self.active > index {
+            self.active -= 1;
+        }
+    }
+
+    pub fn shows_buffer(&self, buffer: usize) -> bool {
+        self.panes.iter().any(|pane| pane.buffer == buffer)
+    }
+
+    /// Keeps buffer indices valid after the buffer list dropped `removed`.
+    pub fn buffer_removed(&mut self, removed: usize) {
+        for pane in &mut self.panes {
+            if pane.buffer > removed {
+                pane.buffer -= 1;
+            }
+        }
+    }
+}
@@ -0,0 +1,54 @@
+// src/lib.rs
+
+//! The editor's engine and everything it is built from, used by the
+//! `rusty` binary and open to anything else that wants to embed it. See
+//! `editor::Editor` to drive a buffer with commands without a terminal.
+
+pub mod a11y;
+pub mod autosave;
+pub mod backend;
+pub mod brackets;
+pub mod buffer;
+pub mod buffers;
+pub mod build;
+pub mod clipboard;
+pub mod columns;
+pub mod config;
+pub mod crypt;
+pub mod diff;
+pub mod editor;
+pub mod ex;
+pub mod export;
+pub mod fileio;
+pub mod fold;
+pub mod git_gutter;
+pub mod git_panel;
+pub mod grep;
+pub mod hex;
+pub mod highlight;
+pub mod history;
+pub mod input;
+pub mod layout;
+pub mod loader;
+pub mod lsp;
+pub mod merge;
+pub mod pairs;
+pub mod plugins;
+pub mod positions;
+pub mod recovery;
+pub mod registers;
+pub mod remote;
+pub mod render;
+pub mod scripting;
+pub mod search;
+pub mod search_history;
+pub mod signs;
+pub mod snippets;
+pub mod stats;
+pub mod status;
+pub mod syntax;
+pub mod tasks;
+pub mod theme;
+pub mod tree;
+pub mod watch;
+pub mod words;
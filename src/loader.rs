@@ -0,0 +1,100 @@
+// src/loader.rs
+
+use crate::buffer::{EditorBuffer, LineEnding};
+use crate::remote::{self, RemotePath};
+use encoding_rs::{DecoderResult, Encoding, UTF_8, WINDOWS_1252};
+use ropey::RopeBuilder;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+
+/// Bytes read and decoded at a time.
+const CHUNK_LEN: usize = 1 << 20;
+
+/// Loads a file chunk by chunk into a rope, so the whole text never sits in
+/// memory twice. `progress` gets the bytes read so far and the file size
+/// after every chunk.
+///
+/// The encoding is taken from a byte order mark, else UTF-8 is assumed; a
+/// file that turns out not to be UTF-8 is read again as Windows-1252.
+/// An `sftp://` path or a web address is downloaded first.
+pub fn load(path: &str, mut progress: impl FnMut(u64, u64)) -> io::Result<EditorBuffer> {
+    if let Some(remote) = RemotePath::parse(path) {
+        return load(remote.fetch()?.path(), progress);
+    }
+    if remote::is_url(path) {
+        return load(remote::fetch_url(path)?.path(), progress);
+    }
+    let mut reader = BufReader::with_capacity(CHUNK_LEN, File::open(path)?);
+    let (encoding, bom_len) = Encoding::for_bom(reader.fill_buf()?).unwrap_or((UTF_8, 0));
+    reader.consume(bom_len);
+    if let Some(buffer) = stream(reader, encoding, bom_len > 0, &mut progress)? {
+        return Ok(buffer);
+    }
+    let buffer = stream(BufReader::with_capacity(CHUNK_LEN, File::open(path)?), WINDOWS_1252, false, &mut progress)?;
+    buffer.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "could not decode file"))
+}
+
+/// Decodes everything `reader` has left. None when UTF-8 without a byte
+/// order mark turns out to be malformed.
+fn stream(
+    mut reader: BufReader<File>,
+    encoding: &'static Encoding,
+    bom: bool,
+    progress: &mut impl FnMut(u64, u64),
+) -> io::Result<Option<EditorBuffer>> {
+    let total = reader.get_ref().metadata()?.len();
+    let strict = encoding == UTF_8 && !bom;
+    let mut decoder = encoding.new_decoder_without_bom_handling();
+    let mut builder = RopeBuilder::new();
+    let mut bytes = vec![0; CHUNK_LEN];
+    let mut text = String::new();
+    let mut done = 0;
+    // A `\r` ending a chunk may be the first half of a `\r\n`
+    let mut held_cr = false;
+    let (mut crlf, mut lf) = (0, 0);
+    loop {
+        let read = reader.read(&mut bytes)?;
+        let last = read == 0;
+        done += read as u64;
+
+        text.clear();
+        if held_cr {
+            text.push('\r');
+            held_cr = false;
+        }
+        if strict {
+            text.reserve(decoder.max_utf8_buffer_length_without_replacement(read).unwrap_or(read * 3));
+            let (result, _) = decoder.decode_to_string_without_replacement(&bytes[..read], &mut text, last);
+            if let DecoderResult::Malformed(..) = result {
+                return Ok(None);
+            }
+        } else {
+            text.reserve(decoder.max_utf8_buffer_length(read).unwrap_or(read * 3));
+            // Room was reserved for all of it, so the input is always used up
+            let _ = decoder.decode_to_string(&bytes[..read], &mut text, last);
+        }
+        if !last && text.ends_with('\r') {
+            text.pop();
+            held_cr = true;
+        }
+
+        crlf += text.matches("\r\n").count();
+        lf += text.matches('\n').count();
+        if text.contains("\r\n") {
+            builder.append(&text.replace("\r\n", "\n"));
+        } else {
+            builder.append(&text);
+        }
+        progress(done, total);
+        if last {
+            break;
+        }
+    }
+
+    let mut buffer = EditorBuffer::new();
+    buffer.rope = builder.finish();
+    buffer.encoding = encoding;
+    buffer.bom = bom;
+    buffer.line_ending = LineEnding::from_counts(crlf, lf);
+    Ok(Some(buffer))
+}
@@ -0,0 +1,417 @@
+// src/lsp.rs
+
+use crate::build::{Diagnostic, Severity};
+use crate::buffer::EditOp;
+use ropey::Rope;
+use serde_json::{json, Value};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long a server gets to exit by itself once told to, before it is killed.
+const EXIT_GRACE: Duration = Duration::from_millis(500);
+
+/// A completion offered by the server.
+#[derive(Clone, Debug)]
+pub struct CompletionItem {
+    pub label: String,
+    /// Text inserted in place of the word before the cursor.
+    pub insert: String,
+}
+
+/// Talks JSON-RPC to a language server over its stdin/stdout for the one
+/// open document. Messages are written and replies read on threads of
+/// their own, so a slow server never holds up the editor; replies are
+/// picked up by `poll`.
+pub struct LspClient {
+    child: Child,
+    /// Bodies of messages for the writer thread.
+    outgoing: Sender<String>,
+    rx: Receiver<Value>,
+    next_id: u64,
+    uri: String,
+    /// Path as the user typed it, for messages.
+    display_path: String,
+    path: PathBuf,
+    language_id: String,
+    version: i64,
+    initialized: bool,
+    /// The document as the server has it, or will once it is initialized.
+    text: Rope,
+    /// The server takes changes as edited ranges rather than whole texts.
+    incremental: bool,
+    completion_request: Option<u64>,
+    pub diagnostics: Vec<Diagnostic>,
+    pub completions: Vec<CompletionItem>,
+    pub selected: usize,
+}
+
+/// `file://` URI of the absolute `path`, every byte but the unreserved
+/// ones and `/` percent-encoded.
+fn file_uri(path: &Path) -> String {
+    let mut uri = "file://".to_string();
+    for byte in path.to_string_lossy().bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    uri
+}
+
+/// Reads `Content-Length` framed messages until the server goes away.
+fn read_messages<R: Read>(reader: R, tx: std::sync::mpsc::Sender<Value>) {
+    let mut reader = BufReader::new(reader);
+    loop {
+        let mut length = None;
+        loop {
+            let mut header = String::new();
+            match reader.read_line(&mut header) {
+                Ok(0) | Err(_) => return,
+                Ok(_) => {}
+            }
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(value) = header.strip_prefix("Content-Length:") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+        let Some(length) = length else {
+            continue;
+        };
+        let mut body = vec![0; length];
+        if reader.read_exact(&mut body).is_err() {
+            return;
+        }
+        if let Ok(message) = serde_json::from_slice(&body)
+            && tx.send(message).is_err()
+        {
+            return;
+        }
+    }
+}
+
+/// Writes each message as it comes, `Content-Length` framed, until the
+/// client or the server goes away.
+fn write_messages<W: Write>(mut writer: W, rx: Receiver<String>) {
+    for body in rx {
+        if write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body).and_then(|()| writer.flush()).is_err() {
+            return;
+        }
+    }
+}
+
+/// LSP position of char `char_idx`: its line and UTF-16 offset in it.
+fn position(text: &Rope, char_idx: usize) -> Value {
+    let line = text.char_to_line(char_idx);
+    let character = text.char_to_utf16_cu(char_idx) - text.char_to_utf16_cu(text.line_to_char(line));
+    json!({ "line": line, "character": character })
+}
+
+/// Char column in `line` of the UTF-16 offset `character`, clamped to the
+/// line's text; the other way round from `position`.
+fn char_col(text: &Rope, line: usize, character: usize) -> usize {
+    let line_start = text.line_to_char(line);
+    let line_len = text.line(line).chars().take_while(|&c| c != '\n' && c != '\r').count();
+    let start_cu = text.char_to_utf16_cu(line_start);
+    let cu = (start_cu + character).min(text.char_to_utf16_cu(line_start + line_len));
+    text.utf16_cu_to_char(cu) - line_start
+}
+
+/// `command` as run by the platform's shell.
+fn shell_command(command: &str) -> Command {
+    #[cfg(windows)]
+    let (shell, flag) = ("cmd", "/C");
+    #[cfg(not(windows))]
+    let (shell, flag) = ("sh", "-c");
+    let mut shell = Command::new(shell);
+    shell.arg(flag).arg(command);
+    shell
+}
+
+impl LspClient {
+    /// Spawns `command` and starts initializing it for the file at `path`.
+    pub fn start(command: &str, display_path: &str, text: Rope) -> io::Result<Self> {
+        let path = Path::new(display_path).canonicalize()?;
+        let root = std::env::current_dir()?;
+        let mut child = shell_command(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let stdin = child.stdin.take().ok_or_else(|| io::Error::other("no stdin"))?;
+        let stdout = child.stdout.take().ok_or_else(|| io::Error::other("no stdout"))?;
+        let (tx, rx) = channel();
+        thread::spawn(move || read_messages(stdout, tx));
+        let (outgoing, outgoing_rx) = channel();
+        thread::spawn(move || write_messages(stdin, outgoing_rx));
+
+        let language_id = path.extension().and_then(|e| e.to_str()).map_or("plaintext", |ext| match ext {
+            "rs" => "rust",
+            "py" => "python",
+            "c" | "h" => "c",
+            "cpp" | "hpp" | "cc" => "cpp",
+            "js" => "javascript",
+            "ts" => "typescript",
+            "go" => "go",
+            other => other,
+        })
+        .to_string();
+        let mut client = LspClient {
+            child,
+            outgoing,
+            rx,
+            next_id: 1,
+            uri: file_uri(&path),
+            display_path: display_path.to_string(),
+            path,
+            language_id,
+            version: 0,
+            initialized: false,
+            text,
+            incremental: false,
+            completion_request: None,
+            diagnostics: Vec::new(),
+            completions: Vec::new(),
+            selected: 0,
+        };
+        client.request(
+            "initialize",
+            json!({
+                "processId": std::process::id(),
+                "rootUri": file_uri(&root),
+                "capabilities": {
+                    "textDocument": {
+                        "publishDiagnostics": {},
+                        "completion": { "completionItem": { "snippetSupport": false } },
+                        "synchronization": { "didSave": false },
+                    },
+                },
+            }),
+        )?;
+        Ok(client)
+    }
+
+    fn send(&mut self, message: Value) -> io::Result<()> {
+        self.outgoing
+            .send(message.to_string())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "language server is gone"))
+    }
+
+    fn request(&mut self, method: &str, params: Value) -> io::Result<u64> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }))?;
+        Ok(id)
+    }
+
+    fn notify(&mut self, method: &str, params: Value) -> io::Result<()> {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+    }
+
+    /// Sends `edits`, made in order to get the document to `text`, as the
+    /// ranges they changed, or the whole text to servers that want it so.
+    pub fn did_change(&mut self, edits: &[EditOp], text: &Rope) {
+        let mut changes = Vec::new();
+        for op in edits {
+            let (start, end, new_text) = match op {
+                EditOp::Insert { char_idx, content } => (*char_idx, *char_idx, content.as_str()),
+                EditOp::Delete { char_idx, content } => (*char_idx, char_idx + content.chars().count(), ""),
+            };
+            if end > self.text.len_chars() {
+                break;
+            }
+            changes.push(json!({
+                "range": { "start": position(&self.text, start), "end": position(&self.text, end) },
+                "text": new_text,
+            }));
+            self.text.remove(start..end);
+            self.text.insert(start, new_text);
+        }
+        // Edits that do not add up to the text mean one was missed
+        if self.text.len_chars() != text.len_chars() || changes.len() < edits.len() {
+            return self.did_replace(text);
+        }
+        if !self.incremental {
+            changes = vec![json!({ "text": self.text.to_string() })];
+        }
+        self.send_changes(changes);
+    }
+
+    /// Sends the whole new text of the document, e.g. after a reload.
+    pub fn did_replace(&mut self, text: &Rope) {
+        self.text = text.clone();
+        self.send_changes(vec![json!({ "text": self.text.to_string() })]);
+    }
+
+    fn send_changes(&mut self, changes: Vec<Value>) {
+        // The server gets the text as it is then when it is initialized
+        if !self.initialized || changes.is_empty() {
+            return;
+        }
+        self.version += 1;
+        let params = json!({
+            "textDocument": { "uri": self.uri, "version": self.version },
+            "contentChanges": changes,
+        });
+        let _ = self.notify("textDocument/didChange", params);
+    }
+
+    /// Asks for completions at `line` and UTF-16 `character`.
+    pub fn request_completion(&mut self, line: usize, character: usize) {
+        self.completions.clear();
+        self.selected = 0;
+        let params = json!({
+            "textDocument": { "uri": self.uri },
+            "position": { "line": line, "character": character },
+        });
+        self.completion_request = self.request("textDocument/completion", params).ok();
+    }
+
+    /// Whether the server process has gone away.
+    pub fn has_exited(&mut self) -> bool {
+        !matches!(self.child.try_wait(), Ok(None))
+    }
+
+    /// Whether a completion reply is still outstanding.
+    pub fn is_waiting(&self) -> bool {
+        self.completion_request.is_some()
+    }
+
+    pub fn cancel_completion(&mut self) {
+        self.completion_request = None;
+        self.completions.clear();
+    }
+
+    /// Handles everything the server sent since the last call. Returns true
+    /// if diagnostics or completions changed.
+    pub fn poll(&mut self) -> bool {
+        let mut changed = false;
+        while let Ok(message) = self.rx.try_recv() {
+            let id = message.get("id").and_then(Value::as_u64);
+            match message.get("method").and_then(Value::as_str) {
+                Some("textDocument/publishDiagnostics") => {
+                    let params = &message["params"];
+                    if params["uri"].as_str() == Some(self.uri.as_str()) {
+                        self.diagnostics = self.parse_diagnostics(&params["diagnostics"]);
+                        changed = true;
+                    }
+                }
+                // Server-to-client requests we do not support still need an answer
+                Some(_) if id.is_some() => {
+                    let _ = self.send(json!({ "jsonrpc": "2.0", "id": message["id"], "result": null }));
+                }
+                Some(_) => {}
+                None if id == Some(1) && !self.initialized => {
+                    self.initialized = true;
+                    // Sync kind 2, plain or in an object, is incremental
+                    let sync = &message["result"]["capabilities"]["textDocumentSync"];
+                    self.incremental = sync.as_u64().or(sync["change"].as_u64()) == Some(2);
+                    let _ = self.notify("initialized", json!({}));
+                    let params = json!({
+                        "textDocument": {
+                            "uri": self.uri,
+                            "languageId": self.language_id,
+                            "version": self.version,
+                            "text": self.text.to_string(),
+                        },
+                    });
+                    let _ = self.notify("textDocument/didOpen", params);
+                }
+                None if id.is_some() && id == self.completion_request => {
+                    self.completion_request = None;
+                    let result = &message["result"];
+                    let items = result.get("items").unwrap_or(result);
+                    self.completions = items
+                        .as_array()
+                        .map(|items| {
+                            items
+                                .iter()
+                                .filter_map(|item| {
+                                    let label = item["label"].as_str()?.to_string();
+                                    let insert = item["textEdit"]["newText"]
+                                        .as_str()
+                                        .or(item["insertText"].as_str())
+                                        .unwrap_or(&label)
+                                        .to_string();
+                                    Some(CompletionItem { label, insert })
+                                })
+                                .collect()
+                        })
+                        .unwrap_or_default();
+                    changed = true;
+                }
+                None => {}
+            }
+        }
+        changed
+    }
+
+    fn parse_diagnostics(&self, list: &Value) -> Vec<Diagnostic> {
+        list.as_array()
+            .map(|list| {
+                list.iter()
+                    .filter_map(|d| {
+                        // 1 is error, 2 warning; hints and information are not shown
+                        let severity = match d["severity"].as_u64() {
+                            Some(1) | None => Severity::Error,
+                            Some(2) => Severity::Warning,
+                            _ => return None,
+                        };
+                        let line = d["range"]["start"]["line"].as_u64()? as usize;
+                        let character = d["range"]["start"]["character"].as_u64().unwrap_or(0) as usize;
+                        let col = if line < self.text.len_lines() { char_col(&self.text, line, character) } else { character };
+                        Some(Diagnostic {
+                            path: self.display_path.clone(),
+                            canonical_path: Some(self.path.clone()),
+                            line,
+                            col,
+                            severity,
+                            message: d["message"].as_str().unwrap_or_default().to_string(),
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn count(&self, severity: Severity) -> usize {
+        self.diagnostics.iter().filter(|d| d.severity == severity).count()
+    }
+
+    pub fn select_next(&mut self) {
+        if self.selected + 1 < self.completions.len() {
+            self.selected += 1;
+        }
+    }
+
+    pub fn select_prev(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+}
+
+/// Asks the server to shut down and exit, and kills it only when it has
+/// not done so after `EXIT_GRACE`.
+impl Drop for LspClient {
+    fn drop(&mut self) {
+        let _ = self.request("shutdown", Value::Null);
+        let _ = self.notify("exit", Value::Null);
+        // The writer thread sends what is queued and stops once the channel closes
+        drop(std::mem::replace(&mut self.outgoing, channel().0));
+        let deadline = Instant::now() + EXIT_GRACE;
+        while Instant::now() < deadline {
+            if !matches!(self.child.try_wait(), Ok(None)) {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
@@ -1,151 +1,2327 @@
-mod buffer;
-mod input;
-mod render;
+mod cli;
+#[cfg(unix)]
+mod session;
 
+use crossterm::event::{DisableFocusChange, DisableMouseCapture, EnableFocusChange, EnableMouseCapture};
 use crossterm::terminal::{enable_raw_mode, disable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
 use crossterm::ExecutableCommand;
 use std::io::{stdout, Result};
-use crate::buffer::{EditorBuffer, UndoRedoStacks};
-use crate::input::{InputHandler, InputMode, Command};
-use crate::render::{Renderer, VirtualScreen};
+use rusty::{brackets, buffer, config, ex, export, git_gutter, hex, loader, merge, recovery, remote, snippets, words};
+use rusty::a11y::Announcer;
+use rusty::autosave::Autosave;
+use rusty::backend::{Backend, ClearRegion};
+use rusty::buffer::{EditOp, EditorBuffer, UndoTree};
+use rusty::buffers::{BufferList, BufferState};
+use rusty::build::{BuildRunner, Severity};
+use crate::cli::{Args, USAGE};
+use rusty::clipboard::Clipboard;
+use rusty::editor::Editor;
+use rusty::config::{Config, SnippetsConfig};
+use rusty::crypt::{Cipher, Encryption};
+use rusty::diff::DiffView;
+use rusty::ex::{DiffTarget, ExCommand};
+use rusty::fileio::{FileEvent, FileWorker};
+use rusty::fold::FoldSet;
+use rusty::git_gutter::GitGutter;
+use rusty::git_panel::GitPanel;
+use rusty::grep::GrepResults;
+use rusty::hex::HexView;
+use rusty::highlight::HighlightCache;
+use rusty::history::LocalHistory;
+use rusty::input::{InputHandler, InputMode, Command, ViewAlign};
+use rusty::layout::{Layout, Rect, SplitDirection};
+use rusty::lsp::LspClient;
+use rusty::pairs::AutoPairs;
+use rusty::plugins::PluginHost;
+use rusty::positions::CursorPositions;
+use rusty::recovery::SwapWriter;
+use rusty::registers::Registers;
+use rusty::search::{MatchList, ReplaceWalk};
+use rusty::scripting::ScriptHost;
+use rusty::search_history::SearchHistory;
+#[cfg(unix)]
+use crate::session::Inbox;
+use rusty::render::Renderer;
+use rusty::signs::{Sign, SignColumn, PRIORITY_ERROR, PRIORITY_FOLD, PRIORITY_WARNING};
+use rusty::snippets::{ActiveSnippet, Snippet};
+use rusty::stats::SessionStats;
+use rusty::status::{git_branch, StatusFormat, StatusInfo, StatusLine};
+use rusty::syntax::{SyntaxLanguage, SyntaxTree};
+use rusty::tasks::TaskRunner;
+use rusty::theme::Theme;
+use rusty::tree::FileTree;
+use rusty::watch::FileWatcher;
+use rusty::words::{WordIndex, WordMenu};
+use crossterm::style::Color;
+use ropey::Rope;
 use std::collections::HashSet;
-use std::time::{Duration, Instant};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Without sessions there is no socket for `rusty --remote` to reach.
+#[cfg(not(unix))]
+struct Inbox;
+
+#[cfg(not(unix))]
+impl Inbox {
+    fn listen() -> Option<Self> {
+        None
+    }
+
+    fn next(&self) -> Option<String> {
+        None
+    }
+}
+
+fn modified_time(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Puts `state` in place of the active buffer, in the editor and in the
+/// locals kept beside it, leaving the active buffer's state in `state`.
+#[allow(clippy::too_many_arguments)]
+fn swap_buffer(
+    state: &mut BufferState,
+    editor: &mut Editor,
+    path: &mut Option<String>,
+    viewport_row: &mut usize,
+    syntax: &mut Option<SyntaxTree>,
+    highlights: &mut HighlightCache,
+    disk_base: &mut Rope,
+    disk_mtime: &mut Option<SystemTime>,
+    disk_changed: &mut bool,
+    lsp: &mut Option<LspClient>,
+    branch: &mut Option<String>,
+) {
+    editor.swap_buffer(state);
+    std::mem::swap(path, &mut state.path);
+    std::mem::swap(viewport_row, &mut state.viewport_row);
+    std::mem::swap(syntax, &mut state.syntax);
+    std::mem::swap(highlights, &mut state.highlights);
+    std::mem::swap(disk_base, &mut state.disk_base);
+    std::mem::swap(disk_mtime, &mut state.disk_mtime);
+    std::mem::swap(disk_changed, &mut state.disk_changed);
+    std::mem::swap(lsp, &mut state.lsp);
+    std::mem::swap(branch, &mut state.branch);
+}
+
+/// Top line after scrolling `top` by `delta` visible lines, stopping at the
+/// last line that may be at the top of `rows` rows.
+fn scroll_viewport(buffer: &EditorBuffer, folds: &FoldSet, top: usize, delta: isize, rows: usize, past_end: bool) -> usize {
+    let last_top = if past_end { buffer.len_lines() - 1 } else { buffer.len_lines().saturating_sub(rows) };
+    let mut top = top;
+    for _ in 0..delta.unsigned_abs() {
+        if delta < 0 && top > 0 {
+            top = folds.prev_visible_line(buffer, top);
+        } else if delta > 0 && top < last_top {
+            top = folds.next_visible_line(buffer, top);
+        }
+    }
+    top
+}
+
+/// The visible line `count` visible lines below `line`, stopping at the last line.
+fn lines_below(buffer: &EditorBuffer, folds: &FoldSet, line: usize, count: usize) -> usize {
+    let mut line = line;
+    for _ in 0..count {
+        let next = folds.next_visible_line(buffer, line);
+        if next >= buffer.len_lines() {
+            break;
+        }
+        line = next;
+    }
+    line
+}
+
+/// The visible line `count` visible lines above `line`, stopping at the first line.
+fn lines_above(buffer: &EditorBuffer, folds: &FoldSet, line: usize, count: usize) -> usize {
+    let mut line = line;
+    for _ in 0..count {
+        if line == 0 {
+            break;
+        }
+        line = folds.prev_visible_line(buffer, line);
+    }
+    line
+}
+
+/// Lines of the old screen still shown after PageUp/PageDown.
+const PAGE_OVERLAP: usize = 2;
+
+/// Files smaller than this load too quickly to be worth a progress report.
+const LOAD_PROGRESS_MIN: u64 = 8 << 20;
+
+/// Letters of a word typed before its completions pop up by themselves.
+const AUTO_COMPLETE_MIN_LEN: usize = 3;
+
+/// Shows how far loading `path` has got on the status row.
+fn draw_load_progress(out: &mut impl Backend, row: u16, path: &str, done: u64, total: u64) {
+    if total < LOAD_PROGRESS_MIN {
+        return;
+    }
+    let _ = out.move_to(0, row);
+    let _ = out.clear_region(ClearRegion::Line);
+    let _ = out.print(&format!("Loading {}… {}%", path, done * 100 / total));
+    let _ = out.flush();
+}
+
+/// Loads `path` into a new buffer with its syntax tree and language server.
+fn open_buffer(config: &Config, path: &str, status_message: &mut String, progress: impl FnMut(u64, u64)) -> std::io::Result<BufferState> {
+    Ok(buffer_state(config, path, loader::load(path, progress)?, status_message))
+}
+
+/// Sets up the state of a buffer just loaded from `path`.
+fn buffer_state(config: &Config, path: &str, buffer: EditorBuffer, status_message: &mut String) -> BufferState {
+    let mut state = BufferState::new(buffer, Some(path.to_string()), config.editor.undo);
+    state.disk_mtime = modified_time(path);
+    state.syntax = SyntaxLanguage::from_path(path).map(SyntaxTree::new);
+    if let Some(syntax) = state.syntax.as_mut() {
+        syntax.parse(&state.buffer);
+    }
+    if remote::is_url(path) {
+        *status_message = format!("{} is read-only; Save As writes a local copy", path);
+    }
+    // Language servers and git only see local files
+    if remote::is_remote(path) {
+        return state;
+    }
+    state.lsp = start_lsp(config, path, &state.buffer, status_message);
+    state.branch = Path::new(path).parent().and_then(git_branch).or_else(|| git_branch(Path::new(".")));
+    state
+}
+
+/// Asks whether to recover the swap file left behind for a freshly opened
+/// file, returning its text for the answer.
+fn offer_recovery(path: &str, rope: &Rope, input_handler: &mut InputHandler, status_message: &mut String) -> Option<String> {
+    let text = recovery::find(path, rope)?;
+    *status_message = format!("Recover unsaved changes to {}? (y/n)", path);
+    input_handler.start_recover_prompt();
+    Some(text)
+}
+
+/// Asks for the secret of `path` when it is encrypted and not open yet;
+/// otherwise opens it in the hex view when it is binary, or switches to
+/// its buffer if it is open or starts loading it in the background.
+/// Returns the buffer to switch to.
+fn open_file(
+    path: &str,
+    hex_view: &mut Option<HexView>,
+    input_handler: &mut InputHandler,
+    buffers: &BufferList,
+    current_path: Option<&str>,
+    file_worker: &mut FileWorker,
+    status_message: &mut String,
+) -> Option<usize> {
+    // A file that is already open is switched to, not loaded twice
+    let encrypted = Cipher::for_path(path).filter(|_| buffers.find(path, current_path).is_none());
+    if let Some(cipher) = encrypted {
+        *status_message = format!("{} for {}", cipher.secret_name(), path);
+        input_handler.start_secret_prompt(path, cipher.masks_secret());
+    } else if hex::is_binary(path) {
+        match HexView::open(path) {
+            Ok(view) => {
+                *hex_view = Some(view);
+                input_handler.start_hex_view();
+            }
+            Err(err) => *status_message = format!("Could not open {}: {}", path, err),
+        }
+    } else if let Some(index) = buffers.find(path, current_path) {
+        return Some(index);
+    } else if file_worker.is_busy(path) {
+        *status_message = format!("Still loading {}", path);
+    } else {
+        file_worker.open(path);
+    }
+    None
+}
+
+/// The file tree's rectangle while it is shown, and the text area beside it.
+fn sidebar_split(file_tree: &FileTree, cols: u16, lines: usize) -> (Option<Rect>, Rect) {
+    let height = lines as u16;
+    if !file_tree.visible {
+        return (None, Rect { x: 0, y: 1, width: cols, height });
+    }
+    // One column between them for the separator
+    let width = file_tree.width(cols);
+    (Some(Rect { x: 0, y: 1, width, height }), Rect { x: width + 1, y: 1, width: cols - width - 1, height })
+}
+
+/// A renderer for `lines` rows of text, set up from the config.
+fn new_renderer(config: &Config, theme: &Theme, lines: usize, cols: usize) -> Renderer {
+    let status_format = config.status_line.format.as_deref().map(StatusFormat::parse);
+    let mut renderer = Renderer::new(lines, cols, StatusLine::new(config.status_line.segments.clone(), status_format));
+    renderer.sign_column = config.editor.sign_column;
+    renderer.screen_reader = config.accessibility.screen_reader;
+    renderer.tab_width = config.editor.tab_width.max(1);
+    renderer.show_invisibles = config.editor.show_invisibles;
+    renderer.soft_wrap = config.editor.soft_wrap;
+    renderer.line_numbers = config.editor.line_numbers;
+    // Column 0 does not exist
+    renderer.rulers = config.editor.rulers.iter().copied().filter(|&ruler| ruler > 0).collect();
+    renderer.theme = theme.clone();
+    renderer
+}
+
+/// Start of the identifier that ends at `char_idx`, the text a completion replaces.
+fn word_start(buffer: &EditorBuffer, char_idx: usize) -> usize {
+    let mut start = char_idx;
+    while start > 0 && words::is_word_char(buffer.rope.char(start - 1)) {
+        start -= 1;
+    }
+    start
+}
+
+/// Where the trigger word of a snippet starts, if one ends at `cursor`,
+/// and the snippet's body.
+fn snippet_before<'a>(config: &'a SnippetsConfig, path: Option<&str>, buffer: &EditorBuffer, cursor: usize) -> Option<(usize, &'a str)> {
+    let start = word_start(buffer, cursor);
+    if start == cursor {
+        return None;
+    }
+    snippets::find(config, path, &buffer.slice(start..cursor)).map(|body| (start, body))
+}
+
+/// Selection anchor and cursor that select the text of a snippet stop.
+fn select_stop((start, end): (usize, usize)) -> (Option<usize>, usize) {
+    ((end > start).then_some(start), end)
+}
+
+/// Starts the language server configured for `path`'s extension, if any.
+fn start_lsp(config: &Config, path: &str, buffer: &EditorBuffer, status_message: &mut String) -> Option<LspClient> {
+    let extension = Path::new(path).extension()?.to_str()?;
+    let command = config.lsp.servers.get(extension)?;
+    match LspClient::start(command, path, buffer.rope.clone()) {
+        Ok(client) => Some(client),
+        Err(err) => {
+            *status_message = format!("Could not start {}: {}", command, err);
+            None
+        }
+    }
+}
 
 fn main() -> Result<()> {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("rusty: {}\n\n{}", err, USAGE);
+            std::process::exit(2);
+        }
+    };
+    if args.version {
+        println!("rusty {}", env!("CARGO_PKG_VERSION"));
+        return Ok(());
+    }
+    if args.help {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    #[cfg(unix)]
+    {
+        if let Some(name) = args.serve.as_deref() {
+            return session::serve(name);
+        }
+        if args.sessions {
+            session::list().iter().for_each(|name| println!("{}", name));
+            return Ok(());
+        }
+        if let Some(name) = args.session.as_deref() {
+            let raw: Vec<String> = std::env::args().skip(1).collect();
+            return session::attach(name, &raw, args.file.as_deref());
+        }
+        // Without an editor to hand it to, the file is opened here
+        if args.remote
+            && let Some(path) = args.file.as_deref()
+            && session::open_in_running(path)
+        {
+            return Ok(());
+        }
+    }
+    // Sessions run over Unix sockets and pseudo-terminals
+    #[cfg(not(unix))]
+    if args.serve.is_some() || args.sessions || args.session.is_some() || args.remote {
+        eprintln!("rusty: sessions and --remote need a Unix system");
+        std::process::exit(2);
+    }
+    // `rusty --diff a b` starts straight in the side-by-side diff view
+    let mut diff_view = match args.diff.as_ref() {
+        Some((left, right)) => Some(DiffView::from_files(left, right)?),
+        None => None,
+    };
+
+    if args.trust
+        && let Err(err) = config::trust(Path::new("."))
+    {
+        eprintln!("rusty: could not trust this directory: {}", err);
+        std::process::exit(1);
+    }
+    let (config, config_error) = Config::load();
+    let (mut theme, theme_error) = match Theme::load(&config.editor.theme) {
+        Ok(theme) => (theme, None),
+        Err(err) => (Theme::dark(), Some(err)),
+    };
+    // Exports keep the theme's own colors; the screen gets what the terminal shows
+    let export_theme = theme.clone();
+    theme.convert_colors(config.editor.colors.resolve());
+
     let mut stdout = stdout();
     stdout.execute(EnterAlternateScreen)?;
+    stdout.execute(EnableMouseCapture)?;
+    stdout.execute(EnableFocusChange)?;
     enable_raw_mode()?;
 
-    let (cols, rows) = crossterm::terminal::size()?;
-    let max_lines = (rows - 2) as usize;
+    let (mut cols, rows) = crossterm::terminal::size()?;
+    let mut screen_lines = (rows - 2) as usize;
 
     // State setup
-    let mut buffer = EditorBuffer::new();
-    let mut undo_redo = UndoRedoStacks::new();
+    // The active buffer with its undo history, folds and cursor
+    let mut editor = Editor::with_config(&config.editor);
     let mut input_handler = InputHandler::new();
-    let mut renderer = Renderer::new(max_lines);
+    // Renderer of the active pane; the other panes keep theirs in the layout
+    let mut renderer = new_renderer(&config, &theme, screen_lines, cols as usize);
+    let mut layout = Layout::new(renderer.area);
+    // Set when the whole screen was painted over and the panes must be redrawn
+    let mut redraw_panes = false;
+    let no_signs = SignColumn::new();
+    let screen_reader = config.accessibility.screen_reader;
+    let mut announcer = Announcer::new(config.accessibility.announce_file.as_deref());
+    let mut signs = SignColumn::new();
+    let mut git_gutter = GitGutter::new();
+    let mut git_panel = GitPanel::new();
+    let mut syntax: Option<SyntaxTree> = None;
+    let mut highlights = HighlightCache::new();
+    let mut clipboard = Clipboard::new(config.clipboard.backend);
+    let mut registers = Registers::new();
+    // Range and kill ring index of the text just pasted, so Alt+V can swap it
+    let mut last_paste: Option<(usize, usize, Option<usize>)> = None;
+    // The last command was Ctrl+K, so another one adds to the same kill
+    let mut killed_line = false;
+    let mut build = BuildRunner::new();
+    let mut lsp: Option<LspClient> = None;
+    let mut tasks = TaskRunner::new();
+    let mut history = LocalHistory::new(&config.history);
+    let mut autosave = Autosave::new(&config.autosave);
+    let mut swap = SwapWriter::new(&config.recovery);
+    let mut watcher = FileWatcher::new();
+    let mut file_worker = FileWorker::new();
+    let (mut plugins, plugin_errors) = PluginHost::load(config::config_dir().map(|dir| dir.join("plugins")).as_deref());
+    let (scripts, script_error) = ScriptHost::load(config::config_dir().map(|dir| dir.join("init.rhai")).as_deref());
+    // Files `rusty --remote` asks this editor to open
+    let inbox = Inbox::listen();
+    // A buffer opened in the background, waiting to be switched to
+    let mut loaded_buffer: Option<BufferState> = None;
+    let mut search_history = SearchHistory::new();
+    let mut search_matches = MatchList::new();
+    let mut auto_pairs = AutoPairs::new();
+    let mut snippet: Option<ActiveSnippet> = None;
+    // Matches visited so far by an interactive replace
+    let mut replace_walk: Option<ReplaceWalk> = None;
+    let mut grep_results = GrepResults::new();
+    let mut file_tree = FileTree::new(Path::new("."));
+    // Set when the file tree sidebar must be drawn again
+    let mut redraw_tree = false;
+    // File of a search result or build error being opened in the background,
+    // and the line and column to show
+    let mut open_target: Option<(String, usize, usize)> = None;
+    // Text of a swap file waiting for the recover prompt to be answered
+    let mut pending_recovery: Option<String> = None;
+    let mut stats = SessionStats::new();
+    let mut positions = CursorPositions::new();
+    let mut current_path: Option<String> = None;
+    // Open buffers other than the active one, whose state is in the locals here
+    let mut buffers = BufferList::new();
+    let mut status_message = config_error.or(theme_error).or(script_error).or(plugin_errors.into_iter().next()).unwrap_or_default();
+    let mut branch = git_branch(Path::new("."));
+    // Content last read from or written to disk, the common ancestor for merges
+    let mut disk_base = Rope::new();
+    let mut disk_mtime: Option<SystemTime> = None;
+    let mut disk_changed = false;
+    if diff_view.is_some() {
+        input_handler.start_diff_view();
+    }
 
     let mut viewport_row = 0;
-    let mut cursor_char_idx = 0;
-    let mut dirty_lines = (0..max_lines).collect::<HashSet<_>>();
+    // Set while the mouse wheel has scrolled the cursor out of view; the
+    // view follows the cursor again with the next key
+    let mut viewport_detached = false;
+    // Set by a Ctrl+Q that was refused because of unsaved changes
+    let mut quit_armed = false;
+    editor.dirty_lines.extend(0..screen_lines);
+
+    // A file named on the command line; one that does not exist yet is
+    // created on the first save, and a binary one is shown as hex
+    let mut hex_view: Option<HexView> = None;
+    if let Some(path) = args.file.as_deref()
+        && let Some(cipher) = Cipher::for_path(path)
+    {
+        status_message = format!("{} for {}", cipher.secret_name(), path);
+        input_handler.start_secret_prompt(path, cipher.masks_secret());
+    } else if let Some(path) = args.file.as_deref()
+        && hex::is_binary(path)
+    {
+        match HexView::open(path) {
+            Ok(view) => {
+                hex_view = Some(view);
+                input_handler.start_hex_view();
+            }
+            Err(err) => status_message = format!("Could not open {}: {}", path, err),
+        }
+    } else if let Some(path) = args.file.as_deref() {
+        let progress = |done, total| draw_load_progress(&mut stdout, screen_lines as u16 + 1, path, done, total);
+        match open_buffer(&config, path, &mut status_message, progress) {
+            Ok(mut state) => {
+                swap_buffer(
+                    &mut state,
+                    &mut editor,
+                    &mut current_path,
+                    &mut viewport_row,
+                    &mut syntax,
+                    &mut highlights,
+                    &mut disk_base,
+                    &mut disk_mtime,
+                    &mut disk_changed,
+                    &mut lsp,
+                    &mut branch,
+                );
+                history.reset(&editor.buffer.rope);
+                if !args.read_only {
+                    pending_recovery = offer_recovery(path, &editor.buffer.rope, &mut input_handler, &mut status_message);
+                }
+                if let Some(line) = args.line {
+                    let line = line.saturating_sub(1).min(editor.buffer.len_lines() - 1);
+                    editor.cursor = editor.buffer.line_to_char(line);
+                } else if let Some(cursor) = positions.recall(path, &editor.buffer) {
+                    // Back where the file was left last time, in the middle of the screen
+                    editor.cursor = cursor;
+                    viewport_row = editor.buffer.char_to_line(cursor).saturating_sub(screen_lines / 2);
+                }
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                current_path = Some(path.to_string());
+                status_message = format!("New file: {}", path);
+            }
+            Err(err) => status_message = format!("Could not open {}: {}", path, err),
+        }
+    }
+    let read_only = args.read_only;
+    if read_only && status_message.is_empty() {
+        status_message = "Read-only".to_string();
+    }
+    // Words of all open buffers, offered to complete the one being typed
+    let mut words = WordIndex::new();
+    words.add(editor.buffer.rope.slice(..));
+    let mut word_menu: Option<WordMenu> = None;
 
     let mut cursor_visible = true;
     let mut last_cursor_toggle = Instant::now();
     const CURSOR_BLINK_INTERVAL: Duration = Duration::from_millis(500);
+    // Text brought back by undo or redo is highlighted this long
+    const UNDO_FLASH_DURATION: Duration = Duration::from_millis(600);
+    let mut undo_flash: Option<((usize, usize), Instant)> = None;
+    // Longest wait for input before timers and background work are checked
+    const IDLE_TICK: Duration = Duration::from_millis(50);
 
     'mainloop: loop {
+        // Rows of the active pane
+        let max_lines = renderer.area.height as usize;
+
         // Blink cursor timing
-        if last_cursor_toggle.elapsed() >= CURSOR_BLINK_INTERVAL {
+        if !screen_reader && last_cursor_toggle.elapsed() >= CURSOR_BLINK_INTERVAL {
             cursor_visible = !cursor_visible;
             last_cursor_toggle = Instant::now();
         }
+        input_handler.expire_chord();
+        if let Some(((start, end), since)) = undo_flash
+            && since.elapsed() >= UNDO_FLASH_DURATION
+        {
+            let len = editor.buffer.len_chars();
+            editor.dirty_lines.extend(editor.buffer.char_to_line(start.min(len))..=editor.buffer.char_to_line(end.min(len)));
+            undo_flash = None;
+        }
+
+        // The engine moves through wrapped rows as the renderer lays them out
+        editor.wrap_width = renderer.soft_wrap.then(|| renderer.text_width());
 
         // Calculate current line and cursor col
-        let current_line = buffer.char_to_line(cursor_char_idx);
-        let line_start_char_idx = buffer.line_to_char(current_line);
-        let cursor_col = cursor_char_idx.saturating_sub(line_start_char_idx);
-
-        // Adjust viewport for cursor
-        if current_line < viewport_row {
-            viewport_row = current_line;
-            dirty_lines.extend(viewport_row..viewport_row+max_lines);
-        } else if current_line >= viewport_row + max_lines {
-            viewport_row = current_line - max_lines + 1;
-            dirty_lines.extend(viewport_row..viewport_row+max_lines);
-        }
-
-        // Rendering
-        renderer.render(
-            &mut stdout,
-            &buffer,
-            &dirty_lines,
-            viewport_row,
-            max_lines,
-            cursor_col,
-            current_line,
-            cursor_visible,
-            input_handler.get_mode(),
-            &input_handler.filename_input,
-            &input_handler.find_input,
-            &input_handler.confirmed_find_term,
-        )?;
-        dirty_lines.clear();
-
-        // Input handling
-        if let Some(command) = input_handler.process_input()? {
+        let current_line = editor.buffer.char_to_line(editor.cursor);
+        let line_start_char_idx = editor.buffer.line_to_char(current_line);
+        let cursor_col = editor.cursor.saturating_sub(line_start_char_idx);
+        // Where the cursor is on screen, tabs taken into account
+        let cursor_display_col = editor.cursor_display_col();
+
+        // Adjust viewport for cursor, counting closed folds as a single row
+        // and keeping the scroll-off margin in view around it
+        viewport_row = editor.folds.visible_line(&editor.buffer, viewport_row);
+        let scroll_off = config.editor.scroll_off.min(max_lines.saturating_sub(1) / 2);
+        let margin_top = lines_above(&editor.buffer, &editor.folds, current_line, scroll_off);
+        let margin_bottom = lines_below(&editor.buffer, &editor.folds, current_line, scroll_off);
+        let mut lowest_top = margin_bottom;
+        if renderer.soft_wrap {
+            // Count screen rows, down to the one the cursor is on and the margin below it
+            let starts = editor.row_starts(current_line);
+            let mut rows = starts.partition_point(|&start| start <= cursor_col);
+            let mut line = current_line;
+            while line < margin_bottom {
+                line = editor.folds.next_visible_line(&editor.buffer, line);
+                rows += editor.row_starts(line).len();
+            }
+            lowest_top = current_line;
+            while lowest_top > viewport_row {
+                let prev = editor.folds.prev_visible_line(&editor.buffer, lowest_top);
+                rows += editor.row_starts(prev).len();
+                if rows > max_lines {
+                    break;
+                }
+                lowest_top = prev;
+            }
+        } else {
+            for _ in 1..max_lines {
+                if lowest_top <= viewport_row {
+                    break;
+                }
+                lowest_top = editor.folds.prev_visible_line(&editor.buffer, lowest_top);
+            }
+        }
+        if !viewport_detached {
+            if margin_top < viewport_row {
+                viewport_row = margin_top;
+                editor.dirty_lines.extend(viewport_row..viewport_row+max_lines);
+            } else if lowest_top > viewport_row {
+                viewport_row = lowest_top;
+                editor.dirty_lines.extend(viewport_row..viewport_row+max_lines);
+            }
+        }
+
+        let selection = editor.selection();
+        search_matches.update(input_handler.search.as_ref(), &editor.buffer);
+
+        // Keys already queued up (key repeat, a paste without bracketed paste)
+        // are all applied first: polling the background work, rebuilding the
+        // signs, announcing and rendering happen once after the burst.
+        if !input_handler.input_pending()? {
+            // Pick up files opened and saved in the background
+            for event in file_worker.poll() {
+                match event {
+                    FileEvent::Saved { buffer: index, path, rope, encrypted, autosave, result: Ok(()) } => {
+                        if !autosave && !encrypted {
+                            let _ = history.snapshot(&path, &rope);
+                            stats.record_save();
+                            stats.persist();
+                        }
+                        // Indices shift when buffers close, so go by path where it is known
+                        let index = buffers.find(&path, current_path.as_deref()).unwrap_or(index);
+                        if index == buffers.active {
+                            disk_base = rope;
+                            editor.buffer.dirty = editor.buffer.rope != disk_base;
+                            disk_mtime = modified_time(&path);
+                            disk_changed = false;
+                            current_path = Some(path);
+                        } else if let Some(state) = buffers.get_mut(index) {
+                            state.disk_base = rope;
+                            state.buffer.dirty = state.buffer.rope != state.disk_base;
+                            state.disk_mtime = modified_time(&path);
+                            state.disk_changed = false;
+                            state.path = Some(path);
+                        }
+                    }
+                    FileEvent::Saved { path, autosave: true, result: Err(err), .. } => {
+                        status_message = format!("Autosave failed: {}: {}", path, err);
+                    }
+                    FileEvent::Saved { path, result: Err(err), .. } => {
+                        status_message = format!("Could not save {}: {}", path, err);
+                    }
+                    FileEvent::Opened { path, result: Ok(new_buffer) } => {
+                        status_message.clear();
+                        let mut state = buffer_state(&config, &path, new_buffer, &mut status_message);
+                        if let Some(cursor) = positions.recall(&path, &state.buffer) {
+                            state.cursor_char_idx = cursor;
+                            state.viewport_row = state.buffer.char_to_line(cursor).saturating_sub(screen_lines / 2);
+                        }
+                        loaded_buffer = Some(state);
+                    }
+                    FileEvent::Opened { path, result: Err(err) } => {
+                        status_message = format!("Could not open {}: {}", path, err);
+                    }
+                }
+            }
+            if let Some((path, done, total)) = file_worker.progress.as_ref()
+                && *total >= LOAD_PROGRESS_MIN
+            {
+                status_message = format!("Loading {}… {}%", path, done * 100 / total);
+            }
+
+            // Write modified buffers back to their files
+            if !read_only && autosave.due() {
+                if editor.buffer.dirty
+                    && !disk_changed
+                    && let Some(path) = current_path.as_deref()
+                    && !file_worker.is_busy(path)
+                {
+                    file_worker.save(buffers.active, path, editor.buffer.clone(), &config.backup, true);
+                }
+                for index in 0..buffers.len() {
+                    if let Some(state) = buffers.get_mut(index)
+                        && state.buffer.dirty
+                        && !state.disk_changed
+                        && let Some(path) = state.path.as_deref()
+                        && !file_worker.is_busy(path)
+                    {
+                        file_worker.save(index, path, state.buffer.clone(), &config.backup, true);
+                    }
+                }
+            }
+
+            // The plain text of an encrypted file never goes to disk
+            swap.tick(current_path.as_deref().filter(|_| editor.buffer.encryption.is_none()), &editor.buffer.rope, editor.buffer.dirty);
+
+            // Notice the file changing on disk: reload it if the buffer is clean,
+            // otherwise leave it to `:merge`
+            // Our own saves are not changes from outside, so wait for them
+            watcher.watch(current_path.as_deref());
+            if current_path.as_deref().is_none_or(|path| !file_worker.is_busy(path))
+                && watcher.changed()
+                && let Some(path) = current_path.as_deref()
+                && let Some(mtime) = modified_time(path)
+                && disk_mtime != Some(mtime)
+            {
+                disk_mtime = Some(mtime);
+                if editor.buffer.rope == disk_base {
+                    let reloaded = match editor.buffer.encryption.clone() {
+                        Some(encryption) => buffer::open_encrypted(path, encryption),
+                        None => buffer::open_file(path),
+                    };
+                    if let Ok(new_buffer) = reloaded {
+                        words.remove(editor.buffer.rope.slice(..));
+                        editor.buffer = new_buffer;
+                        words.add(editor.buffer.rope.slice(..));
+                        search_matches.invalidate();
+                        disk_base = editor.buffer.rope.clone();
+                        editor.undo_redo = UndoTree::new(config.editor.undo);
+                        editor.folds = FoldSet::new();
+                        if let Some(syntax) = syntax.as_mut() {
+                            syntax.parse(&editor.buffer);
+                        }
+                        highlights.clear();
+                        if let Some(client) = lsp.as_mut() {
+                            client.did_replace(&editor.buffer.rope);
+                        }
+                        editor.cursor = editor.cursor.min(editor.buffer.len_chars());
+                        editor.dirty_lines.extend(0..editor.buffer.len_lines().max(viewport_row + max_lines));
+                        status_message = format!("Reloaded {}", path);
+                        continue;
+                    }
+                } else {
+                    disk_changed = true;
+                    status_message = format!("{} changed on disk; Ctrl+L :merge to merge it in", path);
+                }
+            }
+
+            // Pick up finished builds and mark their diagnostics in the gutter
+            match build.poll() {
+                Some(Ok(())) => {
+                    status_message = format!(
+                        "Build finished: {} errors, {} warnings",
+                        build.count(Severity::Error),
+                        build.count(Severity::Warning)
+                    );
+                    editor.dirty_lines.extend(viewport_row..viewport_row + max_lines);
+                }
+                Some(Err(err)) => status_message = format!("Could not run {}: {}", config.build.command, err),
+                None => {}
+            }
+            // Language server replies arrive in the background
+            if let Some(client) = lsp.as_mut() {
+                if client.has_exited() {
+                    lsp = None;
+                    status_message = "Language server exited".to_string();
+                    if *input_handler.get_mode() == InputMode::Completion {
+                        input_handler.mode = InputMode::Editing;
+                    }
+                } else if client.poll()
+                    && *input_handler.get_mode() == InputMode::Completion
+                    && word_menu.is_none()
+                    && !client.is_waiting()
+                    && client.completions.is_empty()
+                {
+                    input_handler.mode = InputMode::Editing;
+                    status_message = "No completions".to_string();
+                }
+            }
+
+            let lsp_diagnostics = lsp.iter().flat_map(|client| client.diagnostics.iter());
+            signs.clear_source("diagnostics");
+            let mut cursor_diagnostic = None;
+            if let Some(canonical) = current_path.as_ref().and_then(|p| Path::new(p).canonicalize().ok()) {
+                for diagnostic in build.quickfix.iter().chain(lsp_diagnostics).filter(|d| d.canonical_path.as_ref() == Some(&canonical)) {
+                    // Distinct glyphs in screen-reader mode, not just colors
+                    let sign = match diagnostic.severity {
+                        Severity::Error => Sign { glyph: if screen_reader { 'E' } else { '●' }, color: Color::Red, priority: PRIORITY_ERROR },
+                        Severity::Warning => {
+                            Sign { glyph: if screen_reader { 'W' } else { '●' }, color: Color::Yellow, priority: PRIORITY_WARNING }
+                        }
+                    };
+                    signs.place(diagnostic.line, "diagnostics", sign);
+                    if diagnostic.line == current_line && cursor_diagnostic.is_none() {
+                        cursor_diagnostic = Some(format!(
+                            "{}:{}:{}: {}",
+                            diagnostic.path,
+                            diagnostic.line + 1,
+                            diagnostic.col + 1,
+                            diagnostic.message
+                        ));
+                    }
+                }
+            }
+            git_gutter.update(current_path.as_deref(), &editor.buffer.rope);
+            signs.clear_source("git");
+            for &(line, change) in git_gutter.markers() {
+                signs.place(line, "git", change.sign());
+            }
+            signs.clear_source("folds");
+            for fold in editor.folds.folds() {
+                let glyph = if fold.closed { '▸' } else { '▾' };
+                signs.place(fold.start_line(&editor.buffer), "folds", Sign { glyph, color: Color::DarkGrey, priority: PRIORITY_FOLD });
+            }
+
+            let mut message = cursor_diagnostic.as_deref().unwrap_or(&status_message);
+            if screen_reader {
+                let line_text = editor.buffer.line(current_line).to_string();
+                announcer.update(input_handler.mode_name(), current_line, line_text.trim_end_matches(['\n', '\r']), message);
+                if !announcer.has_output() {
+                    message = &announcer.latest;
+                }
+            }
+
+            tasks.poll();
+
+            if let Some(view) = hex_view.as_ref() {
+                renderer.render_hex(&mut stdout, view, &status_message)?;
+                redraw_panes = true;
+            } else if let Some(view) = diff_view.as_ref() {
+                renderer.render_diff(&mut stdout, view)?;
+                redraw_panes = true;
+            } else if *input_handler.get_mode() == InputMode::TaskPicker {
+                let labels: Vec<String> = tasks.tasks.iter().map(|t| t.label.clone()).collect();
+                let top = tasks.selected.saturating_sub(screen_lines.saturating_sub(1));
+                let footer = if labels.is_empty() { "No tasks found  q: close" } else { "Enter: run  j/k: move  q: close" };
+                renderer.render_panel(&mut stdout, "Tasks", &labels, top, Some(tasks.selected), footer)?;
+            } else if *input_handler.get_mode() == InputMode::BufferPicker {
+                let labels = buffers.labels(current_path.as_deref(), editor.buffer.dirty);
+                let top = buffers.selected.saturating_sub(screen_lines.saturating_sub(1));
+                renderer.render_panel(&mut stdout, "Buffers", &labels, top, Some(buffers.selected), "Enter: switch  j/k: move  q: close")?;
+            } else if *input_handler.get_mode() == InputMode::GrepResults {
+                let labels = grep_results.labels();
+                let top = grep_results.selected.saturating_sub(screen_lines.saturating_sub(1));
+                let title = format!(
+                    "Grep {}: {}{} results",
+                    input_handler.find_input,
+                    if grep_results.truncated { "first " } else { "" },
+                    labels.len()
+                );
+                renderer.render_panel(&mut stdout, &title, &labels, top, Some(grep_results.selected), "Enter: open  j/k: move  q: close")?;
+            } else if *input_handler.get_mode() == InputMode::ErrorList {
+                let labels = build.labels();
+                let selected = build.selected.unwrap_or(0);
+                let top = selected.saturating_sub(screen_lines.saturating_sub(1));
+                let title = format!("Build errors: {}", config.build.command);
+                renderer.render_panel(&mut stdout, &title, &labels, top, Some(selected), "Enter: open  j/k: move  q: close")?;
+            } else if *input_handler.get_mode() == InputMode::UndoTree {
+                let labels = editor.undo_redo.labels();
+                let top = editor.undo_redo.selected.saturating_sub(screen_lines.saturating_sub(1));
+                renderer.render_panel(&mut stdout, "Undo tree", &labels, top, Some(editor.undo_redo.selected), "Enter: go to state  j/k: move  q: close")?;
+            } else if *input_handler.get_mode() == InputMode::HistoryBrowser {
+                let labels = history.labels();
+                let top = history.selected.saturating_sub(screen_lines.saturating_sub(1));
+                let footer = if labels.is_empty() {
+                    "No snapshots  q: close"
+                } else {
+                    "Enter/d: view diff  r: restore  j/k: move  q: close"
+                };
+                renderer.render_panel(&mut stdout, "Local history", &labels, top, Some(history.selected), footer)?;
+            } else if *input_handler.get_mode() == InputMode::GitPanel {
+                let labels = git_panel.labels();
+                let top = git_panel.selected.saturating_sub(screen_lines.saturating_sub(1));
+                let footer = if labels.is_empty() {
+                    "Nothing to commit  c: commit  r: refresh  q: close"
+                } else {
+                    "Space/s: stage or unstage  Enter: open  c: commit  r: refresh  j/k: move  q: close"
+                };
+                renderer.render_panel(&mut stdout, "Git status", &labels, top, Some(git_panel.selected), footer)?;
+            } else if *input_handler.get_mode() == InputMode::TaskOutput {
+                let lines = tasks.output_lines();
+                let top = tasks.scroll.unwrap_or(lines.len().saturating_sub(screen_lines));
+                let footer = format!("{}  Ctrl+C: kill  r: re-run  j/k: scroll  q: close", tasks.status);
+                renderer.render_panel(&mut stdout, "Task output", &lines, top, None, &footer)?;
+                drop(lines);
+            } else {
+                if redraw_panes {
+                    for pane in layout.panes.iter_mut() {
+                        pane.renderer.iter_mut().for_each(Renderer::invalidate);
+                    }
+                    renderer.draw_separators(&mut stdout, &layout.separators())?;
+                    redraw_panes = false;
+                    redraw_tree = true;
+                }
+                if redraw_tree && let (Some(rect), _) = sidebar_split(&file_tree, cols, screen_lines) {
+                    let focused = *input_handler.get_mode() == InputMode::FileTree;
+                    renderer.render_tree(&mut stdout, rect, &file_tree.labels(), file_tree.top, focused.then_some(file_tree.selected))?;
+                }
+                redraw_tree = false;
+                // Other panes first, so the cursor ends up in the active one
+                for pane in layout.panes.iter_mut() {
+                    let Some(pane_renderer) = pane.renderer.as_mut() else {
+                        continue;
+                    };
+                    if pane.buffer == buffers.active {
+                        pane_renderer.render_inactive(
+                            &mut stdout,
+                            &editor.buffer,
+                            &editor.dirty_lines,
+                            pane.viewport_row,
+                            &editor.folds,
+                            &signs,
+                            syntax.as_ref().map(|syntax| (syntax, &mut highlights)),
+                        )?;
+                    } else if let Some(state) = buffers.get_mut(pane.buffer) {
+                        pane_renderer.render_inactive(
+                            &mut stdout,
+                            &state.buffer,
+                            &HashSet::new(),
+                            pane.viewport_row,
+                            &state.folds,
+                            &no_signs,
+                            state.syntax.as_ref().map(|syntax| (syntax, &mut state.highlights)),
+                        )?;
+                    }
+                }
+                renderer.render(
+                    &mut stdout,
+                    &editor.buffer,
+                    &editor.dirty_lines,
+                    viewport_row,
+                    cursor_display_col + editor.virtual_col,
+                    current_line,
+                    // The cursor stays in the text but hides while the tree has the keyboard
+                    cursor_visible && *input_handler.get_mode() != InputMode::FileTree,
+                    input_handler.get_mode(),
+                    &input_handler.shown_input(),
+                    &input_handler.find_input,
+                    input_handler.find_regex,
+                    &search_matches,
+                    selection,
+                    undo_flash.map(|(span, _)| span),
+                    brackets::matching_pair(&editor.buffer, editor.cursor),
+                    &editor.folds,
+                    &signs,
+                    syntax.as_ref().map(|syntax| (syntax, &mut highlights)),
+                    &StatusInfo {
+                        message,
+                        pending_keys: &input_handler.pending_keys(),
+                        mode: input_handler.mode_name(),
+                        file: current_path.as_deref(),
+                        modified: editor.buffer.dirty,
+                        git_branch: branch.as_deref(),
+                        errors: build.count(Severity::Error) + lsp.as_ref().map_or(0, |l| l.count(Severity::Error)),
+                        warnings: build.count(Severity::Warning) + lsp.as_ref().map_or(0, |l| l.count(Severity::Warning)),
+                        encoding: editor.buffer.encoding_name(),
+                        line_ending: editor.buffer.line_ending(),
+                        line: current_line,
+                        col: cursor_col + editor.virtual_col,
+                        total_lines: editor.buffer.line_count(),
+                        matches: search_matches.position(editor.cursor),
+                        editing_secs: stats.session.editing_secs,
+                    },
+                )?;
+                if *input_handler.get_mode() == InputMode::Completion {
+                    if let Some(menu) = word_menu.as_ref() {
+                        renderer.render_popup(&mut stdout, &menu.items, menu.selected)?;
+                    } else if let Some(client) = lsp.as_ref() {
+                        let labels: Vec<String> = client.completions.iter().map(|item| item.label.clone()).collect();
+                        renderer.render_popup(&mut stdout, &labels, client.selected)?;
+                    }
+                }
+            }
+            editor.dirty_lines.clear();
+        }
+
+        // Input handling. Only wait for a key until the next timer is due, so
+        // blinking, builds, tasks and the language server carry on while idle.
+        // A buffer that finished loading is switched to as if by a key, once
+        // no prompt is open, and so is a file asked for from outside.
+        let editing = *input_handler.get_mode() == InputMode::Editing;
+        let command = if loaded_buffer.is_some() && editing {
+            Some(Command::OpenLoadedFile)
+        } else if let Some(path) = inbox.as_ref().filter(|_| editing).and_then(Inbox::next) {
+            Some(Command::OpenRequested(path))
+        } else {
+            let timeout = CURSOR_BLINK_INTERVAL.saturating_sub(last_cursor_toggle.elapsed()).min(IDLE_TICK);
+            if !input_handler.wait_for_input(timeout)? {
+                continue;
+            }
+            input_handler.process_input()?
+        };
+        if let Some(err) = input_handler.chord_error.take() {
+            status_message = err;
+        }
+        if let Some(command) = command {
+            stats.record_key();
+            let prev_cursor = editor.cursor;
+            // The next key ends the undo highlight, whose span edits would leave behind
+            if let Some(((start, end), _)) = undo_flash.take() {
+                let len = editor.buffer.len_chars();
+                editor.dirty_lines.extend(editor.buffer.char_to_line(start.min(len))..=editor.buffer.char_to_line(end.min(len)));
+            }
+            if !matches!(command, Command::WheelUp { .. } | Command::WheelDown { .. }) {
+                viewport_detached = false;
+            }
+            let tree_rect = sidebar_split(&file_tree, cols, screen_lines).0;
+            // A click outside the file tree gives the keyboard back to the text
+            if let Command::MouseDown { column, row } = command
+                && *input_handler.get_mode() == InputMode::FileTree
+                && !tree_rect.is_some_and(|rect| rect.contains(column, row))
+            {
+                input_handler.leave_file_tree();
+                redraw_tree = true;
+            }
+            // Read-only mode turns away anything that would change the file
+            let rejected = match &command {
+                Command::RunEx(line) => matches!(
+                    ex::parse(line, current_line, editor.buffer.len_lines().saturating_sub(1)),
+                    Ok(ExCommand::Substitute { .. }
+                        | ExCommand::Sort { .. }
+                        | ExCommand::Filter { .. }
+                        | ExCommand::Merge
+                        | ExCommand::Transform { .. }
+                        | ExCommand::Call(_))
+                ),
+                command => command.modifies_buffer(),
+            };
+            if read_only && rejected {
+                status_message = "Read-only: the buffer cannot be changed or saved".to_string();
+                continue;
+            }
+            // A page fetched from the web can only be saved elsewhere
+            let from_web = current_path.as_deref().is_some_and(remote::is_url);
+            if from_web && rejected && !matches!(command, Command::SaveFile | Command::StartSaveFile | Command::ConfirmSaveFile) {
+                status_message = "Read-only: save a local copy with Save As to edit it".to_string();
+                continue;
+            }
+            let mut edits: Vec<EditOp> = Vec::new();
+            // A key bound in the init script or taken by a plugin does
+            // nothing else
+            let key_output = match input_handler.last_key.take().filter(|_| !read_only && !from_web) {
+                Some(key) => match scripts.binding(&key) {
+                    Some(function) => Some(scripts.run_command(&function, &editor.buffer.rope, editor.cursor, current_path.as_deref())),
+                    None if plugins.handles_keys() => Some(plugins.on_key(&key, &editor.buffer.rope, editor.cursor, current_path.as_deref())),
+                    None => None,
+                },
+                None => None,
+            };
+            let command = match key_output {
+                Some(mut output) => {
+                    if let Some(status) = output.status.take() {
+                        status_message = status;
+                    }
+                    let handled = output.handled;
+                    edits = editor.apply_hook_output(output);
+                    if handled {
+                        editor.dirty_lines.extend(viewport_row..editor.buffer.len_lines().max(viewport_row + max_lines));
+                        Command::KeyHandled
+                    } else {
+                        command
+                    }
+                }
+                _ => command,
+            };
+            // Buffer to make active once this command is done
+            let mut switch_to: Option<usize> = None;
+            // Line and column to put the cursor on once the switch is done
+            let mut goto: Option<(usize, usize)> = None;
+            // Pane to move the focus to, and whether the active one closes
+            let mut focus_pane: Option<usize> = None;
+            let mut close_pane = false;
+            // Snippet expanded by this command, tracking edits from the next one on
+            let mut new_snippet: Option<ActiveSnippet> = None;
+            // Tab right after a snippet's trigger word expands it
+            let expansion = match command {
+                Command::Indent if snippet.is_none() && selection.is_none_or(|(start, end)| start == end) => {
+                    snippet_before(&config.snippets, current_path.as_deref(), &editor.buffer, editor.cursor)
+                }
+                _ => None,
+            };
+            let prev_paste = last_paste.take();
+            let kill_continues = std::mem::take(&mut killed_line);
+            let quit_confirmed = std::mem::take(&mut quit_armed);
+            // Typing on in a word keeps the word popup matching it
+            let word_typed = match command {
+                Command::InsertChar(c) => words::is_word_char(c),
+                Command::Backspace => word_menu.is_some(),
+                _ => false,
+            };
+            editor.begin_command(&command);
             match command {
+                // Unsaved work needs a second Ctrl+Q in a row
+                Command::Quit if !quit_confirmed && (editor.buffer.dirty || buffers.any_dirty() || hex_view.as_ref().is_some_and(|view| view.modified)) => {
+                    status_message = "Unsaved changes, press Ctrl+Q again to quit".to_string();
+                    quit_armed = true;
+                }
                 Command::Quit => break 'mainloop,
                 Command::InsertChar(c) => {
-                    buffer.insert_char(cursor_char_idx, c);
-                    undo_redo.add_insert(cursor_char_idx, c.to_string());
-                    cursor_char_idx += 1;
-                    dirty_lines.insert(buffer.char_to_line(cursor_char_idx));
-                }
-                Command::MoveLeft => { if cursor_char_idx > 0 { cursor_char_idx -= 1; } }
-                Command::MoveRight => { if cursor_char_idx < buffer.len_chars() { cursor_char_idx += 1; } }
-                Command::MoveUp => {
-                    if current_line > 0 {
-                        let target_line = current_line - 1;
-                        let target_line_start = buffer.line_to_char(target_line);
-                        let target_line_len = buffer.line(target_line).len_chars();
-                        let new_col = cursor_col.min(target_line_len.saturating_sub(1));
-                        cursor_char_idx = target_line_start + new_col;
-                    }
-                }
-                Command::MoveDown => {
-                    if current_line + 1 < buffer.len_lines() {
-                        let target_line = current_line + 1;
-                        let target_line_start = buffer.line_to_char(target_line);
-                        let target_line_len = buffer.line(target_line).len_chars();
-                        let new_col = cursor_col.min(target_line_len.saturating_sub(1));
-                        cursor_char_idx = target_line_start + new_col;
-                    }
-                }
-                Command::Backspace => {
-                    if cursor_char_idx > 0 {
-                        let del_start = cursor_char_idx - 1;
-                        let content = buffer.slice(del_start..cursor_char_idx);
-                        buffer.remove(del_start, content.len());
-                        cursor_char_idx = del_start;
-                        undo_redo.add_delete(del_start, content);
-                        dirty_lines.insert(buffer.char_to_line(cursor_char_idx));
-                    }
-                }
-                Command::InsertNewline => {
-                    buffer.insert_char(cursor_char_idx, '\n');
-                    undo_redo.add_insert(cursor_char_idx, "\n".to_string());
-                    let curr_line = buffer.char_to_line(cursor_char_idx);
-                    dirty_lines.insert(curr_line);
-                    dirty_lines.insert(curr_line + 1);
-                    cursor_char_idx += 1;
-                }
-                Command::Undo => undo_redo.undo(&mut buffer, &mut cursor_char_idx, &mut dirty_lines),
-                Command::Redo => undo_redo.redo(&mut buffer, &mut cursor_char_idx, &mut dirty_lines),
-                Command::StartFind => input_handler.start_find(),
-                Command::ConfirmFind => input_handler.confirm_find(&buffer, &mut dirty_lines),
+                    // Typing over the selected placeholder of a snippet stop replaces it
+                    let placeholder = snippet.as_ref().map(ActiveSnippet::current_stop).filter(|&stop| selection == Some(stop));
+                    edits = editor.type_char(c, placeholder, |buffer, at| {
+                        auto_pairs.type_char(&config.auto_pairs, current_path.as_deref(), buffer, at, c)
+                    });
+                }
+                Command::SelectLeft
+                | Command::SelectRight
+                | Command::SelectUp
+                | Command::SelectDown
+                | Command::SelectWordLeft
+                | Command::SelectWordRight
+                | Command::MoveLeft
+                | Command::MoveRight
+                | Command::MoveWordLeft
+                | Command::MoveWordRight
+                | Command::MoveHome
+                | Command::MoveEnd
+                | Command::MoveDocStart
+                | Command::MoveDocEnd
+                | Command::MoveUp
+                | Command::MoveDown => {
+                    editor.execute(&command, selection);
+                }
+                Command::ScrollDown => {
+                    let last_top = if config.editor.scroll_past_end {
+                        editor.buffer.len_lines() - 1
+                    } else {
+                        editor.buffer.len_lines().saturating_sub(max_lines)
+                    };
+                    if viewport_row < last_top {
+                        viewport_row = editor.folds.next_visible_line(&editor.buffer, viewport_row);
+                        // Drag the cursor along when it falls into the margin at the top
+                        let first = lines_below(&editor.buffer, &editor.folds, viewport_row, scroll_off);
+                        if current_line < first {
+                            (editor.cursor, editor.virtual_col) = editor.column_on_line(first, cursor_display_col + editor.virtual_col);
+                        }
+                    }
+                }
+                Command::ScrollUp if viewport_row > 0 => {
+                    viewport_row = editor.folds.prev_visible_line(&editor.buffer, viewport_row);
+                    let mut bottom_line = viewport_row;
+                    for _ in 1..max_lines {
+                        bottom_line = editor.folds.next_visible_line(&editor.buffer, bottom_line);
+                    }
+                    // Drag the cursor along when it falls into the margin at the bottom
+                    let last = lines_above(&editor.buffer, &editor.folds, bottom_line, scroll_off);
+                    if current_line > last {
+                        (editor.cursor, editor.virtual_col) = editor.column_on_line(last, cursor_display_col + editor.virtual_col);
+                    }
+                }
+                Command::ScrollUp => {}
+                // The viewport and the cursor move by the same number of visible lines
+                Command::PageUp | Command::PageDown => {
+                    let page = max_lines.saturating_sub(PAGE_OVERLAP).max(1);
+                    let up = matches!(command, Command::PageUp);
+                    let delta = if up { -(page as isize) } else { page as isize };
+                    viewport_row = scroll_viewport(&editor.buffer, &editor.folds, viewport_row, delta, max_lines, config.editor.scroll_past_end);
+                    let mut target_line = current_line;
+                    for _ in 0..page {
+                        target_line = if up {
+                            editor.folds.prev_visible_line(&editor.buffer, target_line)
+                        } else {
+                            editor.folds.next_visible_line(&editor.buffer, target_line).min(editor.buffer.len_lines() - 1)
+                        };
+                    }
+                    (editor.cursor, editor.virtual_col) = editor.column_on_line(target_line, cursor_display_col + editor.virtual_col);
+                    editor.dirty_lines.extend(viewport_row..viewport_row + max_lines);
+                }
+                Command::Backspace
+                | Command::DeleteForward
+                | Command::DeleteWordBackward
+                | Command::DeleteWordForward
+                | Command::InsertNewline
+                | Command::InsertText(_)
+                | Command::DeleteLine
+                | Command::DuplicateLine
+                | Command::MoveLineUp
+                | Command::MoveLineDown
+                | Command::ChangeCase(_) => edits = editor.execute(&command, selection).unwrap_or_default(),
+                // The view jumps to changes made out of sight, which are highlighted for a moment
+                Command::Undo | Command::Redo | Command::JumpToUndoState => {
+                    edits = editor.execute(&command, selection).unwrap_or_default();
+                    if let Some((start, end)) = buffer::edited_span(&edits) {
+                        let (first, last) = (editor.buffer.char_to_line(start), editor.buffer.char_to_line(end));
+                        if first < viewport_row || last >= viewport_row + max_lines {
+                            viewport_row = first.saturating_sub(max_lines / 3);
+                            editor.dirty_lines.extend(viewport_row..viewport_row + max_lines);
+                        }
+                        if end > start {
+                            undo_flash = Some(((start, end), Instant::now()));
+                        }
+                    }
+                }
+                Command::ShowUndoTree => {
+                    editor.undo_redo.select_current();
+                    input_handler.start_undo_tree();
+                }
+                Command::UndoTreeSelectNext => editor.undo_redo.select_next(),
+                Command::UndoTreeSelectPrev => editor.undo_redo.select_prev(),
+                Command::CloseUndoTree => {}
+                Command::StartFind => {
+                    search_history.reset();
+                    input_handler.start_find();
+                }
+                Command::ConfirmFind => match input_handler.confirm_find(&editor.buffer, &mut editor.dirty_lines) {
+                    Ok(()) => search_history.push(&input_handler.find_input),
+                    Err(err) => status_message = format!("Invalid regex: {}", err),
+                },
+                Command::ClearHighlights => {
+                    input_handler.search = None;
+                    editor.dirty_lines.extend(viewport_row..viewport_row + max_lines);
+                }
+                Command::FindHistoryOlder => {
+                    if let Some(term) = search_history.older(&input_handler.find_input) {
+                        input_handler.find_input = term.to_string();
+                    }
+                }
+                Command::FindHistoryNewer => {
+                    if let Some(term) = search_history.newer() {
+                        input_handler.find_input = term.to_string();
+                    }
+                }
+                Command::StartReplace => {
+                    search_history.reset();
+                    input_handler.start_replace();
+                }
+                Command::StartGrep => {
+                    search_history.reset();
+                    input_handler.start_grep();
+                }
+                Command::ConfirmGrep => match input_handler.confirm_find(&editor.buffer, &mut editor.dirty_lines) {
+                    Ok(()) => {
+                        search_history.push(&input_handler.find_input);
+                        if let Some(search) = input_handler.search.as_ref() {
+                            grep_results.run(Path::new("."), search);
+                            if grep_results.hits.is_empty() {
+                                status_message = format!("No matches for {}", input_handler.find_input);
+                            } else {
+                                input_handler.start_grep_results();
+                            }
+                        }
+                    }
+                    Err(err) => status_message = format!("Invalid regex: {}", err),
+                },
+                Command::GrepSelectNext => grep_results.select_next(),
+                Command::GrepSelectPrev => grep_results.select_prev(),
+                Command::OpenGrepResult => {
+                    if let Some(hit) = grep_results.selected_hit() {
+                        if let Some(index) = buffers.find(&hit.path, current_path.as_deref()) {
+                            switch_to = Some(index);
+                            goto = Some((hit.line, 0));
+                        } else if file_worker.is_busy(&hit.path) {
+                            status_message = format!("Still loading {}", hit.path);
+                        } else {
+                            file_worker.open(&hit.path);
+                            open_target = Some((hit.path.clone(), hit.line, 0));
+                        }
+                    }
+                }
+                Command::CloseGrepResults => {}
+                Command::ConfirmReplacePattern => match input_handler.confirm_replace_pattern(&editor.buffer, &mut editor.dirty_lines) {
+                    Ok(()) => search_history.push(&input_handler.find_input),
+                    Err(err) => status_message = format!("Invalid regex: {}", err),
+                },
+                Command::ReplaceNext => {
+                    let replaced = input_handler
+                        .search
+                        .as_ref()
+                        .and_then(|search| editor.replace_next(search, editor.cursor, &input_handler.filename_input));
+                    match replaced {
+                        Some(((_, end), ops)) => {
+                            edits = ops;
+                            editor.cursor = end;
+                        }
+                        None => {
+                            input_handler.mode = InputMode::Editing;
+                            status_message = format!("Pattern not found: {}", input_handler.find_input);
+                        }
+                    }
+                }
+                Command::ReplaceAll => {
+                    if let Some(search) = input_handler.search.as_ref() {
+                        let count;
+                        (count, edits) = editor.replace_all(search, &input_handler.filename_input);
+                        status_message = format!("Replaced {} occurrences", count);
+                        editor.dirty_lines.extend(0..editor.buffer.len_lines().max(viewport_row + max_lines));
+                    }
+                }
+                // The whole interactive replace is undone in one step
+                answer @ (Command::StartReplaceEach
+                | Command::ReplaceYes
+                | Command::ReplaceNo
+                | Command::ReplaceRest
+                | Command::StopReplace) => {
+                    if matches!(answer, Command::StartReplaceEach) {
+                        replace_walk = Some(editor.start_replace_walk());
+                    }
+                    if let Some(walk) = replace_walk.as_mut() {
+                        match input_handler.search.as_ref() {
+                            Some(search) => edits = editor.answer_replace(walk, search, &input_handler.filename_input, &answer),
+                            None => editor.end_replace_walk(walk),
+                        }
+                    }
+                    // The next match is shown selected until answered
+                    match replace_walk.as_ref() {
+                        Some(walk) if walk.current.is_some() => input_handler.start_replace_confirm(),
+                        walk => {
+                            if let Some(walk) = walk {
+                                status_message = format!("Replaced {} occurrences", walk.count);
+                            }
+                            replace_walk = None;
+                            input_handler.mode = InputMode::Editing;
+                        }
+                    }
+                }
+                Command::SearchWordUnderCursor => {
+                    if let Some((start, end)) = editor.buffer.word_at(editor.cursor) {
+                        input_handler.set_word_search(editor.buffer.slice(start..end));
+                        if let Some((next, _)) = input_handler.search.as_ref().and_then(|search| search.find_next(&editor.buffer, end)) {
+                            editor.cursor = next;
+                        }
+                        editor.dirty_lines.extend(0..editor.buffer.len_lines());
+                    }
+                }
+                Command::ToggleFold => {
+                    editor.anchor = None;
+                    if let Some((start, end)) = selection {
+                        let first_line = editor.buffer.char_to_line(start);
+                        let last_line = editor.buffer.char_to_line(end.saturating_sub(1).max(start));
+                        if editor.folds.add(&editor.buffer, first_line, last_line) {
+                            editor.cursor = editor.buffer.line_to_char(first_line);
+                        }
+                    } else if !editor.folds.toggle(&editor.buffer, current_line)
+                        && let Some(syntax) = syntax.as_mut()
+                    {
+                        // No manual fold here: fold the syntax node under the cursor
+                        syntax.parse(&editor.buffer);
+                        if let Some((first_line, last_line)) = syntax.fold_range_at(&editor.buffer, editor.cursor) {
+                            editor.folds.add(&editor.buffer, first_line, last_line);
+                        }
+                    }
+                }
+                Command::FoldAllFunctions => {
+                    if let Some(syntax) = syntax.as_mut() {
+                        syntax.parse(&editor.buffer);
+                        for (first_line, last_line) in syntax.function_ranges(&editor.buffer) {
+                            editor.folds.add(&editor.buffer, first_line, last_line);
+                        }
+                    }
+                }
+                Command::RemoveFold => {
+                    editor.folds.remove(&editor.buffer, current_line);
+                }
                 Command::StartOpenFile => input_handler.start_open_file(),
+                Command::ToggleFileTree => {
+                    file_tree.visible = !file_tree.visible;
+                    if file_tree.visible {
+                        file_tree.refresh();
+                        input_handler.start_file_tree();
+                    } else {
+                        input_handler.leave_file_tree();
+                    }
+                    layout.set_area(sidebar_split(&file_tree, cols, screen_lines).1);
+                    layout.arrange(&mut renderer);
+                    redraw_panes = true;
+                }
+                Command::FileTreeSelectNext | Command::FileTreeSelectPrev | Command::FileTreeExpand | Command::FileTreeCollapse => {
+                    match command {
+                        Command::FileTreeSelectNext => file_tree.select_next(),
+                        Command::FileTreeSelectPrev => file_tree.select_prev(),
+                        Command::FileTreeExpand => file_tree.expand(),
+                        _ => file_tree.collapse(),
+                    }
+                    file_tree.scroll_to_selected(screen_lines);
+                    redraw_tree = true;
+                }
+                Command::LeaveFileTree => redraw_tree = true,
+                Command::FileTreeOpen => {
+                    // Opening a file hands the keyboard to it
+                    if let Some(path) = file_tree.activate() {
+                        input_handler.leave_file_tree();
+                        let current = current_path.as_deref();
+                        switch_to =
+                            open_file(&path, &mut hex_view, &mut input_handler, &buffers, current, &mut file_worker, &mut status_message);
+                    }
+                    file_tree.scroll_to_selected(screen_lines);
+                    redraw_tree = true;
+                }
+                Command::MouseDown { column, row } if let Some(rect) = tree_rect && rect.contains(column, row) => {
+                    input_handler.start_file_tree();
+                    if file_tree.select_row((row - rect.y) as usize)
+                        && let Some(path) = file_tree.activate()
+                    {
+                        input_handler.leave_file_tree();
+                        let current = current_path.as_deref();
+                        switch_to =
+                            open_file(&path, &mut hex_view, &mut input_handler, &buffers, current, &mut file_worker, &mut status_message);
+                    }
+                    redraw_tree = true;
+                }
                 Command::ConfirmOpenFile => {
                     if let Some(path) = input_handler.confirm_open_file() {
-                        if let Ok(new_buffer) = buffer::open_file(&path) {
-                            buffer = new_buffer;
-                            cursor_char_idx = 0;
-                            viewport_row = 0;
-                            dirty_lines.extend(0..max_lines);
+                        let current = current_path.as_deref();
+                        switch_to =
+                            open_file(&path, &mut hex_view, &mut input_handler, &buffers, current, &mut file_worker, &mut status_message);
+                    }
+                },
+                Command::OpenRequested(path) => {
+                    let current = current_path.as_deref();
+                    switch_to = open_file(&path, &mut hex_view, &mut input_handler, &buffers, current, &mut file_worker, &mut status_message);
+                }
+                Command::KeyHandled => {}
+                Command::Redraw => {
+                    // A terminal attaching to a session is in none of the editor's modes
+                    stdout.execute(EnterAlternateScreen)?;
+                    stdout.execute(EnableMouseCapture)?;
+                    stdout.execute(EnableFocusChange)?;
+                    stdout.execute(crossterm::terminal::Clear(crossterm::terminal::ClearType::All))?;
+                    // The terminal may have been resized, or be another one of a different size
+                    if let Ok((new_cols, new_rows)) = crossterm::terminal::size()
+                        && (new_cols, new_rows.saturating_sub(2) as usize) != (cols, screen_lines)
+                    {
+                        cols = new_cols;
+                        screen_lines = new_rows.saturating_sub(2).max(1) as usize;
+                        renderer.resize(screen_lines, cols as usize);
+                        for pane_renderer in layout.panes.iter_mut().filter_map(|pane| pane.renderer.as_mut()) {
+                            pane_renderer.resize(screen_lines, cols as usize);
                         }
+                        layout.set_area(sidebar_split(&file_tree, cols, screen_lines).1);
+                        layout.arrange(&mut renderer);
                     }
+                    renderer.invalidate();
+                    redraw_panes = true;
+                    redraw_tree = true;
+                    editor.dirty_lines.extend(viewport_row..viewport_row + renderer.area.height as usize);
+                }
+                Command::OpenLoadedFile => {
+                    if let Some(state) = loaded_buffer.take()
+                        && let Some(path) = state.path.clone()
+                    {
+                        goto = open_target.take_if(|(target, _, _)| *target == path).map(|(_, line, col)| (line, col));
+                        if let Some(index) = buffers.find(&path, current_path.as_deref()) {
+                            switch_to = Some(index);
+                        } else {
+                            if !read_only {
+                                pending_recovery = offer_recovery(&path, &state.buffer.rope, &mut input_handler, &mut status_message);
+                            }
+                            words.add(state.buffer.rope.slice(..));
+                            switch_to = Some(buffers.push(state));
+                        }
+                    }
+                }
+                Command::OpenBufferPicker => {
+                    buffers.selected = buffers.active;
+                    input_handler.start_buffer_picker();
+                }
+                Command::NextBuffer if buffers.len() > 1 => switch_to = Some((buffers.active + 1) % buffers.len()),
+                Command::NextBuffer => status_message = "No other buffers".to_string(),
+                Command::BufferSelectNext => buffers.select_next(),
+                Command::BufferSelectPrev => buffers.select_prev(),
+                Command::SwitchToSelectedBuffer => switch_to = Some(buffers.selected),
+                Command::CloseBufferPicker => {}
+                Command::MouseDown { column, row } if renderer.area.contains(column, row) => {
+                    if let Some(idx) = renderer.char_at(&editor.buffer, column, row) {
+                        editor.cursor = idx;
+                    }
+                }
+                Command::MouseDown { column, row } => {
+                    // A click in another pane focuses it with the cursor where clicked
+                    let clicked = layout.panes.iter().position(|pane| {
+                        pane.renderer.as_ref().is_some_and(|pane_renderer| pane_renderer.area.contains(column, row))
+                    });
+                    if let Some(index) = clicked {
+                        let pane = &mut layout.panes[index];
+                        let pane_buffer = if pane.buffer == buffers.active {
+                            Some(&editor.buffer)
+                        } else {
+                            buffers.get_mut(pane.buffer).map(|state| &state.buffer)
+                        };
+                        if let Some(idx) =
+                            pane_buffer.and_then(|b| pane.renderer.as_ref().and_then(|r| r.char_at(b, column, row)))
+                        {
+                            pane.cursor_char_idx = idx;
+                        }
+                        focus_pane = Some(index);
+                    }
+                }
+                Command::WheelUp { column, row } | Command::WheelDown { column, row } => {
+                    let steps = config.editor.scroll_lines as isize;
+                    let delta = if matches!(command, Command::WheelUp { .. }) { -steps } else { steps };
+                    let past_end = config.editor.scroll_past_end;
+                    if let Some(rect) = tree_rect
+                        && rect.contains(column, row)
+                    {
+                        file_tree.scroll(delta, rect.height as usize);
+                        redraw_tree = true;
+                    } else if renderer.area.contains(column, row) {
+                        let top = scroll_viewport(&editor.buffer, &editor.folds, viewport_row, delta, max_lines, past_end);
+                        if top != viewport_row {
+                            // Mark the lines scrolled into view
+                            let (old_bottom, new_bottom) = (viewport_row + max_lines, top + max_lines);
+                            editor.dirty_lines.extend(top.min(viewport_row)..new_bottom.max(old_bottom));
+                            viewport_row = top;
+                            viewport_detached = true;
+                        }
+                    } else if let Some(pane) = layout.panes.iter_mut().find(|pane| {
+                        pane.renderer.as_ref().is_some_and(|pane_renderer| pane_renderer.area.contains(column, row))
+                    }) {
+                        let rows = pane.renderer.as_ref().map_or(0, |pane_renderer| pane_renderer.area.height as usize);
+                        if pane.buffer == buffers.active {
+                            pane.viewport_row = scroll_viewport(&editor.buffer, &editor.folds, pane.viewport_row, delta, rows, past_end);
+                        } else if let Some(state) = buffers.get_mut(pane.buffer) {
+                            pane.viewport_row =
+                                scroll_viewport(&state.buffer, &state.folds, pane.viewport_row, delta, rows, past_end);
+                        }
+                    }
+                }
+                Command::MouseDrag { column, row } => {
+                    if let Some(idx) = renderer.char_at(&editor.buffer, column, row) {
+                        editor.cursor = idx;
+                    }
+                }
+                Command::SplitHorizontal | Command::SplitVertical => {
+                    let direction =
+                        if matches!(command, Command::SplitHorizontal) { SplitDirection::Horizontal } else { SplitDirection::Vertical };
+                    let mut pane_renderer = new_renderer(&config, &theme, screen_lines, cols as usize);
+                    pane_renderer.show_invisibles = renderer.show_invisibles;
+                    pane_renderer.soft_wrap = renderer.soft_wrap;
+                    pane_renderer.line_numbers = renderer.line_numbers;
+                    if layout.split(direction, pane_renderer, editor.cursor, viewport_row) {
+                        layout.arrange(&mut renderer);
+                        redraw_panes = true;
+                    } else {
+                        status_message = "Pane is too small to split".to_string();
+                    }
+                }
+                Command::ToggleInvisibles => {
+                    renderer.show_invisibles = !renderer.show_invisibles;
+                    for pane_renderer in layout.panes.iter_mut().filter_map(|pane| pane.renderer.as_mut()) {
+                        pane_renderer.show_invisibles = renderer.show_invisibles;
+                    }
+                    renderer.invalidate();
+                    redraw_panes = true;
+                    status_message = if renderer.show_invisibles { "Showing invisibles" } else { "Hiding invisibles" }.to_string();
+                }
+                Command::ToggleSoftWrap => {
+                    renderer.soft_wrap = !renderer.soft_wrap;
+                    for pane_renderer in layout.panes.iter_mut().filter_map(|pane| pane.renderer.as_mut()) {
+                        pane_renderer.soft_wrap = renderer.soft_wrap;
+                    }
+                    renderer.invalidate();
+                    redraw_panes = true;
+                    status_message = if renderer.soft_wrap { "Wrapping long lines" } else { "Not wrapping long lines" }.to_string();
+                }
+                Command::AlignView(align) => {
+                    let rows_above = match align {
+                        ViewAlign::Top => 0,
+                        ViewAlign::Center => max_lines / 2,
+                        ViewAlign::Bottom => max_lines.saturating_sub(1),
+                    };
+                    // Walk up from the cursor row until the rows above it are filled
+                    let starts = editor.row_starts(current_line);
+                    let mut rows = starts.partition_point(|&start| start <= cursor_col).saturating_sub(1);
+                    let mut top = current_line;
+                    while top > 0 {
+                        let prev = editor.folds.prev_visible_line(&editor.buffer, top);
+                        let height = editor.row_starts(prev).len();
+                        if rows + height > rows_above {
+                            break;
+                        }
+                        rows += height;
+                        top = prev;
+                    }
+                    viewport_row = top;
+                    editor.dirty_lines.extend(viewport_row..viewport_row + max_lines);
+                }
+                Command::CycleLineNumbers => {
+                    renderer.line_numbers = renderer.line_numbers.next();
+                    for pane_renderer in layout.panes.iter_mut().filter_map(|pane| pane.renderer.as_mut()) {
+                        pane_renderer.line_numbers = renderer.line_numbers;
+                    }
+                    renderer.invalidate();
+                    redraw_panes = true;
+                    status_message = format!("Line numbers: {}", renderer.line_numbers.name());
+                }
+                Command::FocusNextPane | Command::ClosePane if layout.len() < 2 => {
+                    status_message = "There is only one pane".to_string();
+                }
+                Command::FocusNextPane => focus_pane = Some(layout.next()),
+                Command::ClosePane => {
+                    focus_pane = Some(layout.next());
+                    close_pane = true;
+                }
+                Command::StartExportHtml => input_handler.start_export_html(),
+                Command::ConfirmExportHtml => {
+                    if let Some(path) = input_handler.confirm_export_html() {
+                        // Export the selection if there is one, otherwise the whole buffer
+                        let (start, end) = selection.unwrap_or((0, editor.buffer.len_chars()));
+                        editor.anchor = None;
+                        status_message = match export::export_html(&path, &editor.buffer, start, end, syntax.as_mut(), &export_theme) {
+                            Ok(()) => format!("Exported to {}", path),
+                            Err(err) => format!("Could not export to {}: {}", path, err),
+                        };
+                    }
+                }
+                Command::CopyStyled => {
+                    let (start, end) = selection.unwrap_or_else(|| editor.line_range(current_line));
+                    let plain = editor.buffer.slice(start..end);
+                    let ansi = export::buffer_to_ansi(
+                        &editor.buffer,
+                        start,
+                        end,
+                        syntax.as_mut(),
+                        &export_theme,
+                        input_handler.search.as_ref(),
+                    );
+                    let html = export::buffer_to_html(&editor.buffer, start, end, syntax.as_mut(), &export_theme, "rusty");
+                    clipboard.set_styled(plain, ansi, html);
+                }
+                Command::Copy => {
+                    let (start, end) = selection.unwrap_or_else(|| editor.line_range(current_line));
+                    clipboard.set_text(editor.buffer.slice(start..end));
+                    status_message = format!("Copied {} characters", end - start);
+                }
+                Command::Cut => {
+                    edits = editor.execute(&command, selection).unwrap_or_default();
+                    if let Some(EditOp::Delete { content, .. }) = edits.first() {
+                        clipboard.set_text(content.clone());
+                        registers.push_kill(content.clone());
+                        status_message = format!("Cut {} characters", content.chars().count());
+                    }
+                }
+                // Tab and Shift+Tab move between the stops of a snippet being filled in
+                Command::Indent | Command::Dedent if snippet.is_some() => {
+                    let mut done = false;
+                    if let Some(active) = snippet.as_mut() {
+                        if matches!(command, Command::Indent) {
+                            active.next();
+                        } else {
+                            active.prev();
+                        }
+                        (editor.anchor, editor.cursor) = select_stop(active.current_stop());
+                        done = active.is_done();
+                    }
+                    if done {
+                        snippet = None;
+                    }
+                }
+                Command::Indent if expansion.is_some() => {
+                    if let Some((start, body)) = expansion {
+                        let line_start = editor.buffer.line_to_char(current_line);
+                        let indent = editor.buffer.slice(line_start..line_start + editor.buffer.indent_len(current_line));
+                        let parsed = Snippet::parse(body, &indent);
+                        // The snippet takes the place of its trigger word
+                        edits = editor.execute(&Command::InsertText(parsed.text.clone()), Some((start, editor.cursor))).unwrap_or_default();
+                        let active = ActiveSnippet::new(&parsed, start);
+                        (editor.anchor, editor.cursor) = select_stop(active.current_stop());
+                        new_snippet = (!active.is_done()).then_some(active);
+                    }
+                }
+                Command::Indent | Command::Dedent => edits = editor.execute(&command, selection).unwrap_or_default(),
+                Command::KillLine => {
+                    edits = editor.execute(&command, selection).unwrap_or_default();
+                    if let Some(EditOp::Delete { content, .. }) = edits.first() {
+                        if kill_continues {
+                            registers.append_kill(content);
+                        } else {
+                            registers.push_kill(content.clone());
+                        }
+                        clipboard.set_text(registers.kill(0).unwrap_or_default().to_string());
+                    }
+                    killed_line = true;
+                }
+                Command::Paste => {
+                    // Pasting over a selection replaces it
+                    let start = selection.filter(|(start, end)| end > start).map_or(editor.cursor, |(start, _)| start);
+                    edits = editor.execute(&Command::InsertText(clipboard.get_text()), selection).unwrap_or_default();
+                    if let Some(EditOp::Delete { content, .. }) = edits.first() {
+                        registers.push_kill(content.clone());
+                    }
+                    let pasted = editor.buffer.slice(start..editor.cursor);
+                    let ring_index = (registers.kill(0) == Some(pasted.as_str())).then_some(0);
+                    last_paste = Some((start, editor.cursor - start, ring_index));
+                }
+                Command::YankToRegister(name) => {
+                    let (start, end) = selection.unwrap_or_else(|| editor.line_range(current_line));
+                    registers.set(name, editor.buffer.slice(start..end));
+                    status_message = format!("Yanked {} characters into register {}", end - start, name);
+                }
+                Command::PasteFromRegister(name) => match registers.get(name).map(str::to_string) {
+                    Some(text) => edits = editor.execute(&Command::InsertText(text), None).unwrap_or_default(),
+                    None => status_message = format!("Register {} is empty", name),
                 },
+                Command::CycleKillRing if registers.kill_count() == 0 => status_message = "Kill ring is empty".to_string(),
+                Command::CycleKillRing => {
+                    // Right after a paste, swap the pasted text for the next older kill
+                    let (pasted, index) = match prev_paste {
+                        Some((start, len, ring_index)) => (Some((start, start + len)), ring_index.map_or(0, |i| (i + 1) % registers.kill_count())),
+                        None => (None, 0),
+                    };
+                    let start = pasted.map_or(editor.cursor, |(start, _)| start);
+                    let text = registers.kill(index).unwrap_or_default().to_string();
+                    edits = editor.execute(&Command::InsertText(text), pasted).unwrap_or_default();
+                    last_paste = Some((start, editor.cursor - start, Some(index)));
+                    status_message = format!("Kill ring {}/{}", index + 1, registers.kill_count());
+                }
+                Command::StartDiff => input_handler.start_diff_files(),
+                Command::ConfirmDiff => {
+                    if let Some((left, right)) = input_handler.confirm_diff_files() {
+                        match DiffView::from_files(&left, &right) {
+                            Ok(view) => {
+                                diff_view = Some(view);
+                                input_handler.start_diff_view();
+                            }
+                            Err(err) => status_message = format!("Could not diff: {}", err),
+                        }
+                    } else {
+                        status_message = "Diff needs two files".to_string();
+                    }
+                }
+                Command::DiffScrollUp => diff_view.iter_mut().for_each(|view| view.scroll(-1, screen_lines)),
+                Command::DiffScrollDown => diff_view.iter_mut().for_each(|view| view.scroll(1, screen_lines)),
+                Command::DiffNextHunk => diff_view.iter_mut().for_each(DiffView::next_hunk),
+                Command::DiffPrevHunk => diff_view.iter_mut().for_each(DiffView::prev_hunk),
+                Command::CloseDiff => diff_view = None,
+                Command::RunBuild => {
+                    if !build.is_running() {
+                        build.start(&config.build.command);
+                        status_message = format!("Building: {}", config.build.command);
+                    }
+                }
+                Command::OpenErrorList if build.quickfix.is_empty() => status_message = "No build errors".to_string(),
+                Command::OpenErrorList => input_handler.start_error_list(),
+                Command::ErrorSelectNext => build.select_next(),
+                Command::ErrorSelectPrev => build.select_prev(),
+                Command::NextError | Command::PrevError | Command::OpenSelectedError => {
+                    let diagnostic = match command {
+                        Command::NextError => build.next_entry(),
+                        Command::PrevError => build.prev_entry(),
+                        _ => build.current(),
+                    };
+                    match diagnostic.cloned() {
+                        Some(diagnostic) => {
+                            status_message = diagnostic.message.clone();
+                            if let Some(index) = buffers.find(&diagnostic.path, current_path.as_deref()) {
+                                switch_to = Some(index);
+                                goto = Some((diagnostic.line, diagnostic.col));
+                            } else if file_worker.is_busy(&diagnostic.path) {
+                                status_message = format!("Still loading {}", diagnostic.path);
+                            } else {
+                                file_worker.open(&diagnostic.path);
+                                open_target = Some((diagnostic.path.clone(), diagnostic.line, diagnostic.col));
+                            }
+                        }
+                        None if build.quickfix.is_empty() => status_message = "No build errors".to_string(),
+                        None => status_message = "No more build errors".to_string(),
+                    }
+                }
+                Command::CloseErrorList => {}
+                Command::OpenTaskPicker => {
+                    tasks.refresh();
+                    input_handler.start_task_picker();
+                }
+                Command::TaskSelectNext => tasks.select_next(),
+                Command::TaskSelectPrev => tasks.select_prev(),
+                Command::RunSelectedTask => tasks.run_selected(),
+                Command::RerunTask => tasks.rerun_last(),
+                Command::TaskScrollUp => tasks.scroll_by(-1, screen_lines),
+                Command::TaskScrollDown => tasks.scroll_by(1, screen_lines),
+                Command::KillTask => tasks.kill(),
+                Command::CloseTaskPanel => {}
+                Command::StartCommandLine => {
+                    input_handler.start_command_line();
+                    // A selection becomes the range of the command, as in `:3,7`
+                    if let Some(selection) = selection.filter(|(start, end)| end > start) {
+                        let (first, last) = editor.selected_lines(selection);
+                        input_handler.filename_input = format!("{},{}", first + 1, last + 1);
+                    }
+                }
+                Command::RunEx(line) => {
+                    let last_line = editor.buffer.len_lines().saturating_sub(1);
+                    match ex::parse(&line, current_line, last_line) {
+                        Ok(ExCommand::Substitute { first, last, pattern, replacement, global }) => {
+                            match editor.substitute(first, last, &pattern, &replacement, global) {
+                                Ok((count, ops)) => {
+                                    edits = ops;
+                                    status_message = if count == 0 {
+                                        format!("Pattern not found in lines {}-{}: {}", first + 1, last + 1, pattern)
+                                    } else {
+                                        format!("Replaced {} occurrences in lines {}-{}", count, first + 1, last + 1)
+                                    };
+                                }
+                                Err(err) => status_message = err,
+                            }
+                        }
+                        Ok(ExCommand::Sort { first, last, numeric, reverse }) => {
+                            edits = editor.sort_lines(first, last, numeric, reverse);
+                            status_message = format!("Sorted lines {}-{}", first + 1, last + 1);
+                        }
+                        Ok(ExCommand::Filter { first, last, command }) => {
+                            match editor.filter_lines(first, last, &command) {
+                                Ok(ops) => {
+                                    edits = ops;
+                                    status_message = format!("Filtered lines {}-{} through {}", first + 1, last + 1, command);
+                                    editor.dirty_lines.extend(first..editor.buffer.len_lines().max(viewport_row + max_lines));
+                                }
+                                Err(err) => status_message = err,
+                            }
+                        }
+                        Ok(ExCommand::Merge) => match current_path.as_deref().map(|path| buffer::read_text(path, editor.buffer.encryption.as_ref())) {
+                            Some(Ok(theirs)) => {
+                                let (merged, conflicts) =
+                                    merge::merge3(&disk_base.to_string(), &editor.buffer.rope.to_string(), &theirs);
+                                edits = editor.replace_text(merged);
+                                disk_base = Rope::from_str(&theirs);
+                                disk_changed = false;
+                                disk_mtime = current_path.as_deref().and_then(modified_time);
+                                editor.dirty_lines.extend(0..editor.buffer.len_lines().max(viewport_row + max_lines));
+                                status_message = if conflicts == 0 {
+                                    "Merged changes from disk".to_string()
+                                } else {
+                                    format!("Merged changes from disk with {} conflicts", conflicts)
+                                };
+                            }
+                            Some(Err(err)) => status_message = format!("Merge failed: {}", err),
+                            None => status_message = "No file to merge with".to_string(),
+                        },
+                        Ok(ExCommand::History) => match current_path.as_deref() {
+                            Some(_) if editor.buffer.encryption.is_some() => status_message = "No history is kept of encrypted files".to_string(),
+                            Some(path) => {
+                                // Make sure the current state can be found again
+                                let _ = history.snapshot(path, &editor.buffer.rope);
+                                history.refresh(path);
+                                input_handler.start_history_browser();
+                            }
+                            None => status_message = "No file, no history".to_string(),
+                        },
+                        Ok(ExCommand::Stats) => status_message = stats.summary(),
+                        Ok(ExCommand::Commit) => match git_panel.refresh().and_then(|()| git_panel.message_path()) {
+                            // Run from the message buffer it commits with what was written there
+                            Ok(message_path) if current_path.as_deref() == Some(message_path.as_str()) => {
+                                match git_panel.commit(&editor.buffer.rope.to_string()) {
+                                    Ok(summary) => {
+                                        // Ready for the next commit
+                                        edits = editor.replace_text(git_panel.message_template());
+                                        // A fresh template has nothing worth saving
+                                        disk_base = editor.buffer.rope.clone();
+                                        // Git wrote the message to the file, which is no reason to reload it
+                                        disk_mtime = current_path.as_deref().and_then(modified_time);
+                                        editor.cursor = 0;
+                                        editor.dirty_lines.extend(0..editor.buffer.len_lines().max(viewport_row + max_lines));
+                                        status_message = summary;
+                                    }
+                                    Err(err) => status_message = err,
+                                }
+                            }
+                            Ok(message_path) => {
+                                switch_to = Some(buffers.find(&message_path, current_path.as_deref()).unwrap_or_else(|| {
+                                    let mut message = EditorBuffer::new();
+                                    message.rope = Rope::from_str(&git_panel.message_template());
+                                    words.add(message.rope.slice(..));
+                                    let mut state = BufferState::new(message, Some(message_path.clone()), config.editor.undo);
+                                    // What an earlier commit left in the file is not reloaded over it
+                                    state.disk_mtime = modified_time(&message_path);
+                                    buffers.push(state)
+                                }));
+                                status_message = "Write the commit message, then run :commit".to_string();
+                            }
+                            Err(err) => status_message = err,
+                        },
+                        Ok(ExCommand::Diff(target)) => {
+                            let name = current_path.as_deref().unwrap_or("buffer");
+                            let reference = match target {
+                                DiffTarget::Disk => match current_path.as_deref().map(|path| buffer::read_text(path, editor.buffer.encryption.as_ref())) {
+                                    Some(Ok(text)) => Ok((format!("{} (disk)", name), text)),
+                                    Some(Err(err)) => Err(format!("Could not read {}: {}", name, err)),
+                                    None => Err("No file on disk to compare with".to_string()),
+                                },
+                                DiffTarget::Head => current_path
+                                    .as_deref()
+                                    .and_then(git_gutter::committed_text)
+                                    .map(|text| (format!("{} (HEAD)", name), text))
+                                    .ok_or_else(|| "Not committed in git".to_string()),
+                                // A number counts from 1 as in the buffer list
+                                DiffTarget::Buffer(other) => match other
+                                    .parse::<usize>()
+                                    .ok()
+                                    .and_then(|number| number.checked_sub(1))
+                                    .or_else(|| buffers.find(&other, current_path.as_deref()))
+                                {
+                                    Some(index) if index == buffers.active => Err("That is the current buffer".to_string()),
+                                    Some(index) => match buffers.get_mut(index) {
+                                        Some(state) => Ok((
+                                            state.path.clone().unwrap_or_else(|| "[No Name]".to_string()),
+                                            state.buffer.rope.to_string(),
+                                        )),
+                                        None => Err(format!("No buffer {}", other)),
+                                    },
+                                    None => Err(format!("No buffer {}", other)),
+                                },
+                            };
+                            match reference {
+                                Ok((label, text)) => {
+                                    let view = DiffView::from_texts(&label, &text, name, &editor.buffer.rope.to_string());
+                                    status_message = match view.hunk_count() {
+                                        0 => "No differences".to_string(),
+                                        1 => "1 hunk".to_string(),
+                                        count => format!("{} hunks", count),
+                                    };
+                                    diff_view = Some(view);
+                                    input_handler.start_diff_view();
+                                }
+                                Err(err) => status_message = err,
+                            }
+                        }
+                        Ok(ExCommand::Transform { first, last, name }) => {
+                            let start = editor.buffer.line_to_char(first);
+                            let end = editor.line_range(last).1;
+                            let path = current_path.as_deref();
+                            // The init script's transforms come before the plugins'
+                            let transformed = if scripts.has_function(&name, 1) {
+                                scripts.transform(&name, &editor.buffer.rope, start, end, editor.cursor, path)
+                            } else {
+                                plugins.transform(&name, &editor.buffer.rope, start, end, editor.cursor, path)
+                            };
+                            match transformed {
+                                Ok(mut output) => {
+                                    status_message = output
+                                        .status
+                                        .take()
+                                        .unwrap_or_else(|| format!("Transformed lines {}-{} with {}", first + 1, last + 1, name));
+                                    edits = editor.apply_hook_output(output);
+                                    editor.dirty_lines.extend(first..editor.buffer.len_lines().max(viewport_row + max_lines));
+                                }
+                                Err(err) => status_message = err,
+                            }
+                        }
+                        Ok(ExCommand::Call(name)) if scripts.has_function(&name, 0) => {
+                            let mut output = scripts.run_command(&name, &editor.buffer.rope, editor.cursor, current_path.as_deref());
+                            if let Some(status) = output.status.take() {
+                                status_message = status;
+                            }
+                            edits = editor.apply_hook_output(output);
+                            editor.dirty_lines.extend(viewport_row..editor.buffer.len_lines().max(viewport_row + max_lines));
+                        }
+                        Ok(ExCommand::Call(name)) => status_message = format!("Unknown command: {}", name),
+                        Ok(ExCommand::Plugins) => {
+                            status_message = match plugins.names() {
+                                names if names.is_empty() => "No plugins loaded".to_string(),
+                                names => format!("Plugins: {}", names.join(", ")),
+                            };
+                        }
+                        Err(err) => status_message = err,
+                    }
+                }
+                Command::HistorySelectNext => history.select_next(),
+                Command::HistorySelectPrev => history.select_prev(),
+                Command::DiffHistorySnapshot => {
+                    if let Some((label, text)) = history.selected_snapshot() {
+                        let name = current_path.as_deref().unwrap_or("buffer");
+                        diff_view = Some(DiffView::from_texts(&label, &text, name, &editor.buffer.rope.to_string()));
+                        input_handler.start_diff_view();
+                    }
+                }
+                Command::RestoreHistorySnapshot => {
+                    if let Some((label, text)) = history.selected_snapshot() {
+                        edits = editor.replace_text(text);
+                        editor.dirty_lines.extend(0..editor.buffer.len_lines().max(viewport_row + max_lines));
+                        status_message = format!("Restored snapshot from {}", label);
+                    }
+                }
+                Command::CloseHistory => {}
+                Command::OpenGitPanel => match git_panel.refresh() {
+                    Ok(()) => {
+                        if let Some(path) = current_path.as_deref() {
+                            git_panel.select_path(path);
+                        }
+                        input_handler.start_git_panel();
+                    }
+                    Err(err) => status_message = err,
+                },
+                Command::GitSelectNext => git_panel.select_next(),
+                Command::GitSelectPrev => git_panel.select_prev(),
+                Command::GitToggleStage => {
+                    if let Err(err) = git_panel.toggle_selected() {
+                        status_message = err;
+                    }
+                }
+                Command::GitRefresh => {
+                    if let Err(err) = git_panel.refresh() {
+                        status_message = err;
+                    }
+                }
+                Command::GitOpenFile => {
+                    if let Some(path) = git_panel.selected_path() {
+                        let current = current_path.as_deref();
+                        switch_to =
+                            open_file(&path, &mut hex_view, &mut input_handler, &buffers, current, &mut file_worker, &mut status_message);
+                    }
+                }
+                Command::CloseGitPanel => {}
+                Command::FocusGained if disk_changed => {
+                    status_message = format!(
+                        "{} changed on disk: (r)eload, (o)verwrite or (d)iff?",
+                        current_path.as_deref().unwrap_or_default()
+                    );
+                    input_handler.start_disk_changed_prompt();
+                }
+                // A commit made meanwhile changes what the gutter compares against
+                Command::FocusGained => git_gutter.forget_heads(),
+                Command::ReloadFromDisk => {
+                    // Undoable, so the edits it throws away are not lost for good
+                    match current_path.as_deref().map(|path| buffer::read_text(path, editor.buffer.encryption.as_ref())) {
+                        Some(Ok(text)) => {
+                            disk_base = Rope::from_str(&text);
+                            disk_mtime = current_path.as_deref().and_then(modified_time);
+                            disk_changed = false;
+                            edits = editor.replace_text(text);
+                            editor.buffer.dirty = false;
+                            editor.dirty_lines.extend(0..editor.buffer.len_lines().max(viewport_row + max_lines));
+                            status_message = "Reloaded from disk".to_string();
+                        }
+                        Some(Err(err)) => status_message = format!("Reload failed: {}", err),
+                        None => {}
+                    }
+                }
+                Command::DiffWithDisk => {
+                    if let Some(path) = current_path.as_deref()
+                        && let Ok(text) = buffer::read_text(path, editor.buffer.encryption.as_ref())
+                    {
+                        diff_view = Some(DiffView::from_texts(&format!("{} (disk)", path), &text, "buffer", &editor.buffer.rope.to_string()));
+                        input_handler.start_diff_view();
+                    }
+                }
+                Command::HexLeft => hex_view.iter_mut().for_each(|view| view.move_by(-1, screen_lines)),
+                Command::HexRight => hex_view.iter_mut().for_each(|view| view.move_by(1, screen_lines)),
+                Command::HexUp => hex_view.iter_mut().for_each(|view| view.move_by(-(hex::BYTES_PER_ROW as isize), screen_lines)),
+                Command::HexDown => hex_view.iter_mut().for_each(|view| view.move_by(hex::BYTES_PER_ROW as isize, screen_lines)),
+                Command::HexPageUp => {
+                    let delta = (hex::BYTES_PER_ROW * screen_lines) as isize;
+                    hex_view.iter_mut().for_each(|view| view.move_by(-delta, screen_lines));
+                }
+                Command::HexPageDown => {
+                    let delta = (hex::BYTES_PER_ROW * screen_lines) as isize;
+                    hex_view.iter_mut().for_each(|view| view.move_by(delta, screen_lines));
+                }
+                Command::HexDigit(digit) => {
+                    hex_view.iter_mut().for_each(|view| view.type_digit(digit, screen_lines));
+                    status_message.clear();
+                }
+                Command::SaveHex => {
+                    if let Some(view) = hex_view.as_mut() {
+                        status_message = match view.save() {
+                            Ok(()) => format!("Saved {}", view.path),
+                            Err(err) => format!("Could not save {}: {}", view.path, err),
+                        };
+                    }
+                }
+                Command::CloseHex if !quit_confirmed && hex_view.as_ref().is_some_and(|view| view.modified) => {
+                    status_message = "Unsaved byte edits, press q again to close".to_string();
+                    quit_armed = true;
+                }
+                Command::CloseHex => {
+                    hex_view = None;
+                    input_handler.mode = InputMode::Editing;
+                    status_message.clear();
+                }
+                Command::RecoverSwap => {
+                    if let Some(text) = pending_recovery.take() {
+                        edits = editor.replace_text(text);
+                        editor.dirty_lines.extend(0..editor.buffer.len_lines().max(viewport_row + max_lines));
+                        status_message = "Recovered unsaved changes".to_string();
+                    }
+                }
+                Command::ConfirmSecret => match input_handler.confirm_secret() {
+                    Some((path, secret)) => {
+                        if let Some(cipher) = Cipher::for_path(&path) {
+                            status_message = format!("Decrypting {}…", path);
+                            file_worker.open_encrypted(&path, Encryption::new(cipher, secret));
+                        }
+                    }
+                    None => status_message.clear(),
+                },
+                Command::CancelSecret => status_message.clear(),
+                Command::DiscardSwap => {
+                    pending_recovery = None;
+                    if let Some(path) = current_path.as_deref() {
+                        recovery::discard(path);
+                    }
+                    status_message.clear();
+                }
+                Command::TriggerCompletion => match lsp.as_mut() {
+                    Some(client) => {
+                        // Positions are in UTF-16 code units
+                        let character = editor.buffer.line(current_line).chars().take(cursor_col).map(char::len_utf16).sum();
+                        client.request_completion(current_line, character);
+                        input_handler.start_completion();
+                    }
+                    // Without a language server the words of the open buffers are offered
+                    None => {
+                        let start = word_start(&editor.buffer, editor.cursor);
+                        let items = if start < editor.cursor { words.complete(&editor.buffer.slice(start..editor.cursor)) } else { Vec::new() };
+                        if items.is_empty() {
+                            status_message = "No completions".to_string();
+                        } else {
+                            word_menu = Some(WordMenu::new(items));
+                            input_handler.start_completion();
+                        }
+                    }
+                },
+                Command::CompletionNext => match word_menu.as_mut() {
+                    Some(menu) => menu.select_next(),
+                    None => lsp.iter_mut().for_each(LspClient::select_next),
+                },
+                Command::CompletionPrev => match word_menu.as_mut() {
+                    Some(menu) => menu.select_prev(),
+                    None => lsp.iter_mut().for_each(LspClient::select_prev),
+                },
+                Command::AcceptCompletion => {
+                    let insert = match word_menu.take() {
+                        Some(menu) => menu.items.get(menu.selected).cloned(),
+                        None => lsp.as_ref().and_then(|client| client.completions.get(client.selected)).map(|item| item.insert.clone()),
+                    };
+                    // The completion takes the place of the word typed so far
+                    if let Some(insert) = insert {
+                        let start = word_start(&editor.buffer, editor.cursor);
+                        edits = editor.execute(&Command::InsertText(insert), Some((start, editor.cursor))).unwrap_or_default();
+                    }
+                    lsp.iter_mut().for_each(LspClient::cancel_completion);
+                }
+                Command::CancelCompletion => {
+                    word_menu = None;
+                    lsp.iter_mut().for_each(LspClient::cancel_completion);
+                }
                 Command::StartSaveFile => input_handler.start_save_file(),
-                Command::ConfirmSaveFile => {
-                    if let Some(path) = input_handler.confirm_save_file() {
-                        let _ = buffer::save_file(&path, &buffer);
+                Command::SaveFile if current_path.is_none() || from_web => input_handler.start_save_file(),
+                save @ (Command::SaveFile | Command::ConfirmSaveFile | Command::OverwriteFile) => {
+                    let path = match save {
+                        Command::ConfirmSaveFile => input_handler.confirm_save_file(),
+                        _ => current_path.clone(),
+                    };
+                    // Ask before writing over changes someone else made to the file
+                    let changed_on_disk = path.is_some()
+                        && path == current_path
+                        && (disk_changed || path.as_deref().and_then(modified_time) != disk_mtime);
+                    if changed_on_disk && !matches!(save, Command::OverwriteFile) {
+                        status_message = format!("{} changed on disk: (r)eload, (o)verwrite or (d)iff?", path.unwrap_or_default());
+                        input_handler.start_disk_changed_prompt();
+                    } else if let Some(path) = path {
+                        if file_worker.is_busy(&path) {
+                            status_message = format!("Still saving {}", path);
+                        } else {
+                            let mut output = plugins.on_save(&editor.buffer.rope, editor.cursor, &path);
+                            if let Some(status) = output.status.take() {
+                                status_message = status;
+                            }
+                            edits = editor.apply_hook_output(output);
+                            if config.editor.trim_trailing_whitespace {
+                                edits.extend(editor.trim_changed_lines(&disk_base));
+                            }
+                            if !edits.is_empty() {
+                                editor.dirty_lines.extend(viewport_row..editor.buffer.len_lines().max(viewport_row + max_lines));
+                            }
+                            file_worker.save(buffers.active, &path, editor.buffer.clone(), &config.backup, false);
+                        }
                     }
                 },
-                _ => {}
+            }
+
+            editor.end_command();
+            for op in &edits {
+                editor.folds.apply_edit(op);
+                search_matches.apply_edit(op);
+                snippet.iter_mut().for_each(|active| active.apply_edit(op));
+            }
+            if new_snippet.is_some() {
+                snippet = new_snippet;
+            }
+            // Moving out of the stop being filled in ends the snippet
+            if snippet.as_ref().is_some_and(|active| !active.contains(editor.cursor)) {
+                snippet = None;
+            }
+            words.apply_edits(&editor.buffer.rope, &edits);
+            if word_typed && (word_menu.is_some() || config.editor.auto_complete) {
+                let start = word_start(&editor.buffer, editor.cursor);
+                let prefix = editor.buffer.slice(start..editor.cursor);
+                // Offered unasked only once the word is a few letters long
+                let min_len = if word_menu.is_some() { 1 } else { AUTO_COMPLETE_MIN_LEN };
+                let items = if prefix.chars().count() >= min_len { words.complete(&prefix) } else { Vec::new() };
+                if items.is_empty() {
+                    word_menu = None;
+                } else {
+                    word_menu = Some(WordMenu::new(items));
+                    input_handler.start_completion();
+                }
+            } else if *input_handler.get_mode() != InputMode::Completion {
+                word_menu = None;
+            }
+            // Undoing back to the saved text makes the buffer clean again
+            if !edits.is_empty() {
+                editor.buffer.dirty = editor.buffer.rope != disk_base;
+            }
+            if !edits.is_empty()
+                && let Some(syntax) = syntax.as_mut()
+            {
+                let changed = syntax.apply_edits(&edits, &editor.buffer);
+                highlights.invalidate(&changed, &editor.buffer, &editor.folds, viewport_row, max_lines, &mut editor.dirty_lines);
+            }
+            if !edits.is_empty()
+                && let Some(client) = lsp.as_mut()
+            {
+                client.did_change(&edits, &editor.buffer.rope);
+            }
+            history.tick(current_path.as_deref(), &editor.buffer.rope);
+            stats.record_edits(&edits);
+
+            // Hand the renderer over to the newly focused pane, which then
+            // shows its buffer at its own position
+            let mut pane_position = None;
+            if let Some(target) = focus_pane {
+                let target_renderer = layout.panes[target].renderer.take().expect("inactive panes have a renderer");
+                let previous = layout.active;
+                let pane = &mut layout.panes[previous];
+                pane.cursor_char_idx = editor.cursor;
+                pane.viewport_row = viewport_row;
+                pane.renderer = Some(std::mem::replace(&mut renderer, target_renderer));
+                layout.active = target;
+                if close_pane {
+                    layout.remove(previous);
+                    layout.arrange(&mut renderer);
+                }
+                let pane = &layout.panes[layout.active];
+                switch_to = Some(pane.buffer);
+                pane_position = Some((pane.cursor_char_idx, pane.viewport_row));
+                renderer.invalidate();
+                redraw_panes = true;
+            }
+
+            // Park the active buffer's state and check out the chosen one
+            if let Some(index) = switch_to
+                && index != buffers.active
+            {
+                if let Some(path) = current_path.as_deref() {
+                    if editor.buffer.encryption.is_none() {
+                        let _ = history.snapshot(path, &editor.buffer.rope);
+                    }
+                    positions.remember(path, &editor.buffer, editor.cursor);
+                    positions.persist();
+                }
+                // The empty scratch buffer the editor starts with is dropped once left
+                let previous = buffers.active;
+                let scratch = current_path.is_none() && editor.buffer.len_chars() == 0;
+                buffers.switch(index, |state| {
+                    swap_buffer(
+                        state,
+                        &mut editor,
+                        &mut current_path,
+                        &mut viewport_row,
+                        &mut syntax,
+                        &mut highlights,
+                        &mut disk_base,
+                        &mut disk_mtime,
+                        &mut disk_changed,
+                        &mut lsp,
+                        &mut branch,
+                    )
+                });
+                layout.panes[layout.active].buffer = buffers.active;
+                if scratch && !layout.shows_buffer(previous) {
+                    buffers.remove(previous);
+                    layout.buffer_removed(previous);
+                }
+                history.reset(&editor.buffer.rope);
+                search_matches.invalidate();
+                auto_pairs.clear();
+                snippet = None;
+                announcer.forget_line();
+                editor.anchor = None;
+                editor.virtual_col = 0;
+                editor.dirty_lines.extend(viewport_row..viewport_row + max_lines);
+            }
+            if let Some((pane_cursor, pane_viewport)) = pane_position {
+                editor.cursor = pane_cursor.min(editor.buffer.len_chars());
+                viewport_row = pane_viewport;
+                editor.anchor = None;
+                editor.virtual_col = 0;
+            }
+            if let Some((line, col)) = goto {
+                let line = line.min(editor.buffer.len_lines() - 1);
+                editor.cursor = editor.buffer.line_to_char(line) + col.min(editor.buffer.line_len(line));
+            }
+
+            // Keep the cursor out of lines hidden by closed folds
+            if let Some(fold) = editor.folds.hiding(&editor.buffer, editor.buffer.char_to_line(editor.cursor)) {
+                let fold_line_end = editor.buffer.line_to_char(fold.start_line(&editor.buffer) + 1) - 1;
+                let past_fold = fold.end.min(editor.buffer.len_chars());
+                editor.cursor = if editor.cursor > prev_cursor && editor.folds.hiding(&editor.buffer, editor.buffer.char_to_line(past_fold)).is_none() {
+                    past_fold
+                } else {
+                    fold_line_end
+                };
+            }
+
+            let new_selection = editor.selection();
+            if new_selection != selection {
+                let len = editor.buffer.len_chars();
+                for (start, end) in [selection, new_selection].into_iter().flatten() {
+                    editor.dirty_lines.extend(editor.buffer.char_to_line(start.min(len))..=editor.buffer.char_to_line(end.min(len)));
+                }
             }
         }
     }
 
+    // Saves still running are let finish; quitting gives up unsaved
+    // changes, so their swap files go
+    file_worker.wait();
+    for path in current_path.iter().chain(buffers.parked_mut().filter_map(|state| state.path.as_ref())) {
+        recovery::discard(path);
+    }
+    stats.persist();
+    if let Some(path) = current_path.as_deref() {
+        positions.remember(path, &editor.buffer, editor.cursor);
+    }
+    for state in buffers.parked_mut() {
+        if let Some(path) = state.path.as_deref() {
+            positions.remember(path, &state.buffer, state.cursor_char_idx);
+        }
+    }
+    positions.persist();
     disable_raw_mode()?;
+    stdout.execute(DisableFocusChange)?;
+    stdout.execute(DisableMouseCapture)?;
     stdout.execute(LeaveAlternateScreen)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusty::backend::TestBackend;
+
+    #[test]
+    fn load_progress_replaces_the_status_row() {
+        let mut out = TestBackend::new(30, 3);
+        out.move_to(0, 2).unwrap();
+        out.print("old status text").unwrap();
+        draw_load_progress(&mut out, 2, "big.log", LOAD_PROGRESS_MIN / 4, LOAD_PROGRESS_MIN);
+        assert_eq!(out.lines()[2].trim_end(), "Loading big.log… 25%");
+    }
+
+    #[test]
+    fn small_files_load_without_progress() {
+        let mut out = TestBackend::new(30, 3);
+        draw_load_progress(&mut out, 2, "small.txt", 10, LOAD_PROGRESS_MIN - 1);
+        assert!(out.lines().iter().all(|line| line.trim().is_empty()));
+    }
+}
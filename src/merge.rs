@@ -0,0 +1,169 @@
+// src/merge.rs
+
+use similar::{DiffOp, TextDiff};
+
+/// A change one side made to the common ancestor: base lines
+/// `start..end` became `lines`.
+struct Hunk<'a> {
+    start: usize,
+    end: usize,
+    lines: Vec<&'a str>,
+}
+
+fn hunks<'a>(base: &[&str], side: &[&'a str]) -> Vec<Hunk<'a>> {
+    TextDiff::from_slices(base, side)
+        .ops()
+        .iter()
+        .filter_map(|op| match *op {
+            DiffOp::Equal { .. } => None,
+            DiffOp::Delete { old_index, old_len, .. } => Some(Hunk { start: old_index, end: old_index + old_len, lines: Vec::new() }),
+            DiffOp::Insert { old_index, new_index, new_len } => {
+                Some(Hunk { start: old_index, end: old_index, lines: side[new_index..new_index + new_len].to_vec() })
+            }
+            DiffOp::Replace { old_index, old_len, new_index, new_len } => Some(Hunk {
+                start: old_index,
+                end: old_index + old_len,
+                lines: side[new_index..new_index + new_len].to_vec(),
+            }),
+        })
+        .collect()
+}
+
+/// Base lines `start..end` with `hunks` (all inside that range) applied.
+fn apply<'a>(base: &[&'a str], start: usize, end: usize, hunks: &[&Hunk<'a>]) -> Vec<&'a str> {
+    let mut out = Vec::new();
+    let mut pos = start;
+    for hunk in hunks {
+        out.extend_from_slice(&base[pos..hunk.start]);
+        out.extend_from_slice(&hunk.lines);
+        pos = hunk.end;
+    }
+    out.extend_from_slice(&base[pos..end]);
+    out
+}
+
+fn push_lines(out: &mut String, lines: &[&str]) {
+    for line in lines {
+        out.push_str(line);
+    }
+    // Keep conflict markers on their own lines
+    if !out.is_empty() && !out.ends_with('\n') {
+        out.push('\n');
+    }
+}
+
+/// Three-way merges `mine` and `theirs` against their common ancestor
+/// `base`, line by line. Changes that do not overlap are both kept; where
+/// both sides changed the same lines differently, the two versions are
+/// written between conflict markers. Returns the merged text and the
+/// number of conflicts.
+pub fn merge3(base: &str, mine: &str, theirs: &str) -> (String, usize) {
+    let base: Vec<&str> = base.split_inclusive('\n').collect();
+    let mine: Vec<&str> = mine.split_inclusive('\n').collect();
+    let theirs: Vec<&str> = theirs.split_inclusive('\n').collect();
+    let mut all: Vec<(bool, Hunk)> = hunks(&base, &mine)
+        .into_iter()
+        .map(|h| (true, h))
+        .chain(hunks(&base, &theirs).into_iter().map(|h| (false, h)))
+        .collect();
+    all.sort_by_key(|(_, h)| (h.start, h.end));
+
+    let mut merged = String::new();
+    let mut conflicts = 0;
+    let mut pos = 0;
+    let mut i = 0;
+    while i < all.len() {
+        // Gather every hunk overlapping or touching the current region
+        let start = all[i].1.start;
+        let mut end = all[i].1.end;
+        let mut j = i + 1;
+        while j < all.len()
+            && (all[j].1.start < end
+                || all[j].1.start == start
+                || (all[j].1.start == end && all[j].1.start == all[j].1.end))
+        {
+            end = end.max(all[j].1.end);
+            j += 1;
+        }
+        let group = &all[i..j];
+        for line in &base[pos..start] {
+            merged.push_str(line);
+        }
+        let mine_hunks: Vec<&Hunk> = group.iter().filter(|(is_mine, _)| *is_mine).map(|(_, h)| h).collect();
+        let theirs_hunks: Vec<&Hunk> = group.iter().filter(|(is_mine, _)| !*is_mine).map(|(_, h)| h).collect();
+        let ours = apply(&base, start, end, &mine_hunks);
+        let other = apply(&base, start, end, &theirs_hunks);
+        if theirs_hunks.is_empty() || ours == other {
+            ours.iter().for_each(|line| merged.push_str(line));
+        } else if mine_hunks.is_empty() {
+            other.iter().for_each(|line| merged.push_str(line));
+        } else {
+            conflicts += 1;
+            push_lines(&mut merged, &[]);
+            merged.push_str("<<<<<<< buffer\n");
+            push_lines(&mut merged, &ours);
+            merged.push_str("=======\n");
+            push_lines(&mut merged, &other);
+            merged.push_str(">>>>>>> disk\n");
+        }
+        pos = end;
+        i = j;
+    }
+    for line in &base[pos..] {
+        merged.push_str(line);
+    }
+    (merged, conflicts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = "one\ntwo\nthree\nfour\n";
+
+    #[test]
+    fn changes_to_different_lines_are_both_kept() {
+        assert_eq!(merge3(BASE, "one\nTWO\nthree\nfour\n", "one\ntwo\nthree\nFOUR\n"), ("one\nTWO\nthree\nFOUR\n".to_string(), 0));
+        // Each side alone, and a line deleted on one side
+        assert_eq!(merge3(BASE, BASE, "one\nthree\nfour\n"), ("one\nthree\nfour\n".to_string(), 0));
+        assert_eq!(merge3(BASE, "zero\none\ntwo\nthree\nfour\n", BASE), ("zero\none\ntwo\nthree\nfour\n".to_string(), 0));
+    }
+
+    #[test]
+    fn the_same_change_on_both_sides_is_taken_once() {
+        let changed = "one\n2\nthree\nfour\nfive\n";
+        assert_eq!(merge3(BASE, changed, changed), (changed.to_string(), 0));
+    }
+
+    #[test]
+    fn different_changes_to_the_same_lines_conflict() {
+        assert_eq!(
+            merge3(BASE, "one\nmine\nthree\nfour\n", "one\ntheirs\nthree\nfour\n"),
+            ("one\n<<<<<<< buffer\nmine\n=======\ntheirs\n>>>>>>> disk\nthree\nfour\n".to_string(), 1)
+        );
+    }
+
+    #[test]
+    fn an_insertion_touching_a_change_on_the_other_side_conflicts() {
+        // Lines added right after a changed line, as git sees it too
+        assert_eq!(
+            merge3(BASE, "one\nmine\nthree\nfour\n", "one\ntwo\nadded\nthree\nfour\n"),
+            ("one\n<<<<<<< buffer\nmine\n=======\ntwo\nadded\n>>>>>>> disk\nthree\nfour\n".to_string(), 1)
+        );
+        // One line further down it is a change of its own
+        assert_eq!(
+            merge3(BASE, "one\nmine\nthree\nfour\n", "one\ntwo\nthree\nadded\nfour\n"),
+            ("one\nmine\nthree\nadded\nfour\n".to_string(), 0)
+        );
+    }
+
+    #[test]
+    fn a_last_line_without_a_break_merges_and_conflicts_on_lines_of_its_own() {
+        let base = "one\ntwo";
+        assert_eq!(merge3(base, "ONE\ntwo", "one\nTWO"), ("ONE\nTWO".to_string(), 0));
+        assert_eq!(
+            merge3(base, "one\nmine", "one\ntheirs"),
+            ("one\n<<<<<<< buffer\nmine\n=======\ntheirs\n>>>>>>> disk\n".to_string(), 1)
+        );
+    }
+}
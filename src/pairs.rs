@@ -0,0 +1,124 @@
+// src/pairs.rs
+
+use crate::buffer::EditorBuffer;
+use crate::config::AutoPairsConfig;
+use std::path::Path;
+
+/// What typing a char at the cursor comes down to.
+pub enum Typed {
+    /// Insert the char alone.
+    Plain,
+    /// Insert the char followed by this closer.
+    Pair(char),
+    /// The char is an auto-inserted closer that is already there; move past it.
+    StepOver,
+}
+
+/// Closers typed along with their opener. They sit right after the cursor
+/// while the text between them is typed, so typing one steps over it
+/// rather than doubling it.
+pub struct AutoPairs {
+    /// Innermost last.
+    closers: Vec<char>,
+    /// Where typing left the cursor; moving it elsewhere forgets the closers.
+    cursor: usize,
+}
+
+impl AutoPairs {
+    pub fn new() -> Self {
+        AutoPairs {
+            closers: Vec::new(),
+            cursor: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.closers.clear();
+    }
+
+    /// Decides what typing `c` at `cursor` does in the file at `path` and
+    /// notes an inserted or stepped-over closer. `cursor` is where the char
+    /// goes; the caller moves the cursor on by one.
+    pub fn type_char(&mut self, config: &AutoPairsConfig, path: Option<&str>, buffer: &EditorBuffer, cursor: usize, c: char) -> Typed {
+        if cursor != self.cursor {
+            self.closers.clear();
+        }
+        self.cursor = cursor + 1;
+        let next = buffer.rope.get_char(cursor);
+        if self.closers.last() == Some(&c) && next == Some(c) {
+            self.closers.pop();
+            return Typed::StepOver;
+        }
+        let pairs = pairs_for(config, path);
+        let Some(&(_, closer)) = pairs.iter().find(|(opener, _)| *opener == c) else {
+            return Typed::Plain;
+        };
+        // Only pair in front of blanks or other closers, so typing before a
+        // word does not leave a stray closer behind
+        let free = next.is_none_or(|next| next.is_whitespace() || pairs.iter().any(|&(_, closer)| closer == next));
+        // A quote right after a word is an apostrophe or closes a string
+        let prev = cursor.checked_sub(1).and_then(|idx| buffer.rope.get_char(idx));
+        let after_word = closer == c && prev.is_some_and(|prev| prev.is_alphanumeric() || prev == '_' || prev == c);
+        if !free || after_word {
+            return Typed::Plain;
+        }
+        self.closers.push(closer);
+        Typed::Pair(closer)
+    }
+}
+
+impl Default for AutoPairs {
+    fn default() -> Self {
+        AutoPairs::new()
+    }
+}
+
+/// Opener and closer of each pair typed together in the file at `path`.
+fn pairs_for(config: &AutoPairsConfig, path: Option<&str>) -> Vec<(char, char)> {
+    if !config.enabled {
+        return Vec::new();
+    }
+    let extension = path.and_then(|path| Path::new(path).extension()?.to_str());
+    let pairs = extension.and_then(|extension| config.languages.get(extension)).unwrap_or(&config.pairs);
+    let chars: Vec<char> = pairs.chars().collect();
+    chars.chunks_exact(2).map(|pair| (pair[0], pair[1])).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ropey::Rope;
+
+    fn buffer(text: &str) -> EditorBuffer {
+        let mut buffer = EditorBuffer::new();
+        buffer.rope = Rope::from_str(text);
+        buffer
+    }
+
+    #[test]
+    fn an_opener_brings_its_closer_which_is_then_stepped_over() {
+        let config = AutoPairsConfig::default();
+        let mut pairs = AutoPairs::new();
+        assert!(matches!(pairs.type_char(&config, None, &buffer("f "), 1, '('), Typed::Pair(')')));
+        assert!(matches!(pairs.type_char(&config, None, &buffer("f() "), 2, ')'), Typed::StepOver));
+        // Only once: the next `)` is typed as it is
+        assert!(matches!(pairs.type_char(&config, None, &buffer("f() "), 3, ')'), Typed::Plain));
+        // Typing elsewhere forgets the closer
+        assert!(matches!(pairs.type_char(&config, None, &buffer(""), 0, '['), Typed::Pair(']')));
+        assert!(matches!(pairs.type_char(&config, None, &buffer("[]"), 0, ' '), Typed::Plain));
+        assert!(matches!(pairs.type_char(&config, None, &buffer(" []"), 2, ']'), Typed::Plain));
+    }
+
+    #[test]
+    fn nothing_is_paired_before_a_word_after_a_word_quote_or_where_turned_off() {
+        let config = AutoPairsConfig::default();
+        let mut pairs = AutoPairs::new();
+        assert!(matches!(pairs.type_char(&config, None, &buffer("word"), 0, '('), Typed::Plain));
+        assert!(matches!(pairs.type_char(&config, None, &buffer("don"), 3, '\''), Typed::Plain));
+        assert!(matches!(pairs.type_char(&config, None, &buffer(" "), 0, '\''), Typed::Pair('\'')));
+        // Rust lifetimes would get a stray quote
+        assert!(matches!(pairs.type_char(&config, Some("lib.rs"), &buffer(" "), 0, '\''), Typed::Plain));
+        let off = AutoPairsConfig { enabled: false, ..AutoPairsConfig::default() };
+        assert!(matches!(pairs.type_char(&off, None, &buffer(""), 0, '('), Typed::Plain));
+    }
+}
@@ -0,0 +1,307 @@
+// src/plugins.rs
+
+use crate::buffer::EditOp;
+use ropey::Rope;
+use std::fs;
+use std::path::Path;
+use wasmi::{Caller, Config, Engine, Instance, Linker, Module, Store, WasmParams, WasmResults};
+
+/// Instructions a plugin may run per hook call, so one stuck in a loop
+/// hands the editor back instead of freezing it.
+const FUEL_PER_CALL: u64 = 10_000_000;
+
+/// What the host API sees and changes while a hook runs: a copy of the
+/// buffer, with every change to it logged so the editor can replay them as
+/// undoable edits. Offsets the API takes and returns are in bytes. Scripts
+/// work on one too.
+#[derive(Default)]
+pub struct HostState {
+    pub text: Rope,
+    /// Char index of the cursor in `text`.
+    pub cursor: usize,
+    /// Name of the key `on_key` is called for, e.g. "Ctrl+A" or "Enter".
+    key: String,
+    pub path: String,
+    pub status: Option<String>,
+    edits: Vec<EditOp>,
+}
+
+impl HostState {
+    pub fn new(text: &Rope, cursor: usize, path: Option<&str>) -> Self {
+        HostState { text: text.clone(), cursor, path: path.unwrap_or_default().to_string(), ..HostState::default() }
+    }
+
+    /// The changes made, for the editor to apply.
+    pub fn into_output(self, handled: bool) -> HookOutput {
+        HookOutput { edits: self.edits, cursor: self.cursor, status: self.status, handled }
+    }
+
+    /// Char range of the byte range `start..end`, when both lie on char
+    /// boundaries within the text.
+    fn char_range(&self, start: i32, end: i32) -> Option<(usize, usize)> {
+        let (start, end) = (usize::try_from(start).ok()?, usize::try_from(end).ok()?);
+        Some((self.char_at(start)?, self.char_at(end)?)).filter(|(start, end)| start <= end)
+    }
+
+    fn char_at(&self, byte: usize) -> Option<usize> {
+        let char_idx = self.text.try_byte_to_char(byte).ok()?;
+        (self.text.char_to_byte(char_idx) == byte).then_some(char_idx)
+    }
+
+    /// Replaces the chars `start..end` with `text`.
+    pub fn replace(&mut self, start: usize, end: usize, text: String) {
+        if end > start {
+            let content = self.text.slice(start..end).to_string();
+            self.text.remove(start..end);
+            self.edits.push(EditOp::Delete { char_idx: start, content });
+        }
+        let len = text.chars().count();
+        if len > 0 {
+            self.text.insert(start, &text);
+            self.edits.push(EditOp::Insert { char_idx: start, content: text });
+        }
+        // A cursor inside the replaced text ends up after what replaced it
+        if self.cursor >= end {
+            self.cursor = self.cursor - (end - start) + len;
+        } else if self.cursor > start {
+            self.cursor = start + len;
+        }
+    }
+}
+
+/// Copies `bytes` into the plugin's memory at `ptr`. False when it has no
+/// memory or they do not fit.
+fn write_memory(caller: &mut Caller<'_, HostState>, ptr: i32, bytes: &[u8]) -> bool {
+    let Some(memory) = caller.get_export("memory").and_then(|export| export.into_memory()) else {
+        return false;
+    };
+    usize::try_from(ptr).is_ok_and(|ptr| memory.write(caller, ptr, bytes).is_ok())
+}
+
+/// The UTF-8 text of `len` bytes at `ptr` in the plugin's memory.
+fn read_memory(caller: &Caller<'_, HostState>, ptr: i32, len: i32) -> Option<String> {
+    let memory = caller.get_export("memory")?.into_memory()?;
+    let mut bytes = vec![0; usize::try_from(len).ok()?];
+    memory.read(caller, usize::try_from(ptr).ok()?, &mut bytes).ok()?;
+    String::from_utf8(bytes).ok()
+}
+
+/// Copies as much of `text` as fits in `cap` bytes to `ptr`. Returns the
+/// full length, so a plugin can call again with room enough, or -1.
+fn write_string(caller: &mut Caller<'_, HostState>, ptr: i32, cap: i32, text: &str) -> i32 {
+    let len = text.len().min(usize::try_from(cap).unwrap_or(0));
+    if write_memory(caller, ptr, &text.as_bytes()[..len]) { text.len() as i32 } else { -1 }
+}
+
+/// The host API, imported by plugins from the module "rusty". Calls that
+/// can fail return -1 for bad offsets, text that is not UTF-8, or memory
+/// out of bounds.
+fn host_api(engine: &Engine) -> Result<Linker<HostState>, wasmi::Error> {
+    let mut linker = Linker::new(engine);
+    linker
+        .func_wrap("rusty", "text_len", |caller: Caller<'_, HostState>| -> i32 { caller.data().text.len_bytes() as i32 })?
+        .func_wrap("rusty", "get_text", |mut caller: Caller<'_, HostState>, start: i32, end: i32, ptr: i32| -> i32 {
+            let Some((start, end)) = caller.data().char_range(start, end) else {
+                return -1;
+            };
+            let text = caller.data().text.slice(start..end).to_string();
+            if write_memory(&mut caller, ptr, text.as_bytes()) { 0 } else { -1 }
+        })?
+        .func_wrap("rusty", "replace", |mut caller: Caller<'_, HostState>, start: i32, end: i32, ptr: i32, len: i32| -> i32 {
+            let (Some((start, end)), Some(text)) = (caller.data().char_range(start, end), read_memory(&caller, ptr, len)) else {
+                return -1;
+            };
+            caller.data_mut().replace(start, end, text);
+            0
+        })?
+        .func_wrap("rusty", "cursor", |caller: Caller<'_, HostState>| -> i32 {
+            let state = caller.data();
+            state.text.char_to_byte(state.cursor) as i32
+        })?
+        .func_wrap("rusty", "set_cursor", |mut caller: Caller<'_, HostState>, pos: i32| -> i32 {
+            let Some((cursor, _)) = caller.data().char_range(pos, pos) else {
+                return -1;
+            };
+            caller.data_mut().cursor = cursor;
+            0
+        })?
+        .func_wrap("rusty", "get_key", |mut caller: Caller<'_, HostState>, ptr: i32, cap: i32| -> i32 {
+            let key = caller.data().key.clone();
+            write_string(&mut caller, ptr, cap, &key)
+        })?
+        .func_wrap("rusty", "get_path", |mut caller: Caller<'_, HostState>, ptr: i32, cap: i32| -> i32 {
+            let path = caller.data().path.clone();
+            write_string(&mut caller, ptr, cap, &path)
+        })?
+        .func_wrap("rusty", "set_status", |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> i32 {
+            let Some(message) = read_memory(&caller, ptr, len) else {
+                return -1;
+            };
+            caller.data_mut().status = Some(message);
+            0
+        })?;
+    Ok(linker)
+}
+
+/// What running a hook left behind for the editor to apply.
+#[derive(Default)]
+pub struct HookOutput {
+    /// Changes to the buffer, in the order they were made.
+    pub edits: Vec<EditOp>,
+    /// Char index of the cursor after them.
+    pub cursor: usize,
+    /// Message for the status line, or why a plugin failed.
+    pub status: Option<String>,
+    /// Whether a plugin took over the key given to `on_key`.
+    pub handled: bool,
+}
+
+struct Plugin {
+    name: String,
+    store: Store<HostState>,
+    instance: Instance,
+}
+
+impl Plugin {
+    /// Runs `export` on `state` when the plugin has it, None otherwise. The
+    /// state comes back with what the plugin did to it.
+    fn call<P: WasmParams, R: WasmResults>(&mut self, export: &str, params: P, state: &mut HostState) -> Option<Result<R, String>> {
+        let func = self.instance.get_typed_func::<P, R>(&self.store, export).ok()?;
+        *self.store.data_mut() = std::mem::take(state);
+        let result = self
+            .store
+            .set_fuel(FUEL_PER_CALL)
+            .map_err(wasmi::Error::from)
+            .and_then(|()| func.call(&mut self.store, params))
+            .map_err(|err| format!("Plugin {} failed in {}: {}", self.name, export, err));
+        *state = std::mem::take(self.store.data_mut());
+        Some(result)
+    }
+
+    fn has(&self, export: &str) -> bool {
+        self.instance.get_func(&self.store, export).is_some()
+    }
+}
+
+/// WebAssembly plugins, each sandboxed in a store of its own. A plugin may
+/// export any of these hooks:
+///
+/// - `on_key() -> i32`: called for each key typed while editing. Nonzero
+///   takes the key over, so the editor does nothing else with it; only a
+///   plugin that does so may change the buffer.
+/// - `on_save()`: called before the buffer is written, to tidy it up.
+/// - `transform_NAME(start, end)`: run with `:transform NAME` on a range
+///   of lines, given as byte offsets.
+pub struct PluginHost {
+    plugins: Vec<Plugin>,
+}
+
+impl PluginHost {
+    /// Loads every `.wasm` file in `dir`, in name order. Returns the host
+    /// along with why each plugin that could not be loaded was left out.
+    pub fn load(dir: Option<&Path>) -> (Self, Vec<String>) {
+        let mut host = PluginHost { plugins: Vec::new() };
+        let mut errors = Vec::new();
+        let Some(entries) = dir.and_then(|dir| fs::read_dir(dir).ok()) else {
+            return (host, errors);
+        };
+        let mut paths: Vec<_> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "wasm"))
+            .collect();
+        if paths.is_empty() {
+            return (host, errors);
+        }
+        paths.sort();
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let linker = match host_api(&engine) {
+            Ok(linker) => linker,
+            Err(err) => return (host, vec![format!("Plugins unavailable: {}", err)]),
+        };
+        for path in paths {
+            let name = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+            let mut store = Store::new(&engine, HostState::default());
+            let instance = fs::read(&path)
+                .map_err(|err| err.to_string())
+                .and_then(|wasm| Module::new(&engine, &wasm[..]).map_err(|err| err.to_string()))
+                .and_then(|module| {
+                    store.set_fuel(FUEL_PER_CALL).map_err(|err| err.to_string())?;
+                    linker
+                        .instantiate(&mut store, &module)
+                        .and_then(|instance| instance.start(&mut store))
+                        .map_err(|err| err.to_string())
+                });
+            match instance {
+                Ok(instance) => host.plugins.push(Plugin { name, store, instance }),
+                Err(err) => errors.push(format!("Could not load plugin {}: {}", name, err)),
+            }
+        }
+        (host, errors)
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.plugins.iter().map(|plugin| plugin.name.as_str()).collect()
+    }
+
+    /// Whether any plugin wants to see keys, which spares copying the
+    /// buffer for every key when none does.
+    pub fn handles_keys(&self) -> bool {
+        self.plugins.iter().any(|plugin| plugin.has("on_key"))
+    }
+
+    /// Offers `key` to each plugin in turn until one takes it over.
+    pub fn on_key(&mut self, key: &str, text: &Rope, cursor: usize, path: Option<&str>) -> HookOutput {
+        let mut output = HookOutput { cursor, ..HookOutput::default() };
+        for plugin in &mut self.plugins {
+            let mut state = HostState::new(text, cursor, path);
+            state.key = key.to_string();
+            match plugin.call::<(), i32>("on_key", (), &mut state) {
+                Some(Ok(0)) | None => output.status = state.status.or(output.status),
+                Some(Ok(_)) => {
+                    state.status = state.status.or(output.status);
+                    return state.into_output(true);
+                }
+                Some(Err(err)) => output.status = Some(err),
+            }
+        }
+        output
+    }
+
+    /// Lets each plugin change the buffer before it is saved, one after
+    /// the other. A plugin that fails leaves the text as it found it.
+    pub fn on_save(&mut self, text: &Rope, cursor: usize, path: &str) -> HookOutput {
+        let mut output = HookOutput { cursor, ..HookOutput::default() };
+        let mut text = text.clone();
+        for plugin in &mut self.plugins {
+            let mut state = HostState::new(&text, output.cursor, Some(path));
+            match plugin.call::<(), ()>("on_save", (), &mut state) {
+                Some(Ok(())) => {
+                    text = state.text;
+                    output.edits.extend(state.edits);
+                    output.cursor = state.cursor;
+                    output.status = state.status.or(output.status);
+                }
+                Some(Err(err)) => output.status = Some(err),
+                None => {}
+            }
+        }
+        output
+    }
+
+    /// Runs the first plugin's `transform_NAME` on the chars `start..end`.
+    pub fn transform(&mut self, name: &str, text: &Rope, start: usize, end: usize, cursor: usize, path: Option<&str>) -> Result<HookOutput, String> {
+        let export = format!("transform_{}", name);
+        let plugin = self
+            .plugins
+            .iter_mut()
+            .find(|plugin| plugin.has(&export))
+            .ok_or_else(|| format!("No transform named {}", name))?;
+        let mut state = HostState::new(text, cursor, path);
+        let range = (text.char_to_byte(start) as i32, text.char_to_byte(end) as i32);
+        plugin.call::<(i32, i32), ()>(&export, range, &mut state).unwrap_or(Ok(()))?;
+        Ok(state.into_output(true))
+    }
+}
@@ -0,0 +1,86 @@
+// src/positions.rs
+
+use crate::buffer::EditorBuffer;
+use crate::config::data_dir;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Files remembered; the ones left longest ago are forgotten first.
+const MAX_FILES: usize = 1000;
+
+#[derive(Deserialize, Serialize)]
+struct Position {
+    /// Absolute path of the file.
+    path: String,
+    line: usize,
+    col: usize,
+}
+
+/// Where the cursor was in each file when it was last left, kept in the
+/// data directory so a file reopened later starts there again.
+pub struct CursorPositions {
+    path: Option<PathBuf>,
+    /// Most recently left last.
+    positions: Vec<Position>,
+}
+
+/// The key a file is remembered under, the same whichever way it was named.
+fn absolute(path: &str) -> Option<String> {
+    Some(Path::new(path).canonicalize().ok()?.to_string_lossy().into_owned())
+}
+
+impl CursorPositions {
+    pub fn new() -> Self {
+        let path = data_dir().map(|dir| dir.join("positions.json"));
+        let positions = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default();
+        CursorPositions { path, positions }
+    }
+
+    /// Notes where the cursor is in the file at `path`.
+    pub fn remember(&mut self, path: &str, buffer: &EditorBuffer, cursor: usize) {
+        let Some(path) = absolute(path) else {
+            return;
+        };
+        let cursor = cursor.min(buffer.len_chars());
+        let line = buffer.char_to_line(cursor);
+        let col = cursor - buffer.line_to_char(line);
+        self.positions.retain(|position| position.path != path);
+        self.positions.push(Position { path, line, col });
+        let excess = self.positions.len().saturating_sub(MAX_FILES);
+        self.positions.drain(..excess);
+    }
+
+    /// Char index the cursor was left at in the file at `path`, kept inside
+    /// the text in case the file got shorter since.
+    pub fn recall(&self, path: &str, buffer: &EditorBuffer) -> Option<usize> {
+        let path = absolute(path)?;
+        let position = self.positions.iter().find(|position| position.path == path)?;
+        let line = position.line.min(buffer.len_lines() - 1);
+        let line_len = buffer.line(line).chars().take_while(|&ch| ch != '\n' && ch != '\r').count();
+        Some(buffer.line_to_char(line) + position.col.min(line_len))
+    }
+
+    /// Writes the positions to disk.
+    pub fn persist(&self) {
+        let Some(path) = self.path.as_ref() else {
+            return;
+        };
+        if let Some(dir) = path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        if let Ok(json) = serde_json::to_string(&self.positions) {
+            let _ = fs::write(path, json);
+        }
+    }
+}
+
+impl Default for CursorPositions {
+    fn default() -> Self {
+        CursorPositions::new()
+    }
+}
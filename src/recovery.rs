@@ -0,0 +1,83 @@
+// src/recovery.rs
+
+use crate::config::{data_dir, RecoveryConfig};
+use ropey::Rope;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Swap file of `path`, named after its absolute path. Swap files live
+/// outside the project, next to the local history.
+fn swap_path(path: &str) -> Option<PathBuf> {
+    let absolute = Path::new(path).canonicalize().ok()?;
+    let name = absolute.to_string_lossy().replace(['/', '\\', ':'], "%");
+    Some(data_dir()?.join("swap").join(format!("{}.swp", name)))
+}
+
+/// Content of the swap file left for `path` when it differs from `rope`,
+/// the text just loaded from disk.
+pub fn find(path: &str, rope: &Rope) -> Option<String> {
+    let text = fs::read_to_string(swap_path(path)?).ok()?;
+    if *rope == text.as_str() {
+        discard(path);
+        return None;
+    }
+    Some(text)
+}
+
+/// Deletes the swap file of `path`, once its changes are saved or given up.
+pub fn discard(path: &str) {
+    if let Some(swap) = swap_path(path) {
+        let _ = fs::remove_file(swap);
+    }
+}
+
+/// Keeps a swap file with the unsaved text of the active buffer, so the
+/// changes survive a crash.
+pub struct SwapWriter {
+    enabled: bool,
+    interval: Duration,
+    last_check: Instant,
+    /// Content of the last swap file written.
+    written: Option<Rope>,
+}
+
+impl SwapWriter {
+    pub fn new(config: &RecoveryConfig) -> Self {
+        SwapWriter {
+            enabled: config.enabled,
+            interval: Duration::from_secs(config.interval_secs),
+            last_check: Instant::now(),
+            written: None,
+        }
+    }
+
+    /// Writes the swap file when the interval has passed and the unsaved
+    /// text changed since the last write.
+    pub fn tick(&mut self, path: Option<&str>, rope: &Rope, dirty: bool) {
+        if !dirty {
+            // Saving deleted the swap file
+            self.written = None;
+            return;
+        }
+        if !self.enabled || self.last_check.elapsed() < self.interval {
+            return;
+        }
+        self.last_check = Instant::now();
+        if let Some(path) = path
+            && self.written.as_ref() != Some(rope)
+            && self.write(path, rope).is_ok()
+        {
+            self.written = Some(rope.clone());
+        }
+    }
+
+    fn write(&self, path: &str, rope: &Rope) -> io::Result<()> {
+        let swap = swap_path(path).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no swap directory"))?;
+        if let Some(dir) = swap.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        rope.write_to(io::BufWriter::new(fs::File::create(swap)?))
+    }
+}
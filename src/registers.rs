@@ -0,0 +1,62 @@
+// src/registers.rs
+
+use std::collections::{HashMap, VecDeque};
+
+/// Deletions kept in the kill ring before the oldest is dropped.
+const KILL_RING_SIZE: usize = 30;
+
+/// Named registers ("a" to "z") plus a kill ring of recent deletions,
+/// newest first.
+pub struct Registers {
+    named: HashMap<char, String>,
+    kill_ring: VecDeque<String>,
+}
+
+impl Registers {
+    pub fn new() -> Self {
+        Registers {
+            named: HashMap::new(),
+            kill_ring: VecDeque::new(),
+        }
+    }
+
+    pub fn set(&mut self, name: char, text: String) {
+        self.named.insert(name, text);
+    }
+
+    pub fn get(&self, name: char) -> Option<&str> {
+        self.named.get(&name).map(String::as_str)
+    }
+
+    /// Remembers deleted text, skipping repeats of the newest entry.
+    pub fn push_kill(&mut self, text: String) {
+        if text.is_empty() || self.kill_ring.front() == Some(&text) {
+            return;
+        }
+        self.kill_ring.push_front(text);
+        self.kill_ring.truncate(KILL_RING_SIZE);
+    }
+
+    /// Adds to the newest deletion, for kills made one right after another.
+    pub fn append_kill(&mut self, text: &str) {
+        match self.kill_ring.front_mut() {
+            Some(newest) => newest.push_str(text),
+            None => self.push_kill(text.to_string()),
+        }
+    }
+
+    /// The `index`-th most recent deletion.
+    pub fn kill(&self, index: usize) -> Option<&str> {
+        self.kill_ring.get(index).map(String::as_str)
+    }
+
+    pub fn kill_count(&self) -> usize {
+        self.kill_ring.len()
+    }
+}
+
+impl Default for Registers {
+    fn default() -> Self {
+        Registers::new()
+    }
+}
@@ -0,0 +1,180 @@
+// src/remote.rs
+
+use crate::config::runtime_dir;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// How long an idle connection stays open for the next transfer.
+const CONTROL_PERSIST: &str = "5m";
+
+/// A file on another machine, named `sftp://[user@]host[:port]/path`.
+/// A path starting with `/~/` is taken relative to the home directory.
+/// A host starting with `-` is refused, as sftp would take it for an option.
+pub struct RemotePath {
+    /// `user@host`, or just the host.
+    destination: String,
+    port: Option<u16>,
+    path: String,
+}
+
+impl RemotePath {
+    pub fn parse(url: &str) -> Option<Self> {
+        let rest = url.strip_prefix("sftp://")?;
+        let (authority, path) = rest.split_at(rest.find('/')?);
+        let (destination, port) = match authority.rsplit_once(':') {
+            Some((destination, port)) => (destination, Some(port.parse().ok()?)),
+            None => (authority, None),
+        };
+        if destination.is_empty() || destination.starts_with('-') || destination.ends_with('@') || path.len() < 2 {
+            return None;
+        }
+        let path = path.strip_prefix("/~/").unwrap_or(path).to_string();
+        Some(RemotePath { destination: destination.to_string(), port, path })
+    }
+
+    /// Downloads the file to a local copy that is deleted when dropped.
+    pub fn fetch(&self) -> io::Result<LocalCopy> {
+        let copy = LocalCopy::new(&self.path)?;
+        self.sftp(&format!("get {} {}", quote(&self.path), quote(&copy.path)))?;
+        Ok(copy)
+    }
+
+    /// Uploads whatever `write` puts in a local file over the remote one.
+    pub fn store(&self, write: impl FnOnce(&str) -> io::Result<()>) -> io::Result<()> {
+        let copy = LocalCopy::new(&self.path)?;
+        write(&copy.path)?;
+        self.sftp(&format!("put {} {}", quote(&copy.path), quote(&self.path)))
+    }
+
+    /// Runs one sftp batch command. The connection is shared between calls,
+    /// so only the first one to a host pays for logging in. Never prompts,
+    /// since the terminal belongs to the editor: keys or an agent must do.
+    fn sftp(&self, command: &str) -> io::Result<()> {
+        let control_path = runtime_dir()?.join("ssh-%C");
+        let mut sftp = Command::new("sftp");
+        sftp.args(["-q", "-b", "-"])
+            .args(["-o", "BatchMode=yes", "-o", "ControlMaster=auto", "-o"])
+            .arg(format!("ControlPath={}", control_path.to_string_lossy()))
+            .arg("-o")
+            .arg(format!("ControlPersist={}", CONTROL_PERSIST));
+        if let Some(port) = self.port {
+            sftp.arg("-P").arg(port.to_string());
+        }
+        let mut child = sftp
+            .arg("--")
+            .arg(&self.destination)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| io::Error::new(err.kind(), format!("could not run sftp: {}", err)))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(format!("{}\n", command).as_bytes())?;
+        }
+        let output = child.wait_with_output()?;
+        if output.status.success() {
+            return Ok(());
+        }
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let message = stderr.lines().find(|line| !line.starts_with("sftp>")).unwrap_or("sftp failed").trim().to_string();
+        let kind = if message.contains("No such file") || message.contains("not found") {
+            io::ErrorKind::NotFound
+        } else {
+            io::ErrorKind::Other
+        };
+        Err(io::Error::new(kind, message))
+    }
+}
+
+/// Whether `path` names a file on another machine rather than a local one.
+pub fn is_remote(path: &str) -> bool {
+    path.starts_with("sftp://") || is_url(path)
+}
+
+/// Whether `path` is a web address, whose content can be read but not saved.
+pub fn is_url(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+/// Downloads `url` with curl to a local copy that is deleted when dropped.
+/// A page that cannot be had is an error, never a new empty file.
+pub fn fetch_url(url: &str) -> io::Result<LocalCopy> {
+    let copy = LocalCopy::new(url.split(['?', '#']).next().unwrap_or(url))?;
+    let output = Command::new("curl")
+        .args(["--silent", "--show-error", "--fail", "--location", "--output"])
+        .arg(&copy.path)
+        .arg(url)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|err| io::Error::new(err.kind(), format!("could not run curl: {}", err)))?;
+    if output.status.success() {
+        return Ok(copy);
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let message = stderr.lines().next().unwrap_or("curl failed");
+    // "curl: (22) The requested URL returned error: 404"
+    let message = message.split_once(") ").map_or(message, |(_, message)| message);
+    Err(io::Error::other(message.to_string()))
+}
+
+/// Quotes an argument of an sftp batch command.
+fn quote(arg: &str) -> String {
+    format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// A temporary file holding a remote file's content, in the user's
+/// private runtime directory.
+pub struct LocalCopy {
+    path: String,
+}
+
+impl LocalCopy {
+    /// An empty file under a fresh name keeping the remote file's own, so
+    /// its extension stays. Only the user can read it.
+    fn new(remote_path: &str) -> io::Result<Self> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let dir = runtime_dir()?;
+        let name = Path::new(remote_path).file_name().unwrap_or_default().to_string_lossy();
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        loop {
+            let unique = format!("download-{}-{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed), name);
+            let path = dir.join(unique);
+            match options.open(&path) {
+                Ok(_) => return Ok(LocalCopy { path: path.to_string_lossy().into_owned() }),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+impl Drop for LocalCopy {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_url_names_the_destination_port_and_path_and_never_an_option() {
+        let remote = RemotePath::parse("sftp://me@host:2222/~/notes.txt").unwrap();
+        assert_eq!((remote.destination.as_str(), remote.port, remote.path.as_str()), ("me@host", Some(2222), "notes.txt"));
+        assert_eq!(RemotePath::parse("sftp://host/etc/hosts").unwrap().path, "/etc/hosts");
+        for url in ["sftp://-oProxyCommand=touch%20x/f", "sftp://-host/f", "sftp://me@/f", "sftp://host:port/f", "sftp://host/"] {
+            assert!(RemotePath::parse(url).is_none(), "{}", url);
+        }
+    }
+}
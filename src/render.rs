@@ -1,18 +1,93 @@
 // src/render.rs
 
+use crate::backend::{Backend, ClearRegion};
 use crate::buffer::EditorBuffer;
+use crate::columns;
+use crate::search::MatchList;
+use crate::diff::{DiffView, RowKind};
+use crate::fold::FoldSet;
+use crate::hex::{HexView, BYTES_PER_ROW};
+use crate::highlight::HighlightCache;
+use crate::layout::{Rect, SplitDirection};
+use crate::signs::{Sign, SignColumn};
+use crate::status::{StatusInfo, StatusLine};
 use crate::input::InputMode;
-use crossterm::{
-    cursor,
-    style::{Print, Stylize},
-    queue, ExecutableCommand,
-};
+use crate::syntax::SyntaxTree;
+use crate::theme::Theme;
+use serde::Deserialize;
+use crossterm::style::{Attribute, ContentStyle, Stylize};
 use std::collections::HashSet;
-use std::io::{Error, Stdout, Write};
+use std::io::Error;
+
+/// Buffer line, closed fold end, sign and the char offset in the line the
+/// row starts at (past 0 on the later rows of a wrapped line) last drawn on
+/// a screen row.
+type RowMapping = (usize, Option<usize>, Option<Sign>, usize);
+
+/// What the gutter shows left of each line.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LineNumbers {
+    Absolute,
+    /// Distance from the cursor line, which shows 0.
+    Relative,
+    /// Distance from the cursor line, which shows its own number.
+    Hybrid,
+    Off,
+}
+
+impl LineNumbers {
+    /// The style after this one, for cycling through them.
+    pub fn next(self) -> Self {
+        match self {
+            LineNumbers::Absolute => LineNumbers::Relative,
+            LineNumbers::Relative => LineNumbers::Hybrid,
+            LineNumbers::Hybrid => LineNumbers::Off,
+            LineNumbers::Off => LineNumbers::Absolute,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LineNumbers::Absolute => "absolute",
+            LineNumbers::Relative => "relative",
+            LineNumbers::Hybrid => "hybrid",
+            LineNumbers::Off => "off",
+        }
+    }
+}
 
 pub struct Renderer {
     pub max_lines: usize,
+    pub cols: usize,
+    /// Part of the screen the text is drawn in; the whole text area unless split.
+    pub area: Rect,
     virtual_screen: VirtualScreen,
+    row_lines: Vec<Option<RowMapping>>,
+    /// Whether the sign column is drawn left of the line numbers.
+    pub sign_column: bool,
+    /// Screen-reader mode: no decorative redraws or color-only cues.
+    pub screen_reader: bool,
+    /// Columns between tab stops.
+    pub tab_width: usize,
+    /// Spaces, tabs and line breaks are drawn as dim glyphs.
+    pub show_invisibles: bool,
+    /// Long lines wrap onto the next rows instead of being cut off.
+    pub soft_wrap: bool,
+    pub line_numbers: LineNumbers,
+    /// Text columns, counted from 1, drawn with the ruler background.
+    pub rulers: Vec<usize>,
+    pub theme: Theme,
+    /// Cursor line the relative numbers were last drawn for.
+    numbered_line: usize,
+    /// Bracket pair highlighted by the last render and the lines holding
+    /// it, which are redrawn once the pair changes.
+    brackets: Option<(usize, usize)>,
+    bracket_lines: Vec<usize>,
+    title_drawn: bool,
+    /// Where the cursor was put by the last render.
+    cursor_pos: (u16, u16),
+    status_line: StatusLine,
 }
 
 pub struct VirtualScreen {
@@ -36,120 +111,820 @@ impl VirtualScreen {
 }
 
 impl Renderer {
-    pub fn new(max_lines: usize) -> Self {
+    pub fn new(max_lines: usize, cols: usize, status_line: StatusLine) -> Self {
         Self {
             max_lines,
+            cols,
+            area: Rect { x: 0, y: 1, width: cols as u16, height: max_lines as u16 },
             virtual_screen: VirtualScreen::new(max_lines),
+            row_lines: vec![None; max_lines],
+            sign_column: true,
+            screen_reader: false,
+            tab_width: 4,
+            show_invisibles: false,
+            soft_wrap: false,
+            line_numbers: LineNumbers::Absolute,
+            rulers: Vec::new(),
+            theme: Theme::dark(),
+            numbered_line: 0,
+            brackets: None,
+            bracket_lines: Vec::new(),
+            title_drawn: false,
+            cursor_pos: (0, 0),
+            status_line,
         }
     }
 
     #[allow(clippy::too_many_arguments)]
     pub fn render(
         &mut self,
-        stdout: &mut Stdout,
+        out: &mut impl Backend,
         buffer: &EditorBuffer,
         dirty_lines: &HashSet<usize>,
         viewport_row: usize,
-        max_lines: usize,
         cursor_col: usize,
         current_line: usize,
         cursor_visible: bool,
         mode: &InputMode,
         filename_input: &str,
         find_input: &str,
-        confirmed_find_term: &Option<String>,
+        find_regex: bool,
+        matches: &MatchList,
+        selection: Option<(usize, usize)>,
+        restored: Option<(usize, usize)>,
+        brackets: Option<(usize, usize)>,
+        folds: &FoldSet,
+        signs: &SignColumn,
+        highlights: Option<(&SyntaxTree, &mut HighlightCache)>,
+        status: &StatusInfo,
     ) -> Result<(), Error> {        
-        let total_lines = buffer.len_lines();
+        let max_lines = self.max_lines;
 
-        stdout.execute(cursor::Hide)?;
-        stdout.execute(cursor::MoveTo(0, 0))?;
-        stdout.execute(crossterm::terminal::Clear(crossterm::terminal::ClearType::CurrentLine))?;
-        write!(stdout, "Welcome to rusty")?;
+        // Screen readers track the cursor, so keep it put and skip the banner repaint
+        if !self.screen_reader {
+            out.show_cursor(false)?;
+        }
+        if !self.screen_reader || !self.title_drawn {
+            out.move_to(0, 0)?;
+            out.clear_region(ClearRegion::Line)?;
+            out.print("Welcome to rusty")?;
+            self.title_drawn = true;
+        }
 
         // Draw prompt/status line at bottom based on mode
-        stdout.execute(cursor::MoveTo(0, (max_lines + 1) as u16))?;
-        stdout.execute(crossterm::terminal::Clear(crossterm::terminal::ClearType::CurrentLine))?;
+        let status_row = (max_lines + 1) as u16;
+        if matches!(
+            mode,
+            InputMode::Editing | InputMode::FileTree | InputMode::Completion | InputMode::Diff | InputMode::TaskPicker | InputMode::TaskOutput
+        ) {
+            self.status_line.draw(out, status_row, self.cols, status, self.theme.status_line.content())?;
+        } else {
+            self.status_line.invalidate();
+            out.move_to(0, status_row)?;
+            out.clear_region(ClearRegion::Line)?;
+        }
         match mode {
             InputMode::EnteringFileNameOpen => {
-                write!(stdout, "Open file: {}", filename_input)?;
+                out.print(&format!("Open file: {}", filename_input))?;
             }
             InputMode::EnteringFileNameSave => {
-                write!(stdout, "Save file: {}", filename_input)?;
+                out.print(&format!("Save file: {}", filename_input))?;
+            }
+            InputMode::EnteringDiffFiles => {
+                out.print(&format!("Diff files: {}", filename_input))?;
+            }
+            InputMode::EnteringFileNameExport => {
+                out.print(&format!("Export HTML: {}", filename_input))?;
             }
             InputMode::Finding => {
-                write!(stdout, "Find: {}", find_input)?;
+                out.print(&format!("{}: {}", if find_regex { "Find regex" } else { "Find" }, find_input))?;
+            }
+            InputMode::ReplacePattern => {
+                out.print(&format!("{}: {}", if find_regex { "Replace regex" } else { "Replace" }, find_input))?;
+            }
+            InputMode::GrepPattern => {
+                out.print(&format!("{}: {}", if find_regex { "Grep regex" } else { "Grep" }, find_input))?;
+            }
+            InputMode::ReplaceWith => {
+                out.print(&format!("Replace {} with: {}  (Enter: next, Ctrl+E: ask each, Ctrl+A: all)", find_input, filename_input))?;
+            }
+            InputMode::ReplaceConfirm => {
+                out.print(&format!("Replace with {}? (y)es, (n)o, (a)ll or (q)uit", filename_input))?;
+            }
+            InputMode::EnteringCommand => {
+                out.print(&format!(":{}", filename_input))?;
+            }
+            InputMode::EnteringSecret => {
+                out.print(&format!("{}: {}", status.message, filename_input))?;
             }
-            InputMode::Editing => {
-                // Leave empty or print status
+            // Questions get the whole row
+            InputMode::RecoverPrompt | InputMode::DiskChangedPrompt => {
+                out.print(status.message)?;
             }
+            InputMode::Editing
+            | InputMode::Completion
+            | InputMode::Diff
+            | InputMode::TaskPicker
+            | InputMode::TaskOutput
+            | InputMode::BufferPicker
+            | InputMode::GrepResults
+            | InputMode::UndoTree
+            | InputMode::ErrorList
+            | InputMode::FileTree
+            | InputMode::Hex
+            | InputMode::HistoryBrowser
+            | InputMode::GitPanel => {}
         }
 
-        // Go through dirty lines and redraw
-        for &line_idx in dirty_lines.iter() {
-            if line_idx < viewport_row || line_idx >= viewport_row + max_lines {
+        let cursor_row = self.draw_rows(
+            out,
+            buffer,
+            dirty_lines,
+            viewport_row,
+            current_line,
+            Some(matches),
+            selection,
+            restored,
+            brackets,
+            folds,
+            signs,
+            highlights,
+        )?;
+
+        // Draw cursor position; it stays hidden while scrolled out of view
+        let (row_offset, cursor_col) = if self.soft_wrap { self.wrapped_cursor(buffer, current_line, cursor_col) } else { (0, cursor_col) };
+        match cursor_row.map(|row| row + row_offset).filter(|&row| row < self.area.height as usize) {
+            Some(cursor_row) => {
+                let cursor_y = self.area.y + cursor_row as u16;
+                let cursor_x = self.area.x + (cursor_col + self.gutter_width()) as u16;
+                out.move_to(cursor_x, cursor_y)?;
+                self.cursor_pos = (cursor_x, cursor_y);
+                if cursor_visible {
+                    out.show_cursor(true)?;
+                } else {
+                    out.show_cursor(false)?;
+                }
+            }
+            None => {
+                out.show_cursor(false)?;
+            }
+        }
+
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Columns text gets right of the gutter.
+    pub fn text_width(&self) -> usize {
+        (self.area.width as usize).saturating_sub(self.gutter_width())
+    }
+
+    /// Char offsets where the rows of `line` start: wrapped at the area's
+    /// edge in soft wrap mode, a single row otherwise.
+    pub fn row_starts(&self, buffer: &EditorBuffer, line: usize) -> Vec<usize> {
+        if self.soft_wrap && line < buffer.len_lines() {
+            columns::wrap_starts(buffer.line(line), self.text_width(), self.tab_width)
+        } else {
+            vec![0]
+        }
+    }
+
+    /// Row within `line` and column within that row of the display column
+    /// `col` of the unwrapped line.
+    fn wrapped_cursor(&self, buffer: &EditorBuffer, line: usize, col: usize) -> (usize, usize) {
+        if line >= buffer.len_lines() {
+            return (0, col);
+        }
+        let text = buffer.line(line);
+        let (char_col, past_end) = columns::char_col(text, col, self.tab_width);
+        let starts = self.row_starts(buffer, line);
+        let row = starts.partition_point(|&start| start <= char_col) - 1;
+        (row, columns::display_col(text.slice(starts[row]..), char_col - starts[row], self.tab_width) + past_end)
+    }
+
+    /// Draws the rows of the text area that changed since the last call and
+    /// returns the first row showing `current_line`, if it is in view.
+    #[allow(clippy::too_many_arguments)]
+    fn draw_rows(
+        &mut self,
+        out: &mut impl Backend,
+        buffer: &EditorBuffer,
+        dirty_lines: &HashSet<usize>,
+        viewport_row: usize,
+        current_line: usize,
+        matches: Option<&MatchList>,
+        selection: Option<(usize, usize)>,
+        restored: Option<(usize, usize)>,
+        brackets: Option<(usize, usize)>,
+        folds: &FoldSet,
+        signs: &SignColumn,
+        mut highlights: Option<(&SyntaxTree, &mut HighlightCache)>,
+    ) -> Result<Option<usize>, Error> {
+        let total_lines = buffer.len_lines();
+        let width = self.area.width as usize;
+        // Walk the screen rows, mapping each to the buffer line it shows
+        // (closed folds collapse several lines into one placeholder row).
+        let mut line_idx = viewport_row;
+        // Row of `line_idx` being drawn and where its rows start
+        let mut line_row = 0;
+        let mut starts = vec![0];
+        let mut cursor_row = None;
+        let bracket_lines: Vec<usize> = brackets
+            .map(|(open, close)| vec![buffer.char_to_line(open), buffer.char_to_line(close)])
+            .unwrap_or_default();
+        let brackets_changed = std::mem::replace(&mut self.brackets, brackets) != brackets || self.bracket_lines != bracket_lines;
+        let old_bracket_lines = std::mem::replace(&mut self.bracket_lines, bracket_lines);
+        // Relative numbers change on every row when the cursor changes lines
+        let renumber = matches!(self.line_numbers, LineNumbers::Relative | LineNumbers::Hybrid)
+            && std::mem::replace(&mut self.numbered_line, current_line) != current_line;
+        for view_line_idx in 0..self.area.height as usize {
+            let fold_end = folds.closed_fold_end(buffer, line_idx);
+            if line_row == 0 {
+                // A closed fold shows as a single row
+                starts = if fold_end.is_some() { vec![0] } else { self.row_starts(buffer, line_idx) };
+            }
+            let row_start = starts[line_row];
+            let row_end = starts.get(line_row + 1).copied();
+            let sign = if self.sign_column && line_row == 0 { signs.top(line_idx) } else { None };
+            let mapping = (line_idx, fold_end, sign, row_start);
+            let remapped = self.row_lines[view_line_idx] != Some(mapping);
+            self.row_lines[view_line_idx] = Some(mapping);
+            if line_idx == current_line && line_row == 0 {
+                cursor_row = Some(view_line_idx);
+            }
+            let next = if row_end.is_some() { (line_idx, line_row + 1) } else { (fold_end.unwrap_or(line_idx) + 1, 0) };
+
+            let bracket_moved = brackets_changed
+                && (self.bracket_lines.contains(&line_idx) || old_bracket_lines.contains(&line_idx));
+            if !remapped && !bracket_moved && !renumber && !dirty_lines.contains(&line_idx) {
+                (line_idx, line_row) = next;
                 continue;
             }
-            let view_line_idx = line_idx - viewport_row;
 
+            let row = self.area.y + view_line_idx as u16;
             if line_idx >= total_lines {
                 // Draw "~" for empty lines outside buffer
                 let tilde_line = format!("{:>width$}~ ", "", width = 3);
                 let cached_line = self.virtual_screen.get_line(view_line_idx).unwrap_or("");
                 if cached_line != tilde_line {
-                    stdout.execute(cursor::MoveTo(0, (view_line_idx + 1) as u16))?;
-                    stdout.execute(crossterm::terminal::Clear(crossterm::terminal::ClearType::CurrentLine))?;
-                    write!(stdout, "{}", tilde_line)?;
+                    out.move_to(self.area.x, row)?;
+                    out.print(&tilde_line)?;
+                    self.clear_rest(out, tilde_line.len())?;
                     self.virtual_screen.update_line(view_line_idx, &tilde_line);
                 }
+                (line_idx, line_row) = next;
                 continue;
             }
 
             let rope_line = buffer.line(line_idx);
-            let line_str = if rope_line.len_chars() > 0 && rope_line.char(rope_line.len_chars() - 1) == '\n' {
-                rope_line.slice(0..rope_line.len_chars() - 1).to_string()
-            } else {
-                rope_line.to_string()
-            };
+            let line_str = rope_line.slice(0..buffer.line_len(line_idx)).to_string();
 
             let gutter_width = 4;
-            let gutter = format!("{:>width$} ", line_idx + 1, width = gutter_width);
-
-            stdout.execute(cursor::MoveTo(0, (view_line_idx + 1) as u16))?;
-            stdout.execute(crossterm::terminal::Clear(crossterm::terminal::ClearType::CurrentLine))?;
-            queue!(stdout, Print(&gutter))?;
-
-            if let Some( find_term) = confirmed_find_term.as_ref() {
-                let mut remaining = line_str.as_str();
-                while !remaining.is_empty() {
-                    if remaining.starts_with(find_term) {
-                        queue!(stdout, Print(find_term.as_str().reverse()))?;
-                        remaining = &remaining[find_term.len()..];
+            // Panes without the focus have no cursor line to count from
+            let number = match self.line_numbers {
+                LineNumbers::Relative if current_line != usize::MAX => line_idx.abs_diff(current_line),
+                LineNumbers::Hybrid if current_line != usize::MAX && line_idx != current_line => line_idx.abs_diff(current_line),
+                _ => line_idx + 1,
+            };
+            // Rows continuing a wrapped line leave the number out
+            let gutter = match self.line_numbers {
+                LineNumbers::Off => String::new(),
+                _ if line_row == 0 => format!("{:>width$} ", number, width = gutter_width),
+                _ => " ".repeat(gutter_width + 1),
+            };
+
+            out.move_to(self.area.x, row)?;
+            if self.sign_column {
+                match sign {
+                    Some(sign) => out.print_styled(sign.glyph.encode_utf8(&mut [0; 4]), ContentStyle::new().with(sign.color))?,
+                    None => out.print(" ")?,
+                }
+            }
+            out.print_styled(&gutter, self.theme.line_numbers.content())?;
+
+
+            // Syntax colors for each byte of the line
+            let mut kinds = vec![None; line_str.len()];
+            if let Some((syntax, cache)) = highlights.as_mut() {
+                for &(start, end, kind) in cache.spans(syntax, buffer, line_idx) {
+                    kinds[start.min(line_str.len())..end.min(line_str.len())].fill(Some(kind));
+                }
+            }
+
+            // Text past the right edge of the area is cut off unless wrapped
+            let text_width = width.saturating_sub(self.gutter_width());
+            let line_start = buffer.line_to_char(line_idx);
+            let found = matches.map_or(&[][..], |matches| matches.within(line_start, line_start + rope_line.len_chars()));
+            // Trailing whitespace gets a warning background
+            let trailing = if self.screen_reader { line_str.len() } else { line_str.trim_end().len() };
+            let mut used = self.gutter_width();
+            let mut col = 0;
+            for (char_offset, (pos, ch)) in line_str.char_indices().enumerate().skip(row_start) {
+                if row_end.is_some_and(|end| char_offset >= end) {
+                    break;
+                }
+                // A tab fills the columns up to the next tab stop
+                let cells = columns::cell_width(ch, col, self.tab_width);
+                if col + cells > text_width {
+                    break;
+                }
+                let char_idx = line_start + char_offset;
+                let theme = &self.theme;
+                let mut style = ContentStyle::new();
+                if let Some(kind) = kinds[pos] {
+                    theme.syntax(kind).apply(&mut style);
+                }
+                if selection.is_some_and(|(start, end)| start <= char_idx && char_idx < end) {
+                    if self.screen_reader {
+                        style.attributes.set(Attribute::Underlined);
+                    } else {
+                        theme.selection.apply(&mut style);
+                    }
+                } else if !self.screen_reader && restored.is_some_and(|(start, end)| start <= char_idx && char_idx < end) {
+                    theme.undo_flash.apply(&mut style);
+                } else if brackets.is_some_and(|(open, close)| char_idx == open || char_idx == close) {
+                    if self.screen_reader {
+                        style.attributes.set(Attribute::Bold);
                     } else {
-                        let ch = remaining.chars().next().unwrap();
-                        let ch_len = ch.len_utf8();
-                        queue!(stdout, Print(ch))?;
-                        remaining = &remaining[ch_len..];
+                        theme.matching_bracket.apply(&mut style);
                     }
+                } else if found.iter().any(|&(start, end)| start <= char_idx && char_idx < end) {
+                    theme.search_match.apply(&mut style);
+                } else if pos >= trailing {
+                    theme.trailing_whitespace.apply(&mut style);
+                }
+                let glyph = match ch {
+                    ' ' if self.show_invisibles => Some('·'),
+                    '\t' if self.show_invisibles => Some('→'),
+                    _ => None,
+                };
+                if glyph.is_some() {
+                    theme.invisibles.apply(&mut style);
+                }
+                for cell in 0..cells {
+                    let ch = match glyph {
+                        Some(glyph) if cell == 0 => glyph,
+                        _ if ch == '\t' => ' ',
+                        _ => ch,
+                    };
+                    // Highlights take precedence over the ruler
+                    let mut style = style;
+                    if style.background_color.is_none()
+                        && !style.attributes.has(Attribute::Reverse)
+                        && self.rulers.contains(&(col + cell + 1))
+                    {
+                        theme.ruler.apply(&mut style);
+                    }
+                    if style == ContentStyle::new() {
+                        out.print(ch.encode_utf8(&mut [0; 4]))?;
+                    } else {
+                        out.print_styled(ch.encode_utf8(&mut [0; 4]), style)?;
+                    }
+                }
+                col += cells;
+                used += cells;
+            }
+            if self.show_invisibles && row_end.is_none() && line_idx + 1 < total_lines && col < text_width {
+                out.print_styled("¶", self.theme.invisibles.content())?;
+                used += 1;
+            }
+
+            if let Some(end) = fold_end {
+                let placeholder: String =
+                    format!(" ··· {} lines", end - line_idx + 1).chars().take(width.saturating_sub(used)).collect();
+                used += placeholder.chars().count();
+                out.print_styled(&placeholder, self.theme.fold_placeholder.content())?;
+            }
+            // Rulers past the end of the text
+            let mut rulers: Vec<usize> = self.rulers.iter().map(|ruler| self.gutter_width() + ruler - 1).collect();
+            rulers.sort_unstable();
+            for at in rulers {
+                if at >= used && at < self.gutter_width() + text_width {
+                    out.print(&" ".repeat(at - used))?;
+                    out.print_styled(" ", self.theme.ruler.content())?;
+                    used = at + 1;
                 }
-            } else {
-                queue!(stdout, Print(&line_str))?;
             }
+            self.clear_rest(out, used)?;
 
             self.virtual_screen.update_line(view_line_idx, &format!("{}{}", gutter, line_str));
+            (line_idx, line_row) = next;
         }
+        Ok(cursor_row)
+    }
 
-        // Draw cursor position
-        let cursor_y = (current_line.saturating_sub(viewport_row) + 1) as u16;
-        let cursor_x = (cursor_col + 4 + 1) as u16;
-        stdout.execute(cursor::MoveTo(cursor_x, cursor_y))?;
+    /// Blanks the rest of the current row of the area after `used` columns.
+    fn clear_rest(&self, out: &mut impl Backend, used: usize) -> Result<(), Error> {
+        if self.area.x + self.area.width >= self.cols as u16 {
+            out.clear_region(ClearRegion::RestOfLine)?;
+        } else {
+            out.print(&" ".repeat((self.area.width as usize).saturating_sub(used)))?;
+        }
+        Ok(())
+    }
 
-        if cursor_visible {
-            stdout.execute(cursor::Show)?;
+    /// Draws a pane that does not have the focus: no cursor, selection or
+    /// status line.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_inactive(
+        &mut self,
+        out: &mut impl Backend,
+        buffer: &EditorBuffer,
+        dirty_lines: &HashSet<usize>,
+        viewport_row: usize,
+        folds: &FoldSet,
+        signs: &SignColumn,
+        highlights: Option<(&SyntaxTree, &mut HighlightCache)>,
+    ) -> Result<(), Error> {
+        self.draw_rows(out, buffer, dirty_lines, viewport_row, usize::MAX, None, None, None, None, folds, signs, highlights)?;
+        Ok(())
+    }
+
+    /// Buffer position shown at a screen cell, from what was last drawn.
+    /// Cells outside the area are moved onto its nearest edge and cells in
+    /// the gutter map to the start of the line.
+    pub fn char_at(&self, buffer: &EditorBuffer, column: u16, row: u16) -> Option<usize> {
+        let area = self.area;
+        let view_row = row.clamp(area.y, (area.y + area.height).saturating_sub(1)) - area.y;
+        let (line, _, _, row_start) = (*self.row_lines.get(view_row as usize)?)?;
+        if line >= buffer.len_lines() {
+            return Some(buffer.len_chars());
+        }
+        let col = (column.saturating_sub(area.x) as usize).saturating_sub(self.gutter_width());
+        let mut char_col = row_start + columns::char_col(buffer.line(line).slice(row_start..), col, self.tab_width).0;
+        // Past the end of a wrapped row is its last char, not the next row
+        if let Some(&next_start) = self.row_starts(buffer, line).iter().find(|&&start| start > row_start) {
+            char_col = char_col.min(next_start - 1);
+        }
+        Some(buffer.line_to_char(line) + char_col)
+    }
+
+    /// Draws the lines between split panes.
+    pub fn draw_separators(&self, out: &mut impl Backend, separators: &[(SplitDirection, Rect)]) -> Result<(), Error> {
+        for &(direction, rect) in separators {
+            match direction {
+                SplitDirection::Vertical => {
+                    for row in rect.y..rect.y + rect.height {
+                        out.move_to(rect.x, row)?;
+                        out.print_styled("│", ContentStyle::new().dark_grey())?;
+                    }
+                }
+                SplitDirection::Horizontal => {
+                    out.move_to(rect.x, rect.y)?;
+                    out.print_styled(&"─".repeat(rect.width as usize), ContentStyle::new().dark_grey())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Draws the file tree sidebar in `rect`, with a separator down its
+    /// right side. The `selected` row is highlighted.
+    pub fn render_tree(&self, out: &mut impl Backend, rect: Rect, labels: &[String], top: usize, selected: Option<usize>) -> Result<(), Error> {
+        let width = rect.width as usize;
+        for offset in 0..rect.height {
+            let idx = top + offset as usize;
+            let label: String = labels.get(idx).map_or("", |label| label.as_str()).chars().take(width).collect();
+            let cell = format!("{:<w$}", label, w = width);
+            out.move_to(rect.x, rect.y + offset)?;
+            if selected == Some(idx) {
+                out.print_styled(&cell, self.theme.list_selected.content())?;
+            } else {
+                out.print(&cell)?;
+            }
+            out.print_styled("│", ContentStyle::new().dark_grey())?;
+        }
+        Ok(())
+    }
+
+    /// Moves the text area to `area`, e.g. after a split.
+    pub fn set_area(&mut self, area: Rect) {
+        self.area = area;
+        self.invalidate();
+    }
+
+    /// Fits the renderer to a screen of `max_lines` text rows and `cols`
+    /// columns, its area covering all of them until it is arranged again.
+    pub fn resize(&mut self, max_lines: usize, cols: usize) {
+        self.max_lines = max_lines;
+        self.cols = cols;
+        self.set_area(Rect { x: 0, y: 1, width: cols as u16, height: max_lines as u16 });
+    }
+
+    /// Columns taken by the sign column, line numbers and separator.
+    pub fn gutter_width(&self) -> usize {
+        let numbers = if self.line_numbers == LineNumbers::Off { 0 } else { 4 + 1 };
+        usize::from(self.sign_column) + numbers
+    }
+
+    /// Draws a list of completions below the cursor (above it near the
+    /// bottom of the area). The rows it covers are redrawn by the next render.
+    pub fn render_popup(&mut self, out: &mut impl Backend, items: &[String], selected: usize) -> Result<(), Error> {
+        const MAX_ITEMS: usize = 8;
+        let (cursor_x, cursor_y) = self.cursor_pos;
+        let (area_top, area_bottom) = (self.area.y as usize, (self.area.y + self.area.height) as usize);
+        let shown = items.len().min(MAX_ITEMS).min((self.area.height as usize).saturating_sub(1));
+        if shown == 0 {
+            return Ok(());
+        }
+        let top = selected.saturating_sub(shown - 1);
+        let width = (items.iter().map(|item| item.chars().count()).max().unwrap_or(0).min(40) + 2).min(self.area.width as usize);
+        let area_right = (self.area.x + self.area.width) as usize;
+        let x = (cursor_x as usize).min(area_right.saturating_sub(width)) as u16;
+        let first_row = if cursor_y as usize + shown < area_bottom {
+            cursor_y as usize + 1
         } else {
-            stdout.execute(cursor::Hide)?;
+            (cursor_y as usize).saturating_sub(shown).max(area_top)
+        };
+
+        for (offset, idx) in (top..top + shown).enumerate() {
+            let row = first_row + offset;
+            let label: String = items[idx].chars().take(width.saturating_sub(2)).collect();
+            let cell = format!(" {:<w$} ", label, w = width.saturating_sub(2));
+            out.move_to(x, row as u16)?;
+            let style = if idx == selected { &self.theme.list_selected } else { &self.theme.popup };
+            out.print_styled(&cell, style.content())?;
+            if let Some(mapping) = self.row_lines.get_mut(row - area_top) {
+                *mapping = None;
+            }
+            self.virtual_screen.update_line(row - area_top, "");
+        }
+
+        out.move_to(cursor_x, cursor_y)?;
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Forgets what is on screen so the next render redraws every row.
+    pub fn invalidate(&mut self) {
+        self.row_lines = vec![None; self.area.height as usize];
+        self.title_drawn = false;
+        self.virtual_screen = VirtualScreen::new(self.area.height as usize);
+        self.status_line.invalidate();
+    }
+
+    /// Draws a side-by-side diff over the whole text area.
+    pub fn render_diff(&mut self, out: &mut impl Backend, view: &DiffView) -> Result<(), Error> {
+        let pane_width = self.cols.saturating_sub(1) / 2;
+        let pane_text = |line: Option<(usize, &String)>| -> String {
+            let text = match line {
+                Some((idx, text)) => format!("{:>4} {}", idx + 1, text),
+                None => String::new(),
+            };
+            let mut cell: String = text.chars().take(pane_width).collect();
+            let len = cell.chars().count();
+            cell.extend(std::iter::repeat_n(' ', pane_width - len));
+            cell
+        };
+
+        out.show_cursor(false)?;
+        out.move_to(0, 0)?;
+        out.clear_region(ClearRegion::Line)?;
+        let title = format!("Diff: {} | {}", view.left_name, view.right_name);
+        out.print(&title.chars().take(self.cols).collect::<String>())?;
+
+        for view_row in 0..self.max_lines {
+            out.move_to(0, (view_row + 1) as u16)?;
+            out.clear_region(ClearRegion::Line)?;
+            let Some(row) = view.rows.get(view.top + view_row) else {
+                out.print(&format!("{:>width$}~ ", "", width = 3))?;
+                continue;
+            };
+            let left = pane_text(row.left.map(|i| (i, &view.left[i])));
+            let right = pane_text(row.right.map(|i| (i, &view.right[i])));
+            let plain = ContentStyle::new();
+            let (left_style, right_style) = match row.kind {
+                RowKind::Same => (plain, plain),
+                RowKind::Removed => (self.theme.diff_removed.content(), plain),
+                RowKind::Added => (plain, self.theme.diff_added.content()),
+                RowKind::Changed => (self.theme.diff_changed.content(), self.theme.diff_changed.content()),
+            };
+            out.print_styled(&left, left_style)?;
+            out.print("│")?;
+            out.print_styled(&right, right_style)?;
         }
 
-        stdout.flush()?;
+        out.move_to(0, (self.max_lines + 1) as u16)?;
+        out.clear_region(ClearRegion::Line)?;
+        let hunk = view.current_hunk.map_or("-".to_string(), |h| (h + 1).to_string());
+        out.print(&format!("Hunk {}/{}  n/p: next/prev hunk  j/k: scroll  q: close", hunk, view.hunk_count()))?;
+
+        out.flush()?;
+        self.invalidate();
         Ok(())
     }
+
+    /// Draws a binary file as offset, hex and ASCII columns, with the byte
+    /// under the cursor highlighted in both. A `message` replaces the key help.
+    pub fn render_hex(&mut self, out: &mut impl Backend, view: &HexView, message: &str) -> Result<(), Error> {
+        out.show_cursor(false)?;
+        out.move_to(0, 0)?;
+        out.clear_region(ClearRegion::Line)?;
+        let title = format!("Hex: {}{}", view.path, if view.modified { " [+]" } else { "" });
+        out.print(&title.chars().take(self.cols).collect::<String>())?;
+
+        for view_row in 0..self.max_lines {
+            out.move_to(0, (view_row + 1) as u16)?;
+            out.clear_region(ClearRegion::Line)?;
+            let start = (view.top + view_row) * BYTES_PER_ROW;
+            if start >= view.bytes.len() {
+                out.print(&format!("{:>width$}~ ", "", width = 3))?;
+                continue;
+            }
+            let row = &view.bytes[start..(start + BYTES_PER_ROW).min(view.bytes.len())];
+            out.print(&format!("{:08x}  ", start))?;
+            for column in 0..BYTES_PER_ROW {
+                let cell = row.get(column).map_or("  ".to_string(), |byte| format!("{:02x}", byte));
+                if start + column == view.cursor {
+                    out.print_styled(&cell, self.theme.list_selected.content())?;
+                } else {
+                    out.print(&cell)?;
+                }
+                out.print(" ")?;
+            }
+            out.print("|")?;
+            for (column, byte) in row.iter().enumerate() {
+                let c = if byte.is_ascii_graphic() || *byte == b' ' { *byte as char } else { '.' };
+                if start + column == view.cursor {
+                    out.print_styled(c.encode_utf8(&mut [0; 4]), self.theme.list_selected.content())?;
+                } else {
+                    out.print(c.encode_utf8(&mut [0; 4]))?;
+                }
+            }
+            out.print("|")?;
+        }
+
+        out.move_to(0, (self.max_lines + 1) as u16)?;
+        out.clear_region(ClearRegion::Line)?;
+        let help = if message.is_empty() { "0-9a-f: edit  hjkl/arrows: move  Ctrl+S: save  q: close" } else { message };
+        let footer = format!("0x{:x}/0x{:x}  {}", view.cursor, view.bytes.len(), help);
+        out.print(&footer.chars().take(self.cols).collect::<String>())?;
+
+        out.flush()?;
+        self.invalidate();
+        Ok(())
+    }
+
+    /// Draws a full-screen list panel (task picker, task output) with an
+    /// optional highlighted row.
+    pub fn render_panel(
+        &mut self,
+        out: &mut impl Backend,
+        title: &str,
+        lines: &[String],
+        top: usize,
+        selected: Option<usize>,
+        footer: &str,
+    ) -> Result<(), Error> {
+        out.show_cursor(false)?;
+        out.move_to(0, 0)?;
+        out.clear_region(ClearRegion::Line)?;
+        out.print(&title.chars().take(self.cols).collect::<String>())?;
+
+        for view_row in 0..self.max_lines {
+            out.move_to(0, (view_row + 1) as u16)?;
+            out.clear_region(ClearRegion::Line)?;
+            let idx = top + view_row;
+            let Some(line) = lines.get(idx) else {
+                continue;
+            };
+            let text: String = line.chars().take(self.cols).collect();
+            if selected == Some(idx) {
+                out.print_styled(&text, self.theme.list_selected.content())?;
+            } else {
+                out.print(&text)?;
+            }
+        }
+
+        out.move_to(0, (self.max_lines + 1) as u16)?;
+        out.clear_region(ClearRegion::Line)?;
+        out.print(&footer.chars().take(self.cols).collect::<String>())?;
+
+        out.flush()?;
+        self.invalidate();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::TestBackend;
+    use crate::config::StatusLineConfig;
+    use crate::search::MatchList;
+
+    fn renderer(cols: usize, max_lines: usize) -> Renderer {
+        Renderer::new(max_lines, cols, StatusLine::new(StatusLineConfig::default().segments, None))
+    }
+
+    fn status(message: &str) -> StatusInfo<'_> {
+        StatusInfo {
+            message,
+            pending_keys: "",
+            mode: "EDIT",
+            file: Some("main.rs"),
+            modified: false,
+            git_branch: None,
+            errors: 0,
+            warnings: 0,
+            encoding: "UTF-8",
+            line_ending: "LF",
+            line: 1,
+            col: 1,
+            total_lines: 2,
+            matches: None,
+            editing_secs: 0,
+        }
+    }
+
+    fn render(renderer: &mut Renderer, out: &mut TestBackend, buffer: &EditorBuffer, cursor_col: usize, current_line: usize, selection: Option<(usize, usize)>) {
+        let dirty: HashSet<usize> = (0..buffer.len_lines()).collect();
+        renderer
+            .render(
+                out,
+                buffer,
+                &dirty,
+                0,
+                cursor_col,
+                current_line,
+                true,
+                &InputMode::Editing,
+                "",
+                "",
+                false,
+                &MatchList::new(),
+                selection,
+                None,
+                None,
+                &FoldSet::new(),
+                &SignColumn::new(),
+                None,
+                &status(""),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn draws_lines_after_the_gutter_with_the_status_line_below() {
+        let mut buffer = EditorBuffer::new();
+        buffer.insert(0, "fn main() {}\nlet x = 1;\n");
+        let mut out = TestBackend::new(40, 6);
+        let mut renderer = renderer(40, 4);
+        render(&mut renderer, &mut out, &buffer, 3, 1, None);
+
+        let lines = out.lines();
+        assert_eq!(lines[1].trim_end(), "    1 fn main() {}");
+        assert_eq!(lines[2].trim_end(), "    2 let x = 1;");
+        assert_eq!(lines[4].trim_end(), "   ~");
+        assert!(lines[5].starts_with("EDIT | main.rs | UTF-8 | LF"));
+        assert_eq!(out.style_at(0, 5), Some(renderer.theme.status_line.content()));
+        // Past the gutter, on the second line
+        assert_eq!(out.cursor(), (9, 2));
+        assert!(out.cursor_visible);
+    }
+
+    #[test]
+    fn selection_is_drawn_in_its_style() {
+        let mut buffer = EditorBuffer::new();
+        buffer.insert(0, "let x = 1;\n");
+        let mut out = TestBackend::new(40, 4);
+        let mut renderer = renderer(40, 2);
+        render(&mut renderer, &mut out, &buffer, 0, 0, Some((4, 5)));
+
+        let selected = renderer.theme.selection.content();
+        assert_eq!(out.style_at(10, 1), Some(selected));
+        assert_ne!(out.style_at(9, 1), Some(selected));
+        assert_ne!(out.style_at(11, 1), Some(selected));
+    }
+
+    #[test]
+    fn panel_highlights_the_selected_row() {
+        let mut out = TestBackend::new(20, 5);
+        let mut renderer = renderer(20, 3);
+        let items = vec!["build".to_string(), "test".to_string()];
+        renderer.render_panel(&mut out, "Tasks", &items, 0, Some(1), "q: close").unwrap();
+
+        let lines = out.lines();
+        assert_eq!(lines[0].trim_end(), "Tasks");
+        assert_eq!(lines[1].trim_end(), "build");
+        assert_eq!(lines[2].trim_end(), "test");
+        assert_eq!(lines[3].trim_end(), "");
+        assert_eq!(lines[4].trim_end(), "q: close");
+        assert_eq!(out.style_at(0, 2), Some(renderer.theme.list_selected.content()));
+        assert_eq!(out.style_at(0, 1), Some(ContentStyle::new()));
+        assert!(!out.cursor_visible);
+    }
+
+    #[test]
+    fn tree_is_cut_to_its_width_and_fenced_off() {
+        let mut out = TestBackend::new(20, 3);
+        let renderer = renderer(20, 1);
+        let labels = vec!["src/".to_string(), "  a_long_file_name.rs".to_string()];
+        renderer.render_tree(&mut out, Rect { x: 0, y: 0, width: 8, height: 3 }, &labels, 0, None).unwrap();
+
+        let lines = out.lines();
+        assert_eq!(lines[0].trim_end(), "src/    │");
+        assert_eq!(lines[1].trim_end(), "  a_long│");
+        assert_eq!(lines[2].trim_end(), "        │");
+    }
 }
@@ -0,0 +1,202 @@
+// src/scripting.rs
+
+use crate::plugins::{HookOutput, HostState};
+use rhai::{CallFnOptions, Dynamic, Engine, EvalAltResult, FuncArgs, Scope, AST};
+use ropey::Rope;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+/// Operations a script may run per call, so one stuck in a loop hands the
+/// editor back instead of freezing it.
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// The Rhai init script, `init.rhai` in the config directory. Its
+/// functions are commands run as `:NAME`, bound to keys with
+/// `bind("Ctrl+T", "NAME")` at the top level, or, taking the text of a
+/// range and returning what replaces it, used by `:transform NAME`.
+///
+/// Scripts see the buffer through functions counting in chars, with lines
+/// from 0: `text()`, `line_count()`, `line(n)`, `set_line(n, text)`,
+/// `cursor()`, `set_cursor(pos)`, `cursor_line()`, `insert(text)`,
+/// `replace(start, end, text)`, `path()` and `status(message)`.
+pub struct ScriptHost {
+    engine: Engine,
+    ast: Option<AST>,
+    /// The buffer while a script runs.
+    state: Rc<RefCell<HostState>>,
+    /// Functions bound to key names, e.g. "Ctrl+T" or "Alt+x".
+    bindings: Rc<RefCell<HashMap<String, String>>>,
+}
+
+impl ScriptHost {
+    /// Compiles and runs `path` when it exists. Returns the host along
+    /// with why the script could not be loaded, if it could not.
+    pub fn load(path: Option<&Path>) -> (Self, Option<String>) {
+        let state = Rc::new(RefCell::new(HostState::default()));
+        let bindings = Rc::new(RefCell::new(HashMap::new()));
+        let mut host = ScriptHost { engine: engine(&state, &bindings), ast: None, state, bindings };
+        let Some(source) = path.and_then(|path| fs::read_to_string(path).ok()) else {
+            return (host, None);
+        };
+        let ast = match host.engine.compile(&source) {
+            Ok(ast) => ast,
+            Err(err) => return (host, Some(format!("Error in init.rhai: {}", err))),
+        };
+        let error = host.engine.run_ast(&ast).err().map(|err| format!("Error in init.rhai: {}", err));
+        host.ast = Some(ast);
+        (host, error)
+    }
+
+    /// Whether the script defines `name` taking `params` arguments.
+    pub fn has_function(&self, name: &str, params: usize) -> bool {
+        self.ast
+            .as_ref()
+            .is_some_and(|ast| ast.iter_functions().any(|function| function.name == name && function.params.len() == params))
+    }
+
+    /// The function bound to `key`, if any.
+    pub fn binding(&self, key: &str) -> Option<String> {
+        self.bindings.borrow().get(key).cloned()
+    }
+
+    /// Runs the command `name` on the buffer.
+    pub fn run_command(&self, name: &str, text: &Rope, cursor: usize, path: Option<&str>) -> HookOutput {
+        let (result, mut state) = self.call(name, (), text, cursor, path);
+        if let Err(err) = result {
+            state.status = Some(err);
+        }
+        state.into_output(true)
+    }
+
+    /// Replaces the chars `start..end` with what `name` makes of them.
+    pub fn transform(&self, name: &str, text: &Rope, start: usize, end: usize, cursor: usize, path: Option<&str>) -> Result<HookOutput, String> {
+        let input = text.slice(start..end).to_string();
+        let (result, mut state) = self.call(name, (input.clone(),), text, cursor, path);
+        let output = result?;
+        let Some(output) = output.into_string().ok() else {
+            return Err(format!("Transform {} did not return a string", name));
+        };
+        // Only the text it returns counts, whatever else the function did
+        let mut result = HostState::new(text, cursor, path);
+        if output != input {
+            result.replace(start, end, output);
+        }
+        result.status = state.status.take();
+        Ok(result.into_output(true))
+    }
+
+    /// Calls `name` with the buffer handed to the API, and takes the
+    /// buffer back with what the function did to it.
+    fn call(
+        &self,
+        name: &str,
+        args: impl FuncArgs,
+        text: &Rope,
+        cursor: usize,
+        path: Option<&str>,
+    ) -> (Result<Dynamic, String>, HostState) {
+        let Some(ast) = self.ast.as_ref() else {
+            return (Err(format!("Unknown command: {}", name)), HostState::new(text, cursor, path));
+        };
+        *self.state.borrow_mut() = HostState::new(text, cursor, path);
+        let options = CallFnOptions::new().eval_ast(false);
+        let result = self
+            .engine
+            .call_fn_with_options::<Dynamic>(options, &mut Scope::new(), ast, name, args)
+            .map_err(|err| format!("Script {} failed: {}", name, err));
+        (result, self.state.take())
+    }
+}
+
+/// A Rhai engine with the editor's API, working on `state`.
+fn engine(state: &Rc<RefCell<HostState>>, bindings: &Rc<RefCell<HashMap<String, String>>>) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    // Printing would scribble over the screen; it goes to the status line
+    let s = Rc::clone(state);
+    engine.on_print(move |message| s.borrow_mut().status = Some(message.to_string()));
+    let s = Rc::clone(state);
+    engine.on_debug(move |message, _, _| s.borrow_mut().status = Some(message.to_string()));
+
+    let b = Rc::clone(bindings);
+    engine.register_fn("bind", move |key: &str, function: &str| {
+        b.borrow_mut().insert(key.to_string(), function.to_string());
+    });
+    let s = Rc::clone(state);
+    engine.register_fn("text", move || s.borrow().text.to_string());
+    let s = Rc::clone(state);
+    engine.register_fn("line_count", move || s.borrow().text.len_lines() as i64);
+    let s = Rc::clone(state);
+    engine.register_fn("line", move |line: i64| -> ScriptResult<String> {
+        let state = s.borrow();
+        let (start, end) = line_span(&state.text, line)?;
+        Ok(state.text.slice(start..end).to_string())
+    });
+    let s = Rc::clone(state);
+    engine.register_fn("set_line", move |line: i64, text: &str| -> ScriptResult<()> {
+        let mut state = s.borrow_mut();
+        let (start, end) = line_span(&state.text, line)?;
+        state.replace(start, end, text.to_string());
+        Ok(())
+    });
+    let s = Rc::clone(state);
+    engine.register_fn("cursor", move || s.borrow().cursor as i64);
+    let s = Rc::clone(state);
+    engine.register_fn("set_cursor", move |pos: i64| -> ScriptResult<()> {
+        let mut state = s.borrow_mut();
+        state.cursor = char_index(&state.text, pos)?;
+        Ok(())
+    });
+    let s = Rc::clone(state);
+    engine.register_fn("cursor_line", move || {
+        let state = s.borrow();
+        state.text.char_to_line(state.cursor) as i64
+    });
+    let s = Rc::clone(state);
+    engine.register_fn("insert", move |text: &str| {
+        let mut state = s.borrow_mut();
+        let cursor = state.cursor;
+        state.replace(cursor, cursor, text.to_string());
+    });
+    let s = Rc::clone(state);
+    engine.register_fn("replace", move |start: i64, end: i64, text: &str| -> ScriptResult<()> {
+        let mut state = s.borrow_mut();
+        let (start, end) = (char_index(&state.text, start)?, char_index(&state.text, end)?);
+        if start > end {
+            return Err("replace: start is after end".into());
+        }
+        state.replace(start, end, text.to_string());
+        Ok(())
+    });
+    let s = Rc::clone(state);
+    engine.register_fn("path", move || s.borrow().path.clone());
+    let s = Rc::clone(state);
+    engine.register_fn("status", move |message: &str| s.borrow_mut().status = Some(message.to_string()));
+    engine
+}
+
+fn char_index(text: &Rope, pos: i64) -> ScriptResult<usize> {
+    usize::try_from(pos)
+        .ok()
+        .filter(|&pos| pos <= text.len_chars())
+        .ok_or_else(|| format!("position {} is outside the buffer", pos).into())
+}
+
+/// Chars of `line` without its line break.
+fn line_span(text: &Rope, line: i64) -> ScriptResult<(usize, usize)> {
+    let line = usize::try_from(line)
+        .ok()
+        .filter(|&line| line < text.len_lines())
+        .ok_or_else(|| Box::<EvalAltResult>::from(format!("no line {}", line)))?;
+    let start = text.line_to_char(line);
+    let mut end = start + text.line(line).len_chars();
+    while end > start && matches!(text.char(end - 1), '\n' | '\r') {
+        end -= 1;
+    }
+    Ok((start, end))
+}
@@ -0,0 +1,410 @@
+// src/search.rs
+
+use crate::buffer::{is_whole_word_at, EditOp, EditorBuffer, UndoTree};
+use regex::{Captures, Regex, RegexBuilder};
+use std::borrow::Cow;
+
+/// A confirmed find term, compiled once. Literal terms are escaped into a
+/// regular expression so both kinds are matched the same way.
+pub struct Search {
+    regex: Regex,
+    /// Replacements of a regular expression may refer to groups as `$1`.
+    is_regex: bool,
+    whole_word: bool,
+}
+
+impl Search {
+    pub fn new(term: &str, is_regex: bool, whole_word: bool) -> Result<Self, regex::Error> {
+        let pattern = if is_regex { Cow::Borrowed(term) } else { Cow::Owned(regex::escape(term)) };
+        // `^` and `$` match at line breaks, as they would searching line by line
+        let regex = RegexBuilder::new(&pattern).multi_line(true).build()?;
+        Ok(Search { regex, is_regex, whole_word })
+    }
+
+    /// Byte ranges of the matches in `text`. Empty matches are left out as
+    /// there is nothing to highlight or jump over.
+    pub fn matches_in<'a>(&'a self, text: &'a str) -> impl Iterator<Item = (usize, usize)> + 'a {
+        self.regex
+            .find_iter(text)
+            .filter(|m| !m.is_empty())
+            .filter(|m| !self.whole_word || is_whole_word_at(text, m.start(), m.len()))
+            .map(|m| (m.start(), m.end()))
+    }
+
+    /// Finds the first match at or after `from`, wrapping around to the start
+    /// of the buffer. Returns the char range of the match.
+    pub fn find_next(&self, buffer: &EditorBuffer, from: usize) -> Option<(usize, usize)> {
+        let (line_idx, start, end) = self.locate(buffer, from)?;
+        let line: Cow<str> = buffer.line(line_idx).into();
+        let start_char = buffer.line_to_char(line_idx) + line[..start].chars().count();
+        Some((start_char, start_char + line[start..end].chars().count()))
+    }
+
+    /// Line and byte range within it of the first match at or after `from`.
+    ///
+    /// Lines are searched one at a time, borrowed straight from the rope
+    /// where they sit in one chunk, so a match never spans a line break.
+    fn locate(&self, buffer: &EditorBuffer, from: usize) -> Option<(usize, usize, usize)> {
+        let from = from.min(buffer.len_chars());
+        let first_line = buffer.char_to_line(from);
+        let lines = buffer.len_lines();
+        let from_byte = buffer.rope.char_to_byte(from) - buffer.rope.line_to_byte(first_line);
+        // The line with `from` comes up twice: first for the matches after
+        // it, and after wrapping around for those before it
+        for i in 0..=lines {
+            let line_idx = (first_line + i) % lines;
+            let line: Cow<str> = buffer.line(line_idx).into();
+            let text = line.strip_suffix('\n').unwrap_or(&line);
+            let found = self.matches_in(text).find(|&(start, _)| match i {
+                0 => start >= from_byte,
+                _ if i == lines => start < from_byte,
+                _ => true,
+            });
+            if let Some((start, end)) = found {
+                return Some((line_idx, start, end));
+            }
+        }
+        None
+    }
+
+    /// `replacement` for one match, with groups filled in for a regular expression.
+    fn expand(&self, captures: &Captures, replacement: &str) -> String {
+        let mut text = String::new();
+        if self.is_regex {
+            captures.expand(replacement, &mut text);
+        } else {
+            text.push_str(replacement);
+        }
+        text
+    }
+
+    /// Replaces the first match at or after `from`. Returns the char range of
+    /// the inserted text and the ops applied.
+    pub fn replace_next(
+        &self,
+        buffer: &mut EditorBuffer,
+        undo_redo: &mut UndoTree,
+        from: usize,
+        replacement: &str,
+    ) -> Option<((usize, usize), Vec<EditOp>)> {
+        let (line_idx, start, _) = self.locate(buffer, from)?;
+        let line = buffer.line(line_idx).to_string();
+        let text = line.strip_suffix('\n').unwrap_or(&line);
+        let captures = self.regex.captures_at(text, start)?;
+        let matched = captures.get(0)?;
+        let char_idx = buffer.line_to_char(line_idx) + text[..start].chars().count();
+        let edits = replace_range(buffer, undo_redo, char_idx, matched.as_str(), self.expand(&captures, replacement));
+        let end = char_idx + edits.last().filter(|op| matches!(op, EditOp::Insert { .. })).map_or(0, EditOp::char_len);
+        Some(((char_idx, end), edits))
+    }
+
+    /// Replaces every match in the buffer as a single undoable action and
+    /// returns how many there were and the ops applied.
+    pub fn replace_all(&self, buffer: &mut EditorBuffer, undo_redo: &mut UndoTree, replacement: &str) -> (usize, Vec<EditOp>) {
+        self.replace_in_lines(buffer, undo_redo, 0, buffer.len_lines() - 1, replacement, true)
+    }
+
+    /// Replaces the matches on lines `first..=last`, every one of a line
+    /// when `global` and otherwise its first, as a single undoable action.
+    /// Returns how many there were and the ops applied.
+    pub fn replace_in_lines(
+        &self,
+        buffer: &mut EditorBuffer,
+        undo_redo: &mut UndoTree,
+        first: usize,
+        last: usize,
+        replacement: &str,
+        global: bool,
+    ) -> (usize, Vec<EditOp>) {
+        let mut edits = Vec::new();
+        let mut count = 0;
+        undo_redo.begin_group();
+        // Work bottom-up so earlier char indices stay valid
+        for line_idx in (first..=last.min(buffer.len_lines() - 1)).rev() {
+            let line = buffer.line(line_idx).to_string();
+            let text = line.strip_suffix('\n').unwrap_or(&line);
+            let line_start = buffer.line_to_char(line_idx);
+            let found: Vec<(usize, String, String)> = self
+                .regex
+                .captures_iter(text)
+                .filter_map(|captures| {
+                    let matched = captures.get(0)?;
+                    let keep = !matched.is_empty() && (!self.whole_word || is_whole_word_at(text, matched.start(), matched.len()));
+                    keep.then(|| (matched.start(), matched.as_str().to_string(), self.expand(&captures, replacement)))
+                })
+                .take(if global { usize::MAX } else { 1 })
+                .collect();
+            for (start, matched, new_text) in found.into_iter().rev() {
+                let char_idx = line_start + text[..start].chars().count();
+                edits.extend(replace_range(buffer, undo_redo, char_idx, &matched, new_text));
+                count += 1;
+            }
+        }
+        undo_redo.end_group();
+        (count, edits)
+    }
+}
+
+/// Progress of an interactive replace, which visits every match once,
+/// going round the buffer from where it started.
+pub struct ReplaceWalk {
+    /// Where the walk began and so where it ends after wrapping around.
+    stop: usize,
+    wrapped: bool,
+    /// Char range of the match waiting for an answer.
+    pub current: Option<(usize, usize)>,
+    pub count: usize,
+}
+
+impl ReplaceWalk {
+    pub fn new(from: usize) -> Self {
+        ReplaceWalk {
+            stop: from,
+            wrapped: false,
+            current: None,
+            count: 0,
+        }
+    }
+
+    /// Moves on to the next match at or after `from`; `current` is None once
+    /// every match has had its turn.
+    pub fn advance(&mut self, search: &Search, buffer: &EditorBuffer, from: usize) {
+        self.current = search.find_next(buffer, from).filter(|&(start, _)| {
+            self.wrapped |= start < from;
+            !self.wrapped || start < self.stop
+        });
+    }
+
+    /// Notes that the `old_len` chars at `at` became `new_len` chars, which
+    /// moves the stop when it comes later.
+    pub fn replaced(&mut self, at: usize, old_len: usize, new_len: usize) {
+        self.count += 1;
+        if at < self.stop {
+            self.stop = (self.stop + new_len).saturating_sub(old_len);
+        }
+    }
+}
+
+/// Puts `new_text` in place of `old_text` at `char_idx`, recording both halves.
+fn replace_range(buffer: &mut EditorBuffer, undo_redo: &mut UndoTree, char_idx: usize, old_text: &str, new_text: String) -> Vec<EditOp> {
+    let mut edits = Vec::new();
+    buffer.remove(char_idx, old_text.chars().count());
+    undo_redo.add_delete(buffer, char_idx, old_text.to_string());
+    edits.push(EditOp::Delete { char_idx, content: old_text.to_string() });
+    if !new_text.is_empty() {
+        buffer.insert(char_idx, &new_text);
+        undo_redo.add_insert(buffer, char_idx, new_text.clone());
+        edits.push(EditOp::Insert { char_idx, content: new_text });
+    }
+    edits
+}
+
+/// Every match of the active search as char ranges in buffer order, found
+/// once and shared by the highlighting and the match counter. Edits only
+/// send the lines they touched through the search again.
+pub struct MatchList {
+    ranges: Vec<(usize, usize)>,
+    /// Pattern and whole-word flag of the search the list was built for.
+    key: Option<(String, bool)>,
+    /// The whole buffer must be searched again.
+    stale: bool,
+    /// Char ranges changed by edits since the last update.
+    changed: Vec<(usize, usize)>,
+}
+
+impl MatchList {
+    pub fn new() -> Self {
+        MatchList {
+            ranges: Vec::new(),
+            key: None,
+            stale: false,
+            changed: Vec::new(),
+        }
+    }
+
+    /// Drops all the matches, for a buffer whose text was swapped out.
+    pub fn invalidate(&mut self) {
+        self.stale = true;
+    }
+
+    /// Moves the matches after an edit along with the text and notes the
+    /// edited span, whose lines are searched again by `update`.
+    pub fn apply_edit(&mut self, op: &EditOp) {
+        let (at, inserted, removed) = match op {
+            EditOp::Insert { char_idx, .. } => (*char_idx, op.char_len(), 0),
+            EditOp::Delete { char_idx, .. } => (*char_idx, 0, op.char_len()),
+        };
+        let end = at + removed;
+        let shift = |idx: usize| {
+            if idx >= end {
+                idx + inserted - removed
+            } else {
+                idx.min(at)
+            }
+        };
+        self.ranges.retain(|&(start, match_end)| match_end <= at || start >= end);
+        for range in self.ranges.iter_mut().chain(self.changed.iter_mut()) {
+            *range = (shift(range.0), shift(range.1));
+        }
+        self.changed.push((at, at + inserted));
+    }
+
+    /// Brings the list up to date with the buffer and the search.
+    pub fn update(&mut self, search: Option<&Search>, buffer: &EditorBuffer) {
+        let key = search.map(|search| (search.regex.as_str().to_string(), search.whole_word));
+        let Some(search) = search.filter(|_| !self.stale && key == self.key) else {
+            self.stale = false;
+            self.key = key;
+            self.changed.clear();
+            self.ranges.clear();
+            if let Some(search) = search {
+                self.ranges = find_in_lines(search, buffer, 0..buffer.len_lines());
+            }
+            return;
+        };
+
+        // Lines covered by the edits, merged where they meet
+        let len = buffer.len_chars();
+        let mut lines: Vec<(usize, usize)> = self
+            .changed
+            .drain(..)
+            .map(|(start, end)| (buffer.char_to_line(start.min(len)), buffer.char_to_line(end.min(len)) + 1))
+            .collect();
+        lines.sort_unstable();
+        let mut merged: Vec<(usize, usize)> = Vec::new();
+        for (first, last) in lines {
+            match merged.last_mut() {
+                Some(prev) if first <= prev.1 => prev.1 = prev.1.max(last),
+                _ => merged.push((first, last)),
+            }
+        }
+        for (first, last) in merged {
+            let last = last.min(buffer.len_lines());
+            let span_start = buffer.line_to_char(first);
+            let span_end = if last < buffer.len_lines() { buffer.line_to_char(last) } else { len + 1 };
+            let from = self.ranges.partition_point(|&(start, _)| start < span_start);
+            let to = self.ranges.partition_point(|&(start, _)| start < span_end);
+            self.ranges.splice(from..to, find_in_lines(search, buffer, first..last));
+        }
+    }
+
+    /// The matches that overlap the chars `start..end`.
+    pub fn within(&self, start: usize, end: usize) -> &[(usize, usize)] {
+        let first = self.ranges.partition_point(|&(_, match_end)| match_end <= start);
+        let last = self.ranges.partition_point(|&(match_start, _)| match_start < end);
+        &self.ranges[first..last.max(first)]
+    }
+
+    /// Number of the last match starting at or before `cursor` (0 when there
+    /// is none) and how many there are; None without a search.
+    pub fn position(&self, cursor: usize) -> Option<(usize, usize)> {
+        self.key.as_ref()?;
+        Some((self.ranges.partition_point(|&(start, _)| start <= cursor), self.ranges.len()))
+    }
+}
+
+impl Default for MatchList {
+    fn default() -> Self {
+        MatchList::new()
+    }
+}
+
+/// Char ranges of the matches on `lines`.
+fn find_in_lines(search: &Search, buffer: &EditorBuffer, lines: std::ops::Range<usize>) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    for line_idx in lines {
+        let line: Cow<str> = buffer.line(line_idx).into();
+        let text = line.strip_suffix('\n').unwrap_or(&line);
+        let line_byte = buffer.rope.line_to_byte(line_idx);
+        for (start, end) in search.matches_in(text) {
+            ranges.push((buffer.rope.byte_to_char(line_byte + start), buffer.rope.byte_to_char(line_byte + end)));
+        }
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::UndoStrategy;
+    use ropey::Rope;
+    use std::collections::HashSet;
+
+    fn buffer(text: &str) -> EditorBuffer {
+        let mut buffer = EditorBuffer::new();
+        buffer.rope = Rope::from_str(text);
+        buffer
+    }
+
+    #[test]
+    fn literal_terms_match_as_written_and_regex_terms_as_patterns() {
+        let text = "a.c abc a.c";
+        let literal = Search::new("a.c", false, false).unwrap();
+        assert_eq!(literal.matches_in(text).collect::<Vec<_>>(), [(0, 3), (8, 11)]);
+        let regex = Search::new("a.c", true, false).unwrap();
+        assert_eq!(regex.matches_in(text).collect::<Vec<_>>(), [(0, 3), (4, 7), (8, 11)]);
+        // Empty matches have nothing to show, and a whole word stands alone
+        assert_eq!(Search::new("x*", true, false).unwrap().matches_in(text).count(), 0);
+        assert_eq!(Search::new("ab", false, true).unwrap().matches_in("ab abc").collect::<Vec<_>>(), [(0, 2)]);
+        assert!(Search::new("(", true, false).is_err());
+    }
+
+    #[test]
+    fn matches_are_found_as_char_ranges_and_the_search_wraps_around() {
+        let buffer = buffer("été x\nnaïve x\n");
+        let search = Search::new("x", false, false).unwrap();
+        assert_eq!(search.find_next(&buffer, 0), Some((4, 5)));
+        assert_eq!(search.find_next(&buffer, 5), Some((12, 13)));
+        assert_eq!(search.find_next(&buffer, 13), Some((4, 5)));
+        // A pattern anchored to the end of a line matches before its break
+        let search = Search::new("x$", true, false).unwrap();
+        assert_eq!(search.find_next(&buffer, 6), Some((12, 13)));
+        assert_eq!(Search::new("y", false, false).unwrap().find_next(&buffer, 0), None);
+    }
+
+    #[test]
+    fn the_match_list_follows_edits_as_a_fresh_search_would() {
+        let mut buffer = buffer("one\ntwo one\nthree\n");
+        let search = Search::new("one", false, false).unwrap();
+        let mut matches = MatchList::new();
+        matches.update(Some(&search), &buffer);
+        assert_eq!(matches.within(0, buffer.len_chars()), [(0, 3), (8, 11)]);
+        assert_eq!(matches.position(8), Some((2, 2)));
+
+        buffer.insert(12, "one ");
+        matches.apply_edit(&EditOp::Insert { char_idx: 12, content: "one ".to_string() });
+        buffer.remove(0, 1);
+        matches.apply_edit(&EditOp::Delete { char_idx: 0, content: "o".to_string() });
+        matches.update(Some(&search), &buffer);
+        assert_eq!(matches.within(0, buffer.len_chars()), find_in_lines(&search, &buffer, 0..buffer.len_lines()));
+        assert_eq!(matches.within(0, buffer.len_chars()), [(7, 10), (11, 14)]);
+        matches.update(None, &buffer);
+        assert_eq!(matches.position(0), None);
+    }
+
+    #[test]
+    fn replacing_next_takes_the_match_after_the_cursor_and_fills_in_groups() {
+        let mut buffer = buffer("key = 1\nkey = 2\n");
+        let mut undo_redo = UndoTree::new(UndoStrategy::OpReplay);
+        let search = Search::new(r"(\w+) = (\d)", true, false).unwrap();
+        let (span, _) = search.replace_next(&mut buffer, &mut undo_redo, 1, "$2 = $1").unwrap();
+        assert_eq!((buffer.rope.to_string().as_str(), span), ("key = 1\n2 = key\n", (8, 15)));
+        // A literal replacement keeps its `$`
+        let search = Search::new("key", false, false).unwrap();
+        search.replace_next(&mut buffer, &mut undo_redo, 0, "$1");
+        assert_eq!(buffer.rope.to_string(), "$1 = 1\n2 = key\n");
+    }
+
+    #[test]
+    fn replacing_all_counts_the_matches_and_undoes_in_one_step() {
+        let mut buffer = buffer("a-a\nb\na\n");
+        let mut undo_redo = UndoTree::new(UndoStrategy::OpReplay);
+        let search = Search::new("a", false, false).unwrap();
+        let (count, edits) = search.replace_all(&mut buffer, &mut undo_redo, "ä");
+        assert_eq!((buffer.rope.to_string().as_str(), count, edits.len()), ("ä-ä\nb\nä\n", 3, 6));
+        undo_redo.undo(&mut buffer, &mut 0, &mut HashSet::new());
+        assert_eq!(buffer.rope.to_string(), "a-a\nb\na\n");
+        assert_eq!(search.replace_all(&mut buffer, &mut undo_redo, "").0, 3);
+        assert_eq!(buffer.rope.to_string(), "-\nb\n\n");
+    }
+}
@@ -0,0 +1,90 @@
+// src/search_history.rs
+
+use crate::config::config_dir;
+use std::fs;
+use std::path::PathBuf;
+
+/// Terms kept; older ones are forgotten.
+const MAX_ENTRIES: usize = 100;
+
+/// Terms confirmed in the find and replace prompts, oldest first, kept one
+/// per line in the config directory so they outlive the session.
+pub struct SearchHistory {
+    path: Option<PathBuf>,
+    entries: Vec<String>,
+    /// Entry shown in the prompt while cycling; None while typing.
+    position: Option<usize>,
+    /// What was typed before cycling started, given back past the newest entry.
+    draft: String,
+}
+
+impl SearchHistory {
+    pub fn new() -> Self {
+        let path = config_dir().map(|dir| dir.join("search_history"));
+        let entries = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|text| text.lines().filter(|line| !line.is_empty()).map(str::to_string).collect())
+            .unwrap_or_default();
+        SearchHistory {
+            path,
+            entries,
+            position: None,
+            draft: String::new(),
+        }
+    }
+
+    /// Records a confirmed term as the newest entry and writes the file.
+    pub fn push(&mut self, term: &str) {
+        self.position = None;
+        if term.is_empty() {
+            return;
+        }
+        self.entries.retain(|entry| entry != term);
+        self.entries.push(term.to_string());
+        let excess = self.entries.len().saturating_sub(MAX_ENTRIES);
+        self.entries.drain(..excess);
+        if let Some(path) = self.path.as_ref() {
+            if let Some(dir) = path.parent() {
+                let _ = fs::create_dir_all(dir);
+            }
+            let _ = fs::write(path, self.entries.join("\n") + "\n");
+        }
+    }
+
+    /// Starts over from the text being typed, for a newly opened prompt.
+    pub fn reset(&mut self) {
+        self.position = None;
+    }
+
+    /// The entry before the one shown; `current` is the prompt's text.
+    pub fn older(&mut self, current: &str) -> Option<&str> {
+        let index = match self.position {
+            None => {
+                self.draft = current.to_string();
+                self.entries.len().checked_sub(1)?
+            }
+            Some(index) => index.saturating_sub(1),
+        };
+        self.position = Some(index);
+        Some(&self.entries[index])
+    }
+
+    /// The entry after the one shown, then the text typed before cycling.
+    pub fn newer(&mut self) -> Option<&str> {
+        let index = self.position?;
+        if index + 1 < self.entries.len() {
+            self.position = Some(index + 1);
+            Some(&self.entries[index + 1])
+        } else {
+            self.position = None;
+            Some(&self.draft)
+        }
+    }
+}
+
+impl Default for SearchHistory {
+    fn default() -> Self {
+        SearchHistory::new()
+    }
+}
@@ -0,0 +1,423 @@
+// src/session.rs
+
+use crossterm::cursor::Show;
+use crossterm::event::{DisableFocusChange, DisableMouseCapture};
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode, LeaveAlternateScreen};
+use crossterm::ExecutableCommand;
+use rusty::config::runtime_dir;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Ctrl+\ leaves a session running and gives the terminal back.
+const DETACH_KEY: u8 = 0x1c;
+
+/// How long a new session gets to come up before attaching gives up.
+const START_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Set in the environment of an editor running in a session, to its name.
+const SESSION_VAR: &str = "RUSTY_SESSION";
+
+/// How often an attached terminal is checked for a change of size.
+const RESIZE_POLL: Duration = Duration::from_millis(100);
+
+/// After the size it starts with, a terminal sends the server frames of a
+/// kind byte, a big-endian u16 length and that many bytes: keys typed, or
+/// a new size as columns and rows.
+const FRAME_KEYS: u8 = 0;
+const FRAME_RESIZE: u8 = 1;
+
+/// Socket a session's server takes terminals on.
+fn session_socket(name: &str) -> io::Result<PathBuf> {
+    Ok(runtime_dir()?.join(format!("{}.session", name)))
+}
+
+/// `args` without `option` and the value after it.
+fn without_option(args: &[String], option: &[&str]) -> Vec<String> {
+    let mut rest = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if option.contains(&arg.as_str()) {
+            args.next();
+        } else {
+            rest.push(arg.clone());
+        }
+    }
+    rest
+}
+
+/// Names of the sessions that are running.
+pub fn list() -> Vec<String> {
+    let Ok(entries) = runtime_dir().and_then(fs::read_dir) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .flatten()
+        .filter(|entry| UnixStream::connect(entry.path()).is_ok())
+        .filter_map(|entry| entry.file_name().to_str()?.strip_suffix(".session").map(str::to_string))
+        .collect();
+    names.sort();
+    names
+}
+
+/// Attaches the terminal to session `name`, starting it with the editor
+/// arguments in `args` when it is not running. `file` is opened in a
+/// session that already is. Returns once detached or the editor quits.
+pub fn attach(name: &str, args: &[String], file: Option<&str>) -> io::Result<()> {
+    let socket = session_socket(name)?;
+    let stream = match UnixStream::connect(&socket) {
+        Ok(stream) => {
+            if let Some(file) = file {
+                send(&runtime_dir()?.join(format!("{}.editor", name)), file)?;
+            }
+            stream
+        }
+        Err(_) => {
+            let _ = fs::remove_file(&socket);
+            start(name, args)?;
+            connect_when_up(&socket)?
+        }
+    };
+    relay(name, stream)
+}
+
+/// Starts the server of session `name` in the background, in a session of
+/// its own so closing the terminal does not take it down.
+fn start(name: &str, args: &[String]) -> io::Result<()> {
+    let mut server = Command::new(env::current_exe()?);
+    server
+        .arg("--serve")
+        .arg(name)
+        .args(without_option(args, &["-s", "--session"]))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    // SAFETY: setsid is async-signal-safe
+    unsafe {
+        server.pre_exec(|| {
+            libc::setsid();
+            Ok(())
+        });
+    }
+    server.spawn().map(|_| ())
+}
+
+fn connect_when_up(socket: &Path) -> io::Result<UnixStream> {
+    let started = Instant::now();
+    loop {
+        match UnixStream::connect(socket) {
+            Ok(stream) => return Ok(stream),
+            Err(err) if started.elapsed() >= START_TIMEOUT => return Err(err),
+            Err(_) => thread::sleep(Duration::from_millis(20)),
+        }
+    }
+}
+
+/// Sends one frame of `kind` to the server.
+fn send_frame(stream: &Mutex<UnixStream>, kind: u8, payload: &[u8]) -> io::Result<()> {
+    let len = u16::try_from(payload.len()).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "frame too long"))?;
+    let frame = [&[kind][..], &len.to_be_bytes(), payload].concat();
+    stream.lock().unwrap_or_else(|err| err.into_inner()).write_all(&frame)
+}
+
+fn size_bytes((cols, rows): (u16, u16)) -> Vec<u8> {
+    [cols.to_be_bytes(), rows.to_be_bytes()].concat()
+}
+
+/// Passes keys and changes of size to the session and its screen back,
+/// until the detach key or the end of the session.
+fn relay(name: &str, mut stream: UnixStream) -> io::Result<()> {
+    // The session takes the size of the terminal attaching
+    let mut size = crossterm::terminal::size()?;
+    stream.write_all(&size_bytes(size))?;
+    enable_raw_mode()?;
+    let input = Arc::new(Mutex::new(stream.try_clone()?));
+    let resize_input = Arc::clone(&input);
+    thread::spawn(move || {
+        loop {
+            thread::sleep(RESIZE_POLL);
+            match crossterm::terminal::size() {
+                Ok(new_size) if new_size != size => {
+                    size = new_size;
+                    if send_frame(&resize_input, FRAME_RESIZE, &size_bytes(size)).is_err() {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+    });
+    let detach_name = name.to_string();
+    thread::spawn(move || {
+        let mut stdin = io::stdin().lock();
+        let mut bytes = [0; 4096];
+        while let Ok(read @ 1..) = stdin.read(&mut bytes) {
+            let keys = &bytes[..read];
+            let detach = keys.iter().position(|&byte| byte == DETACH_KEY);
+            if send_frame(&input, FRAME_KEYS, &keys[..detach.unwrap_or(read)]).is_err() {
+                break;
+            }
+            if detach.is_some() {
+                let _ = restore_terminal();
+                println!("[detached from {}]", detach_name);
+                process::exit(0);
+            }
+        }
+    });
+    let mut out = io::stdout();
+    let mut bytes = [0; 4096];
+    while let Ok(read @ 1..) = stream.read(&mut bytes) {
+        out.write_all(&bytes[..read])?;
+        out.flush()?;
+    }
+    restore_terminal()
+}
+
+/// Leaves the modes the editor put the terminal in.
+fn restore_terminal() -> io::Result<()> {
+    let mut out = io::stdout();
+    out.execute(DisableFocusChange)?;
+    out.execute(DisableMouseCapture)?;
+    out.execute(LeaveAlternateScreen)?;
+    out.execute(Show)?;
+    disable_raw_mode()
+}
+
+/// Runs session `name`: the editor on a pseudo-terminal of its own, with
+/// one terminal at a time attached to it over a socket. Ends with the
+/// editor.
+pub fn serve(name: &str) -> io::Result<()> {
+    let socket = session_socket(name)?;
+    let listener = UnixListener::bind(&socket)?;
+    let result = serve_on(name, &listener);
+    let _ = fs::remove_file(&socket);
+    result
+}
+
+fn serve_on(name: &str, listener: &UnixListener) -> io::Result<()> {
+    let (first, size) = accept(listener)?;
+    let (master, slave) = open_pty(size)?;
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut editor = Command::new(env::current_exe()?);
+    editor
+        .args(without_option(&args, &["--serve"]))
+        .env(SESSION_VAR, name)
+        .stdin(Stdio::from(slave.try_clone()?))
+        .stdout(Stdio::from(slave.try_clone()?))
+        .stderr(Stdio::from(slave));
+    // SAFETY: setsid and ioctl are async-signal-safe
+    unsafe {
+        editor.pre_exec(|| {
+            // The pseudo-terminal becomes the editor's controlling terminal
+            if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY, 0) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let mut child = editor.spawn()?;
+    // Only the editor may hold its end of the terminal, so reading the
+    // screen ends when it quits
+    drop(editor);
+    let pid = child.id() as libc::pid_t;
+
+    let client: Arc<Mutex<Option<UnixStream>>> = Arc::new(Mutex::new(None));
+    let master = File::from(master);
+    attach_client(first, size, &client, &master, pid)?;
+
+    // The screen goes to whichever terminal is attached, or nowhere
+    let mut output = master.try_clone()?;
+    let output_client = Arc::clone(&client);
+    let output_thread = thread::spawn(move || {
+        let mut bytes = [0; 4096];
+        while let Ok(read @ 1..) = output.read(&mut bytes) {
+            let mut client = output_client.lock().unwrap_or_else(|err| err.into_inner());
+            if client.as_mut().is_some_and(|stream| stream.write_all(&bytes[..read]).is_err()) {
+                *client = None;
+            }
+        }
+    });
+
+    let listener = listener.try_clone()?;
+    let accept_client = Arc::clone(&client);
+    thread::spawn(move || {
+        while let Ok((stream, size)) = accept(&listener) {
+            let _ = attach_client(stream, size, &accept_client, &master, pid);
+        }
+    });
+
+    child.wait()?;
+    let _ = output_thread.join();
+    if let Some(stream) = client.lock().unwrap_or_else(|err| err.into_inner()).take() {
+        let _ = stream.shutdown(std::net::Shutdown::Both);
+    }
+    Ok(())
+}
+
+/// The next terminal to attach and its size. Connections that send no
+/// size, like `--sessions` checking what runs, are passed over.
+fn accept(listener: &UnixListener) -> io::Result<(UnixStream, libc::winsize)> {
+    loop {
+        let (mut stream, _) = listener.accept()?;
+        let mut size = [0; 4];
+        if stream.read_exact(&mut size).is_ok() {
+            return Ok((stream, winsize(size)));
+        }
+    }
+}
+
+/// Makes `stream`, a terminal of `size`, the attached one, sending the one
+/// before it away, and passes its keys and changes of size on to the
+/// editor, whose process is `pid`.
+fn attach_client(stream: UnixStream, size: libc::winsize, client: &Mutex<Option<UnixStream>>, master: &File, pid: libc::pid_t) -> io::Result<()> {
+    let mut frames = stream.try_clone()?;
+    let mut editor = master.try_clone()?;
+    set_size(master, size)?;
+    let previous = client.lock().unwrap_or_else(|err| err.into_inner()).replace(stream);
+    if let Some(previous) = previous {
+        let _ = previous.shutdown(std::net::Shutdown::Both);
+    }
+    thread::spawn(move || -> io::Result<()> {
+        loop {
+            let mut header = [0; 3];
+            frames.read_exact(&mut header)?;
+            let mut payload = vec![0; u16::from_be_bytes([header[1], header[2]]) as usize];
+            frames.read_exact(&mut payload)?;
+            match (header[0], <[u8; 4]>::try_from(&payload[..])) {
+                (FRAME_KEYS, _) => editor.write_all(&payload)?,
+                // The kernel tells the editor when the size changes
+                (FRAME_RESIZE, Ok(size)) => set_size(&editor, winsize(size))?,
+                _ => {}
+            }
+        }
+    });
+    // A terminal attaching has an empty screen, even at the same size
+    // SAFETY: signals the editor this server started
+    unsafe {
+        libc::kill(pid, libc::SIGWINCH);
+    }
+    Ok(())
+}
+
+/// A terminal size sent as big-endian columns and rows.
+fn winsize(bytes: [u8; 4]) -> libc::winsize {
+    libc::winsize {
+        ws_col: u16::from_be_bytes([bytes[0], bytes[1]]),
+        ws_row: u16::from_be_bytes([bytes[2], bytes[3]]),
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    }
+}
+
+/// Gives the pseudo-terminal behind `master` the size `size`.
+fn set_size(master: &File, size: libc::winsize) -> io::Result<()> {
+    // SAFETY: the descriptor is open and the pointer valid for the call
+    if unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &size) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// A pseudo-terminal of `size`: the end the server holds, and the one
+/// the editor runs on.
+fn open_pty(size: libc::winsize) -> io::Result<(OwnedFd, OwnedFd)> {
+    let (mut master, mut slave) = (0, 0);
+    // SAFETY: the pointers are valid for the call, and the descriptors it
+    // returns are owned by nothing else
+    unsafe {
+        if libc::openpty(&mut master, &mut slave, std::ptr::null_mut(), std::ptr::null(), &size) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave)))
+    }
+}
+
+/// Asks the editor listening on `socket` to open `path`.
+fn send(socket: &Path, path: &str) -> io::Result<()> {
+    let mut stream = UnixStream::connect(socket)?;
+    stream.write_all(format!("{}\n", absolute(path)).as_bytes())
+}
+
+/// `path` as an editor in another directory finds it.
+fn absolute(path: &str) -> String {
+    if rusty::remote::is_remote(path) {
+        return path.to_string();
+    }
+    std::path::absolute(path).map_or_else(|_| path.to_string(), |path| path.to_string_lossy().into_owned())
+}
+
+/// Opens `path` in the editor started last of those running. False when
+/// none is.
+pub fn open_in_running(path: &str) -> bool {
+    let Ok(entries) = runtime_dir().and_then(fs::read_dir) else {
+        return false;
+    };
+    let mut sockets: Vec<(std::time::SystemTime, PathBuf)> = entries
+        .flatten()
+        .filter(|entry| entry.path().extension().is_some_and(|extension| extension == "editor"))
+        .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+        .collect();
+    sockets.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+    for (_, socket) in sockets {
+        match send(&socket, path) {
+            Ok(()) => return true,
+            // Left behind by an editor that did not get to clean up
+            Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => {
+                let _ = fs::remove_file(&socket);
+            }
+            Err(_) => {}
+        }
+    }
+    false
+}
+
+/// Files other rusty commands ask the running editor to open.
+pub struct Inbox {
+    socket: PathBuf,
+    rx: Receiver<String>,
+}
+
+impl Inbox {
+    /// Listens on a socket named after the session, or after the process
+    /// outside one. None when there is nowhere to put it.
+    pub fn listen() -> Option<Self> {
+        let name = env::var(SESSION_VAR).unwrap_or_else(|_| process::id().to_string());
+        let socket = runtime_dir().ok()?.join(format!("{}.editor", name));
+        let _ = fs::remove_file(&socket);
+        let listener = UnixListener::bind(&socket).ok()?;
+        let (tx, rx) = channel();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let mut line = String::new();
+                if BufReader::new(stream).read_line(&mut line).is_ok() {
+                    let path = line.trim_end_matches('\n');
+                    if !path.is_empty() && tx.send(path.to_string()).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        Some(Inbox { socket, rx })
+    }
+
+    /// The next file asked for, if any.
+    pub fn next(&self) -> Option<String> {
+        self.rx.try_recv().ok()
+    }
+}
+
+impl Drop for Inbox {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.socket);
+    }
+}
@@ -0,0 +1,58 @@
+// src/signs.rs
+
+use crossterm::style::Color;
+use std::collections::HashMap;
+
+/// A glyph shown in the sign column.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sign {
+    pub glyph: char,
+    pub color: Color,
+    /// When several signs land on one line, the highest priority is shown.
+    pub priority: u8,
+}
+
+/// Shared priorities so features rank consistently against each other.
+pub const PRIORITY_GIT: u8 = 5;
+pub const PRIORITY_FOLD: u8 = 10;
+pub const PRIORITY_WARNING: u8 = 30;
+pub const PRIORITY_ERROR: u8 = 40;
+
+/// Signs per buffer line, grouped by the feature that placed them so each
+/// feature can replace its own signs without touching the others.
+pub struct SignColumn {
+    signs: HashMap<usize, Vec<(&'static str, Sign)>>,
+}
+
+impl SignColumn {
+    pub fn new() -> Self {
+        SignColumn { signs: HashMap::new() }
+    }
+
+    pub fn place(&mut self, line: usize, source: &'static str, sign: Sign) {
+        self.signs.entry(line).or_default().push((source, sign));
+    }
+
+    /// Removes every sign placed by `source`.
+    pub fn clear_source(&mut self, source: &'static str) {
+        self.signs.retain(|_, placed| {
+            placed.retain(|(s, _)| *s != source);
+            !placed.is_empty()
+        });
+    }
+
+    /// The sign to draw on `line`. Ties go to the sign placed first.
+    pub fn top(&self, line: usize) -> Option<Sign> {
+        self.signs
+            .get(&line)?
+            .iter()
+            .map(|(_, sign)| *sign)
+            .reduce(|best, sign| if sign.priority > best.priority { sign } else { best })
+    }
+}
+
+impl Default for SignColumn {
+    fn default() -> Self {
+        SignColumn::new()
+    }
+}
@@ -0,0 +1,198 @@
+// src/snippets.rs
+
+use crate::buffer::EditOp;
+use crate::config::SnippetsConfig;
+use std::path::Path;
+
+/// The body of the snippet triggered by `trigger` in the file at `path`,
+/// those of its extension taking precedence over the global ones.
+pub fn find<'a>(config: &'a SnippetsConfig, path: Option<&str>, trigger: &str) -> Option<&'a str> {
+    let extension = path.and_then(|path| Path::new(path).extension()?.to_str());
+    extension
+        .and_then(|extension| config.languages.get(extension)?.get(trigger))
+        .or_else(|| config.global.get(trigger))
+        .map(String::as_str)
+}
+
+/// A snippet body with its tab stops worked out.
+pub struct Snippet {
+    pub text: String,
+    /// Char ranges into `text` in the order Tab visits them, `$0` last.
+    stops: Vec<(usize, usize)>,
+}
+
+impl Snippet {
+    /// Parses `body`: `$1` is a tab stop, `${1:text}` one holding placeholder
+    /// text, and `$0` where the cursor ends up (the end when left out).
+    /// A number used again only repeats its placeholder. `\$` is a plain `$`.
+    /// Lines after the first get `indent` in front.
+    pub fn parse(body: &str, indent: &str) -> Self {
+        let mut text = String::new();
+        let mut len = 0;
+        let mut numbered: Vec<(usize, (usize, usize))> = Vec::new();
+        let mut chars = body.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if matches!(chars.peek(), Some('$' | '\\' | '}')) => {
+                    text.extend(chars.next());
+                    len += 1;
+                }
+                '$' if chars.peek().is_some_and(|c| c.is_ascii_digit() || *c == '{') => {
+                    let braced = chars.next_if_eq(&'{').is_some();
+                    let mut digits = String::new();
+                    while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                        digits.push(digit);
+                    }
+                    let mut placeholder = String::new();
+                    if braced {
+                        if chars.next_if_eq(&':').is_some() {
+                            while let Some(c) = chars.next_if(|c| *c != '}') {
+                                placeholder.push(if c == '\\' { chars.next().unwrap_or(c) } else { c });
+                            }
+                        }
+                        chars.next_if_eq(&'}');
+                    }
+                    let Ok(number) = digits.parse::<usize>() else {
+                        text.push('$');
+                        len += 1;
+                        continue;
+                    };
+                    let start = len;
+                    text.push_str(&placeholder);
+                    len += placeholder.chars().count();
+                    if !numbered.iter().any(|(n, _)| *n == number) {
+                        numbered.push((number, (start, len)));
+                    }
+                }
+                '\n' => {
+                    text.push('\n');
+                    text.push_str(indent);
+                    len += 1 + indent.chars().count();
+                }
+                c => {
+                    text.push(c);
+                    len += 1;
+                }
+            }
+        }
+        let has_end = numbered.iter().any(|(number, _)| *number == 0);
+        // $0 sorts after every other number
+        numbered.sort_by_key(|(number, _)| number.wrapping_sub(1));
+        let mut stops: Vec<(usize, usize)> = numbered.into_iter().map(|(_, range)| range).collect();
+        if !has_end {
+            stops.push((len, len));
+        }
+        Snippet { text, stops }
+    }
+}
+
+/// A snippet being filled in: Tab and Shift+Tab move between its stops,
+/// which follow the edits made to the text around and inside them.
+pub struct ActiveSnippet {
+    /// Char ranges in the buffer, in the order they are visited.
+    stops: Vec<(usize, usize)>,
+    current: usize,
+}
+
+impl ActiveSnippet {
+    /// The stops of `snippet` inserted at char index `at`, on the first one.
+    pub fn new(snippet: &Snippet, at: usize) -> Self {
+        ActiveSnippet {
+            stops: snippet.stops.iter().map(|&(start, end)| (at + start, at + end)).collect(),
+            current: 0,
+        }
+    }
+
+    pub fn current_stop(&self) -> (usize, usize) {
+        self.stops[self.current]
+    }
+
+    /// Whether the current stop is the last, where the snippet is done.
+    pub fn is_done(&self) -> bool {
+        self.current + 1 == self.stops.len()
+    }
+
+    pub fn next(&mut self) {
+        self.current = (self.current + 1).min(self.stops.len() - 1);
+    }
+
+    pub fn prev(&mut self) {
+        self.current = self.current.saturating_sub(1);
+    }
+
+    /// Whether `idx` is in the current stop, ends included.
+    pub fn contains(&self, idx: usize) -> bool {
+        let (start, end) = self.current_stop();
+        (start..=end).contains(&idx)
+    }
+
+    /// Moves the stops along with `op`. Text typed at either end of the
+    /// current stop becomes part of it; other stops only grow from inside.
+    pub fn apply_edit(&mut self, op: &EditOp) {
+        let at = match op {
+            EditOp::Insert { char_idx, .. } | EditOp::Delete { char_idx, .. } => *char_idx,
+        };
+        let len = op.char_len();
+        for (i, (start, end)) in self.stops.iter_mut().enumerate() {
+            let current = i == self.current;
+            match op {
+                EditOp::Insert { .. } => {
+                    if at < *start || at == *start && !current {
+                        *start += len;
+                        *end += len;
+                    } else if at < *end || at == *end && current {
+                        *end += len;
+                    }
+                }
+                EditOp::Delete { .. } => {
+                    let shift = |idx: usize| if idx >= at + len { idx - len } else { idx.min(at) };
+                    (*start, *end) = (shift(*start), shift(*end));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn stops_are_visited_by_number_with_the_end_last() {
+        let snippet = Snippet::parse("fn ${1:name}($2) {\n\t$0\n}", "    ");
+        assert_eq!(snippet.text, "fn name() {\n    \t\n    }");
+        assert_eq!(snippet.stops, [(3, 7), (8, 8), (17, 17)]);
+        // Without `$0` the end is last; a repeated number and `\$` add no stop
+        let snippet = Snippet::parse("\\$${1:a} $1 $2", "");
+        assert_eq!((snippet.text.as_str(), snippet.stops), ("$a  ", vec![(1, 2), (4, 4), (4, 4)]));
+    }
+
+    #[test]
+    fn a_snippet_of_the_file_extension_comes_before_a_global_one() {
+        let config = SnippetsConfig {
+            global: HashMap::from([("fn".to_string(), "global".to_string())]),
+            languages: HashMap::from([("rs".to_string(), HashMap::from([("fn".to_string(), "rust".to_string())]))]),
+        };
+        assert_eq!(find(&config, Some("main.rs"), "fn"), Some("rust"));
+        assert_eq!(find(&config, Some("main.py"), "fn"), Some("global"));
+        assert_eq!(find(&config, None, "if"), None);
+    }
+
+    #[test]
+    fn stops_follow_the_edits_around_and_inside_them() {
+        let mut active = ActiveSnippet::new(&Snippet::parse("f(${1:x}, $2)", ""), 10);
+        assert_eq!(active.current_stop(), (12, 13));
+        // Typing at the end of the current stop grows it and moves the next one on
+        active.apply_edit(&EditOp::Insert { char_idx: 13, content: "yz".to_string() });
+        assert_eq!(active.current_stop(), (12, 15));
+        active.apply_edit(&EditOp::Delete { char_idx: 0, content: "0123".to_string() });
+        active.next();
+        assert_eq!((active.current_stop(), active.is_done()), ((13, 13), false));
+        active.next();
+        assert!(active.is_done() && active.contains(14));
+        active.prev();
+        active.prev();
+        assert_eq!(active.current_stop(), (8, 11));
+    }
+}
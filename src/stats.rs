@@ -0,0 +1,140 @@
+// src/stats.rs
+
+use crate::buffer::EditOp;
+use crate::config::data_dir;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Gaps between keystrokes longer than this count as idle, not editing.
+const IDLE_AFTER: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Totals {
+    pub sessions: u64,
+    pub editing_secs: u64,
+    pub keystrokes: u64,
+    pub chars_inserted: u64,
+    pub chars_deleted: u64,
+    pub files_saved: u64,
+}
+
+impl Totals {
+    fn add(&self, other: &Totals) -> Totals {
+        Totals {
+            sessions: self.sessions + other.sessions,
+            editing_secs: self.editing_secs + other.editing_secs,
+            keystrokes: self.keystrokes + other.keystrokes,
+            chars_inserted: self.chars_inserted + other.chars_inserted,
+            chars_deleted: self.chars_deleted + other.chars_deleted,
+            files_saved: self.files_saved + other.files_saved,
+        }
+    }
+}
+
+/// "1h 05m", "12m" or "40s".
+pub fn format_duration(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m", secs / 60),
+        _ => format!("{}h {:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// Counters for this session, added to the project's stored totals on exit.
+pub struct SessionStats {
+    path: Option<PathBuf>,
+    project: Totals,
+    pub session: Totals,
+    last_key: Option<Instant>,
+    editing: Duration,
+}
+
+/// Stats file for the project in `dir`, named after its absolute path.
+fn stats_path(dir: &Path) -> Option<PathBuf> {
+    let absolute = dir.canonicalize().ok()?;
+    let name = absolute.to_string_lossy().replace(['/', '\\', ':'], "%");
+    Some(data_dir()?.join("stats").join(format!("{}.json", name)))
+}
+
+impl SessionStats {
+    pub fn new() -> Self {
+        let path = stats_path(Path::new("."));
+        let project = path
+            .as_ref()
+            .and_then(|p| fs::read_to_string(p).ok())
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default();
+        SessionStats {
+            path,
+            project,
+            session: Totals { sessions: 1, ..Totals::default() },
+            last_key: None,
+            editing: Duration::ZERO,
+        }
+    }
+
+    /// Counts a keystroke; the time since the previous one counts as editing
+    /// unless it was an idle break.
+    pub fn record_key(&mut self) {
+        let now = Instant::now();
+        if let Some(last) = self.last_key
+            && now - last < IDLE_AFTER
+        {
+            self.editing += now - last;
+        }
+        self.last_key = Some(now);
+        self.session.keystrokes += 1;
+        self.session.editing_secs = self.editing.as_secs();
+    }
+
+    pub fn record_edits(&mut self, edits: &[EditOp]) {
+        for op in edits {
+            match op {
+                EditOp::Insert { .. } => self.session.chars_inserted += op.char_len() as u64,
+                EditOp::Delete { .. } => self.session.chars_deleted += op.char_len() as u64,
+            }
+        }
+    }
+
+    pub fn record_save(&mut self) {
+        self.session.files_saved += 1;
+    }
+
+    /// One-line summary for the stats command.
+    pub fn summary(&self) -> String {
+        let total = self.project.add(&self.session);
+        format!(
+            "Session: {} editing, {} keys, +{}/-{} chars, {} saves | Project: {} over {} sessions, {} saves",
+            format_duration(self.session.editing_secs),
+            self.session.keystrokes,
+            self.session.chars_inserted,
+            self.session.chars_deleted,
+            self.session.files_saved,
+            format_duration(total.editing_secs),
+            total.sessions,
+            total.files_saved,
+        )
+    }
+
+    /// Adds this session to the project totals on disk.
+    pub fn persist(&self) {
+        let Some(path) = self.path.as_ref() else {
+            return;
+        };
+        if let Some(dir) = path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        if let Ok(json) = serde_json::to_string_pretty(&self.project.add(&self.session)) {
+            let _ = fs::write(path, json);
+        }
+    }
+}
+
+impl Default for SessionStats {
+    fn default() -> Self {
+        SessionStats::new()
+    }
+}
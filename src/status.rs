@@ -0,0 +1,317 @@
+// src/status.rs
+
+use crossterm::style::ContentStyle;
+use crate::backend::Backend;
+use crate::stats::format_duration;
+use serde::Deserialize;
+use std::fs::read_to_string;
+use std::io::Error;
+use std::path::Path;
+
+/// A piece of information that can be shown in the status line.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Segment {
+    PendingKeys,
+    Mode,
+    File,
+    GitBranch,
+    Diagnostics,
+    Encoding,
+    LineEnding,
+    Position,
+    TotalLines,
+    Percent,
+    /// Which search match the cursor is at, out of how many.
+    Matches,
+    Clock,
+    SessionTime,
+}
+
+pub fn default_segments() -> Vec<Segment> {
+    vec![
+        Segment::PendingKeys,
+        Segment::Mode,
+        Segment::Matches,
+        Segment::File,
+        Segment::GitBranch,
+        Segment::Diagnostics,
+        Segment::Encoding,
+        Segment::LineEnding,
+        Segment::Position,
+        Segment::TotalLines,
+        Segment::Percent,
+    ]
+}
+
+/// Everything the segments draw from, gathered once per frame.
+pub struct StatusInfo<'a> {
+    pub message: &'a str,
+    pub pending_keys: &'a str,
+    pub mode: &'a str,
+    pub file: Option<&'a str>,
+    /// The buffer differs from the file on disk.
+    pub modified: bool,
+    pub git_branch: Option<&'a str>,
+    pub errors: usize,
+    pub warnings: usize,
+    pub encoding: &'a str,
+    pub line_ending: &'a str,
+    pub line: usize,
+    pub col: usize,
+    pub total_lines: usize,
+    /// Match at or before the cursor (0 for none) and the total, while searching.
+    pub matches: Option<(usize, usize)>,
+    pub editing_secs: u64,
+}
+
+impl Segment {
+    /// Text for this segment, empty when there is nothing to show.
+    fn text(self, info: &StatusInfo) -> String {
+        match self {
+            Segment::PendingKeys => info.pending_keys.to_string(),
+            Segment::Mode => info.mode.to_string(),
+            Segment::File => format!("{}{}", info.file.unwrap_or("[No Name]"), if info.modified { " [+]" } else { "" }),
+            Segment::GitBranch => info.git_branch.map(|b| format!("git:{}", b)).unwrap_or_default(),
+            Segment::Diagnostics if info.errors + info.warnings > 0 => format!("E:{} W:{}", info.errors, info.warnings),
+            Segment::Diagnostics => String::new(),
+            Segment::Encoding => info.encoding.to_string(),
+            Segment::LineEnding => info.line_ending.to_string(),
+            Segment::Position => format!("Ln {}, Col {}", info.line + 1, info.col + 1),
+            Segment::TotalLines => format!("{} lines", info.total_lines),
+            Segment::Percent => format!("{}%", ((info.line + 1) * 100 / info.total_lines.max(1)).min(100)),
+            Segment::Matches => match info.matches {
+                None => String::new(),
+                Some((_, 0)) => "no matches".to_string(),
+                Some((0, total)) => format!("{} matches", total),
+                Some((current, total)) => format!("match {} of {}", current, total),
+            },
+            Segment::Clock => chrono::Local::now().format("%H:%M").to_string(),
+            Segment::SessionTime => format!("editing {}", format_duration(info.editing_secs)),
+        }
+    }
+}
+
+/// A status line written as a template: text with `{field}` placeholders
+/// filled in every frame, e.g. "{file} {modified} | {line}:{col} | {percent}%".
+/// `{{` and `}}` stand for braces; unknown fields are shown as written.
+pub struct StatusFormat {
+    pieces: Vec<Piece>,
+}
+
+enum Piece {
+    Text(String),
+    Field(Field),
+}
+
+#[derive(Clone, Copy)]
+enum Field {
+    File,
+    /// "[+]" when the buffer differs from the file on disk.
+    Modified,
+    Mode,
+    PendingKeys,
+    Branch,
+    Errors,
+    Warnings,
+    Encoding,
+    LineEnding,
+    Line,
+    Col,
+    TotalLines,
+    Percent,
+    Matches,
+    Clock,
+    SessionTime,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Field> {
+        Some(match name {
+            "file" => Field::File,
+            "modified" => Field::Modified,
+            "mode" => Field::Mode,
+            "pending_keys" => Field::PendingKeys,
+            "branch" => Field::Branch,
+            "errors" => Field::Errors,
+            "warnings" => Field::Warnings,
+            "encoding" => Field::Encoding,
+            "line_ending" => Field::LineEnding,
+            "line" => Field::Line,
+            "col" => Field::Col,
+            "total_lines" => Field::TotalLines,
+            "percent" => Field::Percent,
+            "matches" => Field::Matches,
+            "clock" => Field::Clock,
+            "session_time" => Field::SessionTime,
+            _ => return None,
+        })
+    }
+
+    fn text(self, info: &StatusInfo) -> String {
+        match self {
+            Field::File => info.file.unwrap_or("[No Name]").to_string(),
+            Field::Modified => if info.modified { "[+]" } else { "" }.to_string(),
+            Field::Mode => Segment::Mode.text(info),
+            Field::PendingKeys => Segment::PendingKeys.text(info),
+            Field::Branch => info.git_branch.unwrap_or_default().to_string(),
+            Field::Errors => info.errors.to_string(),
+            Field::Warnings => info.warnings.to_string(),
+            Field::Encoding => Segment::Encoding.text(info),
+            Field::LineEnding => Segment::LineEnding.text(info),
+            Field::Line => (info.line + 1).to_string(),
+            Field::Col => (info.col + 1).to_string(),
+            Field::TotalLines => info.total_lines.to_string(),
+            Field::Percent => ((info.line + 1) * 100 / info.total_lines.max(1)).min(100).to_string(),
+            Field::Matches => Segment::Matches.text(info),
+            Field::Clock => Segment::Clock.text(info),
+            Field::SessionTime => format_duration(info.editing_secs),
+        }
+    }
+}
+
+impl StatusFormat {
+    pub fn parse(format: &str) -> Self {
+        let mut pieces = Vec::new();
+        let mut text = String::new();
+        let mut chars = format.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    let mut closed = false;
+                    for c in chars.by_ref() {
+                        if c == '}' {
+                            closed = true;
+                            break;
+                        }
+                        name.push(c);
+                    }
+                    match Field::parse(name.trim()) {
+                        Some(field) if closed => {
+                            pieces.push(Piece::Text(std::mem::take(&mut text)));
+                            pieces.push(Piece::Field(field));
+                        }
+                        _ => {
+                            text.push('{');
+                            text.push_str(&name);
+                            if closed {
+                                text.push('}');
+                            }
+                        }
+                    }
+                }
+                c => text.push(c),
+            }
+        }
+        pieces.push(Piece::Text(text));
+        StatusFormat { pieces }
+    }
+
+    fn render(&self, info: &StatusInfo) -> String {
+        self.pieces
+            .iter()
+            .map(|piece| match piece {
+                Piece::Text(text) => text.clone(),
+                Piece::Field(field) => field.text(info),
+            })
+            .collect()
+    }
+}
+
+/// Finds the checked-out branch by reading `.git/HEAD` in `start` or one of
+/// its ancestors. A detached HEAD shows as a short commit hash.
+pub fn git_branch(start: &Path) -> Option<String> {
+    let start = start.canonicalize().ok()?;
+    let head = start.ancestors().find_map(|dir| read_to_string(dir.join(".git").join("HEAD")).ok())?;
+    let head = head.trim();
+    match head.strip_prefix("ref: refs/heads/") {
+        Some(branch) => Some(branch.to_string()),
+        None => Some(head.chars().take(7).collect()),
+    }
+}
+
+/// Status line made of a message on the left and right-aligned segments,
+/// or the text of a format in their place.
+/// Remembers what it drew so unchanged segments are not rewritten.
+pub struct StatusLine {
+    segments: Vec<Segment>,
+    format: Option<StatusFormat>,
+    drawn_message: Option<String>,
+    drawn_segments: Vec<(usize, String)>,
+}
+
+impl StatusLine {
+    pub fn new(segments: Vec<Segment>, format: Option<StatusFormat>) -> Self {
+        StatusLine {
+            segments,
+            format,
+            drawn_message: None,
+            drawn_segments: Vec::new(),
+        }
+    }
+
+    /// Forces a full redraw next time, after something else used the row.
+    pub fn invalidate(&mut self) {
+        self.drawn_message = None;
+        self.drawn_segments.clear();
+    }
+
+    pub fn draw(&mut self, out: &mut impl Backend, row: u16, cols: usize, info: &StatusInfo, style: ContentStyle) -> Result<(), Error> {
+        // Lay the non-empty segments out right-aligned, separated by " | "
+        let texts: Vec<String> = match &self.format {
+            Some(format) => vec![format.render(info)],
+            None => self.segments.iter().map(|s| s.text(info)).collect(),
+        };
+        let texts: Vec<String> = texts.into_iter().filter(|t| !t.is_empty()).collect();
+        let total: usize = texts.iter().map(|t| t.chars().count()).sum::<usize>() + 3 * texts.len().saturating_sub(1);
+        let mut col = cols.saturating_sub(total);
+        let mut layout = Vec::with_capacity(texts.len());
+        for (i, text) in texts.into_iter().enumerate() {
+            if i > 0 {
+                col += 3;
+            }
+            let width = text.chars().count();
+            layout.push((col, text));
+            col += width;
+        }
+
+        let same_shape = self.drawn_segments.len() == layout.len()
+            && self.drawn_segments.iter().zip(&layout).all(|(old, new)| old.0 == new.0 && old.1.chars().count() == new.1.chars().count());
+        if self.drawn_message.as_deref() != Some(info.message) || !same_shape {
+            // Fill the whole row so the style's background spans it
+            let mut line: Vec<char> = vec![' '; cols];
+            let room = layout.first().map_or(cols, |(c, _)| c.saturating_sub(1));
+            for (at, ch) in info.message.chars().take(room).enumerate() {
+                line[at] = ch;
+            }
+            for (i, (col, text)) in layout.iter().enumerate() {
+                let start = if i > 0 { col - 3 } else { *col };
+                let text = if i > 0 { format!(" | {}", text) } else { text.clone() };
+                for (at, ch) in (start..cols).zip(text.chars()) {
+                    line[at] = ch;
+                }
+            }
+            out.move_to(0, row)?;
+            out.print_styled(&line.into_iter().collect::<String>(), style)?;
+        } else {
+            for (old, (col, text)) in self.drawn_segments.iter().zip(&layout) {
+                if old.1 != *text {
+                    out.move_to(*col as u16, row)?;
+                    out.print_styled(text, style)?;
+                }
+            }
+        }
+        self.drawn_message = Some(info.message.to_string());
+        self.drawn_segments = layout;
+        Ok(())
+    }
+}
@@ -0,0 +1,293 @@
+// src/syntax.rs
+
+use crate::buffer::{EditOp, EditorBuffer};
+use ropey::Rope;
+use std::ops::Range;
+use std::path::Path;
+use tree_sitter::{InputEdit, Node, Parser, Point, Tree};
+
+/// Languages with a bundled tree-sitter grammar.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SyntaxLanguage {
+    Rust,
+}
+
+impl SyntaxLanguage {
+    /// Picks a grammar from the file extension, if one is bundled.
+    pub fn from_path(path: &str) -> Option<Self> {
+        match Path::new(path).extension()?.to_str()? {
+            "rs" => Some(SyntaxLanguage::Rust),
+            _ => None,
+        }
+    }
+
+    fn grammar(self) -> tree_sitter::Language {
+        match self {
+            SyntaxLanguage::Rust => tree_sitter_rust::LANGUAGE.into(),
+        }
+    }
+
+    /// Node kinds that make sensible fold regions.
+    fn foldable_kinds(self) -> &'static [&'static str] {
+        match self {
+            SyntaxLanguage::Rust => &[
+                "function_item",
+                "impl_item",
+                "trait_item",
+                "struct_item",
+                "enum_item",
+                "union_item",
+                "mod_item",
+                "macro_definition",
+                "match_expression",
+                "block",
+            ],
+        }
+    }
+
+    /// Classifies a node. Atomic kinds (comments, strings, literals) are not descended into.
+    fn classify(self, node: Node) -> Option<HighlightKind> {
+        match self {
+            SyntaxLanguage::Rust => {
+                let parent_kind = node.parent().map(|p| p.kind());
+                match node.kind() {
+                    "line_comment" | "block_comment" => Some(HighlightKind::Comment),
+                    "string_literal" | "raw_string_literal" | "char_literal" => Some(HighlightKind::String),
+                    "integer_literal" | "float_literal" | "boolean_literal" => Some(HighlightKind::Number),
+                    "type_identifier" | "primitive_type" => Some(HighlightKind::Type),
+                    "attribute_item" | "inner_attribute_item" => Some(HighlightKind::Attribute),
+                    "self" | "crate" | "super" | "mutable_specifier" => Some(HighlightKind::Keyword),
+                    "identifier" if parent_kind == Some("macro_invocation") => Some(HighlightKind::Macro),
+                    "identifier" if matches!(parent_kind, Some("function_item") | Some("call_expression")) => {
+                        Some(HighlightKind::Function)
+                    }
+                    "!" if parent_kind == Some("macro_invocation") => Some(HighlightKind::Macro),
+                    kind if !node.is_named() && kind.chars().all(|c| c.is_ascii_lowercase() || c == '_') => {
+                        Some(HighlightKind::Keyword)
+                    }
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    fn is_atomic(kind: HighlightKind) -> bool {
+        matches!(kind, HighlightKind::Comment | HighlightKind::String | HighlightKind::Number | HighlightKind::Attribute)
+    }
+
+    fn function_kinds(self) -> &'static [&'static str] {
+        match self {
+            SyntaxLanguage::Rust => &["function_item"],
+        }
+    }
+}
+
+/// Token classes produced by [`SyntaxTree::highlights`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HighlightKind {
+    Keyword,
+    String,
+    Comment,
+    Number,
+    Type,
+    Function,
+    Macro,
+    Attribute,
+}
+
+impl HighlightKind {
+    /// Default palette color as RGB.
+    pub fn rgb(self) -> (u8, u8, u8) {
+        match self {
+            HighlightKind::Keyword => (0x56, 0x9c, 0xd6),
+            HighlightKind::String => (0xce, 0x91, 0x78),
+            HighlightKind::Comment => (0x6a, 0x99, 0x55),
+            HighlightKind::Number => (0xb5, 0xce, 0xa8),
+            HighlightKind::Type => (0x4e, 0xc9, 0xb0),
+            HighlightKind::Function => (0xdc, 0xdc, 0xaa),
+            HighlightKind::Macro => (0xc5, 0x86, 0xc0),
+            HighlightKind::Attribute => (0x9c, 0xdc, 0xfe),
+        }
+    }
+}
+
+/// Default editor foreground and background as RGB.
+pub const DEFAULT_FG: (u8, u8, u8) = (0xd4, 0xd4, 0xd4);
+pub const DEFAULT_BG: (u8, u8, u8) = (0x1e, 0x1e, 0x1e);
+
+pub struct SyntaxTree {
+    pub language: SyntaxLanguage,
+    parser: Parser,
+    tree: Option<Tree>,
+    /// Text the tree was parsed from, used to locate incoming edits.
+    source: Rope,
+}
+
+/// Line range meaning "everything may have changed".
+const ALL_LINES: Range<usize> = 0..usize::MAX;
+
+/// Tree-sitter position (row, byte column) of `byte` in `rope`.
+fn point(rope: &Rope, byte: usize) -> Point {
+    let row = rope.byte_to_line(byte);
+    Point::new(row, byte - rope.line_to_byte(row))
+}
+
+/// Parses `rope` chunk by chunk, reusing the unchanged parts of `old`.
+fn parse_rope(parser: &mut Parser, rope: &Rope, old: Option<&Tree>) -> Option<Tree> {
+    parser.parse_with_options(
+        &mut |byte, _| {
+            if byte >= rope.len_bytes() {
+                return &[][..];
+            }
+            let (chunk, chunk_byte, _, _) = rope.chunk_at_byte(byte);
+            &chunk.as_bytes()[byte - chunk_byte..]
+        },
+        old,
+        None,
+    )
+}
+
+impl SyntaxTree {
+    pub fn new(language: SyntaxLanguage) -> Self {
+        let mut parser = Parser::new();
+        parser
+            .set_language(&language.grammar())
+            .expect("bundled grammar matches the tree-sitter version");
+        SyntaxTree { language, parser, tree: None, source: Rope::new() }
+    }
+
+    /// Parses the whole buffer from scratch.
+    pub fn parse(&mut self, buffer: &EditorBuffer) {
+        self.source = buffer.rope.clone();
+        self.tree = parse_rope(&mut self.parser, &self.source, None);
+    }
+
+    /// Feeds `edits`, applied to the buffer in order since the last parse,
+    /// into the tree and re-parses only what they touched. Returns the line
+    /// ranges whose highlighting may have changed.
+    pub fn apply_edits(&mut self, edits: &[EditOp], buffer: &EditorBuffer) -> Vec<Range<usize>> {
+        let Some(mut tree) = self.tree.take() else {
+            self.parse(buffer);
+            return vec![ALL_LINES];
+        };
+        let mut lines = Vec::new();
+        for op in edits {
+            let (char_idx, old_len, new_len) = match op {
+                EditOp::Insert { char_idx, .. } => (*char_idx, 0, op.char_len()),
+                EditOp::Delete { char_idx, .. } => (*char_idx, op.char_len(), 0),
+            };
+            let start_byte = self.source.char_to_byte(char_idx);
+            let old_end_byte = self.source.char_to_byte(char_idx + old_len);
+            let start_position = point(&self.source, start_byte);
+            let old_end_position = point(&self.source, old_end_byte);
+            op.apply(&mut self.source);
+            let new_end_byte = self.source.char_to_byte(char_idx + new_len);
+            let new_end_position = point(&self.source, new_end_byte);
+            tree.edit(&InputEdit {
+                start_byte,
+                old_end_byte,
+                new_end_byte,
+                start_position,
+                old_end_position,
+                new_end_position,
+            });
+            // Adding or removing lines shifts everything below
+            if old_end_position.row != new_end_position.row {
+                lines.push(start_position.row..usize::MAX);
+            } else {
+                lines.push(start_position.row..new_end_position.row + 1);
+            }
+        }
+        if self.source.len_bytes() != buffer.rope.len_bytes() {
+            // Edits did not add up to the buffer; start over
+            self.parse(buffer);
+            return vec![ALL_LINES];
+        }
+        self.tree = parse_rope(&mut self.parser, &self.source, Some(&tree));
+        if let Some(new_tree) = self.tree.as_ref() {
+            for range in tree.changed_ranges(new_tree) {
+                let first = self.source.byte_to_line(range.start_byte.min(self.source.len_bytes()));
+                let last = self.source.byte_to_line(range.end_byte.min(self.source.len_bytes()));
+                lines.push(first..last + 1);
+            }
+        }
+        lines
+    }
+
+    fn line_range(buffer: &EditorBuffer, node: Node) -> (usize, usize) {
+        let start = buffer.rope.byte_to_line(node.start_byte());
+        let end = buffer.rope.byte_to_line(node.end_byte().saturating_sub(1).max(node.start_byte()));
+        (start, end)
+    }
+
+    /// Line range of the innermost multi-line foldable node around `char_idx`.
+    pub fn fold_range_at(&self, buffer: &EditorBuffer, char_idx: usize) -> Option<(usize, usize)> {
+        let byte = buffer.rope.char_to_byte(char_idx.min(buffer.len_chars()));
+        let kinds = self.language.foldable_kinds();
+        let mut node = self.tree.as_ref()?.root_node().descendant_for_byte_range(byte, byte);
+        while let Some(n) = node {
+            let (start, end) = Self::line_range(buffer, n);
+            if kinds.contains(&n.kind()) && end > start {
+                return Some((start, end));
+            }
+            node = n.parent();
+        }
+        None
+    }
+
+    /// Line ranges of every outermost multi-line function.
+    pub fn function_ranges(&self, buffer: &EditorBuffer) -> Vec<(usize, usize)> {
+        let mut ranges = Vec::new();
+        let Some(tree) = self.tree.as_ref() else {
+            return ranges;
+        };
+        let kinds = self.language.function_kinds();
+        let mut cursor = tree.walk();
+        'walk: loop {
+            let node = cursor.node();
+            let is_function = kinds.contains(&node.kind());
+            if is_function {
+                let (start, end) = Self::line_range(buffer, node);
+                if end > start {
+                    ranges.push((start, end));
+                }
+            }
+            if !is_function && cursor.goto_first_child() {
+                continue;
+            }
+            while !cursor.goto_next_sibling() {
+                if !cursor.goto_parent() {
+                    break 'walk;
+                }
+            }
+        }
+        ranges
+    }
+
+    /// Highlighted byte ranges overlapping `start..end`, in document order.
+    pub fn highlights(&self, start: usize, end: usize) -> Vec<(usize, usize, HighlightKind)> {
+        let mut spans = Vec::new();
+        let Some(tree) = self.tree.as_ref() else {
+            return spans;
+        };
+        let mut cursor = tree.walk();
+        'walk: loop {
+            let node = cursor.node();
+            let overlaps = node.start_byte() < end && node.end_byte() > start;
+            let kind = if overlaps { self.language.classify(node) } else { None };
+            if let Some(kind) = kind {
+                spans.push((node.start_byte(), node.end_byte(), kind));
+            }
+            let descend = overlaps && !kind.is_some_and(SyntaxLanguage::is_atomic);
+            if descend && cursor.goto_first_child() {
+                continue;
+            }
+            while !cursor.goto_next_sibling() {
+                if !cursor.goto_parent() {
+                    break 'walk;
+                }
+            }
+        }
+        spans
+    }
+}
@@ -0,0 +1,233 @@
+// src/tasks.rs
+
+use std::fs::read_to_string;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+
+/// Output lines kept of a task; older ones are dropped as more arrive.
+const MAX_OUTPUT_LINES: usize = 10_000;
+
+/// A runnable project task.
+#[derive(Clone, Debug)]
+pub struct Task {
+    pub label: String,
+    pub command: String,
+}
+
+impl Task {
+    fn new(label: String, command: String) -> Self {
+        Task { label, command }
+    }
+}
+
+fn cargo_tasks(dir: &Path) -> Vec<Task> {
+    let Ok(manifest) = read_to_string(dir.join("Cargo.toml")) else {
+        return Vec::new();
+    };
+    let mut tasks: Vec<Task> = ["build", "run", "test", "check", "clippy"]
+        .iter()
+        .map(|sub| Task::new(format!("cargo {}", sub), format!("cargo {}", sub)))
+        .collect();
+    if let Ok(manifest) = manifest.parse::<toml::Table>()
+        && let Some(bins) = manifest.get("bin").and_then(|b| b.as_array())
+    {
+        for name in bins.iter().filter_map(|b| b.get("name")?.as_str()) {
+            tasks.push(Task::new(format!("cargo run --bin {}", name), format!("cargo run --bin {}", name)));
+        }
+    }
+    tasks
+}
+
+fn make_tasks(dir: &Path) -> Vec<Task> {
+    let Some(makefile) = ["Makefile", "makefile", "GNUmakefile"]
+        .iter()
+        .find_map(|name| read_to_string(dir.join(name)).ok())
+    else {
+        return Vec::new();
+    };
+    let mut tasks = Vec::new();
+    for line in makefile.lines() {
+        // Rule lines look like `target: deps`; skip variables, special and pattern targets
+        let Some((target, _)) = line.split_once(':') else {
+            continue;
+        };
+        let is_rule = !target.is_empty()
+            && !target.starts_with(['.', '\t', ' '])
+            && !line[target.len()..].starts_with(":=")
+            && target.chars().all(|c| c.is_alphanumeric() || "_-./".contains(c));
+        if is_rule && !tasks.iter().any(|t: &Task| t.command == format!("make {}", target)) {
+            tasks.push(Task::new(format!("make {}", target), format!("make {}", target)));
+        }
+    }
+    tasks
+}
+
+fn npm_tasks(dir: &Path) -> Vec<Task> {
+    let Some(package) = read_to_string(dir.join("package.json"))
+        .ok()
+        .and_then(|text| serde_json::from_str::<serde_json::Value>(&text).ok())
+    else {
+        return Vec::new();
+    };
+    package
+        .get("scripts")
+        .and_then(|s| s.as_object())
+        .map(|scripts| {
+            scripts
+                .keys()
+                .map(|name| Task::new(format!("npm run {}", name), format!("npm run {}", name)))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Finds Cargo, Makefile and package.json tasks in `dir`.
+pub fn detect_tasks(dir: &Path) -> Vec<Task> {
+    let mut tasks = cargo_tasks(dir);
+    tasks.extend(make_tasks(dir));
+    tasks.extend(npm_tasks(dir));
+    tasks
+}
+
+/// Runs one task at a time as a child process, collecting its output.
+pub struct TaskRunner {
+    pub tasks: Vec<Task>,
+    pub selected: usize,
+    pub last: Option<Task>,
+    child: Option<Child>,
+    output: Arc<Mutex<Vec<String>>>,
+    /// First output line shown, or None to follow the tail.
+    pub scroll: Option<usize>,
+    pub status: String,
+}
+
+fn pipe_lines<R: Read + Send + 'static>(reader: R, output: Arc<Mutex<Vec<String>>>) {
+    thread::spawn(move || {
+        for line in BufReader::new(reader).lines().map_while(Result::ok) {
+            let mut output = output.lock().unwrap();
+            // Dropping a batch at a time keeps the shifting down
+            if output.len() >= MAX_OUTPUT_LINES {
+                output.drain(..MAX_OUTPUT_LINES / 10);
+            }
+            output.push(line);
+        }
+    });
+}
+
+impl TaskRunner {
+    pub fn new() -> Self {
+        TaskRunner {
+            tasks: Vec::new(),
+            selected: 0,
+            last: None,
+            child: None,
+            output: Arc::new(Mutex::new(Vec::new())),
+            scroll: None,
+            status: String::new(),
+        }
+    }
+
+    /// Re-scans the working directory for tasks.
+    pub fn refresh(&mut self) {
+        self.tasks = detect_tasks(Path::new("."));
+        self.selected = self.selected.min(self.tasks.len().saturating_sub(1));
+    }
+
+    pub fn select_next(&mut self) {
+        if self.selected + 1 < self.tasks.len() {
+            self.selected += 1;
+        }
+    }
+
+    pub fn select_prev(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    pub fn run_selected(&mut self) {
+        if let Some(task) = self.tasks.get(self.selected).cloned() {
+            self.run(task);
+        }
+    }
+
+    pub fn rerun_last(&mut self) {
+        if let Some(task) = self.last.clone() {
+            self.run(task);
+        }
+    }
+
+    /// Starts `task`, killing whatever was still running.
+    pub fn run(&mut self, task: Task) {
+        self.kill();
+        self.output = Arc::new(Mutex::new(Vec::new()));
+        self.scroll = None;
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(&task.command).stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped());
+        // A group of its own, so killing the task reaches what the shell started
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+        let spawned = command.spawn();
+        match spawned {
+            Ok(mut child) => {
+                if let Some(stdout) = child.stdout.take() {
+                    pipe_lines(stdout, Arc::clone(&self.output));
+                }
+                if let Some(stderr) = child.stderr.take() {
+                    pipe_lines(stderr, Arc::clone(&self.output));
+                }
+                self.child = Some(child);
+                self.status = format!("Running: {}", task.label);
+            }
+            Err(err) => self.status = format!("Failed to start {}: {}", task.label, err),
+        }
+        self.last = Some(task);
+    }
+
+    /// Kills the running task along with the processes it started.
+    pub fn kill(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            #[cfg(unix)]
+            // SAFETY: the child leads its own process group, which it is
+            // still in until it is waited for below
+            unsafe {
+                libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+            }
+            #[cfg(not(unix))]
+            let _ = child.kill();
+            let _ = child.wait();
+            self.status = "Killed".to_string();
+        }
+    }
+
+    /// Reaps the child if it has exited.
+    pub fn poll(&mut self) {
+        if let Some(child) = self.child.as_mut()
+            && let Ok(Some(exit)) = child.try_wait()
+        {
+            let label = self.last.as_ref().map_or("", |t| t.label.as_str());
+            self.status = format!("Finished: {} ({})", label, exit);
+            self.child = None;
+        }
+    }
+
+    /// The output collected so far, held back from the task while borrowed.
+    pub fn output_lines(&self) -> MutexGuard<'_, Vec<String>> {
+        self.output.lock().unwrap()
+    }
+
+    pub fn scroll_by(&mut self, delta: isize, visible_rows: usize) {
+        let len = self.output.lock().unwrap().len();
+        let max_top = len.saturating_sub(visible_rows);
+        let top = self.scroll.unwrap_or(max_top).saturating_add_signed(delta);
+        // Scrolling back to the bottom resumes following the tail
+        self.scroll = (top < max_top).then_some(top);
+    }
+}
+
+impl Default for TaskRunner {
+    fn default() -> Self {
+        TaskRunner::new()
+    }
+}
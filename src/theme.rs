@@ -0,0 +1,379 @@
+// src/theme.rs
+
+use crate::config::config_dir;
+use crate::syntax::{HighlightKind, DEFAULT_BG, DEFAULT_FG};
+use crossterm::style::{Attribute, Color, ContentStyle};
+use serde::{Deserialize, Deserializer};
+use std::fs;
+
+/// Colors the terminal can show.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorSupport {
+    /// Worked out from the environment.
+    Auto,
+    Truecolor,
+    #[serde(rename = "256")]
+    Colors256,
+    #[serde(rename = "16")]
+    Colors16,
+}
+
+impl ColorSupport {
+    /// The colors this terminal shows, asking the environment when `Auto`.
+    /// Terminals announce truecolor in COLORTERM and 256 colors in TERM;
+    /// anything else, such as the Linux console, gets the basic 16.
+    pub fn resolve(self) -> ColorSupport {
+        if self != ColorSupport::Auto {
+            return self;
+        }
+        let colorterm = std::env::var("COLORTERM").unwrap_or_default();
+        let term = std::env::var("TERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            ColorSupport::Truecolor
+        } else if term.contains("256color") {
+            ColorSupport::Colors256
+        } else {
+            ColorSupport::Colors16
+        }
+    }
+
+    /// `color` as near as the terminal can show it.
+    fn convert(self, color: Color) -> Color {
+        let Color::Rgb { r, g, b } = color else {
+            return color;
+        };
+        match self {
+            ColorSupport::Auto | ColorSupport::Truecolor => color,
+            ColorSupport::Colors256 => Color::AnsiValue(ansi_256(r, g, b)),
+            ColorSupport::Colors16 => ansi_16(r, g, b),
+        }
+    }
+}
+
+/// The xterm palette: the 16 basic colors, a 6x6x6 cube and 24 grays.
+/// Takes whichever of the nearest cube color and nearest gray is closer.
+fn ansi_256(r: u8, g: u8, b: u8) -> u8 {
+    const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    let level = |channel: u8| (0..6).min_by_key(|&i| LEVELS[i].abs_diff(channel)).unwrap_or(0);
+    let (ri, gi, bi) = (level(r), level(g), level(b));
+    let cube = (LEVELS[ri], LEVELS[gi], LEVELS[bi]);
+    let average = (r as usize + g as usize + b as usize) / 3;
+    let gray_index = (average.saturating_sub(8) / 10).min(23);
+    let gray = (8 + 10 * gray_index) as u8;
+    if distance((r, g, b), (gray, gray, gray)) < distance((r, g, b), cube) {
+        232 + gray_index as u8
+    } else {
+        16 + 36 * ri as u8 + 6 * gi as u8 + bi as u8
+    }
+}
+
+/// The basic color nearest to an RGB value, going by xterm's defaults.
+/// Dark tints are brightened first so they keep their hue: a dim green
+/// background becomes dark green rather than black.
+fn ansi_16(r: u8, g: u8, b: u8) -> Color {
+    let (max, min) = (r.max(g).max(b), r.min(g).min(b));
+    let (r, g, b) = if max - min > 24 && max < 205 {
+        let scale = |channel: u8| (channel as u32 * 205 / max as u32) as u8;
+        (scale(r), scale(g), scale(b))
+    } else {
+        (r, g, b)
+    };
+    const PALETTE: [((u8, u8, u8), Color); 16] = [
+        ((0, 0, 0), Color::Black),
+        ((205, 0, 0), Color::DarkRed),
+        ((0, 205, 0), Color::DarkGreen),
+        ((205, 205, 0), Color::DarkYellow),
+        ((0, 0, 238), Color::DarkBlue),
+        ((205, 0, 205), Color::DarkMagenta),
+        ((0, 205, 205), Color::DarkCyan),
+        ((229, 229, 229), Color::Grey),
+        ((127, 127, 127), Color::DarkGrey),
+        ((255, 0, 0), Color::Red),
+        ((0, 255, 0), Color::Green),
+        ((255, 255, 0), Color::Yellow),
+        ((92, 92, 255), Color::Blue),
+        ((255, 0, 255), Color::Magenta),
+        ((0, 255, 255), Color::Cyan),
+        ((255, 255, 255), Color::White),
+    ];
+    PALETTE.iter().min_by_key(|(rgb, _)| distance((r, g, b), *rgb)).map_or(Color::Reset, |&(_, color)| color)
+}
+
+/// Squared distance between two colors, weighting green the most as the eye does.
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let d = |x: u8, y: u8| x.abs_diff(y) as u32 * x.abs_diff(y) as u32;
+    2 * d(a.0, b.0) + 4 * d(a.1, b.1) + 3 * d(a.2, b.2)
+}
+
+/// A color written "#rrggbb" in theme files.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+struct Rgb(u8, u8, u8);
+
+impl TryFrom<String> for Rgb {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("{} is not a #rrggbb color", text);
+        let hex = text.strip_prefix('#').filter(|hex| hex.len() == 6 && hex.is_ascii()).ok_or_else(invalid)?;
+        let channel = |at: usize| u8::from_str_radix(&hex[at..at + 2], 16).map_err(|_| invalid());
+        Ok(Rgb(channel(0)?, channel(2)?, channel(4)?))
+    }
+}
+
+fn rgb<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Color>, D::Error> {
+    let Rgb(r, g, b) = Rgb::deserialize(deserializer)?;
+    Ok(Some(Color::Rgb { r, g, b }))
+}
+
+/// How one kind of text is drawn. Colors left unset keep what is below:
+/// the terminal's own colors or a style laid down first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Style {
+    #[serde(deserialize_with = "rgb")]
+    pub fg: Option<Color>,
+    #[serde(deserialize_with = "rgb")]
+    pub bg: Option<Color>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub dim: bool,
+    pub reverse: bool,
+}
+
+impl Style {
+    fn fg((r, g, b): (u8, u8, u8)) -> Self {
+        Style { fg: Some(Color::Rgb { r, g, b }), ..Style::default() }
+    }
+
+    fn bg((r, g, b): (u8, u8, u8)) -> Self {
+        Style { bg: Some(Color::Rgb { r, g, b }), ..Style::default() }
+    }
+
+    fn fg_bg(fg: (u8, u8, u8), bg: (u8, u8, u8)) -> Self {
+        Style { bg: Style::bg(bg).bg, ..Style::fg(fg) }
+    }
+
+    /// Lays this style over `style`: the colors it sets replace the ones
+    /// there and its attributes are added.
+    pub fn apply(&self, style: &mut ContentStyle) {
+        if self.fg.is_some() {
+            style.foreground_color = self.fg;
+        }
+        if self.bg.is_some() {
+            style.background_color = self.bg;
+        }
+        let attributes = [
+            (self.bold, Attribute::Bold),
+            (self.italic, Attribute::Italic),
+            (self.underline, Attribute::Underlined),
+            (self.dim, Attribute::Dim),
+            (self.reverse, Attribute::Reverse),
+        ];
+        for (on, attribute) in attributes {
+            if on {
+                style.attributes.set(attribute);
+            }
+        }
+    }
+
+    /// This style on its own.
+    pub fn content(&self) -> ContentStyle {
+        let mut style = ContentStyle::new();
+        self.apply(&mut style);
+        style
+    }
+}
+
+/// Styles of the syntax highlighting's token classes.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct SyntaxStyles {
+    pub keyword: Style,
+    pub string: Style,
+    pub comment: Style,
+    pub number: Style,
+    #[serde(rename = "type")]
+    pub type_name: Style,
+    pub function: Style,
+    #[serde(rename = "macro")]
+    pub macro_name: Style,
+    pub attribute: Style,
+}
+
+impl Default for SyntaxStyles {
+    fn default() -> Self {
+        Theme::dark().syntax
+    }
+}
+
+/// Styles of everything drawn in the text area, status line and panels.
+/// Theme files are TOML with a table per element, e.g.
+/// `selection = { bg = "#264f78" }`, and `[syntax]` for token classes;
+/// elements a file leaves out look as in the dark theme.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Theme {
+    /// Plain text and what is behind it. The editor leaves both to the
+    /// terminal; exports use them.
+    pub text: Style,
+    pub line_numbers: Style,
+    pub status_line: Style,
+    pub selection: Style,
+    pub search_match: Style,
+    pub matching_bracket: Style,
+    pub trailing_whitespace: Style,
+    /// Columns marked by the `rulers` setting.
+    pub ruler: Style,
+    /// Text just brought back by undo or redo.
+    pub undo_flash: Style,
+    /// Glyphs standing in for spaces, tabs and line breaks.
+    pub invisibles: Style,
+    pub fold_placeholder: Style,
+    /// Picked row of lists and popups.
+    pub list_selected: Style,
+    pub popup: Style,
+    pub diff_removed: Style,
+    pub diff_added: Style,
+    pub diff_changed: Style,
+    pub syntax: SyntaxStyles,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme::dark()
+    }
+}
+
+impl Theme {
+    /// Light text on a dark terminal.
+    pub fn dark() -> Self {
+        Theme {
+            text: Style::fg_bg(DEFAULT_FG, DEFAULT_BG),
+            line_numbers: Style::fg((0x85, 0x85, 0x85)),
+            status_line: Style::fg_bg((0xcc, 0xcc, 0xcc), (0x2d, 0x2d, 0x2d)),
+            selection: Style::bg((0x44, 0x47, 0x5a)),
+            search_match: Style { reverse: true, ..Style::default() },
+            matching_bracket: Style::bg((0x00, 0x5f, 0x5f)),
+            trailing_whitespace: Style::bg((0x80, 0x6a, 0x00)),
+            ruler: Style::bg((0x3a, 0x3a, 0x3a)),
+            undo_flash: Style::bg((0x2d, 0x5a, 0x2d)),
+            invisibles: Style { dim: true, ..Style::default() },
+            fold_placeholder: Style { dim: true, ..Style::default() },
+            list_selected: Style { reverse: true, ..Style::default() },
+            popup: Style::bg((0x3c, 0x3c, 0x3c)),
+            diff_removed: Style::bg((0x5a, 0x1d, 0x1d)),
+            diff_added: Style::bg((0x1d, 0x4a, 0x1d)),
+            diff_changed: Style::bg((0x5a, 0x4a, 0x0d)),
+            syntax: SyntaxStyles {
+                keyword: Style::fg(HighlightKind::Keyword.rgb()),
+                string: Style::fg(HighlightKind::String.rgb()),
+                comment: Style::fg(HighlightKind::Comment.rgb()),
+                number: Style::fg(HighlightKind::Number.rgb()),
+                type_name: Style::fg(HighlightKind::Type.rgb()),
+                function: Style::fg(HighlightKind::Function.rgb()),
+                macro_name: Style::fg(HighlightKind::Macro.rgb()),
+                attribute: Style::fg(HighlightKind::Attribute.rgb()),
+            },
+        }
+    }
+
+    /// Dark text for terminals with a light background.
+    pub fn light() -> Self {
+        Theme {
+            text: Style::fg_bg((0x1f, 0x1f, 0x1f), (0xff, 0xff, 0xff)),
+            line_numbers: Style::fg((0x6e, 0x76, 0x81)),
+            status_line: Style::fg_bg((0x33, 0x33, 0x33), (0xe0, 0xe0, 0xe0)),
+            selection: Style::bg((0xad, 0xd6, 0xff)),
+            search_match: Style::bg((0xff, 0xe0, 0x82)),
+            matching_bracket: Style::bg((0xb4, 0xe4, 0xe4)),
+            trailing_whitespace: Style::bg((0xff, 0xd7, 0x87)),
+            ruler: Style::bg((0xe8, 0xe8, 0xe8)),
+            undo_flash: Style::bg((0xc8, 0xf0, 0xc8)),
+            invisibles: Style::fg((0xb0, 0xb0, 0xb0)),
+            fold_placeholder: Style::fg((0x90, 0x90, 0x90)),
+            list_selected: Style { reverse: true, ..Style::default() },
+            popup: Style::bg((0xe8, 0xe8, 0xe8)),
+            diff_removed: Style::bg((0xff, 0xd0, 0xd0)),
+            diff_added: Style::bg((0xd0, 0xf0, 0xd0)),
+            diff_changed: Style::bg((0xf5, 0xe8, 0xb0)),
+            syntax: SyntaxStyles {
+                keyword: Style::fg((0x00, 0x00, 0xff)),
+                string: Style::fg((0xa3, 0x15, 0x15)),
+                comment: Style::fg((0x00, 0x80, 0x00)),
+                number: Style::fg((0x09, 0x86, 0x58)),
+                type_name: Style::fg((0x26, 0x7f, 0x99)),
+                function: Style::fg((0x79, 0x5e, 0x26)),
+                macro_name: Style::fg((0xaf, 0x00, 0xdb)),
+                attribute: Style::fg((0x00, 0x10, 0x80)),
+            },
+        }
+    }
+
+    /// The theme file `themes/<name>.toml` in the config directory if there
+    /// is one, otherwise the built-in theme of that name.
+    pub fn load(name: &str) -> Result<Theme, String> {
+        if let Some(path) = config_dir().map(|dir| dir.join("themes").join(format!("{}.toml", name)))
+            && path.exists()
+        {
+            let text = fs::read_to_string(&path).map_err(|err| format!("{}: {}", path.display(), err))?;
+            return toml::from_str(&text).map_err(|err| format!("{}: {}", path.display(), err.message()));
+        }
+        match name {
+            "dark" => Ok(Theme::dark()),
+            "light" => Ok(Theme::light()),
+            _ => Err(format!("No theme called {}", name)),
+        }
+    }
+
+    /// Brings every color down to ones the terminal can show.
+    pub fn convert_colors(&mut self, support: ColorSupport) {
+        let syntax = &mut self.syntax;
+        let styles = [
+            &mut self.text,
+            &mut self.line_numbers,
+            &mut self.status_line,
+            &mut self.selection,
+            &mut self.search_match,
+            &mut self.matching_bracket,
+            &mut self.trailing_whitespace,
+            &mut self.ruler,
+            &mut self.undo_flash,
+            &mut self.invisibles,
+            &mut self.fold_placeholder,
+            &mut self.list_selected,
+            &mut self.popup,
+            &mut self.diff_removed,
+            &mut self.diff_added,
+            &mut self.diff_changed,
+            &mut syntax.keyword,
+            &mut syntax.string,
+            &mut syntax.comment,
+            &mut syntax.number,
+            &mut syntax.type_name,
+            &mut syntax.function,
+            &mut syntax.macro_name,
+            &mut syntax.attribute,
+        ];
+        for style in styles {
+            style.fg = style.fg.map(|color| support.convert(color));
+            style.bg = style.bg.map(|color| support.convert(color));
+        }
+    }
+
+    /// Style of a syntax token class.
+    pub fn syntax(&self, kind: HighlightKind) -> &Style {
+        match kind {
+            HighlightKind::Keyword => &self.syntax.keyword,
+            HighlightKind::String => &self.syntax.string,
+            HighlightKind::Comment => &self.syntax.comment,
+            HighlightKind::Number => &self.syntax.number,
+            HighlightKind::Type => &self.syntax.type_name,
+            HighlightKind::Function => &self.syntax.function,
+            HighlightKind::Macro => &self.syntax.macro_name,
+            HighlightKind::Attribute => &self.syntax.attribute,
+        }
+    }
+}
@@ -0,0 +1,176 @@
+// src/tree.rs
+
+use ignore::WalkBuilder;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// Columns the sidebar takes, separator not included.
+const TREE_WIDTH: u16 = 30;
+
+pub struct TreeEntry {
+    /// Relative to the tree's root.
+    pub path: PathBuf,
+    pub depth: usize,
+    pub is_dir: bool,
+}
+
+/// The project directory as a sidebar of expandable folders. Files left out
+/// by .gitignore are not listed.
+pub struct FileTree {
+    root: PathBuf,
+    expanded: HashSet<PathBuf>,
+    /// Entries inside expanded folders, in display order.
+    entries: Vec<TreeEntry>,
+    pub selected: usize,
+    pub top: usize,
+    pub visible: bool,
+}
+
+impl FileTree {
+    pub fn new(root: &Path) -> Self {
+        FileTree {
+            root: root.to_path_buf(),
+            expanded: HashSet::new(),
+            entries: Vec::new(),
+            selected: 0,
+            top: 0,
+            visible: false,
+        }
+    }
+
+    /// Width of the sidebar on a screen `cols` wide.
+    pub fn width(&self, cols: u16) -> u16 {
+        TREE_WIDTH.min(cols / 3)
+    }
+
+    /// Reads the directories again, keeping the selection on the same path.
+    pub fn refresh(&mut self) {
+        let selected = self.entries.get(self.selected).map(|entry| entry.path.clone());
+        self.entries.clear();
+        let root = self.root.clone();
+        self.read_dir(&root, 0);
+        self.selected = selected
+            .and_then(|path| self.entries.iter().position(|entry| entry.path == path))
+            .unwrap_or(0)
+            .min(self.entries.len().saturating_sub(1));
+    }
+
+    /// Adds the children of `dir`, folders first, then those of the expanded ones.
+    fn read_dir(&mut self, dir: &Path, depth: usize) {
+        // .gitignore counts even outside a git checkout
+        let walker = WalkBuilder::new(dir)
+            .max_depth(Some(1))
+            .hidden(false)
+            .require_git(false)
+            .filter_entry(|entry| entry.file_name() != ".git")
+            .build();
+        let mut children: Vec<(bool, PathBuf)> = walker
+            .flatten()
+            .filter(|entry| entry.depth() == 1)
+            .map(|entry| {
+                let is_dir = entry.file_type().is_some_and(|file_type| file_type.is_dir());
+                let path = entry.path().strip_prefix(&self.root).unwrap_or(entry.path()).to_path_buf();
+                (is_dir, path)
+            })
+            .collect();
+        children.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+        for (is_dir, path) in children {
+            let expanded = is_dir && self.expanded.contains(&path);
+            self.entries.push(TreeEntry { path: path.clone(), depth, is_dir });
+            if expanded {
+                self.read_dir(&self.root.join(&path), depth + 1);
+            }
+        }
+    }
+
+    /// One row per entry: indented name with an arrow on folders.
+    pub fn labels(&self) -> Vec<String> {
+        self.entries
+            .iter()
+            .map(|entry| {
+                let name = entry.path.file_name().map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+                let marker = match entry.is_dir {
+                    true if self.expanded.contains(&entry.path) => "▾ ",
+                    true => "▸ ",
+                    false => "  ",
+                };
+                format!("{}{}{}", "  ".repeat(entry.depth), marker, name)
+            })
+            .collect()
+    }
+
+    pub fn select_next(&mut self) {
+        if self.selected + 1 < self.entries.len() {
+            self.selected += 1;
+        }
+    }
+
+    pub fn select_prev(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    /// Selects the entry on row `row` of the sidebar, if there is one there.
+    pub fn select_row(&mut self, row: usize) -> bool {
+        if self.top + row < self.entries.len() {
+            self.selected = self.top + row;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Scrolls by `delta` rows of a sidebar `rows` high.
+    pub fn scroll(&mut self, delta: isize, rows: usize) {
+        let last_top = self.entries.len().saturating_sub(rows);
+        self.top = self.top.saturating_add_signed(delta).min(last_top);
+    }
+
+    /// Scrolls just enough to show the selection in a sidebar `rows` high.
+    pub fn scroll_to_selected(&mut self, rows: usize) {
+        if self.selected < self.top {
+            self.top = self.selected;
+        } else if self.selected >= self.top + rows {
+            self.top = self.selected + 1 - rows.max(1);
+        }
+    }
+
+    /// Opens or closes the selected folder, or returns the selected file
+    /// for the caller to open.
+    pub fn activate(&mut self) -> Option<String> {
+        let entry = self.entries.get(self.selected)?;
+        if !entry.is_dir {
+            return Some(entry.path.to_string_lossy().into_owned());
+        }
+        let path = entry.path.clone();
+        if !self.expanded.remove(&path) {
+            self.expanded.insert(path);
+        }
+        self.refresh();
+        None
+    }
+
+    /// Opens the selected folder.
+    pub fn expand(&mut self) {
+        if let Some(entry) = self.entries.get(self.selected)
+            && entry.is_dir
+            && self.expanded.insert(entry.path.clone())
+        {
+            self.refresh();
+        }
+    }
+
+    /// Closes the selected folder, or else moves to the folder holding the
+    /// selected entry.
+    pub fn collapse(&mut self) {
+        let Some(entry) = self.entries.get(self.selected) else {
+            return;
+        };
+        if entry.is_dir && self.expanded.remove(&entry.path) {
+            self.refresh();
+        } else if let Some(parent) = entry.path.parent()
+            && let Some(index) = self.entries.iter().position(|entry| entry.path == parent)
+        {
+            self.selected = index;
+        }
+    }
+}
@@ -0,0 +1,79 @@
+// src/watch.rs
+
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+
+/// Watches the file of the active buffer for changes made by other
+/// programs. The directory is watched rather than the file, so the file
+/// is still followed after it gets replaced by a rename.
+pub struct FileWatcher {
+    /// None when the platform watcher could not be started.
+    watcher: Option<RecommendedWatcher>,
+    rx: Receiver<notify::Result<Event>>,
+    /// Path as given to `watch`, and the file and directory it resolved to.
+    requested: Option<String>,
+    file: Option<PathBuf>,
+    dir: Option<PathBuf>,
+    changed: bool,
+}
+
+impl FileWatcher {
+    pub fn new() -> Self {
+        let (tx, rx) = channel();
+        FileWatcher {
+            watcher: notify::recommended_watcher(tx).ok(),
+            rx,
+            requested: None,
+            file: None,
+            dir: None,
+            changed: false,
+        }
+    }
+
+    /// Follows `path` from now on; cheap when it is already the watched one.
+    /// A file that does not exist yet is retried until it does.
+    pub fn watch(&mut self, path: Option<&str>) {
+        if self.requested.as_deref() == path && (self.dir.is_some() || path.is_none() || self.watcher.is_none()) {
+            return;
+        }
+        self.requested = path.map(str::to_string);
+        // Whatever happened to the new file while it was not watched
+        self.changed = true;
+        let Some(watcher) = self.watcher.as_mut() else {
+            return;
+        };
+        if let Some(dir) = self.dir.take() {
+            let _ = watcher.unwatch(&dir);
+        }
+        self.file = path.and_then(|path| Path::new(path).canonicalize().ok());
+        if let Some(dir) = self.file.as_deref().and_then(Path::parent)
+            && watcher.watch(dir, RecursiveMode::NonRecursive).is_ok()
+        {
+            self.dir = Some(dir.to_path_buf());
+        }
+    }
+
+    /// Whether the watched file may have changed since the last call. Always
+    /// true when nothing is being watched, so callers fall back to polling.
+    pub fn changed(&mut self) -> bool {
+        while let Ok(event) = self.rx.try_recv() {
+            if let Ok(event) = event
+                && !matches!(event.kind, EventKind::Access(_))
+                && event.paths.iter().any(|path| Some(path) == self.file.as_ref())
+            {
+                self.changed = true;
+            }
+        }
+        if self.dir.is_none() {
+            return true;
+        }
+        std::mem::take(&mut self.changed)
+    }
+}
+
+impl Default for FileWatcher {
+    fn default() -> Self {
+        FileWatcher::new()
+    }
+}
@@ -0,0 +1,133 @@
+// src/words.rs
+
+use crate::buffer::EditOp;
+use ropey::{Rope, RopeSlice};
+use std::collections::HashMap;
+
+/// Words shorter than this are quicker typed than picked from a list.
+const MIN_WORD_LEN: usize = 3;
+
+/// Most words offered at once.
+const MAX_COMPLETIONS: usize = 50;
+
+pub fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Calls `f` with each word of `text` long enough to be completed.
+fn for_each_word(text: RopeSlice, mut f: impl FnMut(&str)) {
+    let mut word = String::new();
+    for c in text.chars().chain(std::iter::once(' ')) {
+        if is_word_char(c) {
+            word.push(c);
+            continue;
+        }
+        // Numbers are not words
+        if word.chars().count() >= MIN_WORD_LEN && !word.starts_with(|c: char| c.is_ascii_digit()) {
+            f(&word);
+        }
+        word.clear();
+    }
+}
+
+/// Char range of the whole lines holding `from..to`.
+fn line_span(rope: &Rope, from: usize, to: usize) -> (usize, usize) {
+    let start = rope.line_to_char(rope.char_to_line(from));
+    let next = rope.char_to_line(to) + 1;
+    let end = if next < rope.len_lines() { rope.line_to_char(next) } else { rope.len_chars() };
+    (start, end)
+}
+
+/// How often each word appears across the open buffers, kept up to date
+/// as they are edited so completing a word needs no scan of the text.
+pub struct WordIndex {
+    counts: HashMap<String, usize>,
+}
+
+impl WordIndex {
+    pub fn new() -> Self {
+        WordIndex { counts: HashMap::new() }
+    }
+
+    /// Counts the words of a buffer opened or changed as a whole.
+    pub fn add(&mut self, text: RopeSlice) {
+        for_each_word(text, |word| *self.counts.entry(word.to_string()).or_default() += 1);
+    }
+
+    /// Forgets the words of text that went away.
+    pub fn remove(&mut self, text: RopeSlice) {
+        for_each_word(text, |word| {
+            if let Some(count) = self.counts.get_mut(word) {
+                *count -= 1;
+                if *count == 0 {
+                    self.counts.remove(word);
+                }
+            }
+        });
+    }
+
+    /// Brings the counts up to date with `edits`, already applied to `rope`.
+    /// The edits are undone one by one on a copy, swapping the words of the
+    /// lines each one touched as they are after it for those before it.
+    pub fn apply_edits(&mut self, rope: &Rope, edits: &[EditOp]) {
+        let mut rope = rope.clone();
+        for op in edits.iter().rev() {
+            match op {
+                EditOp::Insert { char_idx, .. } => {
+                    let (start, end) = line_span(&rope, *char_idx, char_idx + op.char_len());
+                    self.add(rope.slice(start..end));
+                    rope.remove(*char_idx..char_idx + op.char_len());
+                    let (start, end) = line_span(&rope, *char_idx, *char_idx);
+                    self.remove(rope.slice(start..end));
+                }
+                EditOp::Delete { char_idx, content } => {
+                    let (start, end) = line_span(&rope, *char_idx, *char_idx);
+                    self.add(rope.slice(start..end));
+                    rope.insert(*char_idx, content);
+                    let (start, end) = line_span(&rope, *char_idx, char_idx + op.char_len());
+                    self.remove(rope.slice(start..end));
+                }
+            }
+        }
+    }
+
+    /// Words starting with `prefix` but longer, the most used first.
+    pub fn complete(&self, prefix: &str) -> Vec<String> {
+        let mut words: Vec<(&String, usize)> = self
+            .counts
+            .iter()
+            .filter(|(word, _)| word.len() > prefix.len() && word.starts_with(prefix))
+            .map(|(word, &count)| (word, count))
+            .collect();
+        words.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        words.into_iter().take(MAX_COMPLETIONS).map(|(word, _)| word.clone()).collect()
+    }
+}
+
+impl Default for WordIndex {
+    fn default() -> Self {
+        WordIndex::new()
+    }
+}
+
+/// The popup of words offered for the one being typed.
+pub struct WordMenu {
+    pub items: Vec<String>,
+    pub selected: usize,
+}
+
+impl WordMenu {
+    pub fn new(items: Vec<String>) -> Self {
+        WordMenu { items, selected: 0 }
+    }
+
+    pub fn select_next(&mut self) {
+        if self.selected + 1 < self.items.len() {
+            self.selected += 1;
+        }
+    }
+
+    pub fn select_prev(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+}